@@ -0,0 +1,247 @@
+// A length-delimited echo server on a bare edge-triggered epoll loop, with a
+// pair of mirrored buffers per connection. The client side opens hundreds of
+// connections and writes each frame in two pieces, so the server keeps seeing
+// partial frames, and frames of odd sizes end up straddling the end of the
+// ring. Neither needs special handling: the decoder always gets the whole
+// committed region as one slice.
+//
+// Run with: cargo run --example epoll_server
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::fd::{AsRawFd, RawFd},
+    thread,
+};
+
+use mirrored_buffer::{
+    codec::{length_delimited::Codec, Decoder},
+    MirroredBuffer,
+};
+
+const CONNS: usize = 256;
+const FRAMES: usize = 50;
+const BUF_SIZE: usize = 4096;
+const MAX_PAYLOAD_LEN: usize = 1500;
+
+const LISTENER: u64 = u64::MAX;
+
+struct Conn<'a> {
+    stream: TcpStream,
+    rx: MirroredBuffer<'a>,
+    tx: MirroredBuffer<'a>,
+    codec: Codec,
+    eof: bool,
+}
+
+impl<'a> Conn<'a> {
+    fn new(stream: TcpStream) -> io::Result<Conn<'a>> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        // Names only have to be unique among live buffers, and a connection
+        // gives up its buffers before its fd can be reused.
+        let fd = stream.as_raw_fd();
+        let rx = MirroredBuffer::new(BUF_SIZE, Some(&format!("epoll-{fd}-rx")), None)
+            .map_err(io::Error::other)?;
+        let tx = MirroredBuffer::new(BUF_SIZE, Some(&format!("epoll-{fd}-tx")), None)
+            .map_err(io::Error::other)?;
+
+        Ok(Conn {
+            stream,
+            rx,
+            tx,
+            codec: Codec::new().with_max_len(MAX_PAYLOAD_LEN),
+            eof: false,
+        })
+    }
+
+    // Reads until the socket would block, rx is full or the peer closed.
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut filled = 0;
+        while !self.eof && self.rx.free() > 0 {
+            match self.rx.fill_from(&mut self.stream) {
+                Ok(0) => self.eof = true,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(filled)
+    }
+
+    // Echoes every complete frame in rx for which there is room in tx.
+    fn echo(&mut self) -> io::Result<usize> {
+        let mut echoed = 0;
+        while let Some(committed) = self.rx.committed() {
+            let Some((payload, size)) = self
+                .codec
+                .decode(committed)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?
+            else {
+                break;
+            };
+            if self.tx.free() < size {
+                break;
+            }
+            self.codec.encode(&mut self.tx, payload).unwrap();
+            self.rx.consume(size);
+            echoed += 1;
+        }
+        Ok(echoed)
+    }
+
+    fn drain(&mut self) -> io::Result<usize> {
+        match self.tx.flush_to(&mut self.stream) {
+            Ok(n) => Ok(n),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    // With edge-triggered notifications, each event has to be taken as far
+    // as it goes. Echoing frees rx, which may let more be read, and draining
+    // frees tx, which may let more frames be echoed, so this goes around
+    // until nothing moves. Returns whether the connection is done.
+    fn handle(&mut self) -> io::Result<bool> {
+        loop {
+            let progress = self.fill()? + self.echo()? + self.drain()?;
+            if progress == 0 {
+                return Ok(self.eof && self.tx.used() == 0);
+            }
+        }
+    }
+}
+
+fn epoll_add(epfd: RawFd, fd: RawFd, token: u64) -> io::Result<()> {
+    let mut event = libc::epoll_event {
+        events: (libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET) as u32,
+        u64: token,
+    };
+    if unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn serve(ln: TcpListener) -> io::Result<()> {
+    ln.set_nonblocking(true)?;
+
+    let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+    if epfd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    epoll_add(epfd, ln.as_raw_fd(), LISTENER)?;
+
+    let mut conns: HashMap<u64, Conn> = HashMap::new();
+    let mut accepted = 0;
+    let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 128];
+
+    while accepted < CONNS || !conns.is_empty() {
+        let n = unsafe { libc::epoll_wait(epfd, events.as_mut_ptr(), events.len() as i32, -1) };
+        if n == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        for event in &events[..n as usize] {
+            let token = event.u64;
+            if token == LISTENER {
+                loop {
+                    let stream = match ln.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                        Err(err) => return Err(err),
+                    };
+                    let fd = stream.as_raw_fd();
+                    let mut conn = Conn::new(stream)?;
+                    epoll_add(epfd, fd, fd as u64)?;
+                    // Data may already be waiting; the edge for it might
+                    // have come before the registration.
+                    conn.handle()?;
+                    conns.insert(fd as u64, conn);
+                    accepted += 1;
+                }
+                continue;
+            }
+
+            let Some(conn) = conns.get_mut(&token) else {
+                continue;
+            };
+            let done = match conn.handle() {
+                Ok(done) => done,
+                Err(err) => {
+                    eprintln!("connection {token}: {err}");
+                    true
+                }
+            };
+            if done {
+                // Closing the socket drops it from the epoll set.
+                conns.remove(&token);
+            }
+        }
+    }
+
+    unsafe { libc::close(epfd) };
+    println!("server echoed all frames of {accepted} connections");
+    Ok(())
+}
+
+fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn client(addr: SocketAddr) -> io::Result<()> {
+    let mut conns = (0..CONNS)
+        .map(|_| {
+            let conn = TcpStream::connect(addr)?;
+            conn.set_nodelay(true)?;
+            Ok(conn)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    for i in 0..FRAMES {
+        let frames: Vec<Vec<u8>> = (0..CONNS)
+            .map(|c| {
+                let len = 1 + (i * 131 + c * 17) % MAX_PAYLOAD_LEN;
+                encode(&vec![(i + c) as u8; len])
+            })
+            .collect();
+
+        // Every frame goes out in two writes, split at a different point
+        // each time, so the server reads it in pieces.
+        for (conn, frame) in conns.iter_mut().zip(&frames) {
+            let split = 1 + (i * 7) % (frame.len() - 1);
+            conn.write_all(&frame[..split])?;
+        }
+        for (conn, frame) in conns.iter_mut().zip(&frames) {
+            let split = 1 + (i * 7) % (frame.len() - 1);
+            conn.write_all(&frame[split..])?;
+        }
+
+        for (conn, frame) in conns.iter_mut().zip(&frames) {
+            let mut echo = vec![0; frame.len()];
+            conn.read_exact(&mut echo)?;
+            assert!(&echo == frame);
+        }
+    }
+
+    println!("client received {FRAMES} echoed frames on each of {CONNS} connections");
+    Ok(())
+}
+
+fn main() {
+    let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = ln.local_addr().unwrap();
+    println!("server listening on {addr}");
+
+    let server = thread::spawn(move || serve(ln).unwrap());
+    client(addr).unwrap();
+    server.join().unwrap();
+}
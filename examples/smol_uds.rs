@@ -0,0 +1,117 @@
+// A length-delimited echo over a Unix domain socket, running on smol. Nothing
+// here is specific to smol beyond spawning and the socket types: AsyncBuffered
+// only relies on the futures-io traits.
+//
+// Run with: cargo run --example smol_uds --features futures-io
+
+use std::{
+    env, fs,
+    io::{self, ErrorKind},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    process,
+};
+
+use futures::{future::poll_fn, AsyncWriteExt};
+use mirrored_buffer::{
+    codec::{
+        length_delimited::{Codec, HEADER_LEN},
+        Decoder,
+    },
+    AsyncBuffered, MirroredBuffer,
+};
+use smol::Async;
+
+const FRAMES: usize = 1000;
+
+// Reads the next frame into `stream.rx()`, returning its size, or None on EOF.
+async fn next_frame<S>(
+    stream: &mut AsyncBuffered<'_, S>,
+    codec: &mut Codec,
+) -> io::Result<Option<usize>>
+where
+    S: futures::AsyncRead + Unpin,
+{
+    let mut want = HEADER_LEN;
+    loop {
+        if !poll_fn(|cx| stream.poll_fill_to(cx, want)).await? {
+            return Ok(None);
+        }
+
+        let committed = stream.rx().committed().unwrap();
+        match codec.decode(committed) {
+            Ok(Some((_, size))) => return Ok(Some(size)),
+            Ok(None) => {
+                // The header is in, so the frame's full size is known.
+                let len = u32::from_be_bytes(committed[..HEADER_LEN].try_into().unwrap());
+                want = HEADER_LEN + len as usize;
+            }
+            Err(err) => return Err(io::Error::new(ErrorKind::InvalidData, err)),
+        }
+    }
+}
+
+async fn serve(conn: Async<UnixStream>) -> io::Result<()> {
+    let rx = MirroredBuffer::new(4096, Some("server-rx"), None).unwrap();
+    let tx = MirroredBuffer::new(4096, Some("server-tx"), None).unwrap();
+    let mut stream = AsyncBuffered::new(conn, rx, tx);
+    let mut codec = Codec::new().with_max_len(4096 - HEADER_LEN);
+
+    while let Some(size) = next_frame(&mut stream, &mut codec).await? {
+        // The echo is encoded straight out of rx. Frames that straddle the
+        // end of the ring come out of a single slice all the same.
+        let (rx, tx) = stream.buffers_mut();
+        let (payload, _) = codec.decode(rx.committed().unwrap()).unwrap().unwrap();
+        codec.encode(tx, payload).unwrap();
+        rx.consume(size);
+
+        stream.flush().await?;
+    }
+    Ok(())
+}
+
+async fn client(path: &Path) -> io::Result<()> {
+    let conn = Async::<UnixStream>::connect(path).await?;
+    let rx = MirroredBuffer::new(4096, Some("client-rx"), None).unwrap();
+    let tx = MirroredBuffer::new(4096, Some("client-tx"), None).unwrap();
+    let mut stream = AsyncBuffered::new(conn, rx, tx);
+    let mut codec = Codec::new().with_max_len(4096 - HEADER_LEN);
+
+    for i in 0..FRAMES {
+        // Odd sizes make frames land all over the ring.
+        let payload = vec![i as u8; 1 + (i * 37) % 3000];
+        codec.encode(stream.tx_mut(), &payload).unwrap();
+        stream.flush().await?;
+
+        let size = next_frame(&mut stream, &mut codec).await?.unwrap();
+        let (echo, _) = codec
+            .decode(stream.rx().committed().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(echo, &payload[..]);
+        stream.rx_mut().consume(size);
+    }
+
+    println!("client received {FRAMES} echoed frames");
+    Ok(())
+}
+
+fn main() {
+    let path = env::temp_dir().join(format!("mirrored-buffer-smol-{}.sock", process::id()));
+    let _ = fs::remove_file(&path);
+
+    smol::block_on(async {
+        let ln = Async::new(UnixListener::bind(&path).unwrap()).unwrap();
+        println!("server listening on {}", path.display());
+
+        let server = smol::spawn(async move {
+            let (conn, _) = ln.accept().await.unwrap();
+            serve(conn).await.unwrap();
+        });
+
+        client(&path).await.unwrap();
+        server.cancel().await;
+    });
+
+    fs::remove_file(&path).unwrap();
+}
@@ -1,98 +1,330 @@
+// Latency of parsing a frame that straddles the end of the receive ring.
+//
+// On every request the server writes a small frame followed by the first
+// chunk of a big frame, which together fill the client's 4096 byte ring to
+// its end, then writes the last `n` bytes of the big frame in a second write.
+// Those land at the start of the ring, so the big frame wraps. A mirrored
+// buffer hands it out as one slice as is; a regular ring buffer has to copy
+// it into a contiguous scratch buffer first.
+//
+// Frames are a u16 big-endian payload length followed by the payload. The
+// client busy waits on a nonblocking socket and measures from sending the
+// request to holding the big frame as a single slice, for a range of `n` and
+// small frame sizes, and reports percentiles for both kinds of ring.
+//
+// Run with: cargo run --release --example tcp [-- ITERATIONS]
+
 use std::{
-    io::{Error, Read, Write},
-    net::{self, SocketAddr, SocketAddrV4, TcpListener, TcpStream},
-    str,
-    sync::atomic::{AtomicU16, Ordering},
+    env,
+    hint::black_box,
+    io::{Error, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
     thread,
+    time::{Duration, Instant},
 };
 
 use mirrored_buffer::MirroredBuffer;
 
-struct Frame {}
+const RING_SIZE: usize = 4096;
+const HEADER_LEN: usize = 2;
 
-struct Server<'a> {
-    buf: MirroredBuffer<'a>,
+const SMALL_PAYLOAD_SIZES: [usize; 3] = [16, 256, 1024];
+const SPLITS: [usize; 4] = [1, 16, 256, 1024];
+
+// A request: the small frame's payload size and how many bytes of the big
+// frame go in the second write.
+struct Request {
+    small: u16,
+    n: u16,
+}
+
+struct Server {
     ln: TcpListener,
     pub local_addr: SocketAddr,
 }
 
-impl<'a> Server<'a> {
-    fn new(ip: &str) -> Result<(Server<'a>, u16), Error> {
+impl Server {
+    fn new(ip: &str) -> Result<Server, Error> {
         let ln = TcpListener::bind(format!("{ip}:0"))?;
         let local_addr = ln.local_addr().unwrap();
         println!("server bound to {local_addr}");
 
-        let buf = MirroredBuffer::new(4096, Some("server"), Some(0))
-            .expect("could not initialize mirrored buffer");
-
-        Ok((
-            Server {
-                buf,
-                ln,
-                local_addr,
-            },
-            local_addr.port(),
-        ))
+        Ok(Server { ln, local_addr })
     }
 
     fn run(&mut self) -> Result<(), Error> {
-        println!("server running, listening for connections");
-
         let (mut conn, peer_addr) = self.ln.accept()?;
+        conn.set_nodelay(true)?;
         println!("server {} connected to {}", self.local_addr, peer_addr);
 
-        // server writes a small frame followed by a big one that's partial.
-        // a second write sends the rest of the partial frame
-        // a mirrored buffer will do no memcpy to fit the remaining chunk
-        // while a regular ring buffer will memcpy to have the message continous
-
-        // max message size: 4096
-        // header size: u16
-        // max payload size: 4096 - u16 = 4080 bytes
-        // first frame: u16 + small_frame_msg_size
-        // second frame chunk1: u16 + (4096 - 2 * u16 - (small_frame_msg_size - n))
-        // second frame chunk2: n
-        // n varies: expect worse latencies for smaller n
-        // small_frame_msg_size varies: expect worse latencies the smaller it is
-        // client busy waits for message and we calculate how long does it take to
-        //
+        let mut request = [0u8; 4];
+        let mut out = Vec::with_capacity(2 * RING_SIZE);
+        loop {
+            match conn.read_exact(&mut request) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err),
+            }
+            let request = Request {
+                small: u16::from_be_bytes([request[0], request[1]]),
+                n: u16::from_be_bytes([request[2], request[3]]),
+            };
 
-        Ok(())
+            // The small frame and the first chunk of the big one fill the
+            // ring exactly; the last `n` bytes of the big frame wrap.
+            let small = HEADER_LEN + request.small as usize;
+            let big = RING_SIZE - small + request.n as usize;
+            out.clear();
+            out.extend_from_slice(&request.small.to_be_bytes());
+            out.resize(small, 1);
+            out.extend_from_slice(&((big - HEADER_LEN) as u16).to_be_bytes());
+            out.resize(small + big, 2);
+
+            let split = out.len() - request.n as usize;
+            conn.write_all(&out[..split])?;
+            conn.write_all(&out[split..])?;
+        }
     }
 }
 
-struct Client<'a> {
+// What the client needs from its receive ring.
+trait Ring {
+    fn fill_from(&mut self, conn: &mut TcpStream) -> Result<usize, Error>;
+    // The next whole frame as one slice, with its size.
+    fn frame(&mut self) -> Option<(&[u8], usize)>;
+    fn consume(&mut self, n: usize);
+    // Moves an empty ring's head back to the start.
+    fn rewind(&mut self);
+}
+
+fn frame_size(header: [u8; HEADER_LEN]) -> usize {
+    HEADER_LEN + u16::from_be_bytes(header) as usize
+}
+
+struct Mirrored<'a> {
     buf: MirroredBuffer<'a>,
+    head: usize,
+}
+
+impl Ring for Mirrored<'_> {
+    fn fill_from(&mut self, conn: &mut TcpStream) -> Result<usize, Error> {
+        self.buf.fill_from(conn)
+    }
+
+    fn frame(&mut self) -> Option<(&[u8], usize)> {
+        let committed = self.buf.committed()?;
+        if committed.len() < HEADER_LEN {
+            return None;
+        }
+        let size = frame_size([committed[0], committed[1]]);
+        if committed.len() < size {
+            return None;
+        }
+        Some((&committed[..size], size))
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.head = (self.head + self.buf.consume(n)) % self.buf.size();
+    }
+
+    fn rewind(&mut self) {
+        let n = (self.buf.size() - self.head) % self.buf.size();
+        self.buf.commit(n);
+        self.consume(n);
+    }
+}
+
+// A regular ring buffer over a single copy of the memory.
+struct VecRing {
+    data: Vec<u8>,
+    head: usize,
+    len: usize,
+    scratch: Vec<u8>,
+}
+
+impl VecRing {
+    fn byte(&self, i: usize) -> u8 {
+        self.data[(self.head + i) % self.data.len()]
+    }
+}
+
+impl Ring for VecRing {
+    fn fill_from(&mut self, conn: &mut TcpStream) -> Result<usize, Error> {
+        let tail = (self.head + self.len) % self.data.len();
+        let end = if tail < self.head || self.len == self.data.len() {
+            self.head
+        } else {
+            self.data.len()
+        };
+        let n = conn.read(&mut self.data[tail..end])?;
+        self.len += n;
+        Ok(n)
+    }
+
+    fn frame(&mut self) -> Option<(&[u8], usize)> {
+        if self.len < HEADER_LEN {
+            return None;
+        }
+        let size = frame_size([self.byte(0), self.byte(1)]);
+        if self.len < size {
+            return None;
+        }
+
+        let end = self.head + size;
+        if end <= self.data.len() {
+            return Some((&self.data[self.head..end], size));
+        }
+        // The frame wraps: put it back together.
+        let first = self.data.len() - self.head;
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&self.data[self.head..]);
+        self.scratch.extend_from_slice(&self.data[..size - first]);
+        Some((&self.scratch, size))
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.head = (self.head + n) % self.data.len();
+        self.len -= n;
+    }
+
+    fn rewind(&mut self) {
+        assert!(self.len == 0);
+        self.head = 0;
+    }
+}
+
+struct Client {
     conn: TcpStream,
-    pub local_addr: SocketAddr,
 }
 
-impl<'a> Client<'a> {
-    fn new(peer_addr: &str) -> Result<Client<'a>, Error> {
+impl Client {
+    fn new(peer_addr: &str) -> Result<Client, Error> {
         let conn = TcpStream::connect(peer_addr)?;
+        conn.set_nodelay(true)?;
+        conn.set_nonblocking(true)?;
         let local_addr = conn.local_addr().unwrap();
         let peer_addr = conn.peer_addr().unwrap();
 
         println!("client {local_addr} connected to server {peer_addr}");
 
-        let buf = MirroredBuffer::new(4096, Some("client"), Some(0))
-            .expect("could not initialize mirrored buffer");
+        Ok(Client { conn })
+    }
 
-        Ok(Client {
-            buf,
-            conn,
-            local_addr,
-        })
+    // Spins on the nonblocking socket until `ring` holds a whole frame, and
+    // returns its size.
+    fn next_frame<R: Ring>(&mut self, ring: &mut R) -> Result<usize, Error> {
+        loop {
+            if let Some((frame, size)) = ring.frame() {
+                black_box(frame);
+                return Ok(size);
+            }
+            match ring.fill_from(&mut self.conn) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
     }
 
-    fn run(&mut self) -> Result<(), Error> {
-        println!("client running");
+    fn measure<R: Ring>(
+        &mut self,
+        ring: &mut R,
+        request: &Request,
+        iterations: usize,
+    ) -> Result<Vec<Duration>, Error> {
+        let mut request_bytes = [0u8; 4];
+        request_bytes[..2].copy_from_slice(&request.small.to_be_bytes());
+        request_bytes[2..].copy_from_slice(&request.n.to_be_bytes());
+
+        let mut latencies = Vec::with_capacity(iterations);
+        for _ in 0..iterations {
+            ring.rewind();
+
+            let start = Instant::now();
+            self.conn.write_all(&request_bytes)?;
+            let small = self.next_frame(ring)?;
+            ring.consume(small);
+            let big = self.next_frame(ring)?;
+            latencies.push(start.elapsed());
+
+            ring.consume(big);
+        }
+        Ok(latencies)
+    }
+
+    fn run(&mut self, iterations: usize) -> Result<(), Error> {
+        println!("client running, {iterations} iterations per case");
+        println!(
+            "{:>6} {:>6}  {:>8} {:>8} {:>8} {:>8}  {:>8} {:>8} {:>8} {:>8}",
+            "small",
+            "n",
+            "m p50",
+            "m p90",
+            "m p99",
+            "m p99.9",
+            "v p50",
+            "v p90",
+            "v p99",
+            "v p99.9",
+        );
+
+        let mut mirrored = Mirrored {
+            buf: MirroredBuffer::new(RING_SIZE, Some("client"), Some(0))
+                .expect("could not initialize mirrored buffer"),
+            head: 0,
+        };
+        let mut vec_ring = VecRing {
+            data: vec![0; RING_SIZE],
+            head: 0,
+            len: 0,
+            scratch: Vec::with_capacity(RING_SIZE),
+        };
+
+        for small in SMALL_PAYLOAD_SIZES {
+            for n in SPLITS {
+                // The wrapped bytes only fit once the small frame is
+                // consumed.
+                if n > HEADER_LEN + small {
+                    continue;
+                }
+                let request = Request {
+                    small: small as u16,
+                    n: n as u16,
+                };
+
+                // Warm up both paths before measuring either.
+                self.measure(&mut mirrored, &request, iterations / 10)?;
+                self.measure(&mut vec_ring, &request, iterations / 10)?;
+                let m = percentiles(self.measure(&mut mirrored, &request, iterations)?);
+                let v = percentiles(self.measure(&mut vec_ring, &request, iterations)?);
+
+                println!(
+                    "{small:>6} {n:>6}  {:>8.2?} {:>8.2?} {:>8.2?} {:>8.2?}  {:>8.2?} {:>8.2?} {:>8.2?} {:>8.2?}",
+                    m[0], m[1], m[2], m[3], v[0], v[1], v[2], v[3],
+                );
+            }
+        }
         Ok(())
     }
 }
 
+fn percentiles(mut latencies: Vec<Duration>) -> [Duration; 4] {
+    latencies.sort_unstable();
+    [0.5, 0.9, 0.99, 0.999].map(|p| {
+        let i = ((latencies.len() as f64 * p) as usize).min(latencies.len() - 1);
+        latencies[i]
+    })
+}
+
 fn main() {
-    let (mut server, server_port) = Server::new("127.0.0.1").unwrap();
+    let iterations = env::args()
+        .nth(1)
+        .map(|arg| arg.parse().expect("iterations must be a number"))
+        .unwrap_or(10_000);
+
+    let mut server = Server::new("127.0.0.1").unwrap();
+    let server_port = server.local_addr.port();
     let server_thread = thread::spawn(move || {
         if let Err(err) = server.run() {
             panic!("server error {err}");
@@ -101,11 +333,11 @@ fn main() {
 
     let client_thread = thread::spawn(move || {
         let mut client = Client::new(format!("127.0.0.1:{}", server_port).as_str()).unwrap();
-        if let Err(err) = client.run() {
+        if let Err(err) = client.run(iterations) {
             panic!("client error {err}");
         }
     });
 
-    server_thread.join().unwrap();
     client_thread.join().unwrap();
+    server_thread.join().unwrap();
 }
@@ -0,0 +1,238 @@
+// Localhost TCP echo throughput of length-prefixed frames, with the server's
+// receive side buffered in a mirrored buffer and then in a naive Vec-backed
+// ring, which has to copy every frame that wraps around its end into a
+// scratch Vec before it can hand it out as one slice.
+//
+// Run with: cargo run --release --example tcp_throughput -- \
+//     [--frame-size BYTES] [--frames N] [--buffer-size BYTES]
+
+use std::{
+    env,
+    io::{self, BufWriter, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use mirrored_buffer::{
+    codec::length_delimited::{Codec, HEADER_LEN},
+    MirroredBuffer,
+};
+
+struct Config {
+    frame_size: usize,
+    frames: usize,
+    buffer_size: usize,
+}
+
+impl Config {
+    fn from_args() -> Config {
+        let mut config = Config {
+            frame_size: 1000,
+            frames: 200_000,
+            buffer_size: 64 << 10,
+        };
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or_else(|| panic!("{arg} takes a number"));
+            match arg.as_str() {
+                "--frame-size" => config.frame_size = value,
+                "--frames" => config.frames = value,
+                "--buffer-size" => config.buffer_size = value,
+                _ => panic!("unknown argument {arg}"),
+            }
+        }
+
+        assert!(
+            HEADER_LEN + config.frame_size <= config.buffer_size,
+            "frames have to fit in the buffer"
+        );
+        config
+    }
+}
+
+fn frame_len(header: &[u8]) -> usize {
+    HEADER_LEN + u32::from_be_bytes(header[..HEADER_LEN].try_into().unwrap()) as usize
+}
+
+fn serve_mirrored(conn: TcpStream, config: &Config) -> io::Result<()> {
+    let mut rx = MirroredBuffer::new(config.buffer_size, Some("throughput-rx"), None).unwrap();
+    let mut codec = Codec::new().with_max_len(config.buffer_size - HEADER_LEN);
+    let mut reader = conn.try_clone()?;
+    let mut writer = BufWriter::with_capacity(config.buffer_size, conn);
+
+    loop {
+        writer.flush()?;
+        if rx.fill_from(&mut reader)? == 0 {
+            return Ok(());
+        }
+
+        while let Some((payload, size)) = rx.decode(&mut codec).unwrap() {
+            writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+            writer.write_all(payload)?;
+            rx.consume(size);
+        }
+    }
+}
+
+// The usual ring buffer: one copy of the memory, so both reads and frames
+// are split in two where they cross its end.
+struct VecRing {
+    data: Vec<u8>,
+    head: usize,
+    len: usize,
+    scratch: Vec<u8>,
+}
+
+impl VecRing {
+    fn new(size: usize) -> VecRing {
+        VecRing {
+            data: vec![0; size],
+            head: 0,
+            len: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    fn fill_from<R: Read>(&mut self, r: &mut R) -> io::Result<usize> {
+        let tail = (self.head + self.len) % self.data.len();
+        let end = if tail < self.head || self.len == self.data.len() {
+            self.head
+        } else {
+            self.data.len()
+        };
+        let n = r.read(&mut self.data[tail..end])?;
+        self.len += n;
+        Ok(n)
+    }
+
+    // Fills `out` from the head on, wrapping if need be.
+    fn copy_out(&self, out: &mut [u8]) {
+        let start = self.head;
+        let first = out.len().min(self.data.len() - start);
+        let (a, b) = out.split_at_mut(first);
+        a.copy_from_slice(&self.data[start..start + first]);
+        b.copy_from_slice(&self.data[..b.len()]);
+    }
+
+    // The next whole frame, copied into the scratch Vec if it wraps.
+    fn next_frame(&mut self) -> Option<(&[u8], usize)> {
+        if self.len < HEADER_LEN {
+            return None;
+        }
+        let mut header = [0; HEADER_LEN];
+        self.copy_out(&mut header);
+        let size = frame_len(&header);
+        if self.len < size {
+            return None;
+        }
+
+        if self.head + size <= self.data.len() {
+            return Some((&self.data[self.head..self.head + size], size));
+        }
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.resize(size, 0);
+        self.copy_out(&mut scratch);
+        self.scratch = scratch;
+        Some((&self.scratch, size))
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.head = (self.head + n) % self.data.len();
+        self.len -= n;
+    }
+}
+
+fn serve_vec_ring(conn: TcpStream, config: &Config) -> io::Result<()> {
+    let mut rx = VecRing::new(config.buffer_size);
+    let mut reader = conn.try_clone()?;
+    let mut writer = BufWriter::with_capacity(config.buffer_size, conn);
+
+    loop {
+        writer.flush()?;
+        if rx.fill_from(&mut reader)? == 0 {
+            return Ok(());
+        }
+
+        while let Some((frame, size)) = rx.next_frame() {
+            writer.write_all(frame)?;
+            rx.consume(size);
+        }
+    }
+}
+
+// Sends all frames from one thread while reading the echoes on another, and
+// returns how long it took for the last echo to come back.
+fn run_client(conn: TcpStream, config: &Config) -> io::Result<Duration> {
+    let mut frame = (config.frame_size as u32).to_be_bytes().to_vec();
+    frame.extend((0..config.frame_size).map(|x| x as u8));
+    let batch: Vec<u8> = frame.repeat((256 << 10) / frame.len() + 1);
+    let total = frame.len() * config.frames;
+
+    let start = Instant::now();
+    let mut writer = conn.try_clone()?;
+    let sender = thread::spawn(move || -> io::Result<()> {
+        let mut left = total;
+        while left > 0 {
+            let n = left.min(batch.len());
+            writer.write_all(&batch[..n])?;
+            left -= n;
+        }
+        Ok(())
+    });
+
+    let mut reader = conn;
+    let mut received = 0;
+    let mut chunk = vec![0; 256 << 10];
+    while received < total {
+        match reader.read(&mut chunk)? {
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            n => received += n,
+        }
+    }
+    let elapsed = start.elapsed();
+
+    sender.join().unwrap()?;
+    Ok(elapsed)
+}
+
+fn bench(name: &str, config: &Config, serve: fn(TcpStream, &Config) -> io::Result<()>) {
+    let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+    let conn = TcpStream::connect(ln.local_addr().unwrap()).unwrap();
+    conn.set_nodelay(true).unwrap();
+
+    let elapsed = thread::scope(|s| {
+        let server = s.spawn(|| {
+            let (conn, _) = ln.accept().unwrap();
+            conn.set_nodelay(true).unwrap();
+            serve(conn, config).unwrap();
+        });
+
+        let elapsed = run_client(conn, config).unwrap();
+        server.join().unwrap();
+        elapsed
+    });
+
+    let bytes = ((HEADER_LEN + config.frame_size) * config.frames) as f64;
+    println!(
+        "{name:>14}: {:8.1} MiB/s {:10.0} frames/s ({:.3}s)",
+        bytes / (1 << 20) as f64 / elapsed.as_secs_f64(),
+        config.frames as f64 / elapsed.as_secs_f64(),
+        elapsed.as_secs_f64(),
+    );
+}
+
+fn main() {
+    let config = Config::from_args();
+    println!(
+        "echoing {} frames of {} bytes through a {} byte receive buffer",
+        config.frames, config.frame_size, config.buffer_size
+    );
+
+    bench("mirrored", &config, serve_mirrored);
+    bench("vec ring", &config, serve_vec_ring);
+}
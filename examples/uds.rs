@@ -0,0 +1,112 @@
+// Length-prefixed frames over a Unix domain socket, decoded in place out of a
+// mirrored buffer with the length-delimited codec.
+//
+// The client sends frames of odd sizes, each in two writes, so the server
+// sees partial frames and frames straddling the end of its ring. Nothing is
+// done about either: the server fills the buffer, decodes whatever is
+// complete and leaves the rest committed for the next read. A frame that
+// wraps around is still a single slice, and is echoed back as such.
+//
+// Run with: cargo run --example uds
+
+use std::{
+    env, fs,
+    io::{self, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    process, thread,
+};
+
+use mirrored_buffer::{
+    codec::length_delimited::{Codec, HEADER_LEN},
+    MirroredBuffer,
+};
+
+const BUF_SIZE: usize = 4096;
+const FRAMES: usize = 500;
+
+fn serve(mut conn: UnixStream) -> io::Result<()> {
+    let mut rx = MirroredBuffer::new(BUF_SIZE, Some("uds-rx"), None).unwrap();
+    let mut tx = MirroredBuffer::new(BUF_SIZE, Some("uds-tx"), None).unwrap();
+    let mut codec = Codec::new().with_max_len(BUF_SIZE - HEADER_LEN);
+
+    // Where the next frame starts in the ring, to tell when one wraps.
+    let mut head = 0;
+    let mut wrapped = 0;
+
+    loop {
+        // A frame never exceeds the buffer, so it is never full here and
+        // Ok(0) can only mean EOF.
+        if rx.fill_from(&mut conn)? == 0 {
+            break;
+        }
+
+        loop {
+            let (payload, size) = match rx.decode(&mut codec) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(err) => return Err(io::Error::new(ErrorKind::InvalidData, err)),
+            };
+            if head + size > rx.size() {
+                wrapped += 1;
+            }
+            head = (head + size) % rx.size();
+
+            codec.encode(&mut tx, payload).unwrap();
+            rx.consume(size);
+            tx.flush_to(&mut conn)?;
+        }
+    }
+
+    println!("server echoed {FRAMES} frames, {wrapped} of which wrapped around the ring");
+    Ok(())
+}
+
+fn client(path: &Path) -> io::Result<()> {
+    let mut conn = UnixStream::connect(path)?;
+    let mut rx = MirroredBuffer::new(BUF_SIZE, Some("uds-client-rx"), None).unwrap();
+    let mut codec = Codec::new().with_max_len(BUF_SIZE - HEADER_LEN);
+
+    for i in 0..FRAMES {
+        let len = 1 + (i * 97) % 3000;
+        let mut frame = (len as u32).to_be_bytes().to_vec();
+        frame.extend((0..len).map(|x| (x + i) as u8));
+
+        // Split the frame so the server only ever gets part of it at first.
+        let split = 1 + i % (frame.len() - 1);
+        conn.write_all(&frame[..split])?;
+        conn.flush()?;
+        conn.write_all(&frame[split..])?;
+
+        let size = loop {
+            if let Some((echo, size)) = rx.decode(&mut codec).unwrap() {
+                assert_eq!(echo, &frame[HEADER_LEN..]);
+                break size;
+            }
+            if rx.fill_from(&mut conn)? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        };
+        rx.consume(size);
+    }
+
+    println!("client received {FRAMES} echoed frames");
+    Ok(())
+}
+
+fn main() {
+    let path = env::temp_dir().join(format!("mirrored-buffer-uds-{}.sock", process::id()));
+    let _ = fs::remove_file(&path);
+
+    let ln = UnixListener::bind(&path).unwrap();
+    println!("server listening on {}", path.display());
+
+    let server = thread::spawn(move || {
+        let (conn, _) = ln.accept().unwrap();
+        serve(conn).unwrap();
+    });
+
+    client(&path).unwrap();
+    server.join().unwrap();
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,32 @@
+#ifndef MIRRORED_BUFFER_H
+#define MIRRORED_BUFFER_H
+
+/* Generated with cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct MirroredBuffer MirroredBuffer;
+
+int mirrored_buffer_new(size_t size, MirroredBuffer **out);
+
+void mirrored_buffer_destroy(MirroredBuffer *buf);
+
+size_t mirrored_buffer_size(const MirroredBuffer *buf);
+
+size_t mirrored_buffer_used(const MirroredBuffer *buf);
+
+size_t mirrored_buffer_free(const MirroredBuffer *buf);
+
+uint8_t *mirrored_buffer_claim(MirroredBuffer *buf, size_t size, size_t *len);
+
+size_t mirrored_buffer_commit(MirroredBuffer *buf, size_t size);
+
+const uint8_t *mirrored_buffer_committed(const MirroredBuffer *buf, size_t *len);
+
+size_t mirrored_buffer_consume(MirroredBuffer *buf, size_t size);
+
+#endif /* MIRRORED_BUFFER_H */
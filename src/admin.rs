@@ -0,0 +1,186 @@
+// Finding and removing the shared memory segments of buffers, for operators
+// and for processes cleaning up after peers that crashed: every buffer and
+// shared ring is backed by a segment under /dev/shm, which outlives a
+// process that dies without dropping it.
+use std::{
+    ffi::CString,
+    fs, io,
+    time::{Duration, SystemTime},
+};
+
+const SHM_DIR: &str = "/dev/shm";
+const PREFIX: &str = "mirrored-buffer-";
+
+// A segment named after the crate's scheme.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    // The name past the crate's prefix: the process id of the creator and
+    // the suffix for buffers, the name given to `create_shared` for shared
+    // rings.
+    pub name: String,
+    pub size: u64,
+    pub modified: SystemTime,
+    // The process that created the segment, if the name tells.
+    pub pid: Option<u32>,
+}
+
+impl Segment {
+    // Whether the process that created the segment is known to be gone.
+    pub fn is_orphaned(&self) -> bool {
+        let Some(pid) = self.pid else {
+            return false;
+        };
+        let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+        ret == -1 && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+    }
+}
+
+// The process id buffers put first in their names, see `MirroredBuffer::new`.
+fn pid_of(name: &str) -> Option<u32> {
+    let pid = name.split('-').next()?;
+    pid.parse().ok().filter(|&pid| pid > 0)
+}
+
+// Lists the segments named after the crate's scheme.
+pub fn list() -> io::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(SHM_DIR)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(name) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(PREFIX))
+        else {
+            continue;
+        };
+        // The segment may be gone by now.
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        segments.push(Segment {
+            name: name.to_string(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+            pid: pid_of(name),
+        });
+    }
+    Ok(segments)
+}
+
+// Unlinks the segments whose name starts with `prefix`, that were last
+// modified more than `older_than` ago and whose creator is known to be gone,
+// and returns them. Processes that mapped them keep their mapping. Segments
+// whose name does not tell their creator, e.g. shared rings, are left alone,
+// see `cleanup_shared`.
+pub fn cleanup(prefix: &str, older_than: Duration) -> io::Result<Vec<Segment>> {
+    remove(prefix, older_than, false)
+}
+
+// Like `cleanup`, also unlinking the segments whose name does not tell their
+// creator if they were last modified more than `older_than` ago. Their age is
+// all there is to go by, so one still in use may be removed: pick
+// `older_than` accordingly.
+pub fn cleanup_shared(prefix: &str, older_than: Duration) -> io::Result<Vec<Segment>> {
+    remove(prefix, older_than, true)
+}
+
+fn remove(prefix: &str, older_than: Duration, shared: bool) -> io::Result<Vec<Segment>> {
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    for segment in list()? {
+        let age = now.duration_since(segment.modified).unwrap_or_default();
+        let gone = match segment.pid {
+            Some(_) => segment.is_orphaned(),
+            None => shared,
+        };
+        if !segment.name.starts_with(prefix) || age < older_than || !gone {
+            continue;
+        }
+
+        let shm_name = CString::new(format!("/{PREFIX}{}", segment.name))?;
+        if unsafe { libc::shm_unlink(shm_name.as_ptr()) } == -1 {
+            let err = io::Error::last_os_error();
+            // Someone else cleaned it up first.
+            if err.kind() == io::ErrorKind::NotFound {
+                continue;
+            }
+            return Err(err);
+        }
+        removed.push(segment);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cleanup, cleanup_shared, list};
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use std::{ffi::CString, process, time::Duration};
+
+    #[test]
+    fn admin_lists_and_cleans_up_orphans() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None).unwrap();
+        let name = buf.name().strip_prefix("/mirrored-buffer-").unwrap();
+
+        // A segment left by a process that is gone.
+        let mut child = process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        let orphan = format!("{dead}-{}", next_buffer_index());
+        let shm_name = CString::new(format!("/mirrored-buffer-{orphan}")).unwrap();
+        let fd = unsafe {
+            libc::shm_open(
+                shm_name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                libc::S_IRUSR | libc::S_IWUSR,
+            )
+        };
+        assert!(fd != -1);
+        unsafe { libc::close(fd) };
+
+        let segments = list().unwrap();
+        let ours = segments.iter().find(|s| s.name == name).unwrap();
+        assert!(ours.pid == Some(process::id()) && ours.size == buf.size() as u64);
+        assert!(!ours.is_orphaned());
+        let theirs = segments.iter().find(|s| s.name == orphan).unwrap();
+        assert!(theirs.pid == Some(dead) && theirs.is_orphaned());
+
+        // Too recent.
+        assert!(cleanup(&orphan, Duration::from_secs(3600))
+            .unwrap()
+            .is_empty());
+        // Alive.
+        assert!(cleanup(name, Duration::ZERO).unwrap().is_empty());
+
+        let removed = cleanup(&orphan, Duration::ZERO).unwrap();
+        assert!(removed.len() == 1 && removed[0].name == orphan);
+        assert!(!list().unwrap().iter().any(|s| s.name == orphan));
+    }
+
+    #[test]
+    fn admin_cleans_up_shared_segments_on_request() {
+        // A segment whose name does not tell its creator.
+        let shared = format!("shared-{}", next_buffer_index());
+        let shm_name = CString::new(format!("/mirrored-buffer-{shared}")).unwrap();
+        let fd = unsafe {
+            libc::shm_open(
+                shm_name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                libc::S_IRUSR | libc::S_IWUSR,
+            )
+        };
+        assert!(fd != -1);
+        unsafe { libc::close(fd) };
+        let segments = list().unwrap();
+        let segment = segments.iter().find(|s| s.name == shared).unwrap();
+        assert!(segment.pid.is_none() && !segment.is_orphaned());
+
+        assert!(cleanup(&shared, Duration::ZERO).unwrap().is_empty());
+        assert!(cleanup_shared(&shared, Duration::from_secs(3600))
+            .unwrap()
+            .is_empty());
+        let removed = cleanup_shared(&shared, Duration::ZERO).unwrap();
+        assert!(removed.len() == 1 && removed[0].name == shared);
+        assert!(!list().unwrap().iter().any(|s| s.name == shared));
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{registry, util::round_up_to_page_size, Error, FullPolicy, MirroredBuffer};
+use std::{
+    ffi::CString,
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    process, ptr, slice,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+static ARENA_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+// Many rings in one shared memory segment, e.g. one per connection for a
+// server with thousands of them: the rings share a single fd and a single
+// reserved address range instead of taking a segment and an fd each.
+//
+// A ring at offset `o` of the segment is mirrored at offset `2 * o` of the
+// range, which is twice the capacity. What no ring uses stays reserved, not
+// accessible. Rings go back to the arena when dropped, and the space they
+// took is handed out again, first fit.
+pub struct Arena {
+    inner: Arc<ArenaInner>,
+}
+
+struct ArenaInner {
+    name: CString,
+    fd: libc::c_int,
+    base: *mut u8,
+    capacity: usize,
+    // The free extents of the segment as (offset, len), sorted by offset and
+    // never adjacent.
+    free: Mutex<Vec<(usize, usize)>>,
+}
+
+// The mapping is only touched through the rings, which never overlap.
+unsafe impl Send for ArenaInner {}
+unsafe impl Sync for ArenaInner {}
+
+// A ring carved out of an arena, to be used as a buffer.
+pub struct ArenaRing<'a> {
+    buf: ManuallyDrop<MirroredBuffer<'a>>,
+    arena: Arc<ArenaInner>,
+    offset: usize,
+}
+
+impl Arena {
+    // Creates an arena of at least `capacity` bytes. The segment is unlinked
+    // right away, so it goes away with the arena and its rings, whatever
+    // happens to the process.
+    pub fn new(capacity: usize) -> Result<Arena, Error> {
+        if capacity == 0 {
+            return Err(Error::invalid_size(capacity));
+        }
+        let capacity = round_up_to_page_size(capacity)?;
+
+        let name = format!(
+            "/mirrored-buffer-{}-arena-{}",
+            process::id(),
+            ARENA_INDEX.fetch_add(1, Ordering::Relaxed)
+        );
+        let name = CString::new(name).unwrap();
+        let fd = unsafe {
+            libc::shm_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                libc::S_IRUSR | libc::S_IWUSR,
+            )
+        };
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        unsafe { libc::shm_unlink(name.as_ptr()) };
+
+        let mut inner = ArenaInner {
+            name,
+            fd,
+            base: ptr::null_mut(),
+            capacity,
+            free: Mutex::new(vec![(0, capacity)]),
+        };
+        if unsafe { libc::ftruncate(fd, capacity as libc::off_t) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                capacity * 2,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        inner.base = addr as *mut u8;
+        Ok(Arena {
+            inner: Arc::new(inner),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    // How many bytes no ring takes, which may be in several pieces.
+    pub fn available(&self) -> usize {
+        let free = self.inner.free.lock().unwrap();
+        free.iter().map(|&(_, len)| len).sum()
+    }
+
+    // Carves out a ring of at least `size` bytes, rounded like
+    // `MirroredBuffer::new` does, or fails with NoSpace if no free piece of
+    // the arena is large enough.
+    pub fn ring<'a>(&self, size: usize) -> Result<ArenaRing<'a>, Error> {
+        if size == 0 {
+            return Err(Error::invalid_size(size));
+        }
+        let size_total = round_up_to_page_size(size)?;
+        if !size_total.is_power_of_two() {
+            return Err(Error::invalid_size(size_total));
+        }
+
+        let offset = {
+            let mut free = self.inner.free.lock().unwrap();
+            let Some(x) = free.iter().position(|&(_, len)| len >= size_total) else {
+                return Err(Error::no_space(size_total));
+            };
+            let (offset, len) = free[x];
+            if len == size_total {
+                free.remove(x);
+            } else {
+                free[x] = (offset + size_total, len - size_total);
+            }
+            offset
+        };
+
+        let addr = unsafe { self.inner.base.add(2 * offset) };
+        let mapped = self.inner.map(addr, offset, size_total).and_then(|_| {
+            self.inner
+                .map(unsafe { addr.add(size_total) }, offset, size_total)
+        });
+        if let Err(err) = mapped {
+            self.inner.release(offset, size_total);
+            return Err(err);
+        }
+
+        let buf = MirroredBuffer {
+            name: self.inner.name.clone(),
+            fd: self.inner.fd,
+            file_offset: offset,
+
+            head: 0,
+            tail: 0,
+
+            size_total,
+            size_mask: size_total - 1,
+            size_used: 0,
+
+            full_policy: FullPolicy::Clamp,
+
+            // The rings of an arena share its name, but for their offset.
+            usage: registry::register(
+                &format!("{}@{offset}", self.inner.name.to_str().unwrap()),
+                size_total,
+            ),
+
+            // The mapping is the arena's: it cannot move, nor be resized.
+            pinned: AtomicBool::new(true),
+
+            guard_len: 0,
+
+            read_only_mirror: false,
+
+            zeroize: false,
+
+            reclaim: false,
+
+            dont_fork: false,
+            dont_dump: false,
+
+            map_flags: 0,
+            granularity: 0,
+            prefault: false,
+
+            fill: None,
+            filled: size_total,
+
+            slice: unsafe { slice::from_raw_parts_mut(addr, size_total * 2) },
+        };
+        Ok(ArenaRing {
+            buf: ManuallyDrop::new(buf),
+            arena: self.inner.clone(),
+            offset,
+        })
+    }
+}
+
+impl ArenaInner {
+    // Maps the `len` bytes of the segment at `offset` to `addr`, in the
+    // reserved range.
+    fn map(&self, addr: *mut u8, offset: usize, len: usize) -> Result<(), Error> {
+        let ret = unsafe {
+            libc::mmap(
+                addr as *mut libc::c_void,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                self.fd,
+                offset as libc::off_t,
+            )
+        };
+        if ret == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Reserves the mirror of the ring at `offset` again and frees its
+    // extent.
+    fn release(&self, offset: usize, len: usize) {
+        unsafe {
+            libc::mmap(
+                self.base.add(2 * offset) as *mut libc::c_void,
+                2 * len,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        // The pages go back to the system as well, and the next ring gets
+        // them zeroed.
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::fallocate(
+                self.fd,
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        };
+
+        let mut free = self.free.lock().unwrap();
+        let x = free.partition_point(|&(o, _)| o < offset);
+        free.insert(x, (offset, len));
+        if x + 1 < free.len() && free[x].0 + free[x].1 == free[x + 1].0 {
+            free[x].1 += free[x + 1].1;
+            free.remove(x + 1);
+        }
+        if x > 0 && free[x - 1].0 + free[x - 1].1 == free[x].0 {
+            free[x - 1].1 += free[x].1;
+            free.remove(x);
+        }
+    }
+}
+
+impl Drop for ArenaInner {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.base.is_null() {
+                libc::munmap(self.base as *mut libc::c_void, self.capacity * 2);
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl<'a> Deref for ArenaRing<'a> {
+    type Target = MirroredBuffer<'a>;
+
+    fn deref(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+}
+
+impl DerefMut for ArenaRing<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl Drop for ArenaRing<'_> {
+    // The buffer is not dropped, as the segment and the fd are the arena's,
+    // but for its name and its entry in the registry.
+    fn drop(&mut self) {
+        drop(mem::take(&mut self.buf.name));
+        unsafe { ptr::drop_in_place(&mut self.buf.usage) };
+        self.arena.release(self.offset, self.buf.size());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::get_page_size, Arena, ErrorKind};
+
+    #[test]
+    fn arena_carves_and_reuses_rings() {
+        let page_size = get_page_size().unwrap();
+        let arena = Arena::new(4 * page_size).unwrap();
+        assert!(arena.capacity() == 4 * page_size);
+
+        let mut a = arena.ring(1).unwrap();
+        let mut b = arena.ring(2 * page_size).unwrap();
+        let c = arena.ring(page_size).unwrap();
+        assert!(arena.available() == 0);
+        let err = arena.ring(1).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+
+        // Each ring is mirrored on its own.
+        let size = b.size();
+        b.claim(size).unwrap().fill(1);
+        b.commit(size);
+        b.consume(size - 1);
+        b.claim(10).unwrap().fill(2);
+        b.commit(10);
+        assert!(b.committed().unwrap() == [1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2]);
+        a.claim(1).unwrap()[0] = 3;
+        a.commit(1);
+        assert!(a.committed().unwrap() == [3]);
+
+        // Freed pieces coalesce, and come back zeroed on Linux.
+        drop(a);
+        drop(c);
+        assert!(arena.available() == 2 * page_size);
+        assert!(arena.ring(2 * page_size).is_err());
+        drop(b);
+        let mut d = arena.ring(4 * page_size).unwrap();
+        assert!(arena.available() == 0);
+        let size = d.size();
+        #[cfg(target_os = "linux")]
+        assert!(d.claim(size).unwrap().iter().all(|&x| x == 0));
+        assert!(d.claim(size).unwrap().len() == size);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{Error, MirroredBuffer};
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use std::{
+    cmp, io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+// Buffers an async stream through a pair of MirroredBuffers: `rx` is filled
+// from the stream and read from, `tx` is written to and drained into the
+// stream. It implements the runtime-agnostic futures-io traits, so it works
+// with smol, async-std or any other executor.
+//
+// Through AsyncBufRead, `poll_fill_buf` hands out the whole committed region
+// of `rx` as one contiguous slice, so a frame that wraps around the end of
+// the ring can be parsed without being copied.
+pub struct AsyncBuffered<'a, S> {
+    inner: S,
+    rx: MirroredBuffer<'a>,
+    tx: MirroredBuffer<'a>,
+}
+
+impl<'a, S> AsyncBuffered<'a, S> {
+    pub fn new(inner: S, rx: MirroredBuffer<'a>, tx: MirroredBuffer<'a>) -> AsyncBuffered<'a, S> {
+        AsyncBuffered { inner, rx, tx }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn rx(&self) -> &MirroredBuffer<'a> {
+        &self.rx
+    }
+
+    pub fn rx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.rx
+    }
+
+    pub fn tx(&self) -> &MirroredBuffer<'a> {
+        &self.tx
+    }
+
+    pub fn tx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.tx
+    }
+
+    // Both buffers at once, e.g. to encode a reply in `tx` straight out of a
+    // frame still committed in `rx`.
+    pub fn buffers_mut(&mut self) -> (&mut MirroredBuffer<'a>, &mut MirroredBuffer<'a>) {
+        (&mut self.rx, &mut self.tx)
+    }
+
+    pub fn into_inner(self) -> (S, MirroredBuffer<'a>, MirroredBuffer<'a>) {
+        (self.inner, self.rx, self.tx)
+    }
+}
+
+impl<'a, S: AsyncRead + Unpin> AsyncBuffered<'a, S> {
+    // Reads once from the stream into the free region of `rx` and commits
+    // what was read. Ready(Ok(0)) means EOF, or that `rx` is full.
+    pub fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let free = self.rx.free();
+        let Some(claimed) = self.rx.claim(free) else {
+            return Poll::Ready(Ok(0));
+        };
+        let n = ready!(Pin::new(&mut self.inner).poll_read(cx, claimed))?;
+        Poll::Ready(Ok(self.rx.commit(n)))
+    }
+
+    // Fills `rx` until at least `n` bytes are committed, which is how a
+    // decoder is given the rest of a partial frame once it knows its length.
+    // Ready(Ok(false)) means EOF was reached first.
+    pub fn poll_fill_to(&mut self, cx: &mut Context<'_>, n: usize) -> Poll<io::Result<bool>> {
+        if n > self.rx.size() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                Error::no_space(n),
+            )));
+        }
+        while self.rx.used() < n {
+            if ready!(self.poll_fill(cx))? == 0 {
+                return Poll::Ready(Ok(false));
+            }
+        }
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl<'a, S: AsyncWrite + Unpin> AsyncBuffered<'a, S> {
+    // Writes the committed region of `tx` to the stream until it is empty.
+    pub fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(committed) = self.tx.committed() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, committed))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.tx.consume(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a, S: AsyncRead + Unpin> AsyncRead for AsyncBuffered<'a, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let committed = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let n = cmp::min(committed.len(), buf.len());
+        buf[..n].copy_from_slice(&committed[..n]);
+        self.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<'a, S: AsyncRead + Unpin> AsyncBufRead for AsyncBuffered<'a, S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.rx.used() == 0 {
+            ready!(this.poll_fill(cx))?;
+        }
+        Poll::Ready(Ok(this.rx.committed().unwrap_or_default()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().rx.consume(amt);
+    }
+}
+
+impl<'a, S: AsyncWrite + Unpin> AsyncWrite for AsyncBuffered<'a, S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.tx.free() == 0 {
+            ready!(this.poll_drain(cx))?;
+        }
+        let Some(claimed) = this.tx.claim(buf.len()) else {
+            return Poll::Ready(Ok(0));
+        };
+        let n = claimed.len();
+        claimed.copy_from_slice(&buf[..n]);
+        Poll::Ready(Ok(this.tx.commit(n)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncBuffered;
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use futures::{
+        executor::block_on,
+        future::poll_fn,
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, Cursor},
+    };
+
+    fn buffers() -> (MirroredBuffer<'static>, MirroredBuffer<'static>) {
+        (
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn async_buffered_read() {
+        let data: Vec<u8> = (0..=255).collect();
+        let (rx, tx) = buffers();
+        let mut stream = AsyncBuffered::new(Cursor::new(data.clone()), rx, tx);
+
+        block_on(async {
+            let committed = stream.fill_buf().await.unwrap();
+            assert!(committed == data);
+            stream.consume_unpin(200);
+
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest == data[200..]);
+        });
+    }
+
+    #[test]
+    fn async_buffered_write() {
+        let (rx, tx) = buffers();
+        let size = tx.size();
+        let mut stream = AsyncBuffered::new(Cursor::new(Vec::new()), rx, tx);
+
+        block_on(async {
+            // More than fits in tx, so it has to be drained along the way.
+            let data = vec![7u8; size * 2 + 10];
+            stream.write_all(&data).await.unwrap();
+            assert!(stream.tx().used() == 10);
+
+            stream.flush().await.unwrap();
+            assert!(stream.tx().used() == 0);
+            assert!(stream.get_ref().get_ref() == &data);
+
+            stream.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn async_buffered_fill_to() {
+        let (rx, tx) = buffers();
+        let size = rx.size();
+        let mut stream = AsyncBuffered::new(Cursor::new(vec![1u8; 100]), rx, tx);
+
+        block_on(async {
+            assert!(poll_fn(|cx| stream.poll_fill_to(cx, 100)).await.unwrap());
+            assert!(stream.rx().used() == 100);
+            assert!(!poll_fn(|cx| stream.poll_fill_to(cx, 101)).await.unwrap());
+            assert!(poll_fn(|cx| stream.poll_fill_to(cx, size + 1))
+                .await
+                .is_err());
+        });
+    }
+}
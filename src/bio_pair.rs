@@ -0,0 +1,315 @@
+use crate::MirroredBuffer;
+use std::io::{self, Read, Write};
+#[cfg(feature = "tungstenite")]
+use tungstenite::protocol::WebSocketConfig;
+
+// The network side of a protocol engine that does its own I/O through Read
+// and Write, in the style of an openssl BIO pair: ciphertext or frames
+// received from the peer are committed to `rx` and handed to the engine from
+// there, and what the engine produces is committed to `tx` for the caller to
+// send. The engine never touches a socket, so it can sit behind any event
+// loop. It fits openssl's `SslStream` and tungstenite's `WebSocket` alike.
+//
+// When `rx` is empty, reads fail with WouldBlock, which openssl reports as
+// WANT_READ; when `tx` is full, writes fail with WouldBlock, reported as
+// WANT_WRITE. Either way the caller runs `fill` or `drain` and retries.
+//
+// Build the stream with e.g. `SslStream::new(ssl, BioPair::new(rx, tx))`
+// and reach the buffers through `get_mut`.
+pub struct BioPair<'a> {
+    rx: MirroredBuffer<'a>,
+    tx: MirroredBuffer<'a>,
+    eof: bool,
+}
+
+impl<'a> BioPair<'a> {
+    pub fn new(rx: MirroredBuffer<'a>, tx: MirroredBuffer<'a>) -> BioPair<'a> {
+        BioPair { rx, tx, eof: false }
+    }
+
+    pub fn rx(&self) -> &MirroredBuffer<'a> {
+        &self.rx
+    }
+
+    pub fn rx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.rx
+    }
+
+    pub fn tx(&self) -> &MirroredBuffer<'a> {
+        &self.tx
+    }
+
+    pub fn tx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.tx
+    }
+
+    // Marks the network side as closed. Once `rx` is empty, openssl then
+    // sees EOF instead of WANT_READ.
+    pub fn set_eof(&mut self) {
+        self.eof = true;
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    // Reads ciphertext from `r` into `rx`, once. EOF from `r` is recorded
+    // with `set_eof`.
+    pub fn fill<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        let n = self.rx.fill_from(r)?;
+        if n == 0 && self.rx.free() > 0 {
+            self.eof = true;
+        }
+        Ok(n)
+    }
+
+    // Writes the ciphertext in `tx` to `w`, as `flush_to` does.
+    pub fn drain<W: Write + ?Sized>(&mut self, w: &mut W) -> io::Result<usize> {
+        self.tx.flush_to(w)
+    }
+
+    pub fn into_inner(self) -> (MirroredBuffer<'a>, MirroredBuffer<'a>) {
+        (self.rx, self.tx)
+    }
+
+    // A WebSocket configuration sized after the buffers: tungstenite reads
+    // up to a whole `rx` at a time and writes frames through to `tx` as soon
+    // as they are encoded instead of batching them in its own Vec first.
+    #[cfg(feature = "tungstenite")]
+    pub fn websocket_config(&self) -> WebSocketConfig {
+        WebSocketConfig::default()
+            .read_buffer_size(self.rx.size())
+            .write_buffer_size(0)
+    }
+}
+
+impl Read for BioPair<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.rx.used() == 0 && !self.eof && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.rx.read(buf)
+    }
+}
+
+impl Write for BioPair<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.tx.write(buf)? {
+            0 if !buf.is_empty() => Err(io::ErrorKind::WouldBlock.into()),
+            n => Ok(n),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BioPair;
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use std::io::{self, Read, Write};
+
+    fn bio_pair() -> BioPair<'static> {
+        BioPair::new(
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn bio_pair_would_block() {
+        let mut bio = bio_pair();
+        let size = bio.tx().size();
+
+        let err = bio.read(&mut [0; 8]).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WouldBlock);
+        assert!(bio.fill(&mut &b"hello"[..]).unwrap() == 5);
+        let mut out = [0; 8];
+        assert!(bio.read(&mut out).unwrap() == 5);
+        assert!(&out[..5] == b"hello");
+
+        assert!(bio.write(&vec![1; size + 1]).unwrap() == size);
+        let err = bio.write(b"x").unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WouldBlock);
+        let mut sent = Vec::new();
+        assert!(bio.drain(&mut sent).unwrap() == size);
+        assert!(bio.write(b"x").unwrap() == 1);
+        let mut peer = bio_pair();
+        pump(&mut bio, &mut peer);
+        assert!(peer.rx().committed().unwrap() == b"x");
+
+        // EOF from the network side turns WouldBlock into Ok(0).
+        assert!(bio.fill(&mut &b""[..]).unwrap() == 0);
+        assert!(bio.is_eof());
+        assert!(bio.read(&mut out).unwrap() == 0);
+    }
+
+    // Moves what `from` produced over to `to`.
+    fn pump(from: &mut BioPair, to: &mut BioPair) {
+        while let Some(committed) = from.tx().committed() {
+            let n = to.fill(&mut &committed[..]).unwrap();
+            from.tx_mut().consume(n);
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    #[cfg(feature = "openssl")]
+    mod openssl {
+        use super::{bio_pair, BioPair};
+        use ::openssl::{
+            pkey::PKey,
+            ssl::{ErrorCode, Ssl, SslAcceptor, SslConnector, SslMethod, SslStream},
+            x509::X509,
+        };
+        use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+        use std::io::{Read, Write};
+
+        fn pump(from: &mut SslStream<BioPair>, to: &mut SslStream<BioPair>) {
+            super::pump(from.get_mut(), to.get_mut());
+        }
+
+        fn streams() -> (SslStream<BioPair<'static>>, SslStream<BioPair<'static>>) {
+            let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            // openssl takes a leaf with the same name as its issuer for a
+            // self-signed certificate.
+            ca_params
+                .distinguished_name
+                .push(DnType::CommonName, "mirrored-buffer test CA");
+            let ca_key = KeyPair::generate().unwrap();
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec!["localhost".to_string()])
+                .unwrap()
+                .signed_by(&key, &ca, &ca_key)
+                .unwrap();
+
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector
+                .cert_store_mut()
+                .add_cert(X509::from_der(ca.der()).unwrap())
+                .unwrap();
+            let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+            acceptor
+                .set_certificate(&X509::from_der(cert.der()).unwrap())
+                .unwrap();
+            acceptor
+                .set_private_key(&PKey::private_key_from_der(&key.serialize_der()).unwrap())
+                .unwrap();
+
+            let client = connector
+                .build()
+                .configure()
+                .unwrap()
+                .into_ssl("localhost")
+                .unwrap();
+            let server = Ssl::new(acceptor.build().context()).unwrap();
+            (
+                SslStream::new(client, bio_pair()).unwrap(),
+                SslStream::new(server, bio_pair()).unwrap(),
+            )
+        }
+
+        #[test]
+        fn bio_pair_openssl() {
+            let (mut client, mut server) = streams();
+
+            let (mut client_done, mut server_done) = (false, false);
+            while !client_done || !server_done {
+                if !client_done {
+                    match client.connect() {
+                        Ok(()) => client_done = true,
+                        Err(err) => assert!(err.code() == ErrorCode::WANT_READ),
+                    }
+                }
+                pump(&mut client, &mut server);
+                if !server_done {
+                    match server.accept() {
+                        Ok(()) => server_done = true,
+                        Err(err) => assert!(err.code() == ErrorCode::WANT_READ),
+                    }
+                }
+                pump(&mut server, &mut client);
+            }
+
+            // Bigger than either ring, so both WANT_WRITE and WANT_READ come up.
+            let data: Vec<u8> = (0..20_000).map(|x| x as u8).collect();
+            let mut written = 0;
+            let mut received = Vec::new();
+            while received.len() < data.len() {
+                if written < data.len() {
+                    match client.ssl_write(&data[written..]) {
+                        Ok(n) => written += n,
+                        Err(err) => assert!(err.code() == ErrorCode::WANT_WRITE),
+                    }
+                }
+                pump(&mut client, &mut server);
+
+                let mut chunk = [0; 4096];
+                match server.ssl_read(&mut chunk) {
+                    Ok(n) => received.extend_from_slice(&chunk[..n]),
+                    Err(err) => assert!(err.code() == ErrorCode::WANT_READ),
+                }
+            }
+            assert!(received == data);
+
+            server.write_all(b"bye").unwrap();
+            pump(&mut server, &mut client);
+            let mut reply = [0; 3];
+            client.read_exact(&mut reply).unwrap();
+            assert!(&reply == b"bye");
+
+            // Once the network side is closed, a read reports EOF, not WANT_READ.
+            server.get_mut().set_eof();
+            let err = server.ssl_read(&mut [0; 16]).unwrap_err();
+            assert!(err.code() != ErrorCode::WANT_READ);
+        }
+    }
+
+    #[cfg(feature = "tungstenite")]
+    mod websocket {
+        use super::{bio_pair, pump};
+        use tungstenite::{protocol::Role, Error, Message, WebSocket};
+
+        fn would_block(err: &Error) -> bool {
+            matches!(err, Error::Io(err) if err.kind() == std::io::ErrorKind::WouldBlock)
+        }
+
+        #[test]
+        fn bio_pair_websocket() {
+            let (client, server) = (bio_pair(), bio_pair());
+            let config = client.websocket_config();
+            assert!(config.read_buffer_size == client.rx().size());
+            let mut client = WebSocket::from_raw_socket(client, Role::Client, Some(config));
+            let config = server.websocket_config();
+            let mut server = WebSocket::from_raw_socket(server, Role::Server, Some(config));
+
+            // Bigger than either ring, so it crosses over in several rounds.
+            let data: Vec<u8> = (0..20_000).map(|x| x as u8).collect();
+            if let Err(err) = client.send(Message::binary(data.clone())) {
+                assert!(would_block(&err));
+            }
+
+            let received = loop {
+                pump(client.get_mut(), server.get_mut());
+                match server.read() {
+                    Ok(message) => break message,
+                    Err(err) => assert!(would_block(&err)),
+                }
+                if let Err(err) = client.flush() {
+                    assert!(would_block(&err));
+                }
+            };
+            assert!(received.into_data() == data);
+
+            server.send(Message::text("bye")).unwrap();
+            pump(server.get_mut(), client.get_mut());
+            assert!(client.read().unwrap().into_text().unwrap() == "bye");
+        }
+    }
+}
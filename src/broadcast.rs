@@ -0,0 +1,601 @@
+use crate::{codec::Decoder, split::CachePadded, Error, MirroredBuffer};
+use std::{
+    cell::Cell,
+    cmp,
+    io::{self, Read},
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// What the producer and the readers of a broadcast buffer share. As with a
+// split buffer, the buffer is only kept to own the mapping, and indices count
+// bytes and wrap around.
+//
+// Every reader has a cursor of its own, in a table with room for as many
+// readers as were asked for at the split. Readers take a free slot when they
+// join and free it when they are dropped. The producer reclaims space up to
+// the cursor of the slowest reader it has to wait for, so that reader gets to
+// see every byte. Which readers it waits for is up to their `LagPolicy`.
+struct Broadcast<'a> {
+    buf: MirroredBuffer<'a>,
+    tail: CachePadded<AtomicUsize>,
+    cursors: Box<[Cursor]>,
+}
+
+// A cursor is a single word the reader and the producer both compare and
+// swap: the reader's head, with the PINNED bit set while the reader holds a
+// view of the committed region, and the EVICTED bit once the reader is gone
+// or if the slot is free.
+// The producer only moves or evicts readers that are not pinned, so what a
+// reader holds is never written to. Heads wrap around at INDEX_MASK, and so
+// does their distance to the tail.
+const PINNED: usize = 1 << (usize::BITS - 1);
+const EVICTED: usize = 1 << (usize::BITS - 2);
+const INDEX_MASK: usize = EVICTED - 1;
+
+struct Cursor {
+    word: CachePadded<AtomicUsize>,
+    policy: AtomicU8,
+    taken: AtomicBool,
+}
+
+// What the producer does with a reader that lags too far behind to leave it
+// the space it needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    // Wait for the reader: it sees every byte. The default.
+    Block,
+    // Move the reader ahead, just far enough. It loses the bytes in between,
+    // and can tell how many with `lost`.
+    Skip,
+    // Drop the reader: it sees nothing more.
+    Evict,
+}
+
+impl LagPolicy {
+    fn from_u8(policy: u8) -> LagPolicy {
+        match policy {
+            0 => LagPolicy::Block,
+            1 => LagPolicy::Skip,
+            _ => LagPolicy::Evict,
+        }
+    }
+}
+
+impl<'a> Broadcast<'a> {
+    // Takes a free slot for a reader that starts at the tail, if there is one.
+    fn add_reader(self: &Arc<Self>, ptr: *mut u8) -> Option<BroadcastReader<'a>> {
+        let index = self.cursors.iter().position(|cursor| {
+            cursor
+                .taken
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+
+        // The reader starts at the tail, which the producer may move while it
+        // does not know about the reader yet. Storing the cursor and loading
+        // the tail again, against the producer storing the tail and loading
+        // cursors, all sequentially consistent, means that either the
+        // producer sees the reader before it claims past it, or the reader
+        // sees the tail moved and starts over from there.
+        let cursor = &self.cursors[index];
+        cursor
+            .policy
+            .store(LagPolicy::Block as u8, Ordering::Relaxed);
+        let mut tail = self.tail.load(Ordering::SeqCst);
+        loop {
+            cursor.word.store(tail & INDEX_MASK, Ordering::SeqCst);
+            let current = self.tail.load(Ordering::SeqCst);
+            if current == tail {
+                break;
+            }
+            tail = current;
+        }
+        let head = tail & INDEX_MASK;
+
+        Some(BroadcastReader {
+            shared: self.clone(),
+            ptr,
+            index,
+            head: Cell::new(head),
+            lost: Cell::new(0),
+        })
+    }
+
+    // How far behind the tail the slowest reader the producer has to wait for
+    // is: those that block, and those that are pinned.
+    fn lag(&self, tail: usize) -> usize {
+        self.cursors
+            .iter()
+            .filter_map(|cursor| {
+                let word = cursor.word.load(Ordering::SeqCst);
+                let policy = LagPolicy::from_u8(cursor.policy.load(Ordering::Relaxed));
+                if word & EVICTED != 0 || (policy != LagPolicy::Block && word & PINNED == 0) {
+                    return None;
+                }
+                Some(tail.wrapping_sub(word) & INDEX_MASK)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Moves or evicts the readers that do not block and are more than `max`
+    // behind the tail, unless they are pinned. Returns how far behind the
+    // pinned ones that could not be moved are.
+    fn make_room(&self, tail: usize, max: usize) -> usize {
+        let mut lag = 0;
+        for cursor in self.cursors.iter() {
+            let mut word = cursor.word.load(Ordering::Acquire);
+            loop {
+                let behind = tail.wrapping_sub(word) & INDEX_MASK;
+                if word & EVICTED != 0 || behind <= max {
+                    break;
+                }
+                let next = match LagPolicy::from_u8(cursor.policy.load(Ordering::Relaxed)) {
+                    LagPolicy::Block => break,
+                    _ if word & PINNED != 0 => {
+                        lag = lag.max(behind);
+                        break;
+                    }
+                    LagPolicy::Skip => tail.wrapping_sub(max) & INDEX_MASK,
+                    LagPolicy::Evict => word | EVICTED,
+                };
+                match cursor.word.compare_exchange_weak(
+                    word,
+                    next,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => word = current,
+                }
+            }
+        }
+        lag
+    }
+}
+
+// The writing half of a broadcast buffer: it claims and commits.
+pub struct BroadcastProducer<'a> {
+    shared: Arc<Broadcast<'a>>,
+    ptr: *mut u8,
+    tail: usize,
+}
+
+// One of the readers of a broadcast buffer: it reads the committed region
+// from its own cursor on and consumes it for itself only.
+//
+// A view returned by `committed` or `decode` pins the reader, so that the
+// producer does not skip or evict it while the view is held; consuming
+// unpins it.
+pub struct BroadcastReader<'a> {
+    shared: Arc<Broadcast<'a>>,
+    ptr: *mut u8,
+    index: usize,
+    head: Cell<usize>,
+    lost: Cell<usize>,
+}
+
+// The producer only touches the space past the readers it waits for, which
+// no reader touches, and readers only read.
+unsafe impl Send for BroadcastProducer<'_> {}
+unsafe impl Send for BroadcastReader<'_> {}
+
+impl Drop for BroadcastReader<'_> {
+    fn drop(&mut self) {
+        let cursor = self.cursor();
+        cursor.word.store(EVICTED, Ordering::Release);
+        cursor.taken.store(false, Ordering::Release);
+    }
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Splits the buffer into a producer and `readers` readers that each see
+    // all that is committed, e.g. to tee one ingest stream to several
+    // pipelines. Whatever is committed stays committed, for every reader.
+    // More readers can join later, up to `max_readers` at a time.
+    pub fn split_broadcast(
+        mut self,
+        readers: usize,
+        max_readers: usize,
+    ) -> (BroadcastProducer<'a>, Vec<BroadcastReader<'a>>) {
+        assert!(
+            readers <= max_readers,
+            "more readers than there is room for"
+        );
+        self.fill_rest();
+        let ptr = self.slice.as_mut_ptr();
+        let head = self.head & INDEX_MASK;
+        let tail = self.head.wrapping_add(self.size_used);
+
+        let cursors = (0..max_readers)
+            .map(|index| Cursor {
+                word: CachePadded(AtomicUsize::new(if index < readers {
+                    head
+                } else {
+                    EVICTED
+                })),
+                policy: AtomicU8::new(LagPolicy::Block as u8),
+                taken: AtomicBool::new(index < readers),
+            })
+            .collect();
+        let shared = Arc::new(Broadcast {
+            buf: self,
+            tail: CachePadded(AtomicUsize::new(tail)),
+            cursors,
+        });
+
+        let readers = (0..readers)
+            .map(|index| BroadcastReader {
+                shared: shared.clone(),
+                ptr,
+                index,
+                head: Cell::new(head),
+                lost: Cell::new(0),
+            })
+            .collect();
+        (BroadcastProducer { shared, ptr, tail }, readers)
+    }
+}
+
+impl<'a> BroadcastProducer<'a> {
+    pub fn name(&self) -> &str {
+        self.shared.buf.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.shared.buf.size()
+    }
+
+    // Adds a reader that sees what is committed from now on. None if there
+    // are `max_readers` already.
+    pub fn add_reader(&self) -> Option<BroadcastReader<'a>> {
+        self.shared.add_reader(self.ptr)
+    }
+
+    // The space the producer can claim as seen now: past the readers it has
+    // to wait for, lagging readers that do not block being moved out of the
+    // way by `claim`.
+    pub fn free(&self) -> usize {
+        // A reader that is joining can briefly be further behind than that.
+        self.size().saturating_sub(self.shared.lag(self.tail))
+    }
+
+    // Up to `size` bytes of free space, moving lagging readers out of the way
+    // as needed.
+    fn room(&self, size: usize) -> usize {
+        let size = cmp::min(size, self.free());
+        if size == 0 {
+            return 0;
+        }
+        let pinned = self.shared.make_room(self.tail, self.size() - size);
+        cmp::min(size, self.size() - pinned)
+    }
+
+    // Up to `size` bytes of free space to write to. Like with
+    // `MirroredBuffer::claim`, a read-only mirror cuts claims short at the
+    // wrap.
+    pub fn claim(&mut self, mut size: usize) -> Option<&mut [u8]> {
+        size = self.room(size);
+        if size == 0 {
+            return None;
+        }
+        let offset = self.tail & self.shared.buf.size_mask;
+        let size = self.shared.buf.writable(offset, size);
+        Some(unsafe { slice::from_raw_parts_mut(self.ptr.add(offset), size) })
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        let size = self.room(size);
+        self.tail = self.tail.wrapping_add(size);
+        self.shared.tail.store(self.tail, Ordering::SeqCst);
+        size
+    }
+
+    // Like `MirroredBuffer::fill_from`.
+    pub fn fill_from<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        let free = self.free();
+        let Some(claimed) = self.claim(free) else {
+            return Ok(0);
+        };
+
+        loop {
+            match r.read(claimed) {
+                Ok(n) => return Ok(self.commit(n)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl<'a> BroadcastReader<'a> {
+    pub fn name(&self) -> &str {
+        self.shared.buf.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.shared.buf.size()
+    }
+
+    // Like `BroadcastProducer::add_reader`.
+    pub fn add_reader(&self) -> Option<BroadcastReader<'a>> {
+        self.shared.add_reader(self.ptr)
+    }
+
+    fn cursor(&self) -> &Cursor {
+        &self.shared.cursors[self.index]
+    }
+
+    pub fn lag_policy(&self) -> LagPolicy {
+        LagPolicy::from_u8(self.cursor().policy.load(Ordering::Relaxed))
+    }
+
+    pub fn set_lag_policy(&self, policy: LagPolicy) {
+        self.cursor().policy.store(policy as u8, Ordering::Relaxed);
+    }
+
+    // How many bytes this reader lost to being skipped.
+    pub fn lost(&self) -> usize {
+        self.sync(self.cursor().word.load(Ordering::Acquire));
+        self.lost.get()
+    }
+
+    pub fn is_evicted(&self) -> bool {
+        self.cursor().word.load(Ordering::Acquire) & EVICTED != 0
+    }
+
+    // Catches up with where the producer may have moved the cursor to.
+    fn sync(&self, word: usize) {
+        let head = word & INDEX_MASK;
+        if word & EVICTED == 0 && head != self.head.get() {
+            let skipped = head.wrapping_sub(self.head.get()) & INDEX_MASK;
+            self.lost.set(self.lost.get() + skipped);
+            self.head.set(head);
+        }
+    }
+
+    // What this reader has yet to consume, as seen now; nothing once it is
+    // evicted.
+    pub fn used(&self) -> usize {
+        let word = self.cursor().word.load(Ordering::Acquire);
+        if word & EVICTED != 0 {
+            return 0;
+        }
+        self.sync(word);
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head.get()) & INDEX_MASK
+    }
+
+    // The committed region from this reader's cursor on. Pins the reader
+    // until the next `consume`.
+    pub fn committed(&self) -> Option<&[u8]> {
+        let cursor = self.cursor();
+        let mut word = cursor.word.load(Ordering::Acquire);
+        while word & (PINNED | EVICTED) == 0 {
+            match cursor.word.compare_exchange_weak(
+                word,
+                word | PINNED,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => word = current,
+            }
+        }
+        if word & EVICTED != 0 {
+            return None;
+        }
+        self.sync(word);
+
+        let used = self.used();
+        if used == 0 {
+            return None;
+        }
+        let offset = self.head.get() & self.shared.buf.size_mask;
+        Some(unsafe { slice::from_raw_parts(self.ptr.add(offset), used) })
+    }
+
+    // Consumes `size` bytes, or what is committed if that is less, for this
+    // reader, and unpins it.
+    pub fn consume(&mut self, size: usize) -> usize {
+        let cursor = self.cursor();
+        let mut word = cursor.word.load(Ordering::Acquire);
+        loop {
+            if word & EVICTED != 0 {
+                return 0;
+            }
+            self.sync(word);
+            let tail = self.shared.tail.load(Ordering::Acquire);
+            let used = tail.wrapping_sub(self.head.get()) & INDEX_MASK;
+            let size = cmp::min(size, used);
+            let head = self.head.get().wrapping_add(size) & INDEX_MASK;
+            match cursor
+                .word
+                .compare_exchange_weak(word, head, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    self.head.set(head);
+                    return size;
+                }
+                Err(current) => word = current,
+            }
+        }
+    }
+
+    // Like `MirroredBuffer::decode`.
+    pub fn decode<D: Decoder>(
+        &self,
+        decoder: &mut D,
+    ) -> Result<Option<(D::Frame<'_>, usize)>, Error> {
+        match self.committed() {
+            Some(committed) => decoder.decode(committed),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LagPolicy;
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use std::thread;
+
+    #[test]
+    fn broadcast_every_reader_sees_everything() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let (mut producer, mut readers) = buf.split_broadcast(3, 3);
+        let size = producer.size();
+
+        // The slowest reader bounds the free space.
+        producer.claim(100).unwrap().fill(1);
+        producer.commit(100);
+        readers[0].consume(100);
+        readers[1].consume(50);
+        assert!(producer.free() == size - 100);
+        readers[2].consume(100);
+        assert!(producer.free() == size - 50);
+
+        // Nor does a dropped one hold it back.
+        drop(readers.remove(1));
+        assert!(producer.free() == size);
+
+        let total = 20 * size;
+        let handles: Vec<_> = readers
+            .into_iter()
+            .map(|mut reader| {
+                thread::spawn(move || {
+                    let mut seen = 0;
+                    while seen < total {
+                        let Some(committed) = reader.committed() else {
+                            thread::yield_now();
+                            continue;
+                        };
+                        assert!(committed
+                            .iter()
+                            .enumerate()
+                            .all(|(x, &b)| b == ((seen + x) % 251) as u8));
+                        seen += reader.consume(committed.len());
+                    }
+                    assert!(reader.lost() == 0);
+                })
+            })
+            .collect();
+
+        let data: Vec<u8> = (0..total).map(|x| (x % 251) as u8).collect();
+        let mut data = &data[..];
+        while !data.is_empty() {
+            if producer.fill_from(&mut data).unwrap() == 0 {
+                thread::yield_now();
+            }
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn broadcast_lag_policies() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (mut producer, mut readers) = buf.split_broadcast(3, 3);
+        let size = producer.size();
+        assert!(readers[0].lag_policy() == LagPolicy::Block);
+        readers[1].set_lag_policy(LagPolicy::Skip);
+        readers[2].set_lag_policy(LagPolicy::Evict);
+
+        producer.claim(size).unwrap().fill(1);
+        assert!(producer.commit(size) == size);
+        readers[0].consume(size);
+
+        // Pinned readers are waited for, whatever their policy.
+        assert!(readers[1].committed().unwrap().len() == size);
+        assert!(readers[2].committed().unwrap().len() == size);
+        assert!(producer.claim(1).is_none());
+
+        // Once they let go, the producer gets past them.
+        readers[1].consume(10);
+        readers[2].consume(10);
+        producer.claim(100).unwrap().fill(2);
+        assert!(producer.commit(100) == 100);
+
+        assert!(readers[1].lost() == 90);
+        let committed = readers[1].committed().unwrap();
+        assert!(committed.len() == size);
+        assert!(committed[size - 100..].iter().all(|&x| x == 2));
+        readers[1].consume(size);
+
+        assert!(readers[2].is_evicted());
+        assert!(readers[2].committed().is_none());
+        assert!(readers[2].consume(1) == 0);
+
+        // A reader that blocks holds the producer back.
+        producer.claim(size).unwrap();
+        assert!(producer.commit(size) == size - 100);
+        assert!(producer.claim(1).is_none());
+        assert!(readers[0].used() == size);
+    }
+
+    #[test]
+    fn broadcast_readers_join_and_leave() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (mut producer, mut readers) = buf.split_broadcast(1, 2);
+        let size = producer.size();
+
+        producer.claim(100).unwrap().fill(1);
+        producer.commit(100);
+
+        // A new reader starts at the tail, and holds the producer back from
+        // there on.
+        let mut joined = readers[0].add_reader().unwrap();
+        assert!(producer.add_reader().is_none());
+        assert!(joined.committed().is_none());
+        readers[0].consume(100);
+        assert!(producer.free() == size);
+
+        producer.claim(50).unwrap().fill(2);
+        producer.commit(50);
+        assert!(joined.committed().unwrap() == [2; 50]);
+        assert!(joined.consume(10) == 10);
+        readers[0].consume(50);
+        assert!(producer.free() == size - 40);
+
+        // Leaving frees both the space and the slot.
+        drop(joined);
+        assert!(producer.free() == size);
+        let joined = producer.add_reader().unwrap();
+        assert!(joined.used() == 0);
+    }
+
+    #[test]
+    fn broadcast_claims_stop_at_a_read_only_mirror() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+        let buf = buf.with_read_only_mirror().unwrap();
+
+        // Up to the wrap, then on from the start of the buffer.
+        let (mut producer, readers) = buf.split_broadcast(1, 1);
+        let claimed = producer.claim(100).unwrap();
+        assert!(claimed.len() == 10);
+        claimed.fill(1);
+        producer.commit(10);
+        let claimed = producer.claim(90).unwrap();
+        assert!(claimed.len() == 90);
+        claimed.fill(2);
+        producer.commit(90);
+
+        let committed = readers[0].committed().unwrap();
+        assert!(committed.len() == 100);
+        assert!(committed[..10].iter().all(|&x| x == 1));
+        assert!(committed[10..].iter().all(|&x| x == 2));
+    }
+}
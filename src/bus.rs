@@ -0,0 +1,260 @@
+use crate::{Error, MirroredBuffer, SharedRing};
+use std::{
+    ptr,
+    sync::atomic::{self, Ordering},
+    time::Duration,
+};
+
+// A frame is a header of the topic then the length of the payload, both as
+// little endian u32s, followed by the payload. Frames never wrap, thanks to
+// the mirror.
+const HEADER_LEN: usize = 8;
+
+// The publishing side of a bus over a shared ring, e.g. one process fanning
+// market data or telemetry out to others, which each subscribe to the
+// topics they care about, see `MirroredBuffer::subscribe`.
+//
+// Subscribers map the ring read-only and keep their cursors to themselves,
+// so the publisher never waits for them: when the ring is full, it drops the
+// oldest frames to make room, moving the head past them. A subscriber that
+// falls behind the head lost those frames and skips to the head, see
+// `BusSubscriber::lost`.
+pub struct BusPublisher<'a> {
+    ring: SharedRing<'a>,
+}
+
+// A subscriber to a bus, reading frames from a cursor of its own.
+//
+// The publisher may overwrite a frame while a subscriber copies it out, as
+// nothing tells it the subscriber is there. The publisher moves the head
+// before writing, so a subscriber checks the head after copying: if the
+// head moved past the frame, the copy is dropped as lost, seqlock style.
+pub struct BusSubscriber<'a> {
+    ring: SharedRing<'a>,
+    cursor: u64,
+    // None for all topics.
+    topics: Option<Vec<u32>>,
+    lost: u64,
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Creates a bus over a shared ring of at least `size` bytes, named after
+    // `name` for subscribers to attach to.
+    pub fn create_bus(name: &str, size: usize) -> Result<BusPublisher<'a>, Error> {
+        let ring = MirroredBuffer::create_shared(name, size)?;
+        Ok(BusPublisher { ring })
+    }
+
+    // Subscribes to the bus named `name`, from the next frame published on,
+    // to all topics until told otherwise with `BusSubscriber::with_topics`.
+    pub fn subscribe(name: &str) -> Result<BusSubscriber<'a>, Error> {
+        let ring = SharedRing::open(name, true)?;
+        let cursor = ring.tail();
+        Ok(BusSubscriber {
+            ring,
+            cursor,
+            topics: None,
+            lost: 0,
+        })
+    }
+}
+
+impl<'a> BusPublisher<'a> {
+    pub fn ring(&self) -> &SharedRing<'a> {
+        &self.ring
+    }
+
+    // The largest payload a frame can carry.
+    pub fn max_payload(&self) -> usize {
+        self.ring.size() - HEADER_LEN
+    }
+
+    // Publishes `payload` under `topic`, dropping the oldest frames if there
+    // is no room for it.
+    pub fn publish(&mut self, topic: u32, payload: &[u8]) -> Result<(), Error> {
+        let len = HEADER_LEN + payload.len();
+        if payload.len() > self.max_payload() || payload.len() > u32::MAX as usize {
+            return Err(Error::no_space(len));
+        }
+
+        if self.ring.free() < len {
+            while self.ring.free() < len {
+                let oldest = self.ring.committed().unwrap();
+                let payload_len = u32::from_le_bytes(oldest[4..8].try_into().unwrap());
+                self.ring.consume(HEADER_LEN + payload_len as usize);
+            }
+            // The head goes out before the frame overwrites what it dropped.
+            atomic::fence(Ordering::Release);
+        }
+
+        let claimed = self.ring.claim(len).unwrap();
+        claimed[..4].copy_from_slice(&topic.to_le_bytes());
+        claimed[4..8].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        claimed[HEADER_LEN..].copy_from_slice(payload);
+        self.ring.commit(len);
+        Ok(())
+    }
+}
+
+impl<'a> BusSubscriber<'a> {
+    // Keeps the frames of `topics` only.
+    pub fn with_topics(mut self, topics: &[u32]) -> BusSubscriber<'a> {
+        self.topics = Some(topics.to_vec());
+        self
+    }
+
+    // How many bytes of frames the publisher dropped before this subscriber
+    // got to read them.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    fn wants(&self, topic: u32) -> bool {
+        match &self.topics {
+            Some(topics) => topics.contains(&topic),
+            None => true,
+        }
+    }
+
+    // Whether the publisher dropped what is at the cursor, in which case the
+    // cursor skips to the head.
+    fn overrun(&mut self) -> bool {
+        atomic::fence(Ordering::Acquire);
+        let head = self.ring.head();
+        let behind = head.wrapping_sub(self.cursor);
+        if behind == 0 || behind > i64::MAX as u64 {
+            return false;
+        }
+        self.lost += behind;
+        self.cursor = head;
+        true
+    }
+
+    // Copies the payload of the next frame of a topic subscribed to into
+    // `payload` and returns its topic, or None if there is none yet.
+    pub fn try_recv(&mut self, payload: &mut Vec<u8>) -> Option<u32> {
+        loop {
+            self.overrun();
+            if self.cursor == self.ring.tail() {
+                return None;
+            }
+
+            let mut header = [0u8; HEADER_LEN];
+            let at = self.ring.at(self.cursor);
+            unsafe { ptr::copy_nonoverlapping(at, header.as_mut_ptr(), HEADER_LEN) };
+            let topic = u32::from_le_bytes(header[..4].try_into().unwrap());
+            let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+            if self.overrun() {
+                continue;
+            }
+            if len > self.ring.size() - HEADER_LEN {
+                // Only a frame being overwritten looks like this.
+                self.cursor = self.ring.head();
+                continue;
+            }
+
+            let wanted = self.wants(topic);
+            if wanted {
+                payload.clear();
+                payload.reserve(len);
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        self.ring.at(self.cursor + HEADER_LEN as u64),
+                        payload.as_mut_ptr(),
+                        len,
+                    );
+                    payload.set_len(len);
+                }
+                if self.overrun() {
+                    continue;
+                }
+            }
+            self.cursor += (HEADER_LEN + len) as u64;
+            if wanted {
+                return Some(topic);
+            }
+        }
+    }
+
+    // Like `try_recv`, sleeping until there is a frame.
+    pub fn recv(&mut self, payload: &mut Vec<u8>) -> u32 {
+        loop {
+            if let Some(topic) = self.try_recv(payload) {
+                return topic;
+            }
+            let cursor = self.cursor;
+            self.ring
+                .wait_for_commit(|| self.ring.tail() != cursor, None);
+        }
+    }
+
+    // Like `recv`, but gives up after `timeout`.
+    pub fn recv_timeout(&mut self, payload: &mut Vec<u8>, timeout: Duration) -> Option<u32> {
+        if let Some(topic) = self.try_recv(payload) {
+            return Some(topic);
+        }
+        let cursor = self.cursor;
+        self.ring
+            .wait_for_commit(|| self.ring.tail() != cursor, Some(timeout));
+        self.try_recv(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use std::{process, thread, time::Duration};
+
+    #[test]
+    fn bus_filters_topics_and_survives_overruns() {
+        let name = format!("bus-{}-{}", process::id(), next_buffer_index());
+        let mut publisher = MirroredBuffer::create_bus(&name, 1).unwrap();
+        let mut all = MirroredBuffer::subscribe(&name).unwrap();
+        let mut odd = MirroredBuffer::subscribe(&name)
+            .unwrap()
+            .with_topics(&[1, 3]);
+        let mut payload = Vec::new();
+        assert!(all.try_recv(&mut payload).is_none());
+        let big = vec![0; publisher.max_payload() + 1];
+        assert!(publisher.publish(0, &big).is_err());
+
+        for topic in 0..4 {
+            publisher.publish(topic, &[topic as u8; 10]).unwrap();
+        }
+        for topic in 0..4 {
+            assert!(all.try_recv(&mut payload) == Some(topic));
+            assert!(payload == [topic as u8; 10]);
+        }
+        assert!(odd.try_recv(&mut payload) == Some(1));
+        assert!(odd.try_recv(&mut payload) == Some(3) && payload == [3; 10]);
+        assert!(odd.try_recv(&mut payload).is_none());
+
+        // Lapped: the oldest frames are gone, the latest are whole.
+        let frames = publisher.ring().size() / 100 * 3;
+        for x in 0..frames {
+            publisher.publish(1, &[x as u8; 92]).unwrap();
+        }
+        let mut last = None;
+        while let Some(topic) = odd.try_recv(&mut payload) {
+            assert!(topic == 1 && payload.len() == 92);
+            assert!(payload.iter().all(|&b| b == payload[0]));
+            last = Some(payload[0]);
+        }
+        assert!(odd.lost() > 0);
+        assert!(last == Some((frames - 1) as u8));
+        assert!(odd
+            .recv_timeout(&mut payload, Duration::from_millis(10))
+            .is_none());
+
+        // Blocking.
+        while all.try_recv(&mut payload).is_some() {}
+        let subscriber = thread::spawn(move || {
+            let mut payload = Vec::new();
+            let topic = all.recv(&mut payload);
+            (topic, payload)
+        });
+        thread::sleep(Duration::from_millis(10));
+        publisher.publish(7, b"done").unwrap();
+        assert!(subscriber.join().unwrap() == (7, b"done".to_vec()));
+    }
+}
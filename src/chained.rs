@@ -0,0 +1,156 @@
+use crate::{BufferPool, Error, PooledBuffer};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+};
+
+// Buffers without bound, e.g. for a proxy that must absorb whatever burst
+// the other side sends: writes that do not fit overflow into another buffer
+// from a pool instead of failing, and reads drain the buffers in order,
+// handing each back to the pool once empty.
+//
+// There is always at least one buffer, the last, which claims and pushes go
+// to. A claim comes from a single buffer, so it is contiguous like the claim
+// of a buffer and no larger than one.
+pub struct ChainedBuffer {
+    pool: BufferPool,
+    segment_size: usize,
+    // In the order they were written to.
+    segments: VecDeque<PooledBuffer>,
+}
+
+impl ChainedBuffer {
+    // Chains buffers of at least `segment_size` bytes from `pool`, which
+    // fails with InvalidSize if it has no class that large.
+    pub fn new(pool: BufferPool, segment_size: usize) -> Result<ChainedBuffer, Error> {
+        let first = pool.acquire(segment_size)?;
+        Ok(ChainedBuffer {
+            pool,
+            segment_size,
+            segments: VecDeque::from([first]),
+        })
+    }
+
+    // How many buffers are chained.
+    pub fn segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn used(&self) -> usize {
+        self.segments.iter().map(|segment| segment.used()).sum()
+    }
+
+    fn last(&mut self) -> &mut PooledBuffer {
+        self.segments.back_mut().unwrap()
+    }
+
+    // Chains another buffer, for writes the last one has no room for.
+    fn overflow(&mut self) -> Result<(), Error> {
+        let segment = self.pool.acquire(self.segment_size)?;
+        self.segments.push_back(segment);
+        Ok(())
+    }
+
+    // Claims up to `size` bytes, as many as a buffer holds, from the last
+    // buffer, or from another one if that does not have room for them.
+    // Fails like `BufferPool::acquire` when that one cannot be had.
+    pub fn claim(&mut self, size: usize) -> Result<&mut [u8], Error> {
+        let last = self.last();
+        if last.free() < size.min(last.size()) {
+            self.overflow()?;
+        }
+        Ok(self.last().claim(size).unwrap_or_default())
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        self.last().commit(size)
+    }
+
+    // Pushes all of `data`, into as many buffers as it takes. Fails like
+    // `claim`, with what was pushed until then committed.
+    pub fn push(&mut self, mut data: &[u8]) -> Result<usize, Error> {
+        let len = data.len();
+        while !data.is_empty() {
+            if self.last().free() == 0 {
+                self.overflow()?;
+            }
+            let n = self.last().push(data)?;
+            data = &data[n..];
+        }
+        Ok(len)
+    }
+
+    // The committed region of the first buffer, which is all there is to
+    // read until it is consumed.
+    pub fn committed(&self) -> Option<&[u8]> {
+        self.segments.front().unwrap().committed()
+    }
+
+    // Consumes up to `size` bytes, from as many buffers as it takes, and
+    // hands the ones emptied back to the pool.
+    pub fn consume(&mut self, mut size: usize) -> usize {
+        let mut consumed = 0;
+        loop {
+            let first = self.segments.front_mut().unwrap();
+            let n = first.consume(size);
+            consumed += n;
+            size -= n;
+            if first.used() > 0 || self.segments.len() == 1 {
+                return consumed;
+            }
+            self.segments.pop_front();
+        }
+    }
+
+    // Writes what is committed to `w`, buffer after buffer, like
+    // `MirroredBuffer::flush_to`.
+    pub fn flush_to<W: Write + ?Sized>(&mut self, w: &mut W) -> io::Result<usize> {
+        let mut written = 0;
+        loop {
+            let first = self.segments.front_mut().unwrap();
+            match first.flush_to(w) {
+                Ok(n) => written += n,
+                Err(err) if written > 0 && err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(written)
+                }
+                Err(err) => return Err(err),
+            }
+            if first.used() > 0 || self.segments.len() == 1 {
+                return Ok(written);
+            }
+            self.segments.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::get_page_size, BufferPool, ChainedBuffer};
+
+    #[test]
+    fn chained_overflows_and_drains_in_order() {
+        let page_size = get_page_size().unwrap();
+        let pool = BufferPool::new(&[(page_size, 2)]).unwrap();
+        let mut buf = ChainedBuffer::new(pool.clone(), page_size).unwrap();
+        assert!(buf.segments() == 1 && pool.idle() == 1);
+
+        // Pushes spill over, claims move on whole.
+        let data: Vec<u8> = (0..2 * page_size + 10).map(|i| i as u8).collect();
+        buf.push(&data).unwrap();
+        assert!(buf.segments() == 3 && pool.idle() == 0);
+        assert!(buf.claim(page_size).unwrap().len() == page_size);
+        buf.commit(page_size);
+        assert!(buf.segments() == 4 && buf.used() == 3 * page_size + 10);
+
+        // Drained in order, with the emptied buffers back in the pool.
+        assert!(buf.committed().unwrap() == &data[..page_size]);
+        assert!(buf.consume(page_size + 5) == page_size + 5);
+        assert!(buf.segments() == 3 && pool.idle() == 1);
+        assert!(buf.committed().unwrap() == &data[page_size + 5..2 * page_size]);
+        let mut w = Vec::new();
+        assert!(buf.flush_to(&mut w).unwrap() == 2 * page_size + 5);
+        assert!(w[..page_size + 5] == data[page_size + 5..]);
+        assert!(buf.segments() == 1 && buf.used() == 0 && pool.idle() == 3);
+        assert!(buf.committed().is_none());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    split::{Consumer, Producer},
+    Error, MirroredBuffer,
+};
+use std::{
+    cmp,
+    io::{self, BufRead, Read, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// The sending half of a byte channel, see `byte_channel`. It writes through
+// `send` or `io::Write`, waiting for the receiver to make room.
+pub struct ByteSender<'a> {
+    producer: Producer<'a>,
+    closed: Arc<Closed>,
+}
+
+// The receiving half of a byte channel. It reads through `recv`,
+// `io::Read` or, without copying, `io::BufRead`, waiting for the sender to
+// send something.
+pub struct ByteReceiver<'a> {
+    consumer: Consumer<'a>,
+    closed: Arc<Closed>,
+}
+
+// Which halves were dropped. Each half wakes the other when it goes, so that
+// it does not wait forever.
+struct Closed {
+    sender: AtomicBool,
+    receiver: AtomicBool,
+}
+
+// Creates a bounded pipe of bytes between two threads, in the manner of
+// `std::sync::mpsc::sync_channel` but for a contiguous stream: it holds at
+// least `capacity` bytes, rounded up as `MirroredBuffer::new` does.
+pub fn byte_channel<'a>(capacity: usize) -> Result<(ByteSender<'a>, ByteReceiver<'a>), Error> {
+    static CHANNEL_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+    let suffix = format!("channel-{}", CHANNEL_INDEX.fetch_add(1, Ordering::Relaxed));
+    let buf = MirroredBuffer::new(capacity, Some(&suffix), None)?;
+    Ok(buf.into_byte_channel())
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Like `byte_channel`, over this buffer. Whatever is committed is the
+    // first thing received.
+    pub fn into_byte_channel(self) -> (ByteSender<'a>, ByteReceiver<'a>) {
+        let (producer, consumer) = self.split();
+        let closed = Arc::new(Closed {
+            sender: AtomicBool::new(false),
+            receiver: AtomicBool::new(false),
+        });
+
+        (
+            ByteSender {
+                producer,
+                closed: closed.clone(),
+            },
+            ByteReceiver { consumer, closed },
+        )
+    }
+}
+
+impl<'a> ByteSender<'a> {
+    pub fn capacity(&self) -> usize {
+        self.producer.size()
+    }
+
+    // Whether the receiver was dropped, after which sending fails.
+    pub fn is_closed(&self) -> bool {
+        self.closed.receiver.load(Ordering::Acquire)
+    }
+
+    // Sends all of `data`, waiting for room as needed. Fails with BrokenPipe
+    // once the receiver is gone, with what fit before that sent.
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data)
+    }
+}
+
+impl Write for ByteSender<'_> {
+    // Waits for some room, then sends as much of `buf` as fits.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let shared = self.producer.shared.clone();
+        shared
+            .space
+            .wait_until(|| self.is_closed() || self.producer.free() > 0, None);
+        if self.is_closed() {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        // A read-only mirror cuts the claim short at the wrap.
+        let size = cmp::min(buf.len(), self.producer.free());
+        let claimed = self.producer.claim(size).unwrap();
+        let size = claimed.len();
+        claimed.copy_from_slice(&buf[..size]);
+        Ok(self.producer.commit(size))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ByteSender<'_> {
+    fn drop(&mut self) {
+        // Published before closing, so that the receiver sees everything
+        // sent once it sees the sender gone.
+        self.producer.publish();
+        self.closed.sender.store(true, Ordering::Release);
+        self.producer.shared.data.notify();
+    }
+}
+
+impl<'a> ByteReceiver<'a> {
+    pub fn capacity(&self) -> usize {
+        self.consumer.size()
+    }
+
+    // Whether the sender was dropped. What it sent can still be received.
+    pub fn is_closed(&self) -> bool {
+        self.closed.sender.load(Ordering::Acquire)
+    }
+
+    // Receives up to `buf.len()` bytes, waiting for some. Ok(0) means the
+    // sender is gone and everything it sent was received.
+    pub fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read(buf)
+    }
+}
+
+impl Read for ByteReceiver<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let received = self.fill_buf()?;
+        let size = cmp::min(buf.len(), received.len());
+        buf[..size].copy_from_slice(&received[..size]);
+        self.consume(size);
+        Ok(size)
+    }
+}
+
+impl BufRead for ByteReceiver<'_> {
+    // Waits for something to be sent and returns all of it, or nothing if
+    // the sender is gone.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.consumer
+            .shared
+            .data
+            .wait_until(|| self.is_closed() || self.consumer.used() > 0, None);
+        Ok(self.consumer.committed().unwrap_or(&[]))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumer.consume(amt);
+    }
+}
+
+impl Drop for ByteReceiver<'_> {
+    fn drop(&mut self) {
+        self.closed.receiver.store(true, Ordering::Release);
+        self.consumer.shared.space.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{byte_channel, util::next_buffer_index, MirroredBuffer};
+    use std::{
+        io::{ErrorKind, Read, Write},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn channel_pipes_bytes_between_threads() {
+        let (mut tx, mut rx) = byte_channel(1).unwrap();
+        let total = 20 * tx.capacity() + 7;
+        let sender = thread::spawn(move || {
+            let data: Vec<u8> = (0..total).map(|x| (x % 251) as u8).collect();
+            for chunk in data.chunks(1000) {
+                tx.send(chunk).unwrap();
+            }
+        });
+
+        let mut received = Vec::new();
+        assert!(rx.read_to_end(&mut received).unwrap() == total);
+        assert!(received
+            .iter()
+            .enumerate()
+            .all(|(x, &b)| b == (x % 251) as u8));
+        assert!(rx.is_closed());
+        assert!(rx.recv(&mut [0; 10]).unwrap() == 0);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn channel_dropping_the_receiver_wakes_the_sender() {
+        let (mut tx, rx) = byte_channel(1).unwrap();
+        let capacity = tx.capacity();
+        let sender = thread::spawn(move || {
+            // Fills the channel, then waits for room that never comes.
+            let err = tx.send(&vec![1; capacity + 1]).unwrap_err();
+            assert!(err.kind() == ErrorKind::BrokenPipe);
+            assert!(tx.is_closed());
+        });
+        thread::sleep(Duration::from_millis(20));
+        drop(rx);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn channel_read_only_mirror() {
+        // Sends across the wrap go in two writes, the first up to it.
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+        let (mut tx, mut rx) = buf.with_read_only_mirror().unwrap().into_byte_channel();
+
+        let data: Vec<u8> = (0..100).collect();
+        assert!(tx.write(&data).unwrap() == 10);
+        tx.send(&data[10..]).unwrap();
+        let mut received = [0; 100];
+        rx.read_exact(&mut received).unwrap();
+        assert!(received[..] == data[..]);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{Error, MirroredBuffer};
+
+// Layout of the v2 (magic 2) record batch header.
+const BATCH_LENGTH_OFFSET: usize = 8;
+const PARTITION_LEADER_EPOCH_OFFSET: usize = 12;
+const MAGIC_OFFSET: usize = 16;
+const CRC_OFFSET: usize = 17;
+const ATTRIBUTES_OFFSET: usize = 21;
+const LAST_OFFSET_DELTA_OFFSET: usize = 23;
+const BASE_TIMESTAMP_OFFSET: usize = 27;
+const MAX_TIMESTAMP_OFFSET: usize = 35;
+const PRODUCER_ID_OFFSET: usize = 43;
+const PRODUCER_EPOCH_OFFSET: usize = 51;
+const BASE_SEQUENCE_OFFSET: usize = 53;
+const RECORDS_COUNT_OFFSET: usize = 57;
+pub const BATCH_HEADER_LEN: usize = 61;
+
+const MAGIC: u8 = 2;
+
+pub struct Record<'r> {
+    pub timestamp: i64,
+    pub key: Option<&'r [u8]>,
+    pub value: Option<&'r [u8]>,
+    pub headers: &'r [(&'r [u8], Option<&'r [u8]>)],
+}
+
+// Stages a record batch in the claim region of a MirroredBuffer. Records are
+// encoded in place right after room left for the batch header. `finish`
+// fills in the header, now that the batch's length, record count, timestamps
+// and CRC are known, and commits the whole batch at once. Until then nothing
+// is visible in the committed region, so a producer can flush the buffer
+// with a single contiguous write per batch.
+//
+// Dropping the writer without calling `finish` discards the staged records.
+pub struct BatchWriter<'w, 'a> {
+    buf: &'w mut MirroredBuffer<'a>,
+    len: usize,
+    count: i32,
+    base_timestamp: i64,
+    max_timestamp: i64,
+    partition_leader_epoch: i32,
+    producer_id: i64,
+    producer_epoch: i16,
+    base_sequence: i32,
+}
+
+impl<'w, 'a> BatchWriter<'w, 'a> {
+    pub fn new(buf: &'w mut MirroredBuffer<'a>) -> Result<BatchWriter<'w, 'a>, Error> {
+        if buf.free() < BATCH_HEADER_LEN {
+            return Err(Error::no_space(BATCH_HEADER_LEN));
+        }
+
+        Ok(BatchWriter {
+            buf,
+            len: BATCH_HEADER_LEN,
+            count: 0,
+            base_timestamp: 0,
+            max_timestamp: 0,
+            partition_leader_epoch: -1,
+            producer_id: -1,
+            producer_epoch: -1,
+            base_sequence: -1,
+        })
+    }
+
+    // Used by idempotent producers.
+    pub fn with_producer(
+        mut self,
+        producer_id: i64,
+        producer_epoch: i16,
+        base_sequence: i32,
+    ) -> BatchWriter<'w, 'a> {
+        self.producer_id = producer_id;
+        self.producer_epoch = producer_epoch;
+        self.base_sequence = base_sequence;
+        self
+    }
+
+    pub fn with_partition_leader_epoch(mut self, epoch: i32) -> BatchWriter<'w, 'a> {
+        self.partition_leader_epoch = epoch;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn records(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn append(&mut self, record: &Record) -> Result<(), Error> {
+        if self.count == 0 {
+            self.base_timestamp = record.timestamp;
+            self.max_timestamp = record.timestamp;
+        }
+        let timestamp_delta = record.timestamp - self.base_timestamp;
+        let offset_delta = self.count as i64;
+
+        let mut body_len = 1 // attributes
+            + varint_len(timestamp_delta)
+            + varint_len(offset_delta)
+            + bytes_len(record.key)
+            + bytes_len(record.value)
+            + varint_len(record.headers.len() as i64);
+        for (key, value) in record.headers {
+            body_len += bytes_len(Some(key)) + bytes_len(*value);
+        }
+        let record_len = varint_len(body_len as i64) + body_len;
+
+        let start = self.len;
+        let staged = self.staged();
+        if staged.len() < start + record_len {
+            return Err(Error::no_space(start + record_len));
+        }
+        let dst = &mut staged[start..start + record_len];
+
+        let mut n = put_varint(dst, body_len as i64);
+        dst[n] = 0;
+        n += 1;
+        n += put_varint(&mut dst[n..], timestamp_delta);
+        n += put_varint(&mut dst[n..], offset_delta);
+        n += put_bytes(&mut dst[n..], record.key);
+        n += put_bytes(&mut dst[n..], record.value);
+        n += put_varint(&mut dst[n..], record.headers.len() as i64);
+        for (key, value) in record.headers {
+            n += put_bytes(&mut dst[n..], Some(key));
+            n += put_bytes(&mut dst[n..], *value);
+        }
+        debug_assert!(n == record_len);
+
+        self.len += record_len;
+        self.count += 1;
+        self.max_timestamp = self.max_timestamp.max(record.timestamp);
+        Ok(())
+    }
+
+    // Patches the batch header and commits the batch, returning its size. An
+    // empty batch commits nothing.
+    pub fn finish(mut self) -> usize {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let len = self.len;
+        let header = [
+            (BATCH_LENGTH_OFFSET, &((len - 12) as i32).to_be_bytes()[..]),
+            (
+                PARTITION_LEADER_EPOCH_OFFSET,
+                &self.partition_leader_epoch.to_be_bytes(),
+            ),
+            (MAGIC_OFFSET, &[MAGIC]),
+            (ATTRIBUTES_OFFSET, &0i16.to_be_bytes()),
+            (LAST_OFFSET_DELTA_OFFSET, &(self.count - 1).to_be_bytes()),
+            (BASE_TIMESTAMP_OFFSET, &self.base_timestamp.to_be_bytes()),
+            (MAX_TIMESTAMP_OFFSET, &self.max_timestamp.to_be_bytes()),
+            (PRODUCER_ID_OFFSET, &self.producer_id.to_be_bytes()),
+            (PRODUCER_EPOCH_OFFSET, &self.producer_epoch.to_be_bytes()),
+            (BASE_SEQUENCE_OFFSET, &self.base_sequence.to_be_bytes()),
+            (RECORDS_COUNT_OFFSET, &self.count.to_be_bytes()),
+        ];
+
+        let staged = &mut self.staged()[..len];
+        // The base offset is assigned by the broker.
+        staged[..BATCH_LENGTH_OFFSET].fill(0);
+        for (offset, field) in header {
+            staged[offset..offset + field.len()].copy_from_slice(field);
+        }
+        let crc = crc32c(&staged[ATTRIBUTES_OFFSET..]);
+        staged[CRC_OFFSET..CRC_OFFSET + 4].copy_from_slice(&crc.to_be_bytes());
+
+        self.buf.commit(len)
+    }
+
+    // Only free space, whatever the full policy, so that committing never
+    // overwrites the batches before.
+    fn staged(&mut self) -> &mut [u8] {
+        let free = self.buf.free();
+        self.buf.claim(free).unwrap_or_default()
+    }
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn varint_len(v: i64) -> usize {
+    let bits = 64 - (zigzag(v) | 1).leading_zeros() as usize;
+    bits.div_ceil(7)
+}
+
+fn put_varint(dst: &mut [u8], v: i64) -> usize {
+    let mut v = zigzag(v);
+    let mut n = 0;
+    while v >= 0x80 {
+        dst[n] = (v as u8) | 0x80;
+        v >>= 7;
+        n += 1;
+    }
+    dst[n] = v as u8;
+    n + 1
+}
+
+// Nullable byte strings are prefixed by their varint length, -1 for null.
+fn bytes_len(b: Option<&[u8]>) -> usize {
+    match b {
+        Some(b) => varint_len(b.len() as i64) + b.len(),
+        None => varint_len(-1),
+    }
+}
+
+fn put_bytes(dst: &mut [u8], b: Option<&[u8]>) -> usize {
+    match b {
+        Some(b) => {
+            let n = put_varint(dst, b.len() as i64);
+            dst[n..n + b.len()].copy_from_slice(b);
+            n + b.len()
+        }
+        None => put_varint(dst, -1),
+    }
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::next_buffer_index, ErrorKind, FullPolicy};
+
+    fn get_varint(src: &[u8]) -> (i64, usize) {
+        let mut v = 0u64;
+        let mut n = 0;
+        loop {
+            v |= ((src[n] & 0x7f) as u64) << (7 * n);
+            n += 1;
+            if src[n - 1] & 0x80 == 0 {
+                break;
+            }
+        }
+        (((v >> 1) as i64) ^ -((v & 1) as i64), n)
+    }
+
+    #[test]
+    fn kafka_varint() {
+        let mut dst = [0u8; 10];
+        for v in [0, 1, -1, 63, -64, 64, 300, -300, i32::MAX as i64, i64::MIN] {
+            let n = put_varint(&mut dst, v);
+            assert!(n == varint_len(v));
+            assert!(get_varint(&dst) == (v, n));
+        }
+        assert!(put_varint(&mut dst, -1) == 1 && dst[0] == 1);
+        assert!(put_varint(&mut dst, 64) == 2 && dst[..2] == [0x80, 0x01]);
+    }
+
+    #[test]
+    fn kafka_crc32c() {
+        assert!(crc32c(b"123456789") == 0xe3069283);
+        assert!(crc32c(b"") == 0);
+    }
+
+    #[test]
+    fn kafka_batch_writer() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+
+        // Force the batch to wrap around the end of the ring.
+        let offset = buf.size() - 20;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let mut writer = BatchWriter::new(&mut buf).unwrap().with_producer(7, 1, 100);
+        writer
+            .append(&Record {
+                timestamp: 1000,
+                key: Some(b"key"),
+                value: Some(b"value"),
+                headers: &[(b"h", Some(b"v"))],
+            })
+            .unwrap();
+        writer
+            .append(&Record {
+                timestamp: 990,
+                key: None,
+                value: Some(b"second"),
+                headers: &[],
+            })
+            .unwrap();
+        assert!(writer.records() == 2);
+        let len = writer.len();
+        assert!(writer.finish() == len);
+        assert!(buf.used() == len);
+
+        let batch = buf.committed().unwrap();
+        let i32_at = |o: usize| i32::from_be_bytes(batch[o..o + 4].try_into().unwrap());
+        let i64_at = |o: usize| i64::from_be_bytes(batch[o..o + 8].try_into().unwrap());
+
+        assert!(i64_at(0) == 0);
+        assert!(i32_at(BATCH_LENGTH_OFFSET) as usize == len - 12);
+        assert!(batch[MAGIC_OFFSET] == 2);
+        assert!(i32_at(CRC_OFFSET) as u32 == crc32c(&batch[ATTRIBUTES_OFFSET..]));
+        assert!(i32_at(LAST_OFFSET_DELTA_OFFSET) == 1);
+        assert!(i64_at(BASE_TIMESTAMP_OFFSET) == 1000);
+        assert!(i64_at(MAX_TIMESTAMP_OFFSET) == 1000);
+        assert!(i64_at(PRODUCER_ID_OFFSET) == 7);
+        assert!(i32_at(BASE_SEQUENCE_OFFSET) == 100);
+        assert!(i32_at(RECORDS_COUNT_OFFSET) == 2);
+
+        // First record: length, attributes, timestamp delta, offset delta,
+        // then the key.
+        let records = &batch[BATCH_HEADER_LEN..];
+        let (record_len, n) = get_varint(records);
+        assert!(records[n] == 0);
+        assert!(get_varint(&records[n + 1..]) == (0, 1));
+        assert!(get_varint(&records[n + 2..]) == (0, 1));
+        assert!(get_varint(&records[n + 3..]) == (3, 1));
+        assert!(&records[n + 4..n + 7] == b"key");
+
+        // Second record: negative timestamp delta and a null key.
+        let records = &records[n + record_len as usize..];
+        let (_, n) = get_varint(records);
+        assert!(get_varint(&records[n + 1..]) == (-10, 1));
+        assert!(get_varint(&records[n + 2..]) == (1, 1));
+        assert!(get_varint(&records[n + 3..]) == (-1, 1));
+    }
+
+    #[test]
+    fn kafka_batch_writer_no_space() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+
+        let mut writer = BatchWriter::new(&mut buf).unwrap();
+        let value = vec![1u8; size];
+        let err = writer
+            .append(&Record {
+                timestamp: 0,
+                key: None,
+                value: Some(&value),
+                headers: &[],
+            })
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+        assert!(writer.is_empty());
+
+        // An unfinished writer discards whatever was staged.
+        let mut writer = BatchWriter::new(&mut buf).unwrap();
+        writer
+            .append(&Record {
+                timestamp: 0,
+                key: None,
+                value: Some(b"dropped"),
+                headers: &[],
+            })
+            .unwrap();
+        assert!(writer.records() == 1);
+        assert!(buf.used() == 0);
+
+        buf.commit(size - BATCH_HEADER_LEN + 1);
+        assert!(BatchWriter::new(&mut buf).is_err());
+    }
+
+    #[test]
+    fn kafka_batch_writer_overwrite() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0))
+            .unwrap()
+            .with_full_policy(FullPolicy::Overwrite);
+        let size = buf.size();
+        let record = Record {
+            timestamp: 0,
+            key: None,
+            value: Some(b"kept"),
+            headers: &[],
+        };
+        let mut writer = BatchWriter::new(&mut buf).unwrap();
+        writer.append(&record).unwrap();
+        let first = writer.finish();
+
+        // A batch that does not fit in what is free fails to take more,
+        // rather than overwriting the first one.
+        let mut writer = BatchWriter::new(&mut buf).unwrap();
+        let value = vec![1u8; size - first - BATCH_HEADER_LEN];
+        let err = writer
+            .append(&Record {
+                value: Some(&value),
+                ..record
+            })
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+        assert!(writer.finish() == 0);
+        assert!(buf.used() == first);
+    }
+}
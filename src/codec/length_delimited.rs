@@ -0,0 +1,110 @@
+use super::Decoder;
+use crate::{Error, MirroredBuffer};
+
+// Frames are a 4-byte big-endian payload length followed by the payload.
+pub const HEADER_LEN: usize = 4;
+
+pub const DEFAULT_MAX_PAYLOAD_LEN: usize = 8 << 20;
+
+pub struct Codec {
+    max_len: usize,
+}
+
+impl Codec {
+    pub fn new() -> Codec {
+        Codec {
+            max_len: DEFAULT_MAX_PAYLOAD_LEN,
+        }
+    }
+
+    pub fn with_max_len(mut self, max_len: usize) -> Codec {
+        self.max_len = max_len;
+        self
+    }
+
+    // Commits a frame carrying `payload` to `buf`, returning its size.
+    pub fn encode(&self, buf: &mut MirroredBuffer<'_>, payload: &[u8]) -> Result<usize, Error> {
+        if payload.len() > self.max_len {
+            return Err(Error::invalid_frame("payload exceeds the maximum length"));
+        }
+
+        let size = HEADER_LEN + payload.len();
+        let claimed = match buf.claim(size) {
+            Some(claimed) if claimed.len() == size => claimed,
+            _ => return Err(Error::no_space(size)),
+        };
+        claimed[..HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+        claimed[HEADER_LEN..].copy_from_slice(payload);
+
+        Ok(buf.commit(size))
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::new()
+    }
+}
+
+impl Decoder for Codec {
+    // The payload, without the length.
+    type Frame<'b> = &'b [u8];
+
+    fn decode<'b>(&mut self, src: &'b [u8]) -> Result<Option<(&'b [u8], usize)>, Error> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let len = u32::from_be_bytes(src[..HEADER_LEN].try_into().unwrap()) as usize;
+        if len > self.max_len {
+            return Err(Error::invalid_frame("payload exceeds the maximum length"));
+        }
+
+        let size = HEADER_LEN + len;
+        if src.len() < size {
+            return Ok(None);
+        }
+        Ok(Some((&src[HEADER_LEN..size], size)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::next_buffer_index, ErrorKind};
+
+    #[test]
+    fn length_delimited_encode_decode() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut codec = Codec::new().with_max_len(100);
+
+        let offset = buf.size() - 50;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        assert!(codec.encode(&mut buf, &[1; 30]).unwrap() == 34);
+        assert!(codec.encode(&mut buf, &[2; 40]).unwrap() == 44);
+        assert!(codec.encode(&mut buf, b"").unwrap() == 4);
+
+        let committed = buf.committed().unwrap();
+        for i in 0..34 {
+            assert!(codec.decode(&committed[..i]).unwrap().is_none());
+        }
+
+        let (payload, size) = buf.decode(&mut codec).unwrap().unwrap();
+        assert!(payload == [1; 30]);
+        buf.consume(size);
+        let (payload, size) = buf.decode(&mut codec).unwrap().unwrap();
+        assert!(payload == [2; 40]);
+        buf.consume(size);
+        let (payload, size) = buf.decode(&mut codec).unwrap().unwrap();
+        assert!(payload.is_empty() && size == 4);
+        buf.consume(size);
+        assert!(buf.decode(&mut codec).unwrap().is_none());
+
+        let err = codec.encode(&mut buf, &[0; 101]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+        let err = codec.decode(&[0, 0, 0, 101]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+    }
+}
@@ -0,0 +1,34 @@
+pub mod kafka;
+pub mod length_delimited;
+#[cfg(feature = "snow")]
+pub mod noise;
+pub mod postgres;
+pub mod tls;
+
+use crate::{Error, MirroredBuffer};
+
+// A Decoder recognizes frames at the start of a byte slice, typically the
+// committed region of a MirroredBuffer. Since the committed region is always
+// contiguous, a frame that wraps around the end of the ring is returned as a
+// single slice - no bytes are copied to linearize it.
+pub trait Decoder {
+    type Frame<'b>;
+
+    // Returns the first frame in `src` and the number of bytes it spans, or
+    // None if `src` does not yet hold a complete frame.
+    fn decode<'b>(&mut self, src: &'b [u8]) -> Result<Option<(Self::Frame<'b>, usize)>, Error>;
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Decodes the first frame of the committed region. The frame borrows the
+    // buffer, so the returned size must be consumed once the frame is handled.
+    pub fn decode<D: Decoder>(
+        &self,
+        decoder: &mut D,
+    ) -> Result<Option<(D::Frame<'_>, usize)>, Error> {
+        match self.committed() {
+            Some(committed) => decoder.decode(committed),
+            None => Ok(None),
+        }
+    }
+}
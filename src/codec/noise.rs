@@ -0,0 +1,266 @@
+use crate::{Error, MirroredBuffer};
+use snow::TransportState;
+
+// Noise messages are at most 65535 bytes long and carry a 16-byte AEAD tag.
+pub const MAX_MESSAGE_LEN: usize = 65535;
+pub const TAG_LEN: usize = 16;
+pub const MAX_PAYLOAD_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+// Each frame is a 2-byte big-endian length followed by a Noise message.
+pub const HEADER_LEN: usize = 2;
+
+// An encrypted framed channel over a pair of MirroredBuffers, driven by a
+// snow TransportState obtained once the Noise handshake is over.
+//
+// Outgoing frames are encrypted straight into the claim region of the tx
+// buffer. Incoming frames are decrypted in place in the committed region of
+// the rx buffer, including the ones that wrap around the end of the ring.
+pub struct Transport {
+    state: TransportState,
+    // Where the ciphertext of a frame goes while it is decrypted in place,
+    // as snow reads and writes through distinct slices.
+    scratch: Vec<u8>,
+}
+
+impl Transport {
+    pub fn new(state: TransportState) -> Transport {
+        Transport {
+            state,
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> &TransportState {
+        &self.state
+    }
+
+    pub fn into_inner(self) -> TransportState {
+        self.state
+    }
+
+    // Encrypts `payload` into a frame committed to `buf`, returning the size
+    // of the frame. Only free space is used, whatever the full policy:
+    // overwriting older frames would cut into them, and the peer could not
+    // decrypt past the ones lost.
+    pub fn encrypt_into(
+        &mut self,
+        buf: &mut MirroredBuffer<'_>,
+        payload: &[u8],
+    ) -> Result<usize, Error> {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(Error::invalid_frame("noise payload is too long"));
+        }
+
+        let size = HEADER_LEN + payload.len() + TAG_LEN;
+        if size > buf.free() {
+            return Err(Error::no_space(size));
+        }
+        let claimed = match buf.claim(size) {
+            Some(claimed) if claimed.len() == size => claimed,
+            _ => return Err(Error::no_space(size)),
+        };
+
+        let n = self
+            .state
+            .write_message(payload, &mut claimed[HEADER_LEN..])?;
+        claimed[..HEADER_LEN].copy_from_slice(&(n as u16).to_be_bytes());
+
+        Ok(buf.commit(HEADER_LEN + n))
+    }
+
+    // Decrypts the first frame committed to `buf` in place and consumes its
+    // header and tag, leaving the payload at the start of the committed
+    // region, and returns the payload's length, or None if no complete frame
+    // has been received yet. A frame that fails to decrypt is left as it was.
+    pub fn decrypt_from(&mut self, buf: &mut MirroredBuffer<'_>) -> Result<Option<usize>, Error> {
+        let committed = match buf.committed() {
+            Some(committed) if committed.len() >= HEADER_LEN => committed,
+            _ => return Ok(None),
+        };
+
+        let len = u16::from_be_bytes([committed[0], committed[1]]) as usize;
+        if len < TAG_LEN {
+            return Err(Error::invalid_frame("noise message is too short"));
+        }
+        if committed.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        // The payload goes right before the end of the frame, where consuming
+        // the header and the tag leaves it.
+        self.scratch.clear();
+        self.scratch
+            .extend_from_slice(&committed[HEADER_LEN..HEADER_LEN + len]);
+        let start = HEADER_LEN + TAG_LEN;
+        let decrypted = match buf.committed_mut() {
+            Some(committed) if committed.len() >= HEADER_LEN + len => self
+                .state
+                .read_message(&self.scratch, &mut committed[start..HEADER_LEN + len]),
+            // Across the wrap of a read-only mirror, the payload is decrypted
+            // past the ciphertext and written back in two pieces.
+            _ => {
+                self.scratch.resize(2 * len - TAG_LEN, 0);
+                let (ciphertext, payload) = self.scratch.split_at_mut(len);
+                let decrypted = self.state.read_message(ciphertext, payload);
+                if decrypted.is_ok() {
+                    buf.write_committed(start, payload);
+                }
+                decrypted
+            }
+        };
+        let n = match decrypted {
+            Ok(n) => n,
+            Err(err) => {
+                buf.write_committed(HEADER_LEN, &self.scratch[..len]);
+                return Err(err.into());
+            }
+        };
+        buf.consume(start);
+
+        Ok(Some(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::next_buffer_index, ErrorKind, FullPolicy};
+    use snow::Builder;
+
+    const PATTERN: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+
+    fn handshake() -> (TransportState, TransportState) {
+        let mut initiator = Builder::new(PATTERN.parse().unwrap())
+            .build_initiator()
+            .unwrap();
+        let mut responder = Builder::new(PATTERN.parse().unwrap())
+            .build_responder()
+            .unwrap();
+
+        let (mut msg, mut payload) = ([0u8; 1024], [0u8; 1024]);
+        let n = initiator.write_message(&[], &mut msg).unwrap();
+        responder.read_message(&msg[..n], &mut payload).unwrap();
+        let n = responder.write_message(&[], &mut msg).unwrap();
+        initiator.read_message(&msg[..n], &mut payload).unwrap();
+
+        (
+            initiator.into_transport_mode().unwrap(),
+            responder.into_transport_mode().unwrap(),
+        )
+    }
+
+    #[test]
+    fn noise_transport() {
+        let (initiator, responder) = handshake();
+        let (mut tx, mut rx) = (Transport::new(initiator), Transport::new(responder));
+
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+
+        // Make the second frame wrap around the end of the ring.
+        let offset = buf.size() - 100;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let first = [1u8; 50];
+        let second = [2u8; 200];
+        let n = tx.encrypt_into(&mut buf, &first).unwrap();
+        assert!(n == HEADER_LEN + first.len() + TAG_LEN);
+        tx.encrypt_into(&mut buf, &second).unwrap();
+
+        // Ciphertext, not plaintext, is what ends up in the buffer.
+        let committed = buf.committed().unwrap();
+        assert!(committed[HEADER_LEN..HEADER_LEN + first.len()] != first);
+
+        // The payload is left where the frame was, for the caller to consume.
+        let n = rx.decrypt_from(&mut buf).unwrap().unwrap();
+        assert!(buf.committed().unwrap()[..n] == first);
+        buf.consume(n);
+        let n = rx.decrypt_from(&mut buf).unwrap().unwrap();
+        assert!(buf.committed().unwrap() == second);
+        buf.consume(n);
+        assert!(rx.decrypt_from(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn noise_transport_read_only_mirror() {
+        let (initiator, responder) = handshake();
+        let (mut tx, mut rx) = (Transport::new(initiator), Transport::new(responder));
+
+        let mut frames = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let payloads = [[1u8; 50], [2u8; 50], [3u8; 50]];
+        for payload in &payloads {
+            tx.encrypt_into(&mut frames, payload).unwrap();
+        }
+
+        // The second frame runs across the wrap, which the payload cannot be
+        // written through.
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 100;
+        buf.commit(offset);
+        buf.consume(offset);
+        let mut buf = buf.with_read_only_mirror().unwrap();
+        buf.push(frames.committed().unwrap()).unwrap();
+        for payload in &payloads {
+            let n = rx.decrypt_from(&mut buf).unwrap().unwrap();
+            assert!(buf.committed().unwrap()[..n] == *payload);
+            buf.consume(n);
+        }
+        assert!(buf.used() == 0);
+    }
+
+    #[test]
+    fn noise_transport_partial_and_invalid() {
+        let (initiator, responder) = handshake();
+        let (mut tx, mut rx) = (Transport::new(initiator), Transport::new(responder));
+
+        let mut frames = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+
+        let size = tx.encrypt_into(&mut frames, b"hello").unwrap();
+        let frame = frames.committed().unwrap();
+
+        // A partial frame is left untouched.
+        buf.claim(size - 1)
+            .unwrap()
+            .copy_from_slice(&frame[..size - 1]);
+        buf.commit(size - 1);
+        assert!(rx.decrypt_from(&mut buf).unwrap().is_none());
+        assert!(buf.used() == size - 1);
+
+        // So is one that fails to decrypt.
+        buf.claim(1).unwrap()[0] = frame[size - 1] ^ 1;
+        buf.commit(1);
+        let err = rx.decrypt_from(&mut buf).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Noise(_)));
+        let committed = buf.committed().unwrap();
+        assert!(committed[..size - 1] == frame[..size - 1]);
+        assert!(committed[size - 1] == frame[size - 1] ^ 1);
+
+        let err = tx
+            .encrypt_into(&mut buf, &vec![0u8; MAX_PAYLOAD_LEN + 1])
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+    }
+
+    #[test]
+    fn noise_transport_overwrite() {
+        let (initiator, responder) = handshake();
+        let (mut tx, mut rx) = (Transport::new(initiator), Transport::new(responder));
+
+        // A full buffer fails to take more, rather than losing frames.
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0))
+            .unwrap()
+            .with_full_policy(FullPolicy::Overwrite);
+        let payload = vec![1u8; 1000];
+        let mut frames = 0;
+        while tx.encrypt_into(&mut buf, &payload).is_ok() {
+            frames += 1;
+        }
+        assert!(frames == buf.size() / (HEADER_LEN + 1000 + TAG_LEN));
+        for _ in 0..frames {
+            let n = rx.decrypt_from(&mut buf).unwrap().unwrap();
+            assert!(buf.committed().unwrap()[..n] == payload);
+            buf.consume(n);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use super::Decoder;
+use crate::Error;
+
+// Codes carried by the untagged messages a frontend may send before its
+// StartupMessage. After any of them, another untagged message follows.
+const SSL_REQUEST_CODE: u32 = 80877103;
+const GSSENC_REQUEST_CODE: u32 = 80877104;
+
+// Postgres rejects messages larger than 1GiB.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 1 << 30;
+
+// A message in the Postgres frontend/backend protocol. `tag` is None for the
+// untagged messages of the startup phase (StartupMessage, SSLRequest,
+// GSSENCRequest and CancelRequest). `body` excludes the tag and the length.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Message<'b> {
+    pub tag: Option<u8>,
+    pub body: &'b [u8],
+}
+
+pub struct Codec {
+    startup: bool,
+    max_len: usize,
+}
+
+impl Codec {
+    // Decodes tagged messages: everything a backend sends and everything a
+    // frontend sends once the startup phase is over.
+    pub fn new() -> Codec {
+        Codec {
+            startup: false,
+            max_len: DEFAULT_MAX_MESSAGE_LEN,
+        }
+    }
+
+    // Decodes what a frontend sends from the start of a connection: untagged
+    // messages until the StartupMessage, tagged messages afterwards.
+    pub fn startup() -> Codec {
+        Codec {
+            startup: true,
+            max_len: DEFAULT_MAX_MESSAGE_LEN,
+        }
+    }
+
+    // Messages must fit in the buffer they are decoded from, so proxies
+    // typically set this to the buffer's size.
+    pub fn with_max_len(mut self, max_len: usize) -> Codec {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn is_startup(&self) -> bool {
+        self.startup
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::new()
+    }
+}
+
+impl Decoder for Codec {
+    type Frame<'b> = Message<'b>;
+
+    fn decode<'b>(&mut self, src: &'b [u8]) -> Result<Option<(Message<'b>, usize)>, Error> {
+        let header_len = if self.startup { 4 } else { 5 };
+        if src.len() < header_len {
+            return Ok(None);
+        }
+
+        let (tag, len) = if self.startup {
+            (None, &src[..4])
+        } else {
+            (Some(src[0]), &src[1..5])
+        };
+
+        // The length includes itself, but not the tag.
+        let len = i32::from_be_bytes(len.try_into().unwrap());
+        if len < 4 {
+            return Err(Error::invalid_frame("postgres message length is below 4"));
+        }
+        let len = len as usize;
+        if len > self.max_len {
+            return Err(Error::invalid_frame(
+                "postgres message length exceeds the maximum",
+            ));
+        }
+
+        let size = header_len - 4 + len;
+        if src.len() < size {
+            return Ok(None);
+        }
+        let body = &src[header_len..size];
+
+        if self.startup {
+            let code = body
+                .get(..4)
+                .map(|c| u32::from_be_bytes(c.try_into().unwrap()));
+            if code != Some(SSL_REQUEST_CODE) && code != Some(GSSENC_REQUEST_CODE) {
+                self.startup = false;
+            }
+        }
+
+        Ok(Some((Message { tag, body }, size)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Codec, Message, SSL_REQUEST_CODE};
+    use crate::{codec::Decoder, util::next_buffer_index, ErrorKind, MirroredBuffer};
+
+    fn tagged(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut msg = vec![tag];
+        msg.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        msg.extend_from_slice(body);
+        msg
+    }
+
+    fn untagged(body: &[u8]) -> Vec<u8> {
+        let mut msg = (body.len() as i32 + 4).to_be_bytes().to_vec();
+        msg.extend_from_slice(body);
+        msg
+    }
+
+    #[test]
+    fn postgres_decode_tagged() {
+        let mut codec = Codec::new();
+        let msg = tagged(b'Q', b"select 1\0");
+
+        for i in 0..msg.len() {
+            assert!(codec.decode(&msg[..i]).unwrap().is_none());
+        }
+
+        let (decoded, size) = codec.decode(&msg).unwrap().unwrap();
+        assert!(size == msg.len());
+        assert!(
+            decoded
+                == Message {
+                    tag: Some(b'Q'),
+                    body: b"select 1\0"
+                }
+        );
+
+        let empty = tagged(b'S', b"");
+        let (decoded, size) = codec.decode(&empty).unwrap().unwrap();
+        assert!(size == 5);
+        assert!(decoded.body.is_empty());
+    }
+
+    #[test]
+    fn postgres_decode_startup() {
+        let mut codec = Codec::startup();
+
+        let ssl_request = untagged(&SSL_REQUEST_CODE.to_be_bytes());
+        let (decoded, size) = codec.decode(&ssl_request).unwrap().unwrap();
+        assert!(size == 8);
+        assert!(decoded.tag.is_none());
+        assert!(codec.is_startup());
+
+        let mut startup = 196608u32.to_be_bytes().to_vec();
+        startup.extend_from_slice(b"user\0postgres\0\0");
+        let startup = untagged(&startup);
+        let (decoded, size) = codec.decode(&startup).unwrap().unwrap();
+        assert!(size == startup.len());
+        assert!(decoded.tag.is_none());
+        assert!(!codec.is_startup());
+
+        let query = tagged(b'Q', b"select 1\0");
+        let (decoded, _) = codec.decode(&query).unwrap().unwrap();
+        assert!(decoded.tag == Some(b'Q'));
+    }
+
+    #[test]
+    fn postgres_decode_invalid() {
+        let mut codec = Codec::new().with_max_len(64);
+
+        let err = codec.decode(&[b'Q', 0, 0, 0, 3]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+
+        let err = codec.decode(&tagged(b'D', &[0; 64])).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+    }
+
+    #[test]
+    fn postgres_decode_wrapped() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut codec = Codec::new();
+
+        // Move the head and tail close to the end so the next message wraps.
+        let offset = buf.size() - 7;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let msg = tagged(b'D', b"wrapped row");
+        buf.claim(msg.len()).unwrap().copy_from_slice(&msg);
+        buf.commit(msg.len());
+
+        let (decoded, size) = buf.decode(&mut codec).unwrap().unwrap();
+        assert!(decoded.tag == Some(b'D'));
+        assert!(decoded.body == b"wrapped row");
+        assert!(buf.consume(size) == msg.len());
+        assert!(buf.decode(&mut codec).unwrap().is_none());
+    }
+}
@@ -0,0 +1,151 @@
+use super::Decoder;
+use crate::Error;
+
+pub const CONTENT_TYPE_CHANGE_CIPHER_SPEC: u8 = 20;
+pub const CONTENT_TYPE_ALERT: u8 = 21;
+pub const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+pub const CONTENT_TYPE_APPLICATION_DATA: u8 = 23;
+pub const CONTENT_TYPE_HEARTBEAT: u8 = 24;
+
+pub const HEADER_LEN: usize = 5;
+
+// The largest TLSCiphertext fragment allowed by RFC 5246 is 2^14 + 2048 bytes.
+pub const MAX_PAYLOAD_LEN: usize = (1 << 14) + 2048;
+
+// A TLS record. `raw` spans the whole record, header included, and is what
+// TLS libraries expect to be fed with; `payload` is the record's fragment.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record<'b> {
+    pub content_type: u8,
+    pub version: u16,
+    pub payload: &'b [u8],
+    pub raw: &'b [u8],
+}
+
+// Splits committed data into whole TLS records. Only the record layer is
+// looked at - the fragments are yielded as they are, encrypted or not.
+#[derive(Default)]
+pub struct Codec {}
+
+impl Codec {
+    pub fn new() -> Codec {
+        Codec {}
+    }
+}
+
+impl Decoder for Codec {
+    type Frame<'b> = Record<'b>;
+
+    fn decode<'b>(&mut self, src: &'b [u8]) -> Result<Option<(Record<'b>, usize)>, Error> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let content_type = src[0];
+        if !(CONTENT_TYPE_CHANGE_CIPHER_SPEC..=CONTENT_TYPE_HEARTBEAT).contains(&content_type) {
+            return Err(Error::invalid_frame("unknown TLS record content type"));
+        }
+
+        // SSLv3 up to TLS 1.3 all use a major version of 3.
+        let version = u16::from_be_bytes([src[1], src[2]]);
+        if version >> 8 != 3 {
+            return Err(Error::invalid_frame("unknown TLS record version"));
+        }
+
+        let len = u16::from_be_bytes([src[3], src[4]]) as usize;
+        if len > MAX_PAYLOAD_LEN {
+            return Err(Error::invalid_frame("TLS record payload is too long"));
+        }
+
+        let size = HEADER_LEN + len;
+        if src.len() < size {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            Record {
+                content_type,
+                version,
+                payload: &src[HEADER_LEN..size],
+                raw: &src[..size],
+            },
+            size,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::next_buffer_index, ErrorKind, MirroredBuffer};
+
+    fn record(content_type: u8, payload: &[u8]) -> Vec<u8> {
+        let mut record = vec![content_type, 3, 3];
+        record.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        record.extend_from_slice(payload);
+        record
+    }
+
+    #[test]
+    fn tls_decode() {
+        let mut codec = Codec::new();
+        let alert = record(CONTENT_TYPE_ALERT, &[2, 40]);
+
+        for i in 0..alert.len() {
+            assert!(codec.decode(&alert[..i]).unwrap().is_none());
+        }
+
+        let mut src = alert.clone();
+        src.extend_from_slice(&record(CONTENT_TYPE_APPLICATION_DATA, &[7; 100]));
+
+        let (decoded, size) = codec.decode(&src).unwrap().unwrap();
+        assert!(size == alert.len());
+        assert!(decoded.content_type == CONTENT_TYPE_ALERT);
+        assert!(decoded.version == 0x0303);
+        assert!(decoded.payload == [2, 40]);
+        assert!(decoded.raw == alert);
+
+        let (decoded, size) = codec.decode(&src[size..]).unwrap().unwrap();
+        assert!(size == HEADER_LEN + 100);
+        assert!(decoded.content_type == CONTENT_TYPE_APPLICATION_DATA);
+        assert!(decoded.payload.iter().all(|&x| x == 7));
+    }
+
+    #[test]
+    fn tls_decode_invalid() {
+        let mut codec = Codec::new();
+
+        let err = codec.decode(&[0x47, b'E', b'T', b' ', b'/']).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+
+        let err = codec
+            .decode(&[CONTENT_TYPE_HANDSHAKE, 2, 0, 0, 1])
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+
+        let err = codec
+            .decode(&[CONTENT_TYPE_HANDSHAKE, 3, 1, 0xff, 0xff])
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::InvalidFrame(_)));
+    }
+
+    #[test]
+    fn tls_decode_wrapped() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut codec = Codec::new();
+
+        let offset = buf.size() - 3;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let handshake = record(CONTENT_TYPE_HANDSHAKE, &[1; 64]);
+        buf.claim(handshake.len())
+            .unwrap()
+            .copy_from_slice(&handshake);
+        buf.commit(handshake.len());
+
+        let (decoded, size) = buf.decode(&mut codec).unwrap().unwrap();
+        assert!(decoded.raw == handshake);
+        assert!(buf.consume(size) == handshake.len());
+    }
+}
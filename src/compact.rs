@@ -0,0 +1,171 @@
+use crate::{map_mirrored, util::round_up_to_page_size, Error};
+use std::{
+    cmp,
+    ffi::CString,
+    process, slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// The largest compact buffer: the largest power of two a u32 holds.
+pub const COMPACT_MAX_SIZE: u32 = 1 << 31;
+
+static COMPACT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+// A buffer with the bare minimum of metadata, for applications holding
+// thousands of small rings, where the metadata of `MirroredBuffer` adds up
+// and spreads over more cache lines than the rings are worth.
+//
+// Sizes and indices are u32s, so a compact buffer holds COMPACT_MAX_SIZE
+// bytes at most, and it has no name: its segment is unlinked as soon as it
+// is mapped. It clamps when full, like a buffer with the default policy, and
+// has none of the options.
+pub struct CompactBuffer {
+    ptr: *mut u8,
+    fd: libc::c_int,
+    head: u32,
+    tail: u32,
+    size_mask: u32,
+    size_used: u32,
+}
+
+// Two of them fit a cache line on 64 bit targets.
+const _: () = assert!(std::mem::size_of::<CompactBuffer>() <= 32);
+
+// The buffer owns its mapping, and hands out regions of it only through
+// &self or &mut self.
+unsafe impl Send for CompactBuffer {}
+unsafe impl Sync for CompactBuffer {}
+
+impl CompactBuffer {
+    // Creates a buffer of at least `size` bytes, rounded like
+    // `MirroredBuffer::new` does.
+    pub fn new(size: u32) -> Result<CompactBuffer, Error> {
+        if size == 0 {
+            return Err(Error::invalid_size(0));
+        }
+        let size_total = round_up_to_page_size(size as usize)?;
+        if !size_total.is_power_of_two() || size_total > COMPACT_MAX_SIZE as usize {
+            return Err(Error::invalid_size(size_total));
+        }
+
+        let name = format!(
+            "/mirrored-buffer-{}-compact-{}",
+            process::id(),
+            COMPACT_INDEX.fetch_add(1, Ordering::Relaxed)
+        );
+        let name = CString::new(name).unwrap();
+        let fd = unsafe {
+            libc::shm_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                libc::S_IRUSR | libc::S_IWUSR,
+            )
+        };
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        unsafe { libc::shm_unlink(name.as_ptr()) };
+
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let mapped = if unsafe { libc::ftruncate(fd, size_total as libc::off_t) } == -1 {
+            Err(Error::last_os_error())
+        } else {
+            map_mirrored(fd, size_total, 0, prot, 0, 0, 0)
+        };
+        let slice = match mapped {
+            Ok(slice) => slice,
+            Err(err) => {
+                unsafe { libc::close(fd) };
+                return Err(err);
+            }
+        };
+        Ok(CompactBuffer {
+            ptr: slice.as_mut_ptr(),
+            fd,
+            head: 0,
+            tail: 0,
+            size_mask: size_total as u32 - 1,
+            size_used: 0,
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size_mask + 1
+    }
+
+    pub fn used(&self) -> u32 {
+        self.size_used
+    }
+
+    pub fn free(&self) -> u32 {
+        self.size() - self.size_used
+    }
+
+    pub fn claim(&mut self, size: u32) -> Option<&mut [u8]> {
+        let size = cmp::min(size, self.free());
+        if size == 0 {
+            return None;
+        }
+        let claimed = unsafe { self.ptr.add(self.tail as usize) };
+        Some(unsafe { slice::from_raw_parts_mut(claimed, size as usize) })
+    }
+
+    pub fn commit(&mut self, size: u32) -> u32 {
+        let size = cmp::min(size, self.free());
+        self.size_used += size;
+        self.tail = self.tail.wrapping_add(size) & self.size_mask;
+        size
+    }
+
+    pub fn consume(&mut self, size: u32) -> u32 {
+        let size = cmp::min(size, self.size_used);
+        self.size_used -= size;
+        self.head = self.head.wrapping_add(size) & self.size_mask;
+        size
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        if self.size_used == 0 {
+            return None;
+        }
+        let committed = unsafe { self.ptr.add(self.head as usize) };
+        Some(unsafe { slice::from_raw_parts(committed, self.size_used as usize) })
+    }
+}
+
+impl Drop for CompactBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, 2 * self.size() as usize);
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::get_page_size, CompactBuffer, ErrorKind};
+
+    #[test]
+    fn compact_buffer_wraps() {
+        let err = CompactBuffer::new(u32::MAX).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+
+        let mut buf = CompactBuffer::new(1).unwrap();
+        let size = buf.size();
+        assert!(size as usize == get_page_size().unwrap() && buf.free() == size);
+
+        buf.claim(size - 2).unwrap().fill(1);
+        assert!(buf.commit(size - 2) == size - 2);
+        assert!(buf.consume(size) == size - 2);
+        assert!(buf.committed().is_none());
+
+        // Across the wrap.
+        buf.claim(4).unwrap().copy_from_slice(b"abcd");
+        buf.commit(4);
+        assert!(buf.committed().unwrap() == b"abcd");
+        assert!(buf.claim(size).unwrap().len() as u32 == size - 4);
+        assert!(buf.commit(size) == size - 4 && buf.free() == 0);
+        assert!(buf.claim(1).is_none());
+    }
+}
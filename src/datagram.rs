@@ -0,0 +1,640 @@
+use crate::{Error, FullPolicy, MirroredBuffer};
+use std::{
+    cmp, io, mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+    os::unix::{io::AsRawFd, net::UnixDatagram},
+};
+
+// Every datagram is stored behind a fixed-size header:
+//
+// | size: u32 | len: u32 | family: u16 | port: u16 | flowinfo: u32 |
+// | scope_id: u32 | ip: [u8; 16] |
+//
+// `len` is the length of the payload following the header, `size` the
+// distance to the next header, which is at least HEADER_LEN + len. Fields are
+// in native byte order as the ring never leaves the host.
+pub const HEADER_LEN: usize = 36;
+
+// The largest UDP payload.
+pub const DEFAULT_MAX_DATAGRAM_LEN: usize = 65507;
+
+const FAMILY_NONE: u16 = 0;
+const FAMILY_V4: u16 = 4;
+const FAMILY_V6: u16 = 6;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Datagram<'b> {
+    pub payload: &'b [u8],
+    pub addr: Option<SocketAddr>,
+}
+
+// Queues datagrams in a MirroredBuffer while preserving their boundaries, so
+// each one can be retrieved individually instead of as part of a byte stream.
+// Under FullPolicy::Overwrite, the oldest datagrams are dropped whole to make
+// room for new ones.
+pub struct DatagramRing<'a> {
+    buf: MirroredBuffer<'a>,
+    count: usize,
+    max_datagram_len: usize,
+}
+
+impl<'a> DatagramRing<'a> {
+    // Queues datagrams in `buf`. Whatever it has committed is dropped, as it
+    // holds no datagrams this ring could tell apart.
+    pub fn new(mut buf: MirroredBuffer<'a>) -> DatagramRing<'a> {
+        buf.consume(buf.used());
+        DatagramRing {
+            buf,
+            count: 0,
+            max_datagram_len: DEFAULT_MAX_DATAGRAM_LEN,
+        }
+    }
+
+    // Receives only happen if there is room for a datagram of this length,
+    // as the kernel silently truncates datagrams that do not fit.
+    pub fn with_max_datagram_len(mut self, max_datagram_len: usize) -> DatagramRing<'a> {
+        self.max_datagram_len = max_datagram_len;
+        self
+    }
+
+    pub fn max_datagram_len(&self) -> usize {
+        self.max_datagram_len
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn push(&mut self, payload: &[u8], addr: Option<SocketAddr>) -> Result<(), Error> {
+        let size = HEADER_LEN + payload.len();
+        self.make_room(size);
+        let claimed = match self.buf.claim(size) {
+            Some(claimed) if claimed.len() == size => claimed,
+            _ => return Err(Error::no_space(size)),
+        };
+
+        put_header(claimed, size, payload.len(), addr);
+        claimed[HEADER_LEN..].copy_from_slice(payload);
+
+        self.buf.commit(size);
+        self.count += 1;
+        Ok(())
+    }
+
+    // Receives one datagram from `socket` directly into the free region,
+    // returning None without receiving anything if there is no room for a
+    // datagram of `max_datagram_len()`.
+    pub fn recv_from(&mut self, socket: &UdpSocket) -> io::Result<Option<(usize, SocketAddr)>> {
+        let Some(claimed) = self.claim_datagram() else {
+            return Ok(None);
+        };
+
+        let (n, addr) = socket.recv_from(&mut claimed[HEADER_LEN..])?;
+        put_header(claimed, HEADER_LEN + n, n, Some(addr));
+        self.buf.commit(HEADER_LEN + n);
+        self.count += 1;
+
+        Ok(Some((n, addr)))
+    }
+
+    // Same as `recv_from`, for Unix datagram sockets. No address is stored.
+    pub fn recv(&mut self, socket: &UnixDatagram) -> io::Result<Option<usize>> {
+        let Some(claimed) = self.claim_datagram() else {
+            return Ok(None);
+        };
+
+        let n = socket.recv(&mut claimed[HEADER_LEN..])?;
+        put_header(claimed, HEADER_LEN + n, n, None);
+        self.buf.commit(HEADER_LEN + n);
+        self.count += 1;
+
+        Ok(Some(n))
+    }
+
+    pub fn front(&self) -> Option<Datagram<'_>> {
+        let committed = self.buf.committed()?;
+        let (_, len, addr) = get_header(committed);
+        Some(Datagram {
+            payload: &committed[HEADER_LEN..HEADER_LEN + len],
+            addr,
+        })
+    }
+
+    // Consumes the first datagram, returning false if there is none.
+    pub fn pop(&mut self) -> bool {
+        let Some(committed) = self.buf.committed() else {
+            return false;
+        };
+        let (size, _, _) = get_header(committed);
+        self.buf.consume(size);
+        self.count -= 1;
+        true
+    }
+
+    // Receives up to `max_datagrams` datagrams from `socket` with a single
+    // recvmmsg call, each one directly into its own slot of the free region,
+    // and returns how many were received. Blocks until at least one datagram
+    // arrives unless the socket is nonblocking.
+    //
+    // Slots are HEADER_LEN + `max_datagram_len()` bytes apart, so the space
+    // past a short datagram stays unused until it is popped.
+    #[cfg(target_os = "linux")]
+    pub fn recv_mmsg<S: AsRawFd>(&mut self, socket: &S, max_datagrams: usize) -> io::Result<usize> {
+        let slot = HEADER_LEN + self.max_datagram_len;
+        self.make_room(slot);
+        let free = self.buf.free();
+        let count = cmp::min(max_datagrams, free / slot);
+        if count == 0 {
+            return Ok(0);
+        }
+        // With a read-only mirror, the claim stops at the wrap, which may
+        // leave room for fewer slots.
+        let Some(claimed) = self.buf.claim(count * slot) else {
+            return Ok(0);
+        };
+        let count = claimed.len() / slot;
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let mut iovecs: Vec<libc::iovec> = claimed
+            .chunks_exact_mut(slot)
+            .map(|slot| libc::iovec {
+                iov_base: slot[HEADER_LEN..].as_mut_ptr() as *mut libc::c_void,
+                iov_len: slot.len() - HEADER_LEN,
+            })
+            .collect();
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; count];
+        let mut msgs: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iovec, addr)| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+                msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as u32;
+                msg.msg_hdr.msg_iov = iovec;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        let received = loop {
+            let ret = unsafe {
+                libc::recvmmsg(
+                    socket.as_raw_fd(),
+                    msgs.as_mut_ptr(),
+                    msgs.len() as libc::c_uint,
+                    libc::MSG_WAITFORONE,
+                    std::ptr::null_mut(),
+                )
+            };
+            if ret >= 0 {
+                break ret as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+
+        let mut size = 0;
+        for (i, (msg, addr)) in msgs.iter().zip(addrs.iter()).take(received).enumerate() {
+            let len = msg.msg_len as usize;
+            size = if i + 1 < received {
+                slot
+            } else {
+                HEADER_LEN + len
+            };
+            let addr = sockaddr_to_socket_addr(addr, msg.msg_hdr.msg_namelen);
+            put_header(&mut claimed[i * slot..], size, len, addr);
+        }
+
+        if received > 0 {
+            self.buf.commit((received - 1) * slot + size);
+            self.count += received;
+        }
+        Ok(received)
+    }
+
+    // Sends up to `max_datagrams` queued datagrams on `socket` with a single
+    // sendmmsg call, each one to its stored address (or to the socket's peer
+    // if it has none), and pops the ones the kernel accepted.
+    #[cfg(target_os = "linux")]
+    pub fn send_mmsg<S: AsRawFd>(&mut self, socket: &S, max_datagrams: usize) -> io::Result<usize> {
+        let count = cmp::min(max_datagrams, self.count);
+        if count == 0 {
+            return Ok(0);
+        }
+        let committed = self.buf.committed().unwrap();
+
+        let mut iovecs = Vec::with_capacity(count);
+        let mut addrs = Vec::with_capacity(count);
+        let mut offset = 0;
+        for _ in 0..count {
+            let (size, len, addr) = get_header(&committed[offset..]);
+            let payload = &committed[offset + HEADER_LEN..offset + HEADER_LEN + len];
+            iovecs.push(libc::iovec {
+                iov_base: payload.as_ptr() as *mut libc::c_void,
+                iov_len: len,
+            });
+            addrs.push(addr.map(socket_addr_to_sockaddr));
+            offset += size;
+        }
+
+        let mut msgs: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iovec, addr)| {
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                if let Some((addr, len)) = addr {
+                    msg.msg_hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+                    msg.msg_hdr.msg_namelen = *len;
+                }
+                msg.msg_hdr.msg_iov = iovec;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        let sent = loop {
+            let ret = unsafe {
+                libc::sendmmsg(
+                    socket.as_raw_fd(),
+                    msgs.as_mut_ptr(),
+                    count as libc::c_uint,
+                    0,
+                )
+            };
+            if ret >= 0 {
+                break ret as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+
+        for _ in 0..sent {
+            self.pop();
+        }
+        Ok(sent)
+    }
+
+    // Under FullPolicy::Overwrite, pops datagrams until there are `size`
+    // bytes free, so that committing does not cut into the oldest one.
+    fn make_room(&mut self, size: usize) {
+        if self.buf.full_policy() != FullPolicy::Overwrite || size > self.buf.size() {
+            return;
+        }
+        while self.buf.free() < size && self.pop() {}
+    }
+
+    fn claim_datagram(&mut self) -> Option<&mut [u8]> {
+        let size = HEADER_LEN + self.max_datagram_len;
+        self.make_room(size);
+        self.buf.claim(size).filter(|claimed| claimed.len() == size)
+    }
+}
+
+pub(crate) fn put_header(dst: &mut [u8], size: usize, len: usize, addr: Option<SocketAddr>) {
+    let (family, port, flowinfo, scope_id, ip) = match addr {
+        None => (FAMILY_NONE, 0, 0, 0, [0; 16]),
+        Some(SocketAddr::V4(addr)) => {
+            let mut ip = [0; 16];
+            ip[..4].copy_from_slice(&addr.ip().octets());
+            (FAMILY_V4, addr.port(), 0, 0, ip)
+        }
+        Some(SocketAddr::V6(addr)) => (
+            FAMILY_V6,
+            addr.port(),
+            addr.flowinfo(),
+            addr.scope_id(),
+            addr.ip().octets(),
+        ),
+    };
+
+    dst[0..4].copy_from_slice(&(size as u32).to_ne_bytes());
+    dst[4..8].copy_from_slice(&(len as u32).to_ne_bytes());
+    dst[8..10].copy_from_slice(&family.to_ne_bytes());
+    dst[10..12].copy_from_slice(&port.to_ne_bytes());
+    dst[12..16].copy_from_slice(&flowinfo.to_ne_bytes());
+    dst[16..20].copy_from_slice(&scope_id.to_ne_bytes());
+    dst[20..36].copy_from_slice(&ip);
+}
+
+pub(crate) fn get_header(src: &[u8]) -> (usize, usize, Option<SocketAddr>) {
+    let u16_at = |o: usize| u16::from_ne_bytes([src[o], src[o + 1]]);
+    let u32_at = |o: usize| u32::from_ne_bytes(src[o..o + 4].try_into().unwrap());
+
+    let ip: [u8; 16] = src[20..36].try_into().unwrap();
+    let addr = match u16_at(8) {
+        FAMILY_V4 => Some(SocketAddr::V4(SocketAddrV4::new(
+            Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]),
+            u16_at(10),
+        ))),
+        FAMILY_V6 => Some(SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::from(ip),
+            u16_at(10),
+            u32_at(12),
+            u32_at(16),
+        ))),
+        _ => None,
+    };
+
+    (u32_at(0) as usize, u32_at(4) as usize, addr)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn sockaddr_to_socket_addr(
+    addr: &libc::sockaddr_storage,
+    len: libc::socklen_t,
+) -> Option<SocketAddr> {
+    let len = len as usize;
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET if len >= mem::size_of::<libc::sockaddr_in>() => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 if len >= mem::size_of::<libc::sockaddr_in6>() => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn socket_addr_to_sockaddr(
+    addr: SocketAddr,
+) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::next_buffer_index, ErrorKind, FullPolicy};
+
+    #[test]
+    fn datagram_push_pop() {
+        // What was committed before is no datagram.
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        buf.push(&[0xff; 100]).unwrap();
+        let size = buf.size();
+        let mut ring = DatagramRing::new(buf);
+        assert!(ring.buffer().used() == 0);
+        assert!(ring.front().is_none());
+        assert!(!ring.pop());
+
+        let v4: SocketAddr = "10.0.0.1:53".parse().unwrap();
+        let v6: SocketAddr = "[fe80::1%2]:443".parse().unwrap();
+        ring.push(b"first", Some(v4)).unwrap();
+        ring.push(b"", None).unwrap();
+        ring.push(b"third", Some(v6)).unwrap();
+        assert!(ring.len() == 3);
+
+        assert!(
+            ring.front()
+                == Some(Datagram {
+                    payload: b"first",
+                    addr: Some(v4)
+                })
+        );
+        assert!(ring.pop());
+        assert!(
+            ring.front()
+                == Some(Datagram {
+                    payload: b"",
+                    addr: None
+                })
+        );
+        assert!(ring.pop());
+        assert!(ring.front().unwrap().addr == Some(v6));
+        assert!(ring.pop());
+        assert!(ring.is_empty());
+
+        let err = ring.push(&vec![0; size], None).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+    }
+
+    #[test]
+    fn datagram_recv_from() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx.connect(rx.local_addr().unwrap()).unwrap();
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut ring = DatagramRing::new(buf).with_max_datagram_len(1024);
+
+        for i in 0..3u8 {
+            tx.send(&[i; 100]).unwrap();
+        }
+        for _ in 0..3 {
+            let (n, addr) = ring.recv_from(&rx).unwrap().unwrap();
+            assert!(n == 100);
+            assert!(addr == tx.local_addr().unwrap());
+        }
+
+        for i in 0..3u8 {
+            let datagram = ring.front().unwrap();
+            assert!(datagram.payload == [i; 100]);
+            assert!(datagram.addr == Some(tx.local_addr().unwrap()));
+            ring.pop();
+        }
+    }
+
+    #[test]
+    fn datagram_recv_unix() {
+        let (tx, rx) = UnixDatagram::pair().unwrap();
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let mut ring = DatagramRing::new(buf).with_max_datagram_len(size / 2);
+
+        tx.send(b"one").unwrap();
+        tx.send(b"two").unwrap();
+        assert!(ring.recv(&rx).unwrap() == Some(3));
+        assert!(ring.recv(&rx).unwrap() == Some(3));
+
+        // Not enough room left for a datagram of max_datagram_len.
+        ring.push(&vec![0; size / 2 - 100], None).unwrap();
+        assert!(ring.recv(&rx).unwrap().is_none());
+
+        assert!(ring.front().unwrap().payload == b"one");
+        ring.pop();
+        assert!(ring.front().unwrap().payload == b"two");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn datagram_recv_mmsg() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx.connect(rx.local_addr().unwrap()).unwrap();
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let mut ring = DatagramRing::new(buf).with_max_datagram_len(256);
+        let slot = HEADER_LEN + 256;
+
+        for i in 0..5u8 {
+            tx.send(&vec![i; 10 + i as usize]).unwrap();
+        }
+
+        // Only 3 datagrams are asked for, so the other 2 stay queued.
+        assert!(ring.recv_mmsg(&rx, 3).unwrap() == 3);
+        assert!(ring.len() == 3);
+        assert!(ring.buffer().used() == 2 * slot + HEADER_LEN + 12);
+
+        // Not more than what fits in the free region is received.
+        let fits = (size - ring.buffer().used()) / slot;
+        assert!(ring.recv_mmsg(&rx, fits + 10).unwrap() == 2);
+        assert!(ring.len() == 5);
+
+        for i in 0..5u8 {
+            let datagram = ring.front().unwrap();
+            assert!(datagram.payload == vec![i; 10 + i as usize]);
+            assert!(datagram.addr == Some(tx.local_addr().unwrap()));
+            assert!(ring.pop());
+        }
+        assert!(ring.buffer().used() == 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn datagram_recv_mmsg_read_only_mirror() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx.connect(rx.local_addr().unwrap()).unwrap();
+
+        // The tail sits 2 slots and a bit before the wrap, which claims do
+        // not cross.
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let slot = HEADER_LEN + 256;
+        buf.commit(size - 2 * slot - 10);
+        buf.consume(size - 2 * slot - 10);
+        let buf = buf.with_read_only_mirror().unwrap();
+        let mut ring = DatagramRing::new(buf).with_max_datagram_len(256);
+
+        for i in 0..5u8 {
+            tx.send(&[i; 100]).unwrap();
+        }
+        assert!(ring.recv_mmsg(&rx, 5).unwrap() == 2);
+        assert!(ring.len() == 2);
+        assert!(ring.recv_mmsg(&rx, 5).unwrap() == 0);
+        for i in 0..2u8 {
+            assert!(ring.front().unwrap().payload == [i; 100]);
+            assert!(ring.pop());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn datagram_send_mmsg() {
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut ring = DatagramRing::new(buf);
+        assert!(ring.send_mmsg(&tx, 10).unwrap() == 0);
+
+        for i in 0..4u8 {
+            let to = if i % 2 == 0 { &first } else { &second };
+            ring.push(&[i; 32], Some(to.local_addr().unwrap())).unwrap();
+        }
+
+        assert!(ring.send_mmsg(&tx, 3).unwrap() == 3);
+        assert!(ring.len() == 1);
+        assert!(ring.send_mmsg(&tx, 10).unwrap() == 1);
+        assert!(ring.buffer().used() == 0);
+
+        let mut payload = [0u8; 64];
+        for (socket, expected) in [(&first, [0u8, 2]), (&second, [1, 3])] {
+            for i in expected {
+                let (n, from) = socket.recv_from(&mut payload).unwrap();
+                assert!(payload[..n] == [i; 32]);
+                assert!(from == tx.local_addr().unwrap());
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn datagram_sockaddr() {
+        for addr in ["127.0.0.1:8080", "[::1]:53", "[fe80::1%3]:443"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let (storage, len) = socket_addr_to_sockaddr(addr);
+            assert!(sockaddr_to_socket_addr(&storage, len) == Some(addr));
+        }
+    }
+
+    #[test]
+    fn datagram_overwrite() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0))
+            .unwrap()
+            .with_full_policy(FullPolicy::Overwrite);
+        let size = buf.size();
+        let mut ring = DatagramRing::new(buf);
+
+        // Around the end of the buffer a few times, the oldest datagrams
+        // making room for the newest ones.
+        let payload = |i: usize| vec![i as u8; 1000 + i % 7];
+        let count = 3 * size / (HEADER_LEN + 1000);
+        for i in 0..count {
+            ring.push(&payload(i), None).unwrap();
+        }
+        assert!(ring.buffer().used() <= size);
+        let mut i = count - ring.len();
+        while let Some(datagram) = ring.front() {
+            assert!(datagram.payload == payload(i));
+            ring.pop();
+            i += 1;
+        }
+        assert!(i == count && ring.buffer().used() == 0);
+
+        // No further than the buffer holds.
+        let err = ring.push(&vec![0; size], None).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{util::round_up_to_page_size, Error, MirroredBuffer};
+use std::{
+    cmp,
+    io::{self, Read, Write},
+};
+
+// How many consumes in a row must leave the buffer at most a quarter used
+// for it to shrink, unless set with `with_shrink_after`.
+const SHRINK_AFTER: usize = 64;
+
+// Sizes a buffer to what goes through it, between `min` and `max` bytes, so
+// the application does not have to: the buffer grows when a claim, push or
+// read does not fit, and halves after it stayed at most a quarter used for a
+// while. Both only happen as the buffer is used through this wrapper.
+//
+// Growing or shrinking may fail, e.g. when running out of memory or once the
+// buffer is pinned, in which case the buffer keeps its size and does what
+// its full policy says.
+pub struct Elastic<'a> {
+    buf: MirroredBuffer<'a>,
+    min: usize,
+    max: usize,
+    shrink_after: usize,
+    quiet: usize,
+}
+
+impl<'a> Elastic<'a> {
+    // Lets `buf` grow up to `max` bytes and shrink down to `min`, both
+    // rounded like `MirroredBuffer::new` does. Fails with InvalidSize unless
+    // the size of `buf` is between them.
+    pub fn new(buf: MirroredBuffer<'a>, min: usize, max: usize) -> Result<Elastic<'a>, Error> {
+        let min = round_up_to_page_size(min)?;
+        let max = round_up_to_page_size(max)?;
+        if !min.is_power_of_two() || min > buf.size() {
+            return Err(Error::invalid_size(min));
+        }
+        if !max.is_power_of_two() || max < buf.size() {
+            return Err(Error::invalid_size(max));
+        }
+        Ok(Elastic {
+            buf,
+            min,
+            max,
+            shrink_after: SHRINK_AFTER,
+            quiet: 0,
+        })
+    }
+
+    // Shrinks after `consumes` consumes in a row leave the buffer at most a
+    // quarter used, rather than 64.
+    pub fn with_shrink_after(mut self, consumes: usize) -> Elastic<'a> {
+        assert!(consumes > 0, "shrinking takes at least a consume");
+        self.shrink_after = consumes;
+        self
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    pub fn min(&self) -> usize {
+        self.min
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    // Grows the buffer for `size` more bytes, or as far as `max` allows.
+    fn make_room(&mut self, size: usize) {
+        if size <= self.buf.free() || self.buf.size() == self.max {
+            return;
+        }
+        let wanted = (self.buf.used() + size).checked_next_power_of_two();
+        let size = cmp::min(wanted.unwrap_or(self.max), self.max);
+        if self.buf.grow(size).is_ok() {
+            self.quiet = 0;
+        }
+    }
+
+    fn check(&mut self) {
+        let size = self.buf.size();
+        if size == self.min || self.buf.used() > size / 4 {
+            self.quiet = 0;
+            return;
+        }
+        self.quiet += 1;
+        if self.quiet >= self.shrink_after {
+            self.quiet = 0;
+            let _ = self.buf.shrink_to(cmp::max(size / 2, self.min));
+        }
+    }
+
+    pub fn claim(&mut self, size: usize) -> Option<&mut [u8]> {
+        self.make_room(size);
+        self.buf.claim(size)
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        self.buf.commit(size)
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.make_room(data.len());
+        self.buf.push(data)
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        self.buf.committed()
+    }
+
+    pub fn consume(&mut self, size: usize) -> usize {
+        let size = self.buf.consume(size);
+        self.check();
+        size
+    }
+
+    // Like `MirroredBuffer::fill_from`, growing the buffer first if it is
+    // full.
+    pub fn fill_from<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        self.make_room(1);
+        self.buf.fill_from(r)
+    }
+
+    pub fn flush_to<W: Write + ?Sized>(&mut self, w: &mut W) -> io::Result<usize> {
+        let n = self.buf.flush_to(w);
+        // What was flushed before an error still counts.
+        self.check();
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        util::{get_page_size, next_buffer_index},
+        Elastic, ErrorKind, FullPolicy, MirroredBuffer,
+    };
+
+    #[test]
+    fn elastic_grows_and_shrinks_within_bounds() {
+        let page_size = get_page_size().unwrap();
+        let buf = MirroredBuffer::new(2 * page_size, Some(&next_buffer_index()), None).unwrap();
+        let err = Elastic::new(buf, 4 * page_size, 8 * page_size)
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+
+        let buf = MirroredBuffer::new(2 * page_size, Some(&next_buffer_index()), None).unwrap();
+        let buf = buf.with_full_policy(FullPolicy::Error);
+        let mut buf = Elastic::new(buf, page_size, 8 * page_size)
+            .unwrap()
+            .with_shrink_after(2);
+
+        // Up to what is needed, then no further than `max`.
+        let data: Vec<u8> = (0..3 * page_size).map(|i| i as u8).collect();
+        buf.push(&data).unwrap();
+        assert!(buf.buffer().size() == 4 * page_size);
+        assert!(buf.claim(5 * page_size).unwrap().len() == 5 * page_size);
+        buf.commit(5 * page_size);
+        assert!(buf.buffer().size() == 8 * page_size);
+        let err = buf.push(&[0; 1]).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+        assert!(buf.committed().unwrap()[..3 * page_size] == data);
+
+        // Halving after a quiet while, no further than `min`.
+        buf.consume(7 * page_size);
+        assert!(buf.buffer().size() == 8 * page_size);
+        buf.consume(1);
+        assert!(buf.buffer().size() == 4 * page_size);
+        buf.consume(0);
+        buf.consume(0);
+        assert!(buf.buffer().size() == 2 * page_size);
+        buf.consume(page_size / 2);
+        buf.consume(0);
+        for _ in 0..10 {
+            buf.consume(0);
+        }
+        assert!(buf.buffer().size() == page_size);
+        assert!(buf.committed().unwrap().len() == page_size / 2 - 1);
+    }
+}
@@ -7,7 +7,15 @@ pub struct Error(ErrorKind);
 pub enum ErrorKind {
     NoPageSize,
     InvalidSize(usize),
+    InvalidFrame(&'static str),
+    NoSpace(usize),
+    Incompatible(&'static str),
+    Pinned,
+    ReadOnlyMirror,
+    OutOfMemory(usize),
     IO(io::Error),
+    #[cfg(feature = "snow")]
+    Noise(snow::Error),
 }
 
 impl From<ErrorKind> for Error {
@@ -22,6 +30,13 @@ impl From<io::Error> for Error {
     }
 }
 
+#[cfg(feature = "snow")]
+impl From<snow::Error> for Error {
+    fn from(err: snow::Error) -> Self {
+        Error(ErrorKind::Noise(err))
+    }
+}
+
 impl Error {
     pub fn no_page_size() -> Error {
         Error(ErrorKind::NoPageSize)
@@ -31,6 +46,30 @@ impl Error {
         Error(ErrorKind::InvalidSize(size))
     }
 
+    pub fn invalid_frame(reason: &'static str) -> Error {
+        Error(ErrorKind::InvalidFrame(reason))
+    }
+
+    pub fn no_space(size: usize) -> Error {
+        Error(ErrorKind::NoSpace(size))
+    }
+
+    pub fn incompatible(reason: &'static str) -> Error {
+        Error(ErrorKind::Incompatible(reason))
+    }
+
+    pub fn pinned() -> Error {
+        Error(ErrorKind::Pinned)
+    }
+
+    pub fn read_only_mirror() -> Error {
+        Error(ErrorKind::ReadOnlyMirror)
+    }
+
+    pub fn out_of_memory(size: usize) -> Error {
+        Error(ErrorKind::OutOfMemory(size))
+    }
+
     pub fn io(err: io::Error) -> Error {
         Error(ErrorKind::IO(err))
     }
@@ -38,12 +77,18 @@ impl Error {
     pub fn last_os_error() -> Error {
         Error::io(io::Error::last_os_error())
     }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0
+    }
 }
 
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match &self.0 {
             ErrorKind::IO(err) => Some(err),
+            #[cfg(feature = "snow")]
+            ErrorKind::Noise(err) => Some(err),
             _ => None,
         }
     }
@@ -57,7 +102,25 @@ impl fmt::Display for Error {
                 fmt,
                 "the buffer's size: {size} is invalid; must be > 0 and a power of two"
             ),
+            ErrorKind::InvalidFrame(reason) => write!(fmt, "invalid frame: {reason}"),
+            ErrorKind::NoSpace(size) => {
+                write!(fmt, "not enough free space in the buffer for {size} bytes")
+            }
+            ErrorKind::Incompatible(reason) => write!(fmt, "incompatible peer: {reason}"),
+            ErrorKind::Pinned => write!(fmt, "the buffer is pinned; its mapping cannot move"),
+            ErrorKind::ReadOnlyMirror => write!(
+                fmt,
+                "the buffer's mirror is read-only; writes cannot run across the wrap"
+            ),
+            ErrorKind::OutOfMemory(size) => write!(
+                fmt,
+                "could not get {size} bytes of shared memory; check the size of /dev/shm, \
+                 vm.overcommit_memory, vm.max_map_count and the address space limit, \
+                 or create the buffer with new_no_reserve"
+            ),
             ErrorKind::IO(err) => write!(fmt, "IO error: {err}"),
+            #[cfg(feature = "snow")]
+            ErrorKind::Noise(err) => write!(fmt, "noise error: {err}"),
         }
     }
 }
@@ -0,0 +1,130 @@
+use crate::MirroredBuffer;
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+// Peers closing their side must not kill the process with SIGPIPE.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) const SEND_FLAGS: libc::c_int = 0;
+
+impl<'a> MirroredBuffer<'a> {
+    // Receives once from the socket `fd` into the free region with recv(2)
+    // and commits what was received. For sockets managed below std, such as
+    // a `socket2::Socket` or a raw fd, without going through `io::Read`.
+    //
+    // Ok(0) means the peer closed its side, or that the buffer is full. A
+    // recv interrupted by a signal is retried; EAGAIN is returned as
+    // WouldBlock.
+    pub fn fill_from_fd<S: AsRawFd + ?Sized>(&mut self, fd: &S) -> io::Result<usize> {
+        let fd = fd.as_raw_fd();
+        let free = self.free();
+        let Some(claimed) = self.claim(free) else {
+            return Ok(0);
+        };
+
+        let n = recv(fd, claimed)?;
+        Ok(self.commit(n))
+    }
+
+    // Sends the committed region to the socket `fd` with send(2) until it is
+    // empty, consuming what was sent. Behaves like `flush_to`: if the socket
+    // would block after some bytes were sent the count so far is returned,
+    // otherwise the WouldBlock error is.
+    pub fn drain_to_fd<S: AsRawFd + ?Sized>(&mut self, fd: &S) -> io::Result<usize> {
+        let fd = fd.as_raw_fd();
+        let mut sent = 0;
+
+        while let Some(committed) = self.committed() {
+            match send(fd, committed) {
+                Ok(0) => {
+                    if sent > 0 {
+                        return Ok(sent);
+                    }
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                Ok(n) => sent += self.consume(n),
+                Err(err) if sent > 0 && err.kind() == io::ErrorKind::WouldBlock => return Ok(sent),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(sent)
+    }
+}
+
+fn recv(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let ret = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn send(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    loop {
+        let ret = unsafe {
+            libc::send(
+                fd,
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                SEND_FLAGS,
+            )
+        };
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use socket2::{Domain, Socket, Type};
+    use std::io;
+
+    #[test]
+    fn fd_fill_and_drain() {
+        let (a, b) = Socket::pair(Domain::UNIX, Type::STREAM, None).unwrap();
+        a.set_nonblocking(true).unwrap();
+        b.set_nonblocking(true).unwrap();
+
+        let mut tx = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut rx = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        for buf in [&mut tx, &mut rx] {
+            let offset = buf.size() - 100;
+            buf.commit(offset);
+            buf.consume(offset);
+        }
+
+        let err = rx.fill_from_fd(&b).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WouldBlock);
+
+        let data: Vec<u8> = (0..250).map(|x| x as u8).collect();
+        tx.fill_from(&mut &data[..]).unwrap();
+        assert!(tx.drain_to_fd(&a).unwrap() == 250);
+        assert!(tx.used() == 0);
+
+        assert!(rx.fill_from_fd(&b).unwrap() == 250);
+        assert!(rx.committed().unwrap() == data);
+
+        drop(a);
+        assert!(rx.fill_from_fd(&b).unwrap() == 0);
+
+        // With the peer gone, send fails with EPIPE instead of raising SIGPIPE.
+        let err = rx.drain_to_fd(&b).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::BrokenPipe);
+        assert!(rx.used() == 250);
+    }
+}
@@ -0,0 +1,147 @@
+// A C interface to the buffer, for C and C++ network stacks, under the `ffi`
+// feature. The header is include/mirrored_buffer.h, generated with
+// `cbindgen --config cbindgen.toml --output include/mirrored_buffer.h`; link
+// against the crate built with
+// `cargo rustc --release --features ffi --crate-type staticlib`, or cdylib.
+//
+// A buffer is an opaque pointer, from `mirrored_buffer_new` to
+// `mirrored_buffer_destroy`. All the functions taking one share the same
+// contract: it must be a pointer `mirrored_buffer_new` returned and not
+// destroyed yet, used by one thread at a time, and the pointers they return
+// are valid until the next call on the buffer.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::MirroredBuffer;
+use std::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static FFI_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+// Creates a buffer of at least `size` bytes into `*out`. Returns 0, or an
+// errno value on failure: EINVAL for an invalid size, ENOMEM when running out
+// of memory or address space, ENOSYS when the page size cannot be had.
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_new(
+    size: usize,
+    out: *mut *mut MirroredBuffer<'static>,
+) -> libc::c_int {
+    let suffix = format!("ffi-{}", FFI_INDEX.fetch_add(1, Ordering::Relaxed));
+    match MirroredBuffer::new(size, Some(&suffix), None) {
+        Ok(buf) => {
+            unsafe { *out = Box::into_raw(Box::new(buf)) };
+            0
+        }
+        Err(err) => match err.kind() {
+            crate::ErrorKind::IO(err) => err.raw_os_error().unwrap_or(libc::EIO),
+            crate::ErrorKind::OutOfMemory(_) => libc::ENOMEM,
+            crate::ErrorKind::NoPageSize => libc::ENOSYS,
+            _ => libc::EINVAL,
+        },
+    }
+}
+
+// Drops the buffer. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_destroy(buf: *mut MirroredBuffer<'static>) {
+    if !buf.is_null() {
+        drop(unsafe { Box::from_raw(buf) });
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_size(buf: *const MirroredBuffer<'static>) -> usize {
+    unsafe { &*buf }.size()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_used(buf: *const MirroredBuffer<'static>) -> usize {
+    unsafe { &*buf }.used()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_free(buf: *const MirroredBuffer<'static>) -> usize {
+    unsafe { &*buf }.free()
+}
+
+// Claims up to `size` bytes of the free region: returns where they start and
+// stores how many there are in `*len`, or returns NULL if there is no room.
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_claim(
+    buf: *mut MirroredBuffer<'static>,
+    size: usize,
+    len: *mut usize,
+) -> *mut u8 {
+    let (claimed, n) = match unsafe { &mut *buf }.claim(size) {
+        Some(claimed) => (claimed.as_mut_ptr(), claimed.len()),
+        None => (ptr::null_mut(), 0),
+    };
+    unsafe { *len = n };
+    claimed
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_commit(
+    buf: *mut MirroredBuffer<'static>,
+    size: usize,
+) -> usize {
+    unsafe { &mut *buf }.commit(size)
+}
+
+// Returns where the committed region starts and stores its length in `*len`,
+// or returns NULL if nothing is committed.
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_committed(
+    buf: *const MirroredBuffer<'static>,
+    len: *mut usize,
+) -> *const u8 {
+    let (committed, n) = match unsafe { &*buf }.committed() {
+        Some(committed) => (committed.as_ptr(), committed.len()),
+        None => (ptr::null(), 0),
+    };
+    unsafe { *len = n };
+    committed
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mirrored_buffer_consume(
+    buf: *mut MirroredBuffer<'static>,
+    size: usize,
+) -> usize {
+    unsafe { &mut *buf }.consume(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    #[test]
+    fn ffi_round_trip() {
+        unsafe {
+            let mut buf = ptr::null_mut();
+            assert!(mirrored_buffer_new(0, &mut buf) == libc::EINVAL);
+            // Far more than the address space holds.
+            assert!(mirrored_buffer_new(1 << 46, &mut buf) == libc::ENOMEM);
+            assert!(mirrored_buffer_new(1, &mut buf) == 0);
+            let size = mirrored_buffer_size(buf);
+            assert!(mirrored_buffer_free(buf) == size);
+
+            let mut len = 0;
+            let claimed = mirrored_buffer_claim(buf, size + 1, &mut len);
+            assert!(len == size);
+            slice::from_raw_parts_mut(claimed, 3).copy_from_slice(b"abc");
+            assert!(mirrored_buffer_commit(buf, 3) == 3);
+            assert!(mirrored_buffer_used(buf) == 3);
+
+            let committed = mirrored_buffer_committed(buf, &mut len);
+            assert!(slice::from_raw_parts(committed, len) == b"abc");
+            assert!(mirrored_buffer_consume(buf, 10) == 3);
+            assert!(mirrored_buffer_committed(buf, &mut len).is_null() && len == 0);
+
+            mirrored_buffer_destroy(buf);
+            mirrored_buffer_destroy(ptr::null_mut());
+        }
+    }
+}
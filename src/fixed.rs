@@ -0,0 +1,132 @@
+use crate::{Error, MirroredBuffer};
+use std::{cmp, sync::atomic::Ordering};
+
+// The smallest page size of the systems the crate runs on.
+const MIN_PAGE_SIZE: usize = 4096;
+
+// A buffer of exactly `N` bytes, a power of two and a multiple of the page
+// size, checked when building rather than when creating it, e.g.
+// `FixedBuffer::<{ 1 << 20 }>::new` for a MiB. The size and the mask being
+// constants, the compiler folds the index arithmetic of claims, commits and
+// consumes into them.
+//
+// Whether `N` is a multiple of the page size of the system is only known
+// once running: on systems with pages larger than 4KiB, it may not be, and
+// `new` fails then. Like a compact buffer, it clamps when full, and has none
+// of the options of `MirroredBuffer`.
+pub struct FixedBuffer<'a, const N: usize> {
+    buf: MirroredBuffer<'a>,
+}
+
+impl<'a, const N: usize> FixedBuffer<'a, N> {
+    const VALID: () = assert!(
+        N.is_power_of_two() && N.is_multiple_of(MIN_PAGE_SIZE),
+        "the size must be a power of two and a multiple of 4KiB"
+    );
+    const MASK: usize = N - 1;
+
+    // Creates the buffer like `MirroredBuffer::new` does, failing with
+    // InvalidSize if `N` is not a multiple of the page size.
+    pub fn new(
+        name_suffix: Option<&str>,
+        initial_value: Option<u8>,
+    ) -> Result<FixedBuffer<'a, N>, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+        let buf = MirroredBuffer::new(N, name_suffix, initial_value)?;
+        if buf.size() != N {
+            return Err(Error::invalid_size(N));
+        }
+        Ok(FixedBuffer { buf })
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    pub const fn size(&self) -> usize {
+        N
+    }
+
+    pub fn used(&self) -> usize {
+        self.buf.size_used
+    }
+
+    pub fn free(&self) -> usize {
+        N - self.buf.size_used
+    }
+
+    pub fn claim(&mut self, size: usize) -> Option<&mut [u8]> {
+        let size = cmp::min(size, self.free());
+        if size == 0 {
+            return None;
+        }
+        let tail = self.buf.tail;
+        Some(&mut self.buf.slice[tail..tail + size])
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        let size = cmp::min(size, self.free());
+        self.buf.size_used += size;
+        self.buf.tail = (self.buf.tail + size) & Self::MASK;
+        self.buf
+            .usage
+            .used
+            .store(self.buf.size_used, Ordering::Relaxed);
+        size
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let Some(claimed) = self.claim(data.len()) else {
+            return 0;
+        };
+        let size = claimed.len();
+        claimed.copy_from_slice(&data[..size]);
+        self.commit(size)
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        if self.buf.size_used == 0 {
+            return None;
+        }
+        let head = self.buf.head;
+        Some(&self.buf.slice[head..head + self.buf.size_used])
+    }
+
+    pub fn consume(&mut self, size: usize) -> usize {
+        let size = cmp::min(size, self.used());
+        self.buf.size_used -= size;
+        self.buf.head = (self.buf.head + size) & Self::MASK;
+        self.buf
+            .usage
+            .used
+            .store(self.buf.size_used, Ordering::Relaxed);
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, FixedBuffer};
+
+    #[test]
+    fn fixed_buffer_wraps_at_its_size() {
+        const N: usize = 1 << 16;
+        let mut buf = FixedBuffer::<N>::new(Some(&next_buffer_index()), None).unwrap();
+        assert!(buf.size() == N && buf.buffer().size() == N);
+
+        assert!(buf.push(&vec![1; N - 3]) == N - 3);
+        assert!(buf.consume(N) == N - 3);
+        assert!(buf.committed().is_none());
+        assert!(buf.push(b"abcdef") == 6);
+        assert!(buf.committed().unwrap() == b"abcdef");
+        assert!(buf.claim(N).unwrap().len() == N - 6);
+        assert!(buf.commit(N) == N - 6 && buf.free() == 0);
+        assert!(buf.claim(1).is_none() && buf.push(b"g") == 0);
+        assert!(buf.consume(4) == 4 && buf.committed().unwrap()[..2] == *b"ef");
+    }
+}
@@ -0,0 +1,142 @@
+use crate::MirroredBuffer;
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+// Decides when the committed region of a buffer is written out to `w`,
+// trading latency for fewer, larger writes: it flushes once at least
+// `min_len` bytes are committed, or once the oldest byte not flushed yet
+// waited for `max_delay`, whichever comes first.
+//
+// The flusher does not own the buffer; the caller commits to it, then calls
+// `poll` whenever it may be time to flush, e.g. after each commit and when
+// `deadline` passes.
+pub struct Flusher<W: Write> {
+    w: W,
+    min_len: usize,
+    max_delay: Duration,
+    // When `poll` first saw bytes that are still not flushed.
+    pending_since: Option<Instant>,
+}
+
+impl<W: Write> Flusher<W> {
+    // By default, every poll flushes whatever is committed.
+    pub fn new(w: W) -> Flusher<W> {
+        Flusher {
+            w,
+            min_len: 1,
+            max_delay: Duration::ZERO,
+            pending_since: None,
+        }
+    }
+
+    pub fn with_min_len(mut self, min_len: usize) -> Flusher<W> {
+        self.min_len = min_len;
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Flusher<W> {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.w
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    // When the bytes waiting in `buf` are due, if there are any. Event loops
+    // can sleep until then.
+    pub fn deadline(&self, buf: &MirroredBuffer) -> Option<Instant> {
+        if buf.used() == 0 {
+            return None;
+        }
+        match self.pending_since {
+            Some(since) => Some(since + self.max_delay),
+            None => Some(Instant::now() + self.max_delay),
+        }
+    }
+
+    // Flushes `buf` if enough is committed or it waited long enough, and
+    // returns how much was flushed.
+    pub fn poll(&mut self, buf: &mut MirroredBuffer) -> io::Result<usize> {
+        let used = buf.used();
+        if used == 0 {
+            self.pending_since = None;
+            return Ok(0);
+        }
+
+        let now = Instant::now();
+        let since = *self.pending_since.get_or_insert(now);
+        if used < self.min_len && now < since + self.max_delay {
+            return Ok(0);
+        }
+        self.flush(buf)
+    }
+
+    // Flushes `buf` now, whatever the policy, as `flush_to` does, then
+    // flushes `w` once it took everything.
+    pub fn flush(&mut self, buf: &mut MirroredBuffer) -> io::Result<usize> {
+        let written = buf.flush_to(&mut self.w)?;
+        if buf.used() == 0 {
+            self.pending_since = None;
+            self.w.flush()?;
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, Flusher, MirroredBuffer};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn flusher_by_size_and_by_delay() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut flusher = Flusher::new(Vec::new())
+            .with_min_len(100)
+            .with_max_delay(Duration::from_millis(20));
+        assert!(flusher.poll(&mut buf).unwrap() == 0);
+        assert!(flusher.deadline(&buf).is_none());
+
+        // Too little, too soon.
+        buf.claim(60).unwrap().fill(1);
+        buf.commit(60);
+        assert!(flusher.poll(&mut buf).unwrap() == 0);
+        let deadline = flusher.deadline(&buf).unwrap();
+
+        // Enough.
+        buf.claim(40).unwrap().fill(2);
+        buf.commit(40);
+        assert!(flusher.poll(&mut buf).unwrap() == 100);
+        assert!(flusher.get_ref().len() == 100);
+        assert!(flusher.deadline(&buf).is_none());
+
+        // Late enough.
+        buf.claim(10).unwrap().fill(3);
+        buf.commit(10);
+        assert!(flusher.poll(&mut buf).unwrap() == 0);
+        assert!(flusher.deadline(&buf).unwrap() > deadline);
+        thread::sleep(Duration::from_millis(25));
+        assert!(flusher.poll(&mut buf).unwrap() == 10);
+
+        // Forced.
+        buf.claim(1).unwrap().fill(4);
+        buf.commit(1);
+        assert!(flusher.flush(&mut buf).unwrap() == 1);
+        let out = flusher.into_inner();
+        assert!(out.len() == 111);
+        assert!(out[..60].iter().all(|&x| x == 1));
+        assert!(out[100..110].iter().all(|&x| x == 3));
+        assert!(out[110] == 4);
+    }
+}
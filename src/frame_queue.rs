@@ -0,0 +1,266 @@
+use crate::{
+    mpsc::MpscProducer,
+    split::{CachePadded, Shared},
+    Error, MirroredBuffer,
+};
+use std::{
+    hint,
+    ops::Deref,
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// Frames are a u32 header followed by the payload, padded so that the next
+// header is aligned. The header holds the payload length and, once a worker
+// is done with the frame, the DONE bit.
+const FRAME_HEADER_LEN: usize = 4;
+const DONE: u32 = 1 << 31;
+
+// The most a frame can carry, bounded by the header.
+pub const FRAME_QUEUE_MAX_LEN: usize = (DONE - 1) as usize;
+
+fn frame_size(len: usize) -> usize {
+    (FRAME_HEADER_LEN + len).next_multiple_of(FRAME_HEADER_LEN)
+}
+
+// A job queue of frames: any number of threads push frames, and each frame
+// is popped by exactly one of any number of workers, see `into_frame_queue`.
+//
+// Pushing goes through an MPSC producer, so frames are published in the
+// order they were reserved. Workers take published frames by moving the
+// `taken` index over them with a compare and swap, and mark them done in
+// their header when they drop them. Frames may be done out of order; the
+// space of the done ones at the head is handed back to producers by whoever
+// holds `reclaiming`, which is only ever tried and never waited on.
+#[derive(Clone)]
+pub struct FrameQueue<'a> {
+    producer: MpscProducer<'a>,
+    workers: Arc<Workers>,
+}
+
+struct Workers {
+    taken: CachePadded<AtomicUsize>,
+    reclaiming: AtomicBool,
+}
+
+// A frame popped off a queue. Its payload can be read until it is dropped,
+// which marks the frame done.
+pub struct Job<'q, 'a> {
+    queue: &'q FrameQueue<'a>,
+    start: usize,
+    len: usize,
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Turns the buffer into a frame queue, which can be cloned and shared by
+    // producer and worker threads alike. Whatever is committed is dropped:
+    // the queue lays its own frames out. Fails with ReadOnlyMirror if the
+    // buffer has a read-only mirror, like `split_mpsc`.
+    pub fn into_frame_queue(mut self) -> Result<FrameQueue<'a>, Error> {
+        // Frames start from an aligned offset.
+        self.head = 0;
+        self.tail = 0;
+        self.size_used = 0;
+
+        let (producer, _) = self.split_mpsc()?;
+        Ok(FrameQueue {
+            producer,
+            workers: Arc::new(Workers {
+                taken: CachePadded(AtomicUsize::new(0)),
+                reclaiming: AtomicBool::new(false),
+            }),
+        })
+    }
+}
+
+impl<'a> FrameQueue<'a> {
+    pub fn name(&self) -> &str {
+        self.producer.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.producer.size()
+    }
+
+    fn shared(&self) -> &Shared<'a> {
+        &self.producer.shared
+    }
+
+    fn header(&self, index: usize) -> &AtomicU32 {
+        let offset = index & self.shared().buf.size_mask;
+        unsafe { AtomicU32::from_ptr(self.producer.ptr.add(offset) as *mut u32) }
+    }
+
+    // Pushes `payload` as one frame. Fails with NoSpace if the queue does not
+    // have room for it now; a frame that would never fit fails the same way.
+    pub fn push(&self, payload: &[u8]) -> Result<(), Error> {
+        let size = frame_size(payload.len());
+        if payload.len() > FRAME_QUEUE_MAX_LEN {
+            return Err(Error::no_space(size));
+        }
+        let Some(mut reservation) = self.producer.reserve(size) else {
+            return Err(Error::no_space(size));
+        };
+        reservation[..FRAME_HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_ne_bytes());
+        reservation[FRAME_HEADER_LEN..FRAME_HEADER_LEN + payload.len()].copy_from_slice(payload);
+        reservation.commit();
+        Ok(())
+    }
+
+    // Takes the oldest frame no other worker took, if any was pushed.
+    pub fn pop(&self) -> Option<Job<'_, 'a>> {
+        let taken = &self.workers.taken;
+        let mut start = taken.load(Ordering::Acquire);
+        loop {
+            if start == self.shared().tail.load(Ordering::Acquire) {
+                return None;
+            }
+            let len = (self.header(start).load(Ordering::Relaxed) & !DONE) as usize;
+            match taken.compare_exchange_weak(
+                start,
+                start.wrapping_add(frame_size(len)),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return Some(Job {
+                        queue: self,
+                        start,
+                        len,
+                    })
+                }
+                Err(current) => start = current,
+            }
+        }
+    }
+
+    // Hands the space of the done frames at the head back to producers.
+    fn reclaim(&self) {
+        let shared = self.shared();
+        let workers = &self.workers;
+        loop {
+            if workers
+                .reclaiming
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                // Whoever reclaims will check again once done.
+                return;
+            }
+
+            let mut head = shared.head.load(Ordering::Relaxed);
+            let taken = workers.taken.load(Ordering::Acquire);
+            while head != taken {
+                let header = self.header(head).load(Ordering::Acquire);
+                if header & DONE == 0 {
+                    break;
+                }
+                head = head.wrapping_add(frame_size((header & !DONE) as usize));
+            }
+            shared.head.store(head, Ordering::Release);
+            workers.reclaiming.store(false, Ordering::Release);
+
+            // A frame at the head could have been done after it was checked
+            // but before `reclaiming` was released, by a worker that then
+            // found it held.
+            let taken = workers.taken.load(Ordering::Acquire);
+            if head == taken || self.header(head).load(Ordering::Acquire) & DONE == 0 {
+                return;
+            }
+            hint::spin_loop();
+        }
+    }
+}
+
+impl Deref for Job<'_, '_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let offset = (self.start + FRAME_HEADER_LEN) & self.queue.shared().buf.size_mask;
+        unsafe { slice::from_raw_parts(self.queue.producer.ptr.add(offset), self.len) }
+    }
+}
+
+impl Drop for Job<'_, '_> {
+    fn drop(&mut self) {
+        self.queue
+            .header(self.start)
+            .fetch_or(DONE, Ordering::Release);
+        self.queue.reclaim();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, ErrorKind, MirroredBuffer};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn frame_queue_each_frame_popped_once() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let queue = buf.into_frame_queue().unwrap();
+        assert!(queue.pop().is_none());
+        let err = queue.push(&vec![0; queue.size()]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let err = buf
+            .with_read_only_mirror()
+            .unwrap()
+            .into_frame_queue()
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind(), ErrorKind::ReadOnlyMirror));
+
+        let producers = 3;
+        let workers = 4;
+        let frames = 3000;
+        let popped: Vec<AtomicUsize> = (0..producers * frames)
+            .map(|_| AtomicUsize::new(0))
+            .collect();
+        let done = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for id in 0..producers {
+                let queue = queue.clone();
+                s.spawn(move || {
+                    for i in 0..frames {
+                        // [job number][filler]
+                        let job = (id * frames + i) as u32;
+                        let mut payload = job.to_be_bytes().to_vec();
+                        payload.resize(4 + (i * 13) % 300, job as u8);
+                        while queue.push(&payload).is_err() {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+
+            for _ in 0..workers {
+                let queue = queue.clone();
+                let (popped, done) = (&popped, &done);
+                s.spawn(move || {
+                    while done.load(Ordering::Relaxed) < producers * frames {
+                        let Some(payload) = queue.pop() else {
+                            thread::yield_now();
+                            continue;
+                        };
+                        let job = u32::from_be_bytes(payload[..4].try_into().unwrap()) as usize;
+                        assert!(payload.len() == 4 + (job % frames * 13) % 300);
+                        assert!(payload[4..].iter().all(|&x| x == job as u8));
+                        popped[job].fetch_add(1, Ordering::Relaxed);
+                        done.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert!(popped.iter().all(|n| n.load(Ordering::Relaxed) == 1));
+        assert!(queue.pop().is_none());
+    }
+}
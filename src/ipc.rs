@@ -0,0 +1,1177 @@
+use crate::{
+    fd::SEND_FLAGS,
+    map_mirrored,
+    notify::{notify_shared, wait_until_shared},
+    split::CachePadded,
+    util::{get_page_size, round_up_to_page_size},
+    Error, MirroredBuffer,
+};
+use std::{
+    cmp,
+    ffi::CString,
+    fmt, io, mem,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    ptr, slice,
+    str::FromStr,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+// The first two pages of a shared segment, followed by the ring itself. They
+// hold what the two processes share besides the data: its size, and the head
+// and tail as wrapping byte counts. The layout is fixed, with 64 bit fields
+// whatever the pointer width.
+//
+// The segment starts with a magic value and the version of this layout,
+// checked on attach so that a peer built against another layout, or a name
+// clashing with some other segment, fails there instead of corrupting memory.
+// The creator stores the magic value last, so a segment not set up yet does
+// not pass either.
+//
+// The generation counts the producer's claims and commits: it is odd from a
+// claim to the commit ending it, so a producer dying in between leaves it
+// odd, see `SharedRing::is_torn`.
+//
+// Either side may sleep until the other commits or consumes, on futexes in
+// the segment, see `notify::wait_until_shared`. Each wait needs a word only
+// the notifier writes and one only the waiter does, so these are split
+// between the pages as well.
+//
+// Each side may also leave a heartbeat, the CLOCK_MONOTONIC time of its last
+// activity in nanoseconds, or 0 if it never beat, see
+// `SharedRing::with_heartbeat`.
+//
+// What the producer writes is on the first page and what the consumer writes
+// on the second, so a reader can map the first one read-only, see
+// `MirroredBuffer::attach_reader`.
+#[repr(C)]
+struct Control {
+    magic: AtomicU64,
+    layout: u32,
+    _reserved: u32,
+    size: u64,
+    generation: AtomicU64,
+    data_seq: AtomicU32,
+    space_waiters: AtomicU32,
+    producer_beat: AtomicU64,
+    tail: CachePadded<AtomicU64>,
+}
+
+const MAGIC: u64 = u64::from_le_bytes(*b"mirrorbf");
+const LAYOUT_VERSION: u32 = 4;
+
+// Which side of a shared ring a process is, for heartbeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Producer,
+    Consumer,
+}
+
+#[repr(C)]
+struct ConsumerControl {
+    space_seq: AtomicU32,
+    data_waiters: AtomicU32,
+    consumer_beat: AtomicU64,
+    head: CachePadded<AtomicU64>,
+}
+
+// The version of `Handle`, bumped whenever what its fields mean changes, so
+// peers built against different versions tell instead of misreading it.
+pub const HANDLE_VERSION: u32 = 1;
+
+// What a peer needs to attach to a ring, see `SharedRing::handle`, for
+// orchestration code to pass around in config files, environment variables
+// or RPCs. It serializes with serde under the `serde` feature, and to and
+// from a string of the form `version:size:flags:name` otherwise.
+//
+// No flags are defined yet; a handle with flags this version does not know
+// of is rejected, like one of another version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Handle {
+    pub version: u32,
+    pub name: String,
+    pub size: usize,
+    pub flags: u32,
+}
+
+impl Handle {
+    // Attaches to the ring, checking it is the one the handle was made for.
+    pub fn attach<'a>(&self) -> Result<SharedRing<'a>, Error> {
+        if self.version != HANDLE_VERSION {
+            return Err(Error::incompatible("unknown handle version"));
+        }
+        if self.flags != 0 {
+            return Err(Error::incompatible("unknown handle flags"));
+        }
+        let ring = MirroredBuffer::attach(&self.name)?;
+        if ring.size() != self.size {
+            return Err(Error::incompatible("the ring's size is not the handle's"));
+        }
+        Ok(ring)
+    }
+}
+
+impl fmt::Display for Handle {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{}:{}:{}:{}",
+            self.version, self.size, self.flags, self.name
+        )
+    }
+}
+
+impl FromStr for Handle {
+    type Err = Error;
+
+    // The name goes last, as it may contain colons.
+    fn from_str(s: &str) -> Result<Handle, Error> {
+        let invalid = || {
+            Error::io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a handle of the form version:size:flags:name",
+            ))
+        };
+        let mut parts = s.splitn(4, ':');
+        let mut next = || parts.next().ok_or_else(invalid);
+        let version = next()?.parse().map_err(|_| invalid())?;
+        let size = next()?.parse().map_err(|_| invalid())?;
+        let flags = next()?.parse().map_err(|_| invalid())?;
+        let name = next()?.to_string();
+        Ok(Handle {
+            version,
+            name,
+            size,
+            flags,
+        })
+    }
+}
+
+// A ring in a named shared memory segment, which another process can attach
+// to, see `MirroredBuffer::create_shared`.
+//
+// One process produces, claiming and committing, and one consumes, reading
+// the committed region and consuming it; nothing stops a process from doing
+// both, but two producers or two consumers race. Like the split halves, the
+// producer stores the tail with release ordering and the consumer loads it
+// with acquire ordering, and the other way around for the head.
+pub struct SharedRing<'a> {
+    name: String,
+    // None for rings with no name in the filesystem.
+    shm_name: Option<CString>,
+    fd: libc::c_int,
+    owner: bool,
+
+    control: *const Control,
+    control_len: usize,
+
+    size_total: usize,
+    size_mask: usize,
+
+    // The side this process beats for, if any.
+    role: Option<Role>,
+
+    slice: &'a mut [u8],
+}
+
+// The processes sharing the ring only ever touch the region their role owns.
+unsafe impl Send for SharedRing<'_> {}
+
+fn shm_name(name: &str) -> Result<CString, Error> {
+    CString::new(format!("/mirrored-buffer-{name}")).map_err(|_| {
+        Error::io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the name contains a 0-byte",
+        ))
+    })
+}
+
+// The CLOCK_MONOTONIC time in nanoseconds, which all processes on the machine
+// share.
+fn monotonic_now() -> u64 {
+    let mut now: libc::timespec = unsafe { mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+fn control_len() -> Result<usize, Error> {
+    Ok(2 * get_page_size()?)
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Creates a ring of at least `size` bytes in a shared memory segment
+    // named after `name`, for another process to `attach` to. The segment
+    // goes away once the creator drops the ring; processes attached by then
+    // keep their mapping.
+    pub fn create_shared(name: &str, size: usize) -> Result<SharedRing<'a>, Error> {
+        let size_total = shared_size(size)?;
+        let shm_name = shm_name(name)?;
+        let fd = unsafe {
+            libc::shm_open(
+                shm_name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                libc::S_IRUSR | libc::S_IWUSR,
+            )
+        };
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        SharedRing::create(name.to_string(), Some(shm_name), fd, size_total)
+    }
+
+    // Creates a ring of at least `size` bytes in an anonymous memory file,
+    // which has no name anyone could attach by: other processes get to it
+    // through `SharedRing::send_fd` only.
+    //
+    // The file is sealed once sized, so no process it is sent to can grow
+    // or shrink it, which would make its peers fault, nor change the seals,
+    // see `SharedRing::seals`.
+    #[cfg(target_os = "linux")]
+    pub fn create_anonymous(size: usize) -> Result<SharedRing<'a>, Error> {
+        let size_total = shared_size(size)?;
+        let fd = unsafe {
+            libc::memfd_create(
+                c"mirrored-buffer".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        let ring = SharedRing::create(String::new(), None, fd, size_total)?;
+        let seals = libc::F_SEAL_GROW | libc::F_SEAL_SHRINK | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(ring)
+    }
+
+    // Attaches to the ring another process created with `create_shared`.
+    pub fn attach(name: &str) -> Result<SharedRing<'a>, Error> {
+        SharedRing::open(name, false)
+    }
+
+    // Attaches to the ring whose fd another process sent over the Unix
+    // socket `socket` with `SharedRing::send_fd`. The ring has no name then,
+    // even if it was created with one.
+    pub fn receive_shared<S: AsRawFd + ?Sized>(socket: &S) -> Result<SharedRing<'a>, Error> {
+        let fd = receive_fd(socket.as_raw_fd())?;
+        SharedRing::attach_fd(String::new(), None, fd, false)
+    }
+
+    // Attaches to the ring created with `create_shared` as its consumer, with
+    // the data and the producer's index mapped read-only, so a reader that
+    // goes astray cannot corrupt them.
+    pub fn attach_reader(name: &str) -> Result<SharedReader<'a>, Error> {
+        let ring = SharedRing::open(name, true)?;
+        Ok(SharedReader { ring })
+    }
+
+    // Like `attach_reader`, for a ring received over a Unix socket as
+    // `receive_shared` does.
+    pub fn receive_reader<S: AsRawFd + ?Sized>(socket: &S) -> Result<SharedReader<'a>, Error> {
+        let fd = receive_fd(socket.as_raw_fd())?;
+        let ring = SharedRing::attach_fd(String::new(), None, fd, true)?;
+        Ok(SharedReader { ring })
+    }
+}
+
+fn shared_size(size: usize) -> Result<usize, Error> {
+    if size == 0 {
+        return Err(Error::invalid_size(size));
+    }
+    let size_total = round_up_to_page_size(size)?;
+    if !size_total.is_power_of_two() {
+        return Err(Error::invalid_size(size_total));
+    }
+    Ok(size_total)
+}
+
+// Sends `fd` with SCM_RIGHTS, along with a byte as stream sockets do not
+// carry ancillary data on its own.
+fn send_fd(socket: RawFd, fd: RawFd) -> io::Result<()> {
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    // Room for one fd, aligned as cmsghdr is.
+    let mut space = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = space.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) } as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::c_int>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::c_int, fd);
+    }
+
+    loop {
+        let ret = unsafe { libc::sendmsg(socket, &msg, SEND_FLAGS) };
+        if ret >= 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+// Receives the fd `send_fd` sent, close-on-exec where the platform allows.
+fn receive_fd(socket: RawFd) -> io::Result<RawFd> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const RECV_FLAGS: libc::c_int = 0;
+
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    let mut space = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = space.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&space) as _;
+
+    loop {
+        let ret = unsafe { libc::recvmsg(socket, &mut msg, RECV_FLAGS) };
+        if ret > 0 {
+            break;
+        }
+        if ret == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+            || msg.msg_flags & libc::MSG_CTRUNC != 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no fd came with the message",
+            ));
+        }
+        Ok(ptr::read_unaligned(
+            libc::CMSG_DATA(cmsg) as *const libc::c_int
+        ))
+    }
+}
+
+impl<'a> SharedRing<'a> {
+    // Takes `fd`, sizes it and sets up the control block; dropping the ring
+    // on failure unlinks the segment.
+    fn create(
+        name: String,
+        shm_name: Option<CString>,
+        fd: libc::c_int,
+        size_total: usize,
+    ) -> Result<SharedRing<'a>, Error> {
+        let mut ring = SharedRing {
+            name,
+            shm_name,
+            fd,
+            owner: true,
+            control: ptr::null(),
+            control_len: control_len()?,
+            size_total,
+            size_mask: size_total - 1,
+            role: None,
+            slice: &mut [],
+        };
+        let len = ring.control_len + size_total;
+        if unsafe { libc::ftruncate(fd, len as libc::off_t) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        ring.map_control()?;
+        unsafe {
+            ptr::write(
+                ring.control as *mut Control,
+                Control {
+                    magic: AtomicU64::new(0),
+                    layout: LAYOUT_VERSION,
+                    _reserved: 0,
+                    size: size_total as u64,
+                    generation: AtomicU64::new(0),
+                    data_seq: AtomicU32::new(0),
+                    space_waiters: AtomicU32::new(0),
+                    producer_beat: AtomicU64::new(0),
+                    tail: CachePadded(AtomicU64::new(0)),
+                },
+            );
+            ptr::write(
+                ring.control.byte_add(ring.control_len / 2) as *mut ConsumerControl,
+                ConsumerControl {
+                    space_seq: AtomicU32::new(0),
+                    data_waiters: AtomicU32::new(0),
+                    consumer_beat: AtomicU64::new(0),
+                    head: CachePadded(AtomicU64::new(0)),
+                },
+            );
+        }
+        ring.control().magic.store(MAGIC, Ordering::Release);
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        ring.slice = map_mirrored(fd, size_total, ring.control_len, prot, 0, 0, 0)?;
+        Ok(ring)
+    }
+
+    // Opens the segment named after `name` and attaches to its ring.
+    pub(crate) fn open(name: &str, read_only: bool) -> Result<SharedRing<'a>, Error> {
+        let shm_name = shm_name(name)?;
+        let fd = unsafe { libc::shm_open(shm_name.as_ptr(), libc::O_RDWR, 0) };
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+        SharedRing::attach_fd(name.to_string(), Some(shm_name), fd, read_only)
+    }
+
+    // Takes `fd` and maps the ring it holds, as its control block says; for
+    // reading only if `read_only`, but for the consumer's page.
+    fn attach_fd(
+        name: String,
+        shm_name: Option<CString>,
+        fd: libc::c_int,
+        read_only: bool,
+    ) -> Result<SharedRing<'a>, Error> {
+        let mut ring = SharedRing {
+            name,
+            shm_name,
+            fd,
+            owner: false,
+            control: ptr::null(),
+            control_len: control_len()?,
+            size_total: 0,
+            size_mask: 0,
+            role: None,
+            slice: &mut [],
+        };
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        if (stat.st_size as usize) < ring.control_len {
+            return Err(Error::incompatible("the segment is too small"));
+        }
+        ring.map_control()?;
+        let mut prot = libc::PROT_READ | libc::PROT_WRITE;
+        if read_only {
+            prot = libc::PROT_READ;
+            let ret = unsafe {
+                libc::mprotect(
+                    ring.control as *mut libc::c_void,
+                    ring.control_len / 2,
+                    prot,
+                )
+            };
+            if ret == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        let control = ring.control();
+        if control.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(Error::incompatible("not a mirrored buffer segment"));
+        }
+        if control.layout != LAYOUT_VERSION {
+            return Err(Error::incompatible("unknown layout version"));
+        }
+        let size_total = control.size as usize;
+        if !size_total.is_power_of_two()
+            || !size_total.is_multiple_of(get_page_size()?)
+            || ring.control_len + size_total > stat.st_size as usize
+        {
+            return Err(Error::incompatible("the declared size does not fit"));
+        }
+        ring.size_total = size_total;
+        ring.size_mask = size_total - 1;
+        ring.slice = map_mirrored(fd, size_total, ring.control_len, prot, 0, 0, 0)?;
+        Ok(ring)
+    }
+
+    fn map_control(&mut self) -> Result<(), Error> {
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                self.control_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.fd,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        self.control = addr as *const Control;
+        Ok(())
+    }
+
+    fn control(&self) -> &Control {
+        unsafe { &*self.control }
+    }
+
+    fn consumer_control(&self) -> &ConsumerControl {
+        unsafe { &*(self.control.byte_add(self.control_len / 2) as *const ConsumerControl) }
+    }
+
+    // Sends the fd of the ring over the Unix socket `socket`, for the process
+    // at the other end to `MirroredBuffer::receive_shared` it.
+    pub fn send_fd<S: AsRawFd + ?Sized>(&self, socket: &S) -> io::Result<()> {
+        send_fd(socket.as_raw_fd(), self.fd)
+    }
+
+    // The name given to `create_shared` or `attach`, empty for rings created
+    // anonymously or received over a socket.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // What another process needs to attach to the ring by name; one with no
+    // name has to be sent with `send_fd` instead.
+    pub fn handle(&self) -> Handle {
+        Handle {
+            version: HANDLE_VERSION,
+            name: self.name.clone(),
+            size: self.size_total,
+            flags: 0,
+        }
+    }
+
+    // The seals of the file backing the ring, F_SEAL_* flags, e.g. for a
+    // process receiving it to check no one can resize it. Segments of rings
+    // created with a name are never sealed against resizing: they have no
+    // seals, or F_SEAL_SEAL alone, depending on the kernel.
+    #[cfg(target_os = "linux")]
+    pub fn seals(&self) -> io::Result<libc::c_int> {
+        let seals = unsafe { libc::fcntl(self.fd, libc::F_GET_SEALS) };
+        if seals == -1 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EINVAL) {
+                return Ok(0);
+            }
+            return Err(err);
+        }
+        Ok(seals)
+    }
+
+    // Whether this process created the ring.
+    pub fn is_owner(&self) -> bool {
+        self.owner
+    }
+
+    pub fn size(&self) -> usize {
+        self.size_total
+    }
+
+    pub(crate) fn head(&self) -> u64 {
+        self.consumer_control().head.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn tail(&self) -> u64 {
+        self.control().tail.load(Ordering::Acquire)
+    }
+
+    // Where the byte at `index`, a wrapping byte count like the head and the
+    // tail, is mapped; the size past it is mapped as well, thanks to the
+    // mirror.
+    pub(crate) fn at(&self, index: u64) -> *const u8 {
+        unsafe { self.slice.as_ptr().add(index as usize & self.size_mask) }
+    }
+
+    // The committed size as seen now. Indices a dead peer left inconsistent
+    // count as a full ring until `recover` resets them.
+    pub fn used(&self) -> usize {
+        cmp::min(self.tail().wrapping_sub(self.head()), self.size() as u64) as usize
+    }
+
+    // The number of claims plus commits the producer made. Every commit
+    // leaves it even.
+    pub fn generation(&self) -> u64 {
+        self.control().generation.load(Ordering::Acquire)
+    }
+
+    // Whether the producer left the ring between a claim and a commit, or
+    // with indices that make no sense. Either the producer is writing right
+    // now, or it died doing so: only once it is known to be gone is the ring
+    // really torn, and to be `recover`ed.
+    pub fn is_torn(&self) -> bool {
+        self.generation() % 2 == 1 || self.tail().wrapping_sub(self.head()) > self.size() as u64
+    }
+
+    // Takes over from a producer that died mid-write, dropping what it
+    // claimed and did not commit: the ring goes back to the last commit. If
+    // even that is inconsistent, whatever was committed is dropped as well.
+    // Returns whether the ring was torn.
+    pub fn recover(&mut self) -> bool {
+        if !self.is_torn() {
+            return false;
+        }
+        let control = self.control();
+        let generation = control.generation.load(Ordering::Relaxed);
+        control
+            .generation
+            .store(generation + generation % 2, Ordering::Release);
+        if self.tail().wrapping_sub(self.head()) > self.size() as u64 {
+            self.consumer_control()
+                .head
+                .store(self.tail(), Ordering::Release);
+        }
+        true
+    }
+
+    // Makes this process beat as `role`: claims and commits, or consumes,
+    // update its heartbeat, so the other side can tell it is alive, see
+    // `is_peer_alive`. A process idle for long, e.g. waiting, should call
+    // `heartbeat` on its own every so often.
+    pub fn with_heartbeat(mut self, role: Role) -> SharedRing<'a> {
+        self.role = Some(role);
+        self.heartbeat();
+        self
+    }
+
+    // Records that this process is alive, if it beats for a role.
+    pub fn heartbeat(&self) {
+        match self.role {
+            Some(Role::Producer) => self
+                .control()
+                .producer_beat
+                .store(monotonic_now(), Ordering::Relaxed),
+            Some(Role::Consumer) => self
+                .consumer_control()
+                .consumer_beat
+                .store(monotonic_now(), Ordering::Relaxed),
+            None => {}
+        }
+    }
+
+    // Whether the other side beat within `timeout`. A side that never beat,
+    // as it did not ask to, does not count as alive.
+    pub fn is_peer_alive(&self, timeout: Duration) -> bool {
+        let beat = match self
+            .role
+            .expect("no role to have a peer, see with_heartbeat")
+        {
+            Role::Producer => self
+                .consumer_control()
+                .consumer_beat
+                .load(Ordering::Relaxed),
+            Role::Consumer => self.control().producer_beat.load(Ordering::Relaxed),
+        };
+        beat != 0 && monotonic_now().saturating_sub(beat) <= timeout.as_nanos() as u64
+    }
+
+    fn beat_as(&self, role: Role) {
+        if self.role == Some(role) {
+            self.heartbeat();
+        }
+    }
+
+    fn set_writing(&self, writing: bool) {
+        let generation = &self.control().generation;
+        let current = generation.load(Ordering::Relaxed);
+        if (current % 2 == 1) != writing {
+            generation.store(current + 1, Ordering::Release);
+        }
+    }
+
+    // The free space as seen now.
+    pub fn free(&self) -> usize {
+        self.size() - self.used()
+    }
+
+    pub fn claim(&mut self, mut size: usize) -> Option<&mut [u8]> {
+        size = cmp::min(size, self.free());
+        if size == 0 {
+            return None;
+        }
+        self.set_writing(true);
+        self.beat_as(Role::Producer);
+        let offset = self.tail() as usize & self.size_mask;
+        Some(&mut self.slice[offset..offset + size])
+    }
+
+    pub fn commit(&mut self, mut size: usize) -> usize {
+        size = cmp::min(size, self.free());
+        let tail = self.tail().wrapping_add(size as u64);
+        self.control().tail.store(tail, Ordering::Release);
+        self.set_writing(false);
+        self.beat_as(Role::Producer);
+        let consumer = self.consumer_control();
+        notify_shared(&self.control().data_seq, &consumer.data_waiters);
+        size
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        let used = self.used();
+        if used == 0 {
+            return None;
+        }
+        let offset = self.head() as usize & self.size_mask;
+        Some(unsafe { slice::from_raw_parts(self.slice.as_ptr().add(offset), used) })
+    }
+
+    pub fn consume(&mut self, mut size: usize) -> usize {
+        size = cmp::min(size, self.used());
+        let head = self.head().wrapping_add(size as u64);
+        self.consumer_control().head.store(head, Ordering::Release);
+        self.beat_as(Role::Consumer);
+        let control = self.control();
+        notify_shared(&self.consumer_control().space_seq, &control.space_waiters);
+        size
+    }
+
+    // Sleeps until at least `size` bytes are committed.
+    pub fn wait_for_data(&self, size: usize) {
+        self.wait_for_data_inner(size, None);
+    }
+
+    // Like `wait_for_data`, but gives up after `timeout`. Returns whether the
+    // data is committed.
+    pub fn wait_for_data_timeout(&self, size: usize, timeout: Duration) -> bool {
+        self.wait_for_data_inner(size, Some(timeout))
+    }
+
+    fn wait_for_data_inner(&self, size: usize, timeout: Option<Duration>) -> bool {
+        assert!(size <= self.size(), "waiting for more than the ring holds");
+        self.wait_for_commit(|| self.used() >= size, timeout)
+    }
+
+    // Sleeps until `done` holds, checking it whenever the producer commits.
+    pub(crate) fn wait_for_commit(
+        &self,
+        done: impl FnMut() -> bool,
+        timeout: Option<Duration>,
+    ) -> bool {
+        wait_until_shared(
+            &self.control().data_seq,
+            &self.consumer_control().data_waiters,
+            done,
+            timeout,
+        )
+    }
+
+    // Sleeps until at least `size` bytes are free.
+    pub fn wait_for_space(&self, size: usize) {
+        self.wait_for_space_inner(size, None);
+    }
+
+    // Like `wait_for_space`, but gives up after `timeout`. Returns whether
+    // the space is free.
+    pub fn wait_for_space_timeout(&self, size: usize, timeout: Duration) -> bool {
+        self.wait_for_space_inner(size, Some(timeout))
+    }
+
+    fn wait_for_space_inner(&self, size: usize, timeout: Option<Duration>) -> bool {
+        assert!(size <= self.size(), "waiting for more than the ring holds");
+        wait_until_shared(
+            &self.consumer_control().space_seq,
+            &self.control().space_waiters,
+            || self.free() >= size,
+            timeout,
+        )
+    }
+}
+
+// The consumer side of a shared ring, mapped so that it can only write its
+// own index, see `MirroredBuffer::attach_reader`.
+pub struct SharedReader<'a> {
+    ring: SharedRing<'a>,
+}
+
+impl SharedReader<'_> {
+    pub fn name(&self) -> &str {
+        self.ring.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.ring.size()
+    }
+
+    pub fn used(&self) -> usize {
+        self.ring.used()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.ring.generation()
+    }
+
+    pub fn is_torn(&self) -> bool {
+        self.ring.is_torn()
+    }
+
+    // Like `SharedRing::with_heartbeat`, as the consumer.
+    pub fn with_heartbeat(mut self) -> Self {
+        self.ring = self.ring.with_heartbeat(Role::Consumer);
+        self
+    }
+
+    pub fn heartbeat(&self) {
+        self.ring.heartbeat()
+    }
+
+    pub fn is_peer_alive(&self, timeout: Duration) -> bool {
+        self.ring.is_peer_alive(timeout)
+    }
+
+    pub fn wait_for_data(&self, size: usize) {
+        self.ring.wait_for_data(size)
+    }
+
+    pub fn wait_for_data_timeout(&self, size: usize, timeout: Duration) -> bool {
+        self.ring.wait_for_data_timeout(size, timeout)
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        self.ring.committed()
+    }
+
+    pub fn consume(&mut self, size: usize) -> usize {
+        self.ring.consume(size)
+    }
+}
+
+// The fd of the segment, which holds the control pages and then the ring.
+impl AsFd for SharedRing<'_> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+impl AsRawFd for SharedRing<'_> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for SharedRing<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.slice.is_empty() {
+                libc::munmap(
+                    self.slice.as_mut_ptr() as *mut libc::c_void,
+                    self.slice.len(),
+                );
+            }
+            if !self.control.is_null() {
+                libc::munmap(self.control as *mut libc::c_void, self.control_len);
+            }
+            libc::close(self.fd);
+            // Someone may have unlinked it already; there is nothing to do
+            // about it either way.
+            if let (true, Some(shm_name)) = (self.owner, &self.shm_name) {
+                libc::shm_unlink(shm_name.as_ptr());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Control, LAYOUT_VERSION, MAGIC};
+    use crate::{util::next_buffer_index, ErrorKind, Handle, MirroredBuffer, Role, HANDLE_VERSION};
+    use std::{
+        cmp, io, os::unix::net::UnixStream, process, ptr, sync::atomic::Ordering, thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn ipc_create_and_attach() {
+        let name = format!("ipc-{}-{}", process::id(), next_buffer_index());
+        assert!(MirroredBuffer::attach(&name).is_err());
+
+        let mut producer = MirroredBuffer::create_shared(&name, 1).unwrap();
+        assert!(MirroredBuffer::create_shared(&name, 1).is_err());
+        let mut consumer = MirroredBuffer::attach(&name).unwrap();
+        assert!(producer.is_owner() && !consumer.is_owner());
+        assert!(consumer.name() == name);
+        assert!(consumer.size() == producer.size());
+        assert!(consumer.slice.as_ptr() != producer.slice.as_ptr());
+
+        let size = producer.size();
+        let total = 10 * size;
+        let writer = thread::spawn(move || {
+            let mut written = 0;
+            while written < total {
+                let Some(claimed) = producer.claim(total - written) else {
+                    thread::yield_now();
+                    continue;
+                };
+                for (x, b) in claimed.iter_mut().enumerate() {
+                    *b = ((written + x) % 251) as u8;
+                }
+                let n = claimed.len();
+                written += producer.commit(n);
+            }
+            producer
+        });
+
+        let mut read = 0;
+        while read < total {
+            let Some(committed) = consumer.committed() else {
+                thread::yield_now();
+                continue;
+            };
+            assert!(committed
+                .iter()
+                .enumerate()
+                .all(|(x, &b)| b == ((read + x) % 251) as u8));
+            let n = committed.len();
+            read += consumer.consume(n);
+        }
+
+        // The segment goes away with its creator.
+        drop(writer.join().unwrap());
+        assert!(MirroredBuffer::attach(&name).is_err());
+        assert!(consumer.used() == 0);
+    }
+
+    #[test]
+    fn ipc_attach_by_handle() {
+        let name = format!("ipc:{}:{}", process::id(), next_buffer_index());
+        let mut producer = MirroredBuffer::create_shared(&name, 1).unwrap();
+        let handle = producer.handle();
+        assert!(handle.version == HANDLE_VERSION && handle.name == name);
+
+        let passed = handle.to_string();
+        let parsed: Handle = passed.parse().unwrap();
+        assert!(parsed == handle);
+        assert!("1:4096".parse::<Handle>().is_err());
+        assert!("x:4096:0:name".parse::<Handle>().is_err());
+
+        let consumer = parsed.attach().unwrap();
+        producer.claim(3).unwrap().copy_from_slice(b"abc");
+        producer.commit(3);
+        assert!(consumer.committed().unwrap() == b"abc");
+
+        let incompatible = |handle: Handle| {
+            let err = handle.attach().err().unwrap();
+            matches!(err.kind(), ErrorKind::Incompatible(_))
+        };
+        assert!(incompatible(Handle {
+            version: HANDLE_VERSION + 1,
+            ..handle.clone()
+        }));
+        assert!(incompatible(Handle {
+            flags: 1,
+            ..handle.clone()
+        }));
+        assert!(incompatible(Handle {
+            size: handle.size * 2,
+            ..handle.clone()
+        }));
+    }
+
+    #[test]
+    fn ipc_attach_validates_the_control_block() {
+        let name = format!("ipc-{}-{}", process::id(), next_buffer_index());
+        let ring = MirroredBuffer::create_shared(&name, 1).unwrap();
+        let control = ring.control as *mut Control;
+        let incompatible = || {
+            MirroredBuffer::attach(&name)
+                .is_err_and(|err| matches!(err.kind(), ErrorKind::Incompatible(_)))
+        };
+        assert!(!incompatible());
+
+        unsafe {
+            (*control).magic.store(0, Ordering::Relaxed);
+            assert!(incompatible());
+            (*control).magic.store(MAGIC, Ordering::Relaxed);
+
+            (*control).layout = LAYOUT_VERSION + 1;
+            assert!(incompatible());
+            (*control).layout = LAYOUT_VERSION;
+
+            (*control).size *= 2;
+            assert!(incompatible());
+            (*control).size -= 1;
+            assert!(incompatible());
+        }
+    }
+
+    #[test]
+    fn ipc_recover_a_torn_ring() {
+        let name = format!("ipc-{}-{}", process::id(), next_buffer_index());
+        let consumer = MirroredBuffer::create_shared(&name, 1).unwrap();
+        let mut producer = MirroredBuffer::attach(&name).unwrap();
+        producer.claim(3).unwrap().copy_from_slice(b"abc");
+        producer.commit(3);
+        assert!(producer.generation() == 2 && !consumer.is_torn());
+
+        // The producer dies mid-write.
+        producer.claim(3).unwrap().copy_from_slice(b"xyz");
+        drop(producer);
+        assert!(consumer.is_torn());
+        assert!(consumer.committed().unwrap() == b"abc");
+
+        let mut producer = MirroredBuffer::attach(&name).unwrap();
+        assert!(producer.recover());
+        assert!(!producer.recover() && !consumer.is_torn());
+        assert!(producer.generation() == 4);
+        producer.claim(3).unwrap().copy_from_slice(b"def");
+        producer.commit(3);
+        assert!(consumer.committed().unwrap() == b"abcdef");
+
+        // Or leaves the indices in pieces.
+        let size = producer.size() as u64;
+        producer.control().tail.store(10 * size, Ordering::Relaxed);
+        assert!(consumer.is_torn() && consumer.used() == consumer.size());
+        assert!(producer.recover());
+        assert!(consumer.used() == 0 && !consumer.is_torn());
+    }
+
+    #[test]
+    fn ipc_wait_for_data_and_space() {
+        let name = format!("ipc-{}-{}", process::id(), next_buffer_index());
+        let mut producer = MirroredBuffer::create_shared(&name, 1).unwrap();
+        let mut reader = MirroredBuffer::attach_reader(&name).unwrap();
+        let size = producer.size();
+        assert!(!reader.wait_for_data_timeout(1, Duration::from_millis(10)));
+        producer.claim(size).unwrap();
+        producer.commit(size);
+        assert!(!producer.wait_for_space_timeout(1, Duration::from_millis(10)));
+        reader.consume(size);
+
+        let total = 20 * size;
+        let writer = thread::spawn(move || {
+            let mut written = 0;
+            while written < total {
+                producer.wait_for_space(100);
+                let n = producer.claim(total - written).unwrap().len();
+                written += producer.commit(n);
+            }
+            producer
+        });
+
+        let mut read = 0;
+        while read < total {
+            reader.wait_for_data(cmp::min(100, total - read));
+            read += reader.consume(reader.used());
+        }
+        let producer = writer.join().unwrap();
+        assert!(producer.used() == 0);
+    }
+
+    #[test]
+    fn ipc_heartbeats() {
+        let name = format!("ipc-{}-{}", process::id(), next_buffer_index());
+        let mut producer = MirroredBuffer::create_shared(&name, 1)
+            .unwrap()
+            .with_heartbeat(Role::Producer);
+        let mut reader = MirroredBuffer::attach_reader(&name).unwrap();
+        let timeout = Duration::from_millis(50);
+        assert!(!producer.is_peer_alive(timeout));
+
+        reader = reader.with_heartbeat();
+        assert!(producer.is_peer_alive(timeout) && reader.is_peer_alive(timeout));
+
+        // Going quiet...
+        thread::sleep(timeout * 2);
+        assert!(!producer.is_peer_alive(timeout) && !reader.is_peer_alive(timeout));
+
+        // ...until the next activity.
+        producer.claim(1).unwrap();
+        producer.commit(1);
+        assert!(reader.is_peer_alive(timeout) && !producer.is_peer_alive(timeout));
+        reader.consume(1);
+        assert!(producer.is_peer_alive(timeout));
+        thread::sleep(timeout * 2);
+        reader.heartbeat();
+        assert!(producer.is_peer_alive(timeout));
+    }
+
+    #[test]
+    fn ipc_attach_reader() {
+        let name = format!("ipc-{}-{}", process::id(), next_buffer_index());
+        let mut producer = MirroredBuffer::create_shared(&name, 1).unwrap();
+        let mut reader = MirroredBuffer::attach_reader(&name).unwrap();
+        assert!(reader.name() == name && reader.size() == producer.size());
+
+        let size = producer.size();
+        producer.claim(size).unwrap().fill(1);
+        producer.commit(size);
+        assert!(reader.used() == size);
+        assert!(reader.consume(10) == 10);
+        assert!(producer.free() == 10);
+        producer.claim(10).unwrap().fill(2);
+        producer.commit(10);
+        let committed = reader.committed().unwrap();
+        assert!(committed[size - 10..].iter().all(|&x| x == 2));
+
+        // Writing to the data, or to the producer's index, faults.
+        for addr in [
+            committed.as_ptr() as *mut u8,
+            reader.ring.control as *mut u8,
+        ] {
+            match unsafe { libc::fork() } {
+                0 => unsafe {
+                    ptr::write_volatile(addr, 0);
+                    libc::_exit(0);
+                },
+                pid => {
+                    let mut status = 0;
+                    assert!(unsafe { libc::waitpid(pid, &mut status, 0) } == pid);
+                    assert!(libc::WIFSIGNALED(status));
+                }
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn ipc_send_fd() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let mut producer = MirroredBuffer::create_anonymous(1).unwrap();
+        assert!(producer.name().is_empty());
+        producer.send_fd(&a).unwrap();
+        let mut consumer = MirroredBuffer::receive_shared(&b).unwrap();
+        assert!(!consumer.is_owner() && consumer.size() == producer.size());
+
+        // Sealed against resizing.
+        let seals = libc::F_SEAL_GROW | libc::F_SEAL_SHRINK | libc::F_SEAL_SEAL;
+        assert!(consumer.seals().unwrap() == seals);
+        let len = (consumer.control_len + consumer.size()) as libc::off_t;
+        for len in [len - 1, len + 1] {
+            assert!(unsafe { libc::ftruncate(consumer.fd, len) } == -1);
+        }
+        assert!(unsafe { libc::fcntl(consumer.fd, libc::F_ADD_SEALS, libc::F_SEAL_WRITE) } == -1);
+
+        producer.claim(3).unwrap().copy_from_slice(b"abc");
+        producer.commit(3);
+        assert!(consumer.committed().unwrap() == b"abc");
+        consumer.consume(3);
+        assert!(producer.used() == 0);
+
+        // The ring outlives its creator, with no name to clean up.
+        drop(producer);
+        assert!(consumer.claim(1).is_some());
+
+        // Named rings can be sent too.
+        let name = format!("ipc-{}-{}", process::id(), next_buffer_index());
+        let named = MirroredBuffer::create_shared(&name, 1).unwrap();
+        named.send_fd(&b).unwrap();
+        let received = MirroredBuffer::receive_shared(&a).unwrap();
+        assert!(received.name().is_empty() && received.size() == named.size());
+        assert!(received.seals().unwrap() & libc::F_SEAL_GROW == 0);
+
+        // A message with no fd is not a ring.
+        io::Write::write_all(&mut &a, b"x").unwrap();
+        assert!(MirroredBuffer::receive_shared(&b).is_err());
+    }
+}
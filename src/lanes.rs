@@ -0,0 +1,187 @@
+use crate::MirroredBuffer;
+use std::{
+    cmp,
+    collections::VecDeque,
+    io::{self, Write},
+};
+
+// Two buffers feeding one writer, where frames committed to the high lane
+// overtake those committed to the low one, e.g. for control frames that must
+// not wait behind bulk data.
+//
+// Each commit is one whole frame. Frames never interleave: once a low frame
+// started going out, it finishes before anything else does, so the low lane
+// keeps the lengths of its frames to know where it may yield. The high lane
+// is always drained whole, so it needs no such bookkeeping.
+pub struct PriorityLanes<'a> {
+    high: MirroredBuffer<'a>,
+    low: MirroredBuffer<'a>,
+    low_frames: VecDeque<usize>,
+    // What is left to write of the low frame going out.
+    low_partial: usize,
+}
+
+impl<'a> PriorityLanes<'a> {
+    // Whatever the buffers have committed is dropped, as its frames are
+    // unknown.
+    pub fn new(mut high: MirroredBuffer<'a>, mut low: MirroredBuffer<'a>) -> PriorityLanes<'a> {
+        high.consume(high.used());
+        low.consume(low.used());
+        PriorityLanes {
+            high,
+            low,
+            low_frames: VecDeque::new(),
+            low_partial: 0,
+        }
+    }
+
+    pub fn high(&self) -> &MirroredBuffer<'a> {
+        &self.high
+    }
+
+    pub fn low(&self) -> &MirroredBuffer<'a> {
+        &self.low
+    }
+
+    pub fn into_inner(self) -> (MirroredBuffer<'a>, MirroredBuffer<'a>) {
+        (self.high, self.low)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high.used() == 0 && self.low.used() == 0
+    }
+
+    pub fn claim_high(&mut self, size: usize) -> Option<&mut [u8]> {
+        self.high.claim(size)
+    }
+
+    // Commits a frame to the high lane, or nothing if it does not fit.
+    pub fn commit_high(&mut self, size: usize) -> usize {
+        if size > self.high.free() {
+            return 0;
+        }
+        self.high.commit(size)
+    }
+
+    pub fn claim_low(&mut self, size: usize) -> Option<&mut [u8]> {
+        self.low.claim(size)
+    }
+
+    // Commits a frame to the low lane, or nothing if it does not fit.
+    pub fn commit_low(&mut self, size: usize) -> usize {
+        if size == 0 || size > self.low.free() {
+            return 0;
+        }
+        self.low_frames.push_back(size);
+        self.low.commit(size)
+    }
+
+    // Writes to `w` until both lanes are empty, the high lane first and the
+    // low one in between whole frames, and returns how much was written.
+    // Errors are handled as `MirroredBuffer::flush_to` does.
+    pub fn drain_to<W: Write + ?Sized>(&mut self, w: &mut W) -> io::Result<usize> {
+        let mut written = 0;
+        loop {
+            let (buf, limit) = if self.low_partial > 0 {
+                (&mut self.low, self.low_partial)
+            } else if self.high.used() > 0 {
+                (&mut self.high, usize::MAX)
+            } else if let Some(frame) = self.low_frames.pop_front() {
+                self.low_partial = frame;
+                (&mut self.low, frame)
+            } else {
+                return Ok(written);
+            };
+
+            let Some(committed) = buf.committed() else {
+                return Ok(written);
+            };
+            let committed = &committed[..cmp::min(committed.len(), limit)];
+            match w.write(committed) {
+                Ok(0) => {
+                    if written > 0 {
+                        return Ok(written);
+                    }
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                Ok(n) => {
+                    let n = buf.consume(n);
+                    if limit != usize::MAX {
+                        self.low_partial -= n;
+                    }
+                    written += n;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock && written > 0 => {
+                    return Ok(written)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, MirroredBuffer, PriorityLanes};
+    use std::{cmp, io};
+
+    // Takes `budget` bytes, then would block.
+    struct Budget {
+        out: Vec<u8>,
+        budget: usize,
+    }
+
+    impl io::Write for Budget {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = cmp::min(cmp::min(buf.len(), self.budget), 30);
+            if n == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.out.extend_from_slice(&buf[..n]);
+            self.budget -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lanes_high_overtakes_low_between_frames() {
+        let high = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let low = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut lanes = PriorityLanes::new(high, low);
+
+        for frame in [1, 2] {
+            lanes.claim_low(100).unwrap().fill(frame);
+            assert!(lanes.commit_low(100) == 100);
+        }
+        assert!(lanes.commit_low(lanes.low().size() + 1) == 0);
+
+        // The second low frame is cut short...
+        let mut w = Budget {
+            out: Vec::new(),
+            budget: 130,
+        };
+        assert!(lanes.drain_to(&mut w).unwrap() == 130);
+        let err = lanes.drain_to(&mut w).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WouldBlock);
+
+        // ...so it finishes before the high frame, which overtakes the third.
+        lanes.claim_low(100).unwrap().fill(3);
+        lanes.commit_low(100);
+        lanes.claim_high(20).unwrap().fill(9);
+        assert!(lanes.commit_high(20) == 20);
+        w.budget = usize::MAX;
+        assert!(lanes.drain_to(&mut w).unwrap() == 190);
+        assert!(lanes.is_empty());
+
+        let out = w.out;
+        assert!(out[..100].iter().all(|&x| x == 1));
+        assert!(out[100..200].iter().all(|&x| x == 2));
+        assert!(out[200..220].iter().all(|&x| x == 9));
+        assert!(out[220..].iter().all(|&x| x == 3));
+    }
+}
@@ -1,14 +1,113 @@
+#[cfg(target_os = "linux")]
+pub mod admin;
+mod arena;
+#[cfg(feature = "futures-io")]
+mod async_buffered;
+mod bio_pair;
+mod broadcast;
+mod bus;
+mod chained;
+mod channel;
+pub mod codec;
+mod compact;
+mod datagram;
+mod elastic;
 mod error;
+mod fd;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod fixed;
+mod flusher;
+mod frame_queue;
+mod ipc;
+mod lanes;
+#[cfg(target_os = "linux")]
+mod linux;
+mod mpsc;
+mod notify;
+#[cfg(feature = "mio")]
+mod poll_buffered;
+#[cfg(feature = "polling")]
+mod poller_buffered;
+mod pool;
+mod prefetch;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "bytemuck")]
+mod queue;
+#[cfg(feature = "quinn")]
+mod quinn_stream;
+pub mod registry;
+#[cfg(feature = "rustls")]
+mod rustls_io;
+mod sequence;
+mod slots;
+mod split;
+mod stream;
+mod throttle;
+#[cfg(feature = "uring")]
+mod uring;
 mod util;
-
+mod watermark;
+
+pub use arena::{Arena, ArenaRing};
+#[cfg(feature = "futures-io")]
+pub use async_buffered::AsyncBuffered;
+pub use bio_pair::BioPair;
+pub use broadcast::{BroadcastProducer, BroadcastReader, LagPolicy};
+pub use bus::{BusPublisher, BusSubscriber};
+pub use chained::ChainedBuffer;
+pub use channel::{byte_channel, ByteReceiver, ByteSender};
+pub use compact::{CompactBuffer, COMPACT_MAX_SIZE};
+pub use datagram::{Datagram, DatagramRing};
+pub use elastic::Elastic;
 pub use error::{Error, ErrorKind};
-use std::{cmp, ffi::CString, io, process};
+pub use fixed::FixedBuffer;
+pub use flusher::Flusher;
+pub use frame_queue::{FrameQueue, Job, FRAME_QUEUE_MAX_LEN};
+pub use ipc::{Handle, Role, SharedReader, SharedRing, HANDLE_VERSION};
+pub use lanes::PriorityLanes;
+#[cfg(target_os = "linux")]
+pub use linux::{enable_gro, NumaPolicy, XdpUmem, ZeroCopySender, GSO_MAX_SEGMENTS};
+pub use mpsc::{MpscProducer, Reservation};
+#[cfg(feature = "mio")]
+pub use poll_buffered::PollBuffered;
+#[cfg(feature = "polling")]
+pub use poller_buffered::PollerBuffered;
+pub use pool::{BufferPool, PooledBuffer};
+pub use prefetch::PrefetchChunks;
+#[cfg(feature = "bytemuck")]
+pub use queue::MirroredQueue;
+use registry::Usage;
+pub use sequence::{Sequence, Sequencer, Stage};
+pub use slots::SlotRing;
+pub use split::{Consumer, Producer};
+use std::{
+    cmp,
+    ffi::CString,
+    io,
+    mem::{self, MaybeUninit},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    process, ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+pub use stream::{copy_from, copy_into, read_vectored, write_vectored};
+pub use throttle::Throttled;
+#[cfg(feature = "uring")]
+pub use uring::ProvidedBufRing;
 use util::round_up_to_page_size;
-
-// TODO example usage with UDS + a frame and a streaming codec
+pub use util::{get_page_size, set_page_size};
+pub use watermark::{Watermark, Watermarked};
 
 pub struct MirroredBuffer<'a> {
     name: CString,
+    fd: libc::c_int,
+    // Where the buffer starts in the file, which is past 0 only for the
+    // rings of an arena, see `Arena::ring`.
+    file_offset: usize,
 
     head: usize,
     tail: usize,
@@ -17,14 +116,102 @@ pub struct MirroredBuffer<'a> {
     size_mask: usize,
     size_used: usize,
 
+    full_policy: FullPolicy,
+
+    usage: Arc<Usage>,
+
+    // Set once the mapping was handed out for registration, after which it
+    // must stay where it is, see `registration_region`.
+    pinned: AtomicBool,
+
+    // The PROT_NONE bytes reserved on each side of the mapping, see
+    // `with_guard_pages`.
+    guard_len: usize,
+
+    // Whether the second half of the mapping is read-only, see
+    // `with_read_only_mirror`.
+    read_only_mirror: bool,
+
+    // Whether consumed regions and the whole mapping on drop are wiped, see
+    // `with_zeroize`.
+    zeroize: bool,
+
+    // Whether the pages consumed are handed back to the kernel, see
+    // `with_reclaim`.
+    reclaim: bool,
+
+    // Whether children forked off do not get the mapping, see
+    // `with_dont_fork`.
+    dont_fork: bool,
+    // Whether core dumps leave the mapping out, see `with_dont_dump`.
+    dont_dump: bool,
+
+    // The flags the mapping was made with besides the usual ones, see
+    // `new_no_reserve`.
+    map_flags: libc::c_int,
+    // What the start of the mapping is aligned to, 0 for the page size, see
+    // `with_granularity`.
+    granularity: usize,
+    // Whether the page tables of both halves are filled in up front, see
+    // `with_prefault`.
+    prefault: bool,
+    // The value claims fill the buffer with the first time around, and how
+    // far it got, see `with_lazy_fill`.
+    fill: Option<u8>,
+    filled: usize,
+
     slice: &'a mut [u8],
 }
 
+// What claims, commits and pushes do when there is not enough free space,
+// see `with_full_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FullPolicy {
+    // Do nothing: claims return None, commits commit nothing and pushes fail
+    // with NoSpace. Suits logging, where a partial record is worse than none.
+    Error,
+    // Do what fits. The default.
+    #[default]
+    Clamp,
+    // Make room by consuming the oldest committed data, e.g. for telemetry
+    // where only the latest samples matter. A split producer cannot do that
+    // while the consumer may be reading, so it clamps instead.
+    Overwrite,
+    // Wait for the consumer to make room. Only a split producer has one to
+    // wait for; a buffer on its own fails like with Error.
+    Block,
+}
+
 impl<'a> MirroredBuffer<'a> {
     pub fn new(
         size: usize,
         name_suffix: Option<&str>,
         initial_value: Option<u8>,
+    ) -> Result<MirroredBuffer<'a>, Error> {
+        MirroredBuffer::create(size, name_suffix, initial_value, 0)
+    }
+
+    // Like `new`, but maps the buffer with MAP_NORESERVE, for rings far
+    // larger than what they are expected to hold at once. The kernel then
+    // charges nothing for the mapping, even under strict overcommit
+    // (vm.overcommit_memory=2), and takes memory only as pages are touched:
+    // running out shows up as a SIGBUS on a write rather than as an error
+    // here, so it is for whoever planned for it.
+    pub fn new_no_reserve(
+        size: usize,
+        name_suffix: Option<&str>,
+        initial_value: Option<u8>,
+    ) -> Result<MirroredBuffer<'a>, Error> {
+        MirroredBuffer::create(size, name_suffix, initial_value, libc::MAP_NORESERVE)
+    }
+
+    // Running out of memory, or of what the limits allow, fails with
+    // OutOfMemory, which tells what to look at.
+    fn create(
+        size: usize,
+        name_suffix: Option<&str>,
+        initial_value: Option<u8>,
+        map_flags: libc::c_int,
     ) -> Result<MirroredBuffer<'a>, Error> {
         if size == 0 {
             return Err(Error::invalid_size(size));
@@ -44,6 +231,9 @@ impl<'a> MirroredBuffer<'a> {
             )
         });
 
+        let size_total = round_up_to_page_size(size)?;
+        let size_mask = size_total - 1;
+
         let fd = unsafe {
             libc::shm_open(
                 name.as_ptr(),
@@ -55,70 +245,539 @@ impl<'a> MirroredBuffer<'a> {
             return Err(Error::last_os_error());
         }
 
-        let size_total = round_up_to_page_size(size);
-        let size_mask = size_total - 1;
+        let mapped = if size_total & size_mask != 0 {
+            Err(Error::invalid_size(size_total))
+        } else if unsafe { libc::ftruncate(fd, size_total as libc::off_t) } == -1 {
+            Err(Error::last_os_error())
+        } else {
+            let prot = libc::PROT_READ | libc::PROT_WRITE;
+            map_mirrored(fd, size_total, 0, prot, 0, 0, map_flags)
+        };
+        let slice = match mapped {
+            Ok(slice) => slice,
+            Err(err) => {
+                unsafe {
+                    libc::shm_unlink(name.as_ptr());
+                    libc::close(fd);
+                }
+                return Err(match err.kind() {
+                    ErrorKind::IO(err)
+                        if matches!(
+                            err.raw_os_error(),
+                            Some(libc::ENOMEM | libc::ENOSPC | libc::EFBIG)
+                        ) =>
+                    {
+                        Error::out_of_memory(2 * size_total)
+                    }
+                    _ => err,
+                });
+            }
+        };
 
-        if size_total & size_mask != 0 {
-            return Err(Error::invalid_size(size_total));
+        // Only the first half needs filling, the mirror shows it, and a new
+        // segment is zeroed already.
+        if let Some(v) = initial_value.filter(|&v| v != 0) {
+            slice[..size_total].fill(v);
         }
 
-        if unsafe { libc::ftruncate(fd, size_total as libc::off_t) } == -1 {
-            return Err(Error::last_os_error());
+        let usage = registry::register(name.to_str().unwrap(), size_total);
+        Ok(MirroredBuffer {
+            name,
+            fd,
+            file_offset: 0,
+
+            head: 0,
+            tail: 0,
+
+            size_total,
+            size_mask,
+            size_used: 0,
+
+            full_policy: FullPolicy::Clamp,
+
+            usage,
+
+            pinned: AtomicBool::new(false),
+
+            guard_len: 0,
+
+            read_only_mirror: false,
+
+            zeroize: false,
+
+            reclaim: false,
+
+            dont_fork: false,
+            dont_dump: false,
+
+            map_flags,
+            granularity: 0,
+            prefault: false,
+
+            fill: None,
+            filled: size_total,
+
+            slice,
+        })
+    }
+
+    pub fn with_full_policy(mut self, full_policy: FullPolicy) -> MirroredBuffer<'a> {
+        self.full_policy = full_policy;
+        self
+    }
+
+    pub fn full_policy(&self) -> FullPolicy {
+        self.full_policy
+    }
+
+    // Moves the mapping between two PROT_NONE guard pages, so running off
+    // either end of it faults instead of silently landing in whatever is
+    // mapped next to it, e.g. while developing a codec. Fails with Pinned if
+    // the mapping was handed out for registration.
+    pub fn with_guard_pages(mut self) -> Result<MirroredBuffer<'a>, Error> {
+        let guard_len = util::get_page_size()?;
+        self.remap(guard_len, self.granularity)?;
+        Ok(self)
+    }
+
+    // Whether the mapping sits between guard pages.
+    pub fn has_guard_pages(&self) -> bool {
+        self.guard_len > 0
+    }
+
+    // Moves the mapping to start at a multiple of `granularity`, a power of
+    // two larger than the page size, e.g. 64KiB or 2MiB, so the start and
+    // the wrap point of the buffer suit hardware or protocols wanting them
+    // aligned, hugepages or not. The size must be a multiple of it, which
+    // creating the buffer with at least `granularity` bytes makes it; fails
+    // with InvalidSize otherwise, and with Pinned like `with_guard_pages`.
+    pub fn with_granularity(mut self, granularity: usize) -> Result<MirroredBuffer<'a>, Error> {
+        let page_size = util::get_page_size()?;
+        if !granularity.is_power_of_two()
+            || granularity < page_size
+            || !self.size_total.is_multiple_of(granularity)
+        {
+            return Err(Error::invalid_size(granularity));
         }
+        self.remap(self.guard_len, granularity)?;
+        Ok(self)
+    }
 
-        let addr = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                size_total * 2,
-                libc::PROT_NONE,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE,
-                -1,
-                0,
+    // What the start and the wrap point of the buffer are aligned to.
+    pub fn granularity(&self) -> usize {
+        if self.granularity == 0 {
+            return util::get_page_size().expect("could not get the system's page size");
+        }
+        self.granularity
+    }
+
+    // Maps the segment anew, with `guard_len` bytes of guard on each side
+    // and aligned to `granularity`, and unmaps the old mapping.
+    fn remap(&mut self, guard_len: usize, granularity: usize) -> Result<(), Error> {
+        if self.is_pinned() {
+            return Err(Error::pinned());
+        }
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let slice = map_mirrored(
+            self.fd,
+            self.size_total,
+            0,
+            prot,
+            guard_len,
+            granularity,
+            self.map_flags,
+        )?;
+        unsafe {
+            libc::munmap(
+                self.slice.as_mut_ptr().sub(self.guard_len) as *mut libc::c_void,
+                self.slice.len() + 2 * self.guard_len,
             )
         };
-        if addr == libc::MAP_FAILED {
+        self.slice = slice;
+        self.guard_len = guard_len;
+        self.granularity = granularity;
+        if self.read_only_mirror {
+            self.protect_mirror()?;
+        }
+        self.advise()
+    }
+
+    // Grows the buffer to at least `size` bytes, rounded like `new` does,
+    // keeping what is committed, e.g. for a connection that suddenly needs
+    // larger frames. The segment grows and is mapped anew: committed bytes
+    // stay where they are, but for those wrapped around the end of the old
+    // size, which are copied after it in one go. The settings of the buffer
+    // carry over, but for a NUMA policy. Fails with InvalidSize if that is
+    // smaller than the buffer or not a multiple of its granularity, with
+    // Pinned like `with_guard_pages` and otherwise like `new`, leaving the
+    // buffer as it was.
+    pub fn grow(&mut self, size: usize) -> Result<(), Error> {
+        let size_total = round_up_to_page_size(size)?;
+        if size_total < self.size_total {
+            return Err(Error::invalid_size(size));
+        }
+        self.check_resize(size_total)?;
+        if size_total == self.size_total {
+            return Ok(());
+        }
+        let old_size = self.size_total;
+        if unsafe { libc::ftruncate(self.fd, size_total as libc::off_t) } == -1 {
             return Err(Error::last_os_error());
         }
+        let slice = match self.map_resized(size_total) {
+            Ok(slice) => slice,
+            Err(err) => {
+                unsafe { libc::ftruncate(self.fd, old_size as libc::off_t) };
+                return Err(err);
+            }
+        };
 
-        let remap = |addr: *mut libc::c_void| -> Result<(), Error> {
-            let ret = unsafe {
-                libc::mmap(
-                    addr,
-                    size_total,
-                    libc::PROT_READ | libc::PROT_WRITE,
-                    libc::MAP_SHARED | libc::MAP_FIXED,
-                    fd,
-                    0,
-                )
-            };
+        let wrapped = (self.head + self.size_used).saturating_sub(old_size);
+        slice.copy_within(..wrapped, old_size);
+        if self.zeroize {
+            unsafe { util::wipe(slice.as_mut_ptr(), wrapped) };
+        }
+        if wrapped > 0 {
+            self.filled = old_size + wrapped;
+        }
+        self.swap_mapping(slice, size_total)
+    }
 
-            if ret == libc::MAP_FAILED {
-                return Err(Error::last_os_error());
+    // Shrinks the buffer to at least `size` bytes, rounded like `new` does,
+    // keeping what is committed and giving the rest of the memory back, e.g.
+    // for a long-lived connection that had a burst. What is committed moves
+    // to the start of the buffer. Fails with NoSpace if it does not fit, and
+    // otherwise like `grow`.
+    pub fn shrink_to(&mut self, size: usize) -> Result<(), Error> {
+        let size_total = round_up_to_page_size(size)?;
+        if size_total > self.size_total {
+            return Err(Error::invalid_size(size));
+        }
+        if self.size_used > size_total {
+            return Err(Error::no_space(self.size_used));
+        }
+        self.check_resize(size_total)?;
+        if size_total == self.size_total {
+            return Ok(());
+        }
+        let slice = self.map_resized(size_total)?;
+
+        // Both mappings show the same pages, so the committed bytes go
+        // through a copy of their own on their way to the start. What was
+        // left of them past it goes, or is given back with the rest.
+        let mut committed = self.committed().unwrap_or_default().to_vec();
+        slice[..committed.len()].copy_from_slice(&committed);
+        if self.zeroize {
+            unsafe {
+                util::wipe(committed.as_mut_ptr(), committed.len());
+                util::wipe(
+                    slice.as_mut_ptr().add(self.size_used),
+                    size_total - self.size_used,
+                );
             }
-            Ok(())
+        }
+        self.head = 0;
+        self.filled = cmp::max(self.size_used, cmp::min(self.filled, size_total));
+        self.swap_mapping(slice, size_total)?;
+        unsafe { libc::ftruncate(self.fd, size_total as libc::off_t) };
+        Ok(())
+    }
+
+    fn check_resize(&self, size_total: usize) -> Result<(), Error> {
+        if self.is_pinned() {
+            return Err(Error::pinned());
+        }
+        if !size_total.is_power_of_two() || !size_total.is_multiple_of(self.granularity()) {
+            return Err(Error::invalid_size(size_total));
+        }
+        Ok(())
+    }
+
+    // Maps the first `size_total` bytes of the segment like the buffer is.
+    fn map_resized(&self, size_total: usize) -> Result<&'a mut [u8], Error> {
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let guard_len = self.guard_len;
+        map_mirrored(
+            self.fd,
+            size_total,
+            0,
+            prot,
+            guard_len,
+            self.granularity,
+            self.map_flags,
+        )
+    }
+
+    // Unmaps the old mapping for `slice`, a resized one with the committed
+    // bytes from the head on.
+    fn swap_mapping(&mut self, slice: &'a mut [u8], size_total: usize) -> Result<(), Error> {
+        unsafe {
+            libc::munmap(
+                self.slice.as_mut_ptr().sub(self.guard_len) as *mut libc::c_void,
+                self.slice.len() + 2 * self.guard_len,
+            )
         };
+        self.slice = slice;
+        self.size_total = size_total;
+        self.size_mask = size_total - 1;
+        self.tail = (self.head + self.size_used) & self.size_mask;
+        if self.fill.is_none() {
+            self.filled = size_total;
+        }
+        self.usage.size.store(size_total, Ordering::Relaxed);
+        if self.read_only_mirror {
+            self.protect_mirror()?;
+        }
+        self.advise()
+    }
+
+    // Makes the second half of the mapping read-only, so that writing
+    // through a committed region past the end of the first half faults,
+    // e.g. a codec scribbling over what it decodes, while committed regions
+    // still read across the wrap.
+    //
+    // Writes go through the first half only: claims stop at its end, so a
+    // claim across the wrap comes back shorter whatever the full policy, and
+    // pushes write in two pieces.
+    pub fn with_read_only_mirror(mut self) -> Result<MirroredBuffer<'a>, Error> {
+        self.protect_mirror()?;
+        self.read_only_mirror = true;
+        Ok(self)
+    }
 
-        remap(addr)?;
-        remap(unsafe { addr.byte_add(size_total) })?;
+    pub fn has_read_only_mirror(&self) -> bool {
+        self.read_only_mirror
+    }
+
+    // Wipes what is consumed as it is, and the whole buffer when it is
+    // dropped, with writes the compiler cannot optimize out, for buffers
+    // carrying keys, passwords or personal data. What the buffer holds does
+    // not outlive its use in the mapping, though copies taken out of it are
+    // up to whoever took them.
+    pub fn with_zeroize(mut self) -> MirroredBuffer<'a> {
+        self.zeroize = true;
+        self
+    }
+
+    pub fn zeroize(&self) -> bool {
+        self.zeroize
+    }
+
+    // Hands the pages that consumes go past back to the kernel, which zeroes
+    // them, so a large ring sitting mostly idle between bursts does not keep
+    // its peak memory. Only whole pages a consume spans are handed back, so
+    // consuming in large pieces gets the most back. Only Linux reclaims.
+    //
+    // The segment is shared memory, so MADV_DONTNEED or MADV_FREE would only
+    // drop the process's page tables and keep the memory; MADV_REMOVE frees
+    // it, as punching a hole in the segment does.
+    pub fn with_reclaim(mut self) -> MirroredBuffer<'a> {
+        self.reclaim = true;
+        self
+    }
+
+    pub fn reclaim(&self) -> bool {
+        self.reclaim
+    }
+
+    // Keeps the mapping out of the children of fork, e.g. workers forked
+    // off a server, which then fault on it rather than share the ring. The
+    // segment's fd is closed on exec already. Linux has no wiping the
+    // mapping across fork instead, as MADV_WIPEONFORK only takes private
+    // anonymous memory and a buffer is shared memory.
+    #[cfg(target_os = "linux")]
+    pub fn with_dont_fork(mut self) -> Result<MirroredBuffer<'a>, Error> {
+        self.dont_fork = true;
+        self.advise()?;
+        Ok(self)
+    }
+
+    pub fn dont_fork(&self) -> bool {
+        self.dont_fork
+    }
+
+    // Leaves the mapping out of core dumps, which it could make huge, or
+    // fill with what it carries.
+    #[cfg(target_os = "linux")]
+    pub fn with_dont_dump(mut self) -> Result<MirroredBuffer<'a>, Error> {
+        self.dont_dump = true;
+        self.advise()?;
+        Ok(self)
+    }
 
-        let slice = unsafe { std::slice::from_raw_parts_mut(addr as *mut u8, size_total * 2) };
+    pub fn dont_dump(&self) -> bool {
+        self.dont_dump
+    }
 
-        if let Some(v) = initial_value {
-            slice.fill(v);
+    // Gives the kernel the advice the options call for, again whenever the
+    // mapping moves.
+    fn advise(&mut self) -> Result<(), Error> {
+        #[cfg(target_os = "linux")]
+        if self.dont_fork {
+            self.madvise(libc::MADV_DONTFORK)?;
         }
+        #[cfg(target_os = "linux")]
+        if self.dont_dump {
+            self.madvise(libc::MADV_DONTDUMP)?;
+        }
+        if self.prefault {
+            self.populate()?;
+        }
+        Ok(())
+    }
 
-        Ok(MirroredBuffer {
-            name,
+    // Faults in every page of both halves of the mapping, so claims and
+    // reads on the hot path never take a page fault, not even the first
+    // time around the ring: the mirror has page tables of its own, which
+    // the reservation's MAP_POPULATE does not fill in. Again whenever the
+    // mapping moves.
+    pub fn with_prefault(mut self) -> Result<MirroredBuffer<'a>, Error> {
+        self.prefault = true;
+        self.populate()?;
+        Ok(self)
+    }
 
-            head: 0,
-            tail: 0,
+    pub fn prefault(&self) -> bool {
+        self.prefault
+    }
 
-            size_total,
-            size_mask,
-            size_used: 0,
+    // Fills the buffer with `value` as it is claimed rather than all at once
+    // like `new` does with its initial value, which touches every page of a
+    // large buffer up front. Claiming bytes for the first time fills them, so
+    // what a claim hands out is as if the whole buffer had been filled, and
+    // committing or pushing them leaves them as they were written, even when
+    // written without a claim, e.g. by `splice_from_pipe`. Splitting the
+    // buffer or taking it apart fills the rest first. Meant for a new buffer:
+    // nothing up to the end of what is committed is filled.
+    pub fn with_lazy_fill(mut self, value: u8) -> MirroredBuffer<'a> {
+        self.fill = Some(value);
+        self.filled = cmp::min(self.head + self.size_used, self.size_total);
+        self
+    }
 
-            slice,
-        })
+    pub fn lazy_fill(&self) -> Option<u8> {
+        self.fill
+    }
+
+    // Fills whatever is not filled yet of the `size` bytes from the tail.
+    // Everything before the tail is, as it was claimed or pushed first.
+    fn fill_ahead(&mut self, size: usize) {
+        let end = cmp::min(self.tail + size, self.size_total);
+        if let Some(value) = self.fill.filter(|_| self.filled < end) {
+            self.slice[self.filled..end].fill(value);
+            self.filled = end;
+        }
+    }
+
+    // Fills whatever is not filled yet, before the buffer is written through
+    // a raw pointer.
+    pub(crate) fn fill_rest(&mut self) {
+        self.fill_ahead(self.size_total);
+    }
+
+    fn populate(&mut self) -> Result<(), Error> {
+        let writable = [true, !self.read_only_mirror];
+        for (half, writable) in writable.into_iter().enumerate() {
+            let start = unsafe { self.slice.as_mut_ptr().add(half * self.size_total) };
+            // Linux 5.14 on does it in one go, older ones and other systems
+            // take touching every page.
+            #[cfg(target_os = "linux")]
+            {
+                let advice = match writable {
+                    true => libc::MADV_POPULATE_WRITE,
+                    false => libc::MADV_POPULATE_READ,
+                };
+                let ret =
+                    unsafe { libc::madvise(start as *mut libc::c_void, self.size_total, advice) };
+                if ret == 0 {
+                    continue;
+                }
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(libc::EINVAL) {
+                    return Err(Error::io(err));
+                }
+            }
+            let page_size = util::get_page_size()?;
+            for offset in (0..self.size_total).step_by(page_size) {
+                unsafe {
+                    let page = start.add(offset);
+                    let value = ptr::read_volatile(page);
+                    if writable {
+                        ptr::write_volatile(page, value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn madvise(&mut self, advice: libc::c_int) -> Result<(), Error> {
+        let ret = unsafe {
+            libc::madvise(
+                self.slice.as_mut_ptr() as *mut libc::c_void,
+                self.slice.len(),
+                advice,
+            )
+        };
+        if ret == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Hands the whole pages of the `len` bytes at `offset` back to the
+    // kernel.
+    #[cfg(target_os = "linux")]
+    fn reclaim_pages(&mut self, offset: usize, len: usize) {
+        let page_size = util::get_page_size().expect("could not get the system's page size");
+        let end = offset + len;
+        let pieces = [
+            (offset, cmp::min(end, self.size_total)),
+            (0, end.saturating_sub(self.size_total)),
+        ];
+        for (start, end) in pieces {
+            let start = start.next_multiple_of(page_size);
+            let end = if end == self.size_total {
+                end
+            } else {
+                end / page_size * page_size
+            };
+            if start >= end {
+                continue;
+            }
+            unsafe {
+                libc::madvise(
+                    self.slice.as_mut_ptr().add(start) as *mut libc::c_void,
+                    end - start,
+                    libc::MADV_REMOVE,
+                )
+            };
+        }
+    }
+
+    fn protect_mirror(&mut self) -> Result<(), Error> {
+        let mirror = unsafe { self.slice.as_mut_ptr().add(self.size_total) };
+        let ret = unsafe {
+            libc::mprotect(
+                mirror as *mut libc::c_void,
+                self.size_total,
+                libc::PROT_READ,
+            )
+        };
+        if ret == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // How much of `size` bytes from `offset` of the first half can be
+    // written, see `with_read_only_mirror`.
+    pub(crate) fn writable(&self, offset: usize, size: usize) -> usize {
+        if self.read_only_mirror {
+            return cmp::min(size, self.size_total - offset);
+        }
+        size
     }
 
     pub fn name(&self) -> &str {
@@ -137,28 +796,117 @@ impl<'a> MirroredBuffer<'a> {
         self.size_total
     }
 
+    // How much of `size` the full policy lets through.
+    fn room(&self, size: usize) -> usize {
+        match self.full_policy {
+            FullPolicy::Clamp => cmp::min(size, self.free()),
+            FullPolicy::Overwrite => cmp::min(size, self.size()),
+            FullPolicy::Error | FullPolicy::Block if size > self.free() => 0,
+            FullPolicy::Error | FullPolicy::Block => size,
+        }
+    }
+
     pub fn claim(&mut self, mut size: usize) -> Option<&mut [u8]> {
-        size = cmp::min(size, self.free());
+        size = self.writable(self.tail, self.room(size));
         if size == 0 {
             return None;
         }
+        self.fill_ahead(size);
         Some(&mut self.slice[self.tail..(self.tail + size)])
     }
 
+    // Like `claim`, but promises nothing about what the region holds, for
+    // APIs that only write to memory, e.g. `read_buf` style reads, and never
+    // need it filled first. `commit_init` commits it safely once written.
+    pub fn claim_uninit(&mut self, size: usize) -> Option<&mut [MaybeUninit<u8>]> {
+        let claimed = self.claim(size)?;
+        let len = claimed.len();
+        Some(unsafe { std::slice::from_raw_parts_mut(claimed.as_mut_ptr().cast(), len) })
+    }
+
+    // Commits `written`, which must start where the claimed region does,
+    // e.g. the filled part of a region `claim_uninit` returned. Taking the
+    // written bytes rather than a size proves they were initialized.
+    pub fn commit_init(&mut self, written: &[u8]) -> usize {
+        assert!(
+            ptr::eq(written.as_ptr(), &self.slice[self.tail]),
+            "not the start of the claimed region"
+        );
+        self.commit(written.len())
+    }
+
+    // Claims like `claim`, but from a multiple of `align`, a power of two,
+    // e.g. for SIMD encoders and checksums working on aligned data. The
+    // bytes up to there are zeroed and committed as padding, which the
+    // consumer has to know to skip, e.g. from the framing. Pads nothing and
+    // returns None if there is no room for the padding and a byte past it.
+    pub fn claim_aligned(&mut self, size: usize, align: usize) -> Option<&mut [u8]> {
+        assert!(
+            align.is_power_of_two(),
+            "the alignment is not a power of two"
+        );
+        let addr = self.slice.as_ptr() as usize + self.tail;
+        let mut pad = addr.wrapping_neg() & (align - 1);
+        if size == 0 || self.room(pad + size) <= pad {
+            return None;
+        }
+        while pad > 0 {
+            let claimed = self.claim(pad)?;
+            claimed.fill(0);
+            let len = claimed.len();
+            pad -= self.commit(len);
+        }
+        self.claim(size)
+    }
+
     pub fn commit(&mut self, mut size: usize) -> usize {
-        size = cmp::min(size, self.free());
+        size = self.room(size);
+        // Written now, whether through a claim or not, which filling must
+        // not undo.
+        self.filled = cmp::max(self.filled, cmp::min(self.tail + size, self.size_total));
+        if size > self.free() {
+            // Overwriting.
+            self.consume(size - self.free());
+        }
         self.size_used += size;
         self.tail = (self.tail + size) & self.size_mask;
+        self.usage.used.store(self.size_used, Ordering::Relaxed);
         size
     }
 
     pub fn consume(&mut self, mut size: usize) -> usize {
         size = cmp::min(size, self.used());
+        if self.zeroize {
+            let base = self.slice.as_mut_ptr();
+            unsafe { wipe_ring(base, self.size_total, self.head, size) };
+        }
+        #[cfg(target_os = "linux")]
+        if self.reclaim {
+            self.reclaim_pages(self.head, size);
+        }
         self.size_used -= size;
         self.head = (self.head + size) & self.size_mask;
+        self.usage.used.store(self.size_used, Ordering::Relaxed);
         size
     }
 
+    // Copies `data` in and commits it, as the full policy says. Overwriting
+    // keeps the end of `data` if all of it does not fit.
+    pub fn push(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let size = self.room(data.len());
+        let data = match self.full_policy {
+            FullPolicy::Error | FullPolicy::Block if size < data.len() => {
+                return Err(Error::no_space(data.len()))
+            }
+            FullPolicy::Overwrite => &data[data.len() - size..],
+            _ => &data[..size],
+        };
+        let (first, second) = data.split_at(cmp::min(size, self.size_total - self.tail));
+        self.slice[self.tail..self.tail + first.len()].copy_from_slice(first);
+        self.slice[..second.len()].copy_from_slice(second);
+        Ok(self.commit(size))
+    }
+
     pub fn committed(&self) -> Option<&[u8]> {
         if self.used() == 0 {
             return None;
@@ -169,40 +917,419 @@ impl<'a> MirroredBuffer<'a> {
         }
         Some(&self.slice[self.head..(self.tail + self.size())])
     }
+
+    // The committed region to write over, e.g. to decrypt it in place, up to
+    // the wrap if the mirror is read-only.
+    #[cfg(feature = "snow")]
+    pub(crate) fn committed_mut(&mut self) -> Option<&mut [u8]> {
+        if self.used() == 0 {
+            return None;
+        }
+        let size = self.writable(self.head, self.used());
+        Some(&mut self.slice[self.head..self.head + size])
+    }
+
+    // Writes `data` over the committed region from `offset` on, in two
+    // pieces like `push` does.
+    #[cfg(feature = "snow")]
+    pub(crate) fn write_committed(&mut self, offset: usize, data: &[u8]) {
+        let start = (self.head + offset) & self.size_mask;
+        let (first, second) = data.split_at(cmp::min(data.len(), self.size_total - start));
+        self.slice[start..start + first.len()].copy_from_slice(first);
+        self.slice[..second.len()].copy_from_slice(second);
+    }
+}
+
+// Wipes the `len` bytes at `offset` of a mirrored mapping of `size_total`
+// bytes at `base`, going through the first half only, in case the second is
+// read-only.
+pub(crate) unsafe fn wipe_ring(base: *mut u8, size_total: usize, offset: usize, len: usize) {
+    let first = cmp::min(len, size_total - offset);
+    unsafe {
+        util::wipe(base.add(offset), first);
+        util::wipe(base, len - first);
+    }
+}
+
+// Maps the `size_total` bytes of `fd` at `offset` twice, back to back, with
+// the protection `prot` and the extra `flags`, and returns the resulting
+// 2 * `size_total` bytes, with `guard_len` bytes left PROT_NONE on each side.
+// The mapping starts at a multiple of `align` if it is not 0.
+pub(crate) fn map_mirrored<'b>(
+    fd: libc::c_int,
+    size_total: usize,
+    offset: usize,
+    prot: libc::c_int,
+    guard_len: usize,
+    align: usize,
+    flags: libc::c_int,
+) -> Result<&'b mut [u8], Error> {
+    // Aligning takes reserving more and giving back what is not needed on
+    // either side.
+    let len = size_total * 2 + guard_len * 2;
+    let reserved = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len + align,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE | flags,
+            -1,
+            0,
+        )
+    };
+    if reserved == libc::MAP_FAILED {
+        return Err(Error::last_os_error());
+    }
+    let mut addr = unsafe { reserved.byte_add(guard_len) };
+    if align > 0 {
+        let skip = (addr as usize).wrapping_neg() & (align - 1);
+        unsafe {
+            if skip > 0 {
+                libc::munmap(reserved, skip);
+            }
+            if align > skip {
+                libc::munmap(reserved.byte_add(skip + len), align - skip);
+            }
+        }
+        addr = unsafe { addr.byte_add(skip) };
+    }
+
+    let remap = |addr: *mut libc::c_void| -> Result<(), Error> {
+        let ret = unsafe {
+            libc::mmap(
+                addr,
+                size_total,
+                prot,
+                libc::MAP_SHARED | libc::MAP_FIXED | flags,
+                fd,
+                offset as libc::off_t,
+            )
+        };
+
+        if ret == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    };
+
+    remap(addr)?;
+    remap(unsafe { addr.byte_add(size_total) })?;
+
+    Ok(unsafe { std::slice::from_raw_parts_mut(addr as *mut u8, size_total * 2) })
+}
+
+// The fd of the shared memory segment backing the buffer, e.g. to register
+// the memory with io_uring, pass it to another process or fstat it. The
+// segment holds the buffer once, from offset 0; for a ring of an `Arena`, it
+// is the arena's, and holds the ring further in.
+impl AsFd for MirroredBuffer<'_> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+impl AsRawFd for MirroredBuffer<'_> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+// The memory of a buffer as an RDMA device registers it, e.g. with
+// ibv_reg_mr, see `MirroredBuffer::registration_region`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistrationRegion {
+    // The start of the mapping, aligned to `alignment`.
+    pub addr: *mut u8,
+    // Twice the size of the buffer: both halves are registered, so a receive
+    // posted into a claimed region may run past the end of the first.
+    pub len: usize,
+    // The granularity of the buffer, which its size is a multiple of as
+    // well: the page size unless `with_granularity` asked for more.
+    pub alignment: usize,
+}
+
+impl MirroredBuffer<'_> {
+    // The memory to register with an RDMA device, so receives can be posted
+    // straight into claimed regions, at the address `claim` returns.
+    //
+    // The mapping never moves or goes away while the buffer lives: nothing
+    // remaps it, and it is unmapped only once the buffer is gone, which must
+    // not happen before the device lets go of it. Once this is called, the
+    // buffer is pinned, and anything that would move the mapping fails
+    // instead, see `is_pinned`.
+    pub fn registration_region(&self) -> RegistrationRegion {
+        self.pinned.store(true, Ordering::Relaxed);
+        RegistrationRegion {
+            addr: self.slice.as_ptr() as *mut u8,
+            len: self.slice.len(),
+            alignment: self.granularity(),
+        }
+    }
+
+    // Whether the mapping was handed out for registration and must stay
+    // where it is.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned.load(Ordering::Relaxed)
+    }
+}
+
+// How much of a buffer is in memory, see `MirroredBuffer::residency`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Residency {
+    // The pages the buffer holds, each mapped twice.
+    pub pages: usize,
+    // The pages in memory rather than not touched yet, or swapped out.
+    pub resident: usize,
+    // The pages locked in memory through the first half of the mapping,
+    // always 0 but on Linux.
+    pub locked: usize,
+}
+
+impl MirroredBuffer<'_> {
+    // How many of the pages of the buffer are in memory and locked there,
+    // e.g. for operators to check that prefaulting, locking or hugepages
+    // took effect in production.
+    pub fn residency(&self) -> Result<Residency, Error> {
+        let page_size = util::get_page_size()?;
+        let pages = self.size_total / page_size;
+        let mut vec = vec![0u8; pages];
+        let ret = unsafe {
+            libc::mincore(
+                self.slice.as_ptr() as *mut libc::c_void,
+                self.size_total,
+                vec.as_mut_ptr() as *mut _,
+            )
+        };
+        if ret == -1 {
+            return Err(Error::last_os_error());
+        }
+        let resident = vec.iter().filter(|&&page| page & 1 != 0).count();
+
+        #[cfg(target_os = "linux")]
+        let locked = locked_len(self.slice.as_ptr(), self.size_total)? / page_size;
+        #[cfg(not(target_os = "linux"))]
+        let locked = 0;
+
+        Ok(Residency {
+            pages,
+            resident,
+            locked,
+        })
+    }
+}
+
+// How many bytes of the `len` at `addr` are in memory and locked there, per
+// /proc/self/smaps. Its Locked field splits shared pages between their
+// mappings, so the resident bytes of the locked mappings are added up
+// instead.
+#[cfg(target_os = "linux")]
+fn locked_len(addr: *const u8, len: usize) -> io::Result<usize> {
+    let smaps = std::fs::read_to_string("/proc/self/smaps")?;
+    let range = addr as usize..addr as usize + len;
+    let (mut locked, mut rss, mut ours) = (0, 0, false);
+    for line in smaps.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("Rss:") => rss = fields.next().and_then(|kb| kb.parse().ok()).unwrap_or(0),
+            Some("VmFlags:") if ours && fields.any(|flag| flag == "lo") => locked += rss * 1024,
+            Some(field) if !field.ends_with(':') => {
+                let start = field.split('-').next().unwrap_or_default();
+                ours = usize::from_str_radix(start, 16).is_ok_and(|start| range.contains(&start));
+            }
+            _ => {}
+        }
+    }
+    Ok(locked)
+}
+
+// What a buffer is made of, see `MirroredBuffer::into_raw_parts`.
+#[derive(Debug)]
+pub struct RawParts {
+    // The start of the mapping, which is `2 * size` bytes, the second half
+    // mirroring the first.
+    pub ptr: *mut u8,
+    pub size: usize,
+    pub fd: RawFd,
+    // The name of the segment, unlinked when the buffer is dropped.
+    pub name: CString,
+    // Where the committed region starts, and how long it is.
+    pub head: usize,
+    pub used: usize,
+    // How the mapping was made, 0 and false for the defaults: the guard
+    // bytes on each side of it, whether its second half is read-only, the
+    // extra mmap flags and what its start is aligned to.
+    pub guard_len: usize,
+    pub read_only_mirror: bool,
+    pub map_flags: libc::c_int,
+    pub granularity: usize,
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Takes the buffer apart without unmapping, closing or unlinking
+    // anything, e.g. to hand the mapping to a foreign event loop or over an
+    // FFI boundary. What is committed stays where it is; the full policy is
+    // not kept.
+    pub fn into_raw_parts(mut self) -> RawParts {
+        self.fill_rest();
+        let mut buf = mem::ManuallyDrop::new(self);
+        // Drops out of the registry.
+        unsafe { ptr::drop_in_place(&mut buf.usage) };
+        RawParts {
+            ptr: buf.slice.as_mut_ptr(),
+            size: buf.size_total,
+            fd: buf.fd,
+            name: mem::take(&mut buf.name),
+            head: buf.head,
+            used: buf.size_used,
+            guard_len: buf.guard_len,
+            read_only_mirror: buf.read_only_mirror,
+            map_flags: buf.map_flags,
+            granularity: buf.granularity,
+        }
+    }
+
+    /// Puts a buffer back together from its parts.
+    ///
+    /// # Safety
+    ///
+    /// The parts must come from `into_raw_parts`, or be laid out the same: a
+    /// mirrored mapping of `2 * size` bytes at `ptr`, valid for 'a, of the
+    /// segment `fd` named `name`, with `size` a power of two and a multiple
+    /// of the page size, `head` and `used` within it, and `guard_len` bytes
+    /// reserved on each side of it. The buffer owns all of it from then on.
+    /// The other settings of the mapping must be the ones it was made with.
+    pub unsafe fn from_raw_parts(parts: RawParts) -> MirroredBuffer<'a> {
+        debug_assert!(parts.size.is_power_of_two() && parts.head < parts.size);
+        debug_assert!(parts.used <= parts.size);
+        let size_mask = parts.size - 1;
+        let usage = registry::register(parts.name.to_str().unwrap_or(""), parts.size);
+        usage.used.store(parts.used, Ordering::Relaxed);
+        MirroredBuffer {
+            name: parts.name,
+            fd: parts.fd,
+            file_offset: 0,
+
+            head: parts.head,
+            tail: (parts.head + parts.used) & size_mask,
+
+            size_total: parts.size,
+            size_mask,
+            size_used: parts.used,
+
+            full_policy: FullPolicy::Clamp,
+
+            usage,
+
+            pinned: AtomicBool::new(false),
+
+            guard_len: parts.guard_len,
+
+            read_only_mirror: parts.read_only_mirror,
+
+            zeroize: false,
+
+            reclaim: false,
+
+            dont_fork: false,
+            dont_dump: false,
+
+            map_flags: parts.map_flags,
+            granularity: parts.granularity,
+            prefault: false,
+
+            fill: None,
+            filled: parts.size,
+
+            slice: unsafe { std::slice::from_raw_parts_mut(parts.ptr, 2 * parts.size) },
+        }
+    }
+}
+
+// The buffers leaked, by the address of their mapping, with what it takes to
+// destroy them: their size, fd and name.
+static LEAKED: Mutex<Vec<(usize, usize, RawFd, CString)>> = Mutex::new(Vec::new());
+
+impl<'a> MirroredBuffer<'a> {
+    // Gives up on ever dropping the buffer and returns its mapping, twice the
+    // size with the second half mirroring the first, e.g. for a C plugin
+    // host that must not see it torn down by Rust at a time it does not
+    // expect. What was committed is forgotten. `destroy_leaked` tears it
+    // down for good.
+    pub fn leak(self) -> &'static mut [u8] {
+        let parts = self.into_raw_parts();
+        let mut leaked = LEAKED.lock().unwrap();
+        leaked.push((parts.ptr as usize, parts.size, parts.fd, parts.name));
+        unsafe { std::slice::from_raw_parts_mut(parts.ptr, 2 * parts.size) }
+    }
+
+    /// Unmaps a mapping `leak` returned and closes and unlinks its segment.
+    /// Fails if the mapping is not one of a leaked buffer.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may use the mapping afterwards, through any copy of the
+    /// pointer.
+    pub unsafe fn destroy_leaked(mapping: &'static mut [u8]) -> Result<(), Error> {
+        let (_, size, fd, name) = {
+            let mut leaked = LEAKED.lock().unwrap();
+            let addr = mapping.as_mut_ptr() as usize;
+            let Some(x) = leaked
+                .iter()
+                .position(|&(ptr, size, _, _)| ptr == addr && 2 * size == mapping.len())
+            else {
+                return Err(Error::io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "not the mapping of a leaked buffer",
+                )));
+            };
+            leaked.swap_remove(x)
+        };
+
+        unsafe {
+            libc::munmap(mapping.as_mut_ptr() as *mut libc::c_void, 2 * size);
+            libc::close(fd);
+            if libc::shm_unlink(name.as_ptr()) == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MirroredBuffer<'_> {
+    // Duplicates the fd of the segment, for whoever needs it to outlive the
+    // buffer. The segment lives on as long as the duplicate is open, though
+    // its name goes with the buffer.
+    pub fn try_clone_fd(&self) -> io::Result<OwnedFd> {
+        self.as_fd().try_clone_to_owned()
+    }
 }
 
 impl<'a> Drop for MirroredBuffer<'a> {
     fn drop(&mut self) {
+        if self.zeroize {
+            unsafe { util::wipe(self.slice.as_mut_ptr(), self.size_total) };
+        }
         if unsafe { libc::shm_unlink(self.name.as_ptr()) } != 0 {
             panic!("{}", io::Error::last_os_error());
         }
+        unsafe { libc::close(self.fd) };
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{util::get_page_size, MirroredBuffer};
-
-    // Used to prevent opening a MirroredBuffer on an already existing one,
-    // which results in an error as the underlying tmpfs file is opened in
-    // O_EXCL mode. O_EXCL ensures shm_open fails if the underlying file
-    // already exists.
-    //
-    // This can happen if we destroy a MirroredBuffer and then quickly create
-    // a new one with the same exact name. It is a result of calling shm_unlink
-    // when Dropping the MirroredBuffer - the syscall might take a some time to
-    // complete, notably more than it takes the binary to go to the next test
-    // and create a new MirroredBuffer with the same name.
-    //
-    // As a result, each test creates a unique MirroredBuffer by providing the
-    // return value of `next_buffer_index()` as a suffix.
-    static mut BUFFER_INDEX: i32 = 0;
-
-    fn next_buffer_index() -> String {
-        let index = unsafe { BUFFER_INDEX };
-        unsafe { BUFFER_INDEX += 1 };
-        index.to_string()
-    }
+    use crate::{
+        registry,
+        util::{get_page_size, next_buffer_index},
+        ErrorKind, FullPolicy, MirroredBuffer,
+    };
+    use std::{
+        ffi::CString,
+        mem,
+        os::fd::{AsFd, AsRawFd, RawFd},
+        ptr,
+    };
 
     #[test]
     fn mirrored_buffer_new() {
@@ -404,4 +1531,560 @@ mod tests {
         }
         assert!(buf.slice.iter().all(|&x| x == 1 || x == 2));
     }
+
+    #[test]
+    fn mirrored_buffer_registration_region() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        assert!(!buf.is_pinned());
+        let region = buf.registration_region();
+        assert!(buf.is_pinned());
+        assert!(region.alignment == get_page_size().unwrap());
+        assert!((region.addr as usize).is_multiple_of(region.alignment));
+        assert!(region.len == 2 * buf.size());
+
+        // Claimed regions are within, wherever the tail is.
+        let size = buf.size();
+        buf.claim(size - 10).unwrap();
+        buf.commit(size - 10);
+        buf.consume(size - 10);
+        let claimed = buf.claim(100).unwrap();
+        let start = claimed.as_ptr() as usize - region.addr as usize;
+        assert!(start + claimed.len() <= region.len);
+        assert!(buf.registration_region() == region);
+    }
+
+    #[test]
+    fn mirrored_buffer_guard_pages() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        assert!(!buf.has_guard_pages());
+        buf.registration_region();
+        let err = buf.with_guard_pages().err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::Pinned));
+
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        buf.push(b"abc").unwrap();
+        let mut buf = buf.with_guard_pages().unwrap();
+        assert!(buf.has_guard_pages());
+        assert!(buf.committed().unwrap() == b"abc");
+        let size = buf.size();
+        buf.consume(3);
+        buf.claim(size - 3).unwrap().fill(1);
+        buf.commit(size - 3);
+        buf.push(b"def").unwrap();
+        assert!(buf.committed().unwrap().ends_with(b"def"));
+
+        // Off either end faults.
+        let start = buf.slice.as_mut_ptr();
+        for addr in [unsafe { start.sub(1) }, unsafe { start.add(2 * size) }] {
+            match unsafe { libc::fork() } {
+                0 => unsafe {
+                    ptr::write_volatile(addr, 0);
+                    libc::_exit(0);
+                },
+                pid => {
+                    let mut status = 0;
+                    assert!(unsafe { libc::waitpid(pid, &mut status, 0) } == pid);
+                    assert!(libc::WIFSIGNALED(status));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mirrored_buffer_read_only_mirror() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0))
+            .unwrap()
+            .with_read_only_mirror()
+            .unwrap()
+            .with_guard_pages()
+            .unwrap();
+        assert!(buf.has_read_only_mirror());
+
+        // Writes stop at the end of the first half, reads go across.
+        let size = buf.size();
+        buf.push(&vec![1; size - 2]).unwrap();
+        buf.consume(size - 2);
+        assert!(buf.claim(10).unwrap().len() == 2);
+        assert!(buf.push(b"abcd").unwrap() == 4);
+        assert!(buf.committed().unwrap() == b"abcd");
+        assert!(buf.slice[..2] == *b"cd");
+
+        let committed = buf.committed().unwrap().as_ptr() as *mut u8;
+        match unsafe { libc::fork() } {
+            0 => unsafe {
+                ptr::write_volatile(committed.add(2), 0);
+                libc::_exit(0);
+            },
+            pid => {
+                let mut status = 0;
+                assert!(unsafe { libc::waitpid(pid, &mut status, 0) } == pid);
+                assert!(libc::WIFSIGNALED(status));
+            }
+        }
+
+        // So do the halves of a split buffer.
+        let (mut producer, mut consumer) = buf.split();
+        consumer.consume(4);
+        producer.push(&vec![2; size - 4]).unwrap();
+        consumer.consume(size - 4);
+        assert!(producer.claim(10).unwrap().len() == 2);
+        assert!(producer.push(b"efgh").unwrap() == 4);
+        assert!(consumer.committed().unwrap() == b"efgh");
+    }
+
+    #[test]
+    fn mirrored_buffer_claim_uninit() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None).unwrap();
+        let size = buf.size();
+        buf.push(&vec![1; size - 2]).unwrap();
+        buf.consume(size - 2);
+
+        let claimed = buf.claim_uninit(4).unwrap();
+        for (x, b) in claimed.iter_mut().zip(b"abcd") {
+            x.write(*b);
+        }
+        let written = unsafe { std::slice::from_raw_parts(claimed.as_ptr().cast(), 4) };
+        assert!(buf.commit_init(written) == 4);
+        assert!(buf.committed().unwrap() == b"abcd");
+
+        let elsewhere = buf.committed().unwrap().to_vec();
+        let commit = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            buf.commit_init(&elsewhere);
+        }));
+        assert!(commit.is_err());
+    }
+
+    #[test]
+    fn mirrored_buffer_zeroize() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None)
+            .unwrap()
+            .with_zeroize();
+        assert!(buf.zeroize());
+        let size = buf.size();
+        buf.push(&vec![1; size - 2]).unwrap();
+        buf.consume(size - 4);
+        assert!(buf.slice[..size - 4].iter().all(|&x| x == 0));
+        buf.push(b"secret").unwrap();
+
+        // Across the wrap.
+        buf.consume(4);
+        assert!(buf.slice[size - 4..size].iter().all(|&x| x == 0));
+        assert!(buf.committed().unwrap() == b"cret");
+
+        // And all of it on drop.
+        let file = std::fs::File::from(buf.try_clone_fd().unwrap());
+        drop(buf);
+        let mut contents = vec![1; size];
+        std::os::unix::fs::FileExt::read_exact_at(&file, &mut contents, 0).unwrap();
+        assert!(contents.iter().all(|&x| x == 0));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn mirrored_buffer_reclaim() {
+        let page_size = get_page_size().unwrap();
+        let mut buf = MirroredBuffer::new(4 * page_size, Some(&next_buffer_index()), None)
+            .unwrap()
+            .with_reclaim();
+        assert!(buf.reclaim());
+        let size = buf.size();
+        buf.push(&vec![1; size]).unwrap();
+
+        // The pages consumed whole come back zeroed, the others are kept.
+        buf.consume(page_size + 10);
+        assert!(buf.slice[..page_size].iter().all(|&x| x == 0));
+        assert!(buf.slice[page_size..size].iter().all(|&x| x == 1));
+        buf.consume(2 * page_size);
+        assert!(buf.slice[page_size..2 * page_size].iter().all(|&x| x == 1));
+        assert!(buf.slice[2 * page_size..3 * page_size]
+            .iter()
+            .all(|&x| x == 0));
+        assert!(buf.slice[3 * page_size..size].iter().all(|&x| x == 1));
+
+        // Across the wrap.
+        buf.push(&vec![2; 2 * page_size]).unwrap();
+        buf.consume(2 * page_size);
+        assert!(buf.slice[3 * page_size..size].iter().all(|&x| x == 1));
+        assert!(buf.slice[..page_size].iter().all(|&x| x == 0));
+        assert!(buf.committed().unwrap() == vec![2; page_size - 10]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn mirrored_buffer_dont_fork() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None)
+            .unwrap()
+            .with_dont_fork()
+            .unwrap()
+            .with_guard_pages()
+            .unwrap();
+        assert!(buf.dont_fork());
+        buf.push(b"abc").unwrap();
+
+        let committed = buf.committed().unwrap().as_ptr();
+        match unsafe { libc::fork() } {
+            0 => unsafe {
+                ptr::read_volatile(committed);
+                libc::_exit(0);
+            },
+            pid => {
+                let mut status = 0;
+                assert!(unsafe { libc::waitpid(pid, &mut status, 0) } == pid);
+                assert!(libc::WIFSIGNALED(status));
+            }
+        }
+        assert!(buf.committed().unwrap() == b"abc");
+    }
+
+    // The values of `key` in /proc/self/smaps for the mapping starting at
+    // `addr`.
+    #[cfg(target_os = "linux")]
+    fn smaps(addr: *const u8, key: &str) -> Vec<String> {
+        let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+        let start = format!("{:x}-", addr as usize);
+        let mut values = Vec::new();
+        let mut ours = false;
+        for line in smaps.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some(field) if field == key && ours => values.extend(fields.map(String::from)),
+                Some(field) if !field.ends_with(':') => ours = field.starts_with(&start),
+                _ => {}
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn mirrored_buffer_grow_and_shrink() {
+        let page_size = get_page_size().unwrap();
+        let mut buf = MirroredBuffer::new(page_size, Some(&next_buffer_index()), None).unwrap();
+        let data: Vec<u8> = (0..page_size).map(|i| i as u8).collect();
+        buf.grow(1).unwrap();
+        assert!(buf.size() == page_size);
+
+        // Wrapped around the end, then not.
+        buf.commit(page_size - 10);
+        buf.consume(page_size - 10);
+        buf.push(&data[..100]).unwrap();
+        buf.grow(2 * page_size).unwrap();
+        assert!(buf.size() == 2 * page_size && buf.committed().unwrap() == &data[..100]);
+        buf.push(&data).unwrap();
+        buf.grow(4 * page_size).unwrap();
+        assert!(buf.size() == 4 * page_size);
+        assert!(buf.committed().unwrap()[100..] == data);
+        let name = buf.name().to_string();
+        let info = registry::list().into_iter().find(|info| info.name == name);
+        assert!(info.unwrap().size == 4 * page_size);
+
+        // Down to what is committed, around the end of the smaller size too.
+        let err = buf.shrink_to(page_size).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+        buf.consume(100);
+        buf.shrink_to(page_size).unwrap();
+        assert!(buf.size() == page_size && buf.committed().unwrap() == data);
+        let err = buf.shrink_to(2 * page_size).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+        buf.consume(page_size - 1);
+        buf.push(b"ab").unwrap();
+        assert!(buf.committed().unwrap() == [data[page_size - 1], b'a', b'b']);
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        assert!(unsafe { libc::fstat(buf.as_raw_fd(), &mut stat) } == 0);
+        assert!(stat.st_size as usize == page_size);
+
+        // Not while pinned.
+        buf.registration_region();
+        let err = buf.grow(8 * page_size).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::Pinned));
+    }
+
+    #[test]
+    fn mirrored_buffer_lazy_fill() {
+        let page_size = get_page_size().unwrap();
+        let buf = MirroredBuffer::new(4 * page_size, Some(&next_buffer_index()), None).unwrap();
+        let mut buf = buf.with_lazy_fill(7);
+        assert!(buf.lazy_fill() == Some(7));
+        assert!(buf.residency().unwrap().resident == 0);
+
+        // Only what is handed out is filled, and only once.
+        assert!(buf.claim(10).unwrap().iter().all(|&x| x == 7));
+        buf.claim(10).unwrap()[0] = 1;
+        assert!(buf.claim(20).unwrap()[..11] == [1, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7]);
+        assert!(buf.residency().unwrap().resident == 1);
+        buf.claim(page_size).unwrap();
+        buf.commit(page_size);
+        assert!(buf.committed().unwrap()[page_size - 1] == 7);
+        buf.push(&vec![2; page_size]).unwrap();
+        assert!(buf.committed().unwrap()[page_size..]
+            .iter()
+            .all(|&x| x == 2));
+        assert!(buf.residency().unwrap().resident == 2);
+
+        // Around the ring.
+        buf.consume(2 * page_size);
+        assert!(buf.claim(2 * page_size).unwrap().iter().all(|&x| x == 7));
+        buf.commit(2 * page_size);
+        assert!(buf.claim(page_size).unwrap()[..10] == [1, 7, 7, 7, 7, 7, 7, 7, 7, 7]);
+
+        // Eagerly, a new buffer is filled all at once.
+        let buf = MirroredBuffer::new(page_size, Some(&next_buffer_index()), Some(3)).unwrap();
+        assert!(buf.slice.iter().all(|&x| x == 3));
+    }
+
+    #[test]
+    fn mirrored_buffer_residency() {
+        let page_size = get_page_size().unwrap();
+        let mut buf = MirroredBuffer::new(4 * page_size, Some(&next_buffer_index()), None).unwrap();
+        let residency = buf.residency().unwrap();
+        assert!(residency.pages == 4 && residency.resident == 0 && residency.locked == 0);
+
+        buf.push(&vec![1; 2 * page_size]).unwrap();
+        assert!(buf.residency().unwrap().resident == 2);
+        let buf = buf.with_prefault().unwrap();
+        assert!(buf.residency().unwrap().resident == 4);
+
+        // Locking takes a high enough RLIMIT_MEMLOCK.
+        let addr = buf.slice.as_ptr() as *const libc::c_void;
+        #[cfg(target_os = "linux")]
+        if unsafe { libc::mlock(addr, page_size) } == 0 {
+            assert!(buf.residency().unwrap().locked == 1);
+            unsafe { libc::munlock(addr, page_size) };
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn mirrored_buffer_prefault() {
+        // How much of the half of `buf`'s mapping at `offset` is mapped in.
+        let rss = |buf: &MirroredBuffer, offset: usize| {
+            let addr = buf.slice[offset..].as_ptr();
+            smaps(addr, "Rss:")[0].parse::<usize>().unwrap() * 1024
+        };
+
+        let buf = MirroredBuffer::new(1 << 20, Some(&next_buffer_index()), None).unwrap();
+        let size = buf.size();
+        assert!(rss(&buf, size) == 0);
+        let buf = buf
+            .with_read_only_mirror()
+            .unwrap()
+            .with_prefault()
+            .unwrap();
+        assert!(buf.prefault());
+        assert!(rss(&buf, 0) == size && rss(&buf, size) == size);
+
+        // Moving the mapping faults in the new one.
+        let buf = buf.with_guard_pages().unwrap();
+        assert!(rss(&buf, 0) == size && rss(&buf, size) == size);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn mirrored_buffer_dont_dump() {
+        let vm_flags = |addr| smaps(addr, "VmFlags:");
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None).unwrap();
+        assert!(!vm_flags(buf.slice.as_ptr()).contains(&"dd".to_string()));
+        let buf = buf.with_dont_dump().unwrap();
+        assert!(buf.dont_dump());
+        assert!(vm_flags(buf.slice.as_ptr()).contains(&"dd".to_string()));
+    }
+
+    #[test]
+    fn mirrored_buffer_no_reserve() {
+        // Far more than the address space holds.
+        let huge = 1 << 46;
+        for new in [MirroredBuffer::new, MirroredBuffer::new_no_reserve] {
+            let err = new(huge, Some(&next_buffer_index()), None).err().unwrap();
+            assert!(matches!(err.kind(), ErrorKind::OutOfMemory(size) if *size == 2 * huge));
+        }
+
+        let mut buf = MirroredBuffer::new_no_reserve(1 << 32, Some(&next_buffer_index()), None)
+            .unwrap()
+            .with_guard_pages()
+            .unwrap();
+        assert!(buf.size() == 1 << 32);
+        buf.push(b"abc").unwrap();
+        assert!(buf.committed().unwrap() == b"abc");
+    }
+
+    #[test]
+    fn mirrored_buffer_claim_aligned() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(1)).unwrap();
+        buf.push(b"abc").unwrap();
+
+        let claimed = buf.claim_aligned(10, 64).unwrap();
+        assert!((claimed.as_ptr() as usize).is_multiple_of(64) && claimed.len() == 10);
+        claimed.fill(2);
+        assert!(buf.used() == 64);
+        buf.commit(10);
+        let committed = buf.committed().unwrap();
+        assert!(committed[3..64].iter().all(|&x| x == 0));
+        assert!(committed[64..] == [2; 10]);
+
+        // Already aligned, nothing to pad.
+        buf.push(&[3; 54]).unwrap();
+        assert!(buf.claim_aligned(1, 64).unwrap().len() == 1);
+        assert!(buf.used() == 128);
+
+        // No room past the padding.
+        let size = buf.size();
+        let mut buf = buf.with_full_policy(FullPolicy::Error);
+        buf.push(&vec![4; size - 128 - 1]).unwrap();
+        assert!(buf.claim_aligned(1, 64).is_none());
+        assert!(buf.free() == 1);
+    }
+
+    #[test]
+    fn mirrored_buffer_granularity() {
+        let page_size = get_page_size().unwrap();
+        for granularity in [3 << 16, page_size / 2, 1 << 22] {
+            let buf = MirroredBuffer::new(1 << 21, Some(&next_buffer_index()), None).unwrap();
+            let err = buf.with_granularity(granularity).err().unwrap();
+            assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+        }
+
+        let mut buf = MirroredBuffer::new(1 << 21, Some(&next_buffer_index()), None).unwrap();
+        assert!(buf.granularity() == page_size);
+        buf.push(b"abc").unwrap();
+        let buf = buf
+            .with_granularity(1 << 21)
+            .unwrap()
+            .with_guard_pages()
+            .unwrap();
+        assert!(buf.granularity() == 1 << 21);
+        assert!((buf.slice.as_ptr() as usize).is_multiple_of(1 << 21));
+        assert!(buf.committed().unwrap() == b"abc");
+        assert!(buf.registration_region().alignment == 1 << 21);
+    }
+
+    #[test]
+    fn mirrored_buffer_full_policies() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        assert!(buf.full_policy() == FullPolicy::Clamp);
+        let size = buf.size();
+
+        // clamp
+        let mut buf = buf.with_full_policy(FullPolicy::Clamp);
+        assert!(buf.push(&vec![1; size - 10]).unwrap() == size - 10);
+        assert!(buf.push(&[2; 20]).unwrap() == 10);
+        assert!(buf.free() == 0);
+        buf.consume(size);
+
+        // error
+        let mut buf = buf.with_full_policy(FullPolicy::Error);
+        assert!(buf.push(&vec![1; size - 10]).unwrap() == size - 10);
+        assert!(buf.claim(20).is_none());
+        assert!(buf.commit(20) == 0);
+        let err = buf.push(&[2; 20]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(20)));
+        assert!(buf.used() == size - 10);
+
+        // a buffer on its own has nobody to block on
+        let mut buf = buf.with_full_policy(FullPolicy::Block);
+        assert!(buf.push(&[2; 20]).is_err());
+        assert!(buf.push(&[2; 10]).unwrap() == 10);
+        buf.consume(size);
+
+        // overwrite
+        let mut buf = buf.with_full_policy(FullPolicy::Overwrite);
+        assert!(buf.push(&vec![1; size - 10]).unwrap() == size - 10);
+        assert!(buf.push(&[2; 20]).unwrap() == 20);
+        assert!(buf.used() == size);
+        let committed = buf.committed().unwrap();
+        assert!(committed[..size - 20].iter().all(|&x| x == 1));
+        assert!(committed[size - 20..].iter().all(|&x| x == 2));
+
+        let data: Vec<u8> = (0..size + 5).map(|x| x as u8).collect();
+        assert!(buf.push(&data).unwrap() == size);
+        assert!(buf.committed().unwrap() == &data[5..]);
+    }
+
+    #[test]
+    fn mirrored_buffer_fd() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(7)).unwrap();
+        let fstat = |fd: RawFd| {
+            let mut stat: libc::stat = unsafe { mem::zeroed() };
+            assert!(unsafe { libc::fstat(fd, &mut stat) } == 0);
+            stat
+        };
+        let stat = fstat(buf.as_raw_fd());
+        assert!(stat.st_size as usize == buf.size());
+
+        // The segment outlives the buffer through a duplicate.
+        let fd = buf.try_clone_fd().unwrap();
+        assert!(fd.as_raw_fd() != buf.as_fd().as_raw_fd());
+        let size = buf.size();
+        drop(buf);
+        let dup = fstat(fd.as_raw_fd());
+        assert!(dup.st_ino == stat.st_ino && dup.st_size as usize == size);
+        let mut byte = [0u8];
+        let n = unsafe { libc::pread(fd.as_raw_fd(), byte.as_mut_ptr() as *mut _, 1, 0) };
+        assert!(n == 1 && byte[0] == 7);
+    }
+
+    #[test]
+    fn mirrored_buffer_raw_parts() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None).unwrap();
+        let size = buf.size();
+        buf.claim(size).unwrap();
+        buf.commit(size);
+        buf.consume(size - 2);
+        buf.claim(3).unwrap().copy_from_slice(b"xyz");
+        buf.commit(3);
+        let name = buf.name().to_string();
+        let fd = buf.as_raw_fd();
+
+        let parts = buf.into_raw_parts();
+        assert!(parts.size == size && parts.fd == fd && parts.name.to_str() == Ok(&name));
+        assert!(parts.head == size - 2 && parts.used == 5);
+        // Still mapped, and mirrored.
+        assert!(unsafe { *parts.ptr.add(size) } == b'x');
+
+        let mut buf = unsafe { MirroredBuffer::from_raw_parts(parts) };
+        assert!(buf.name() == name && buf.used() == 5);
+        assert!(&buf.committed().unwrap()[2..] == b"xyz");
+        buf.claim(1).unwrap()[0] = b'!';
+        buf.commit(1);
+        assert!(&buf.committed().unwrap()[2..] == b"xyz!");
+
+        // What the mapping was made with comes back with it.
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None)
+            .unwrap()
+            .with_guard_pages()
+            .unwrap()
+            .with_read_only_mirror()
+            .unwrap();
+        let parts = buf.into_raw_parts();
+        assert!(parts.guard_len > 0 && parts.read_only_mirror);
+        let mut buf = unsafe { MirroredBuffer::from_raw_parts(parts) };
+        assert!(buf.has_read_only_mirror());
+        buf.commit(size - 2);
+        buf.consume(size - 2);
+        assert!(buf.claim(10).unwrap().len() == 2);
+        buf.push(b"across").unwrap();
+        assert!(buf.committed().unwrap() == b"across");
+        // Unmaps the guard pages along with the rest.
+        drop(buf);
+    }
+
+    #[test]
+    fn mirrored_buffer_leak() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None).unwrap();
+        let size = buf.size();
+        buf.claim(1).unwrap()[0] = 42;
+        buf.commit(1);
+        let name = buf.name().to_string();
+
+        let mapping = buf.leak();
+        assert!(mapping.len() == 2 * size);
+        assert!(mapping[0] == 42 && mapping[size] == 42);
+        mapping[size + 1] = 43;
+        assert!(mapping[1] == 43);
+
+        let other = Box::leak(Box::new([0u8; 8]));
+        assert!(unsafe { MirroredBuffer::destroy_leaked(other) }.is_err());
+
+        unsafe { MirroredBuffer::destroy_leaked(mapping) }.unwrap();
+        let name = CString::new(name).unwrap();
+        assert!(unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) } == -1);
+    }
 }
@@ -0,0 +1,226 @@
+use crate::{datagram::socket_addr_to_sockaddr, Error, MirroredBuffer};
+use std::{
+    io, mem,
+    net::SocketAddr,
+    os::unix::io::{AsRawFd, RawFd},
+    ptr,
+};
+
+// The most segments the kernel accepts in a single GSO send.
+pub const GSO_MAX_SEGMENTS: usize = 64;
+
+// The largest UDP payload, which bounds a GSO send and a GRO receive alike.
+const UDP_MAX_PAYLOAD: usize = 65507;
+
+// Makes the UDP socket return coalesced datagrams, see `recv_gro`.
+pub fn enable_gro<S: AsRawFd>(socket: &S) -> io::Result<()> {
+    let one: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_GRO,
+            &one as *const libc::c_int as *const libc::c_void,
+            mem::size_of_val(&one) as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Sends the committed region on the UDP socket as datagrams of
+    // `segment_size` bytes, the last one possibly shorter, with a single
+    // UDP_SEGMENT sendmsg call: the kernel or the NIC does the splitting.
+    // `addr` is the destination for unconnected sockets.
+    //
+    // At most GSO_MAX_SEGMENTS segments go out per call and only whole ones,
+    // so datagram boundaries stay put. What was sent is consumed and its size
+    // returned.
+    pub fn send_gso<S: AsRawFd>(
+        &mut self,
+        socket: &S,
+        segment_size: u16,
+        addr: Option<SocketAddr>,
+    ) -> io::Result<usize> {
+        let segment = segment_size as usize;
+        if segment == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                Error::invalid_size(segment),
+            ));
+        }
+        let Some(committed) = self.committed() else {
+            return Ok(0);
+        };
+        let max = gso_max_len(segment);
+        let len = committed.len().min(max);
+
+        let mut iovec = libc::iovec {
+            iov_base: committed.as_ptr() as *mut libc::c_void,
+            iov_len: len,
+        };
+
+        // Room for a cmsghdr carrying a u16, suitably aligned.
+        let mut control = [0u64; 4];
+        let control_len = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as u32) } as usize;
+        debug_assert!(control_len <= mem::size_of_val(&control));
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        let mut name = addr.map(socket_addr_to_sockaddr);
+        if let Some((storage, storage_len)) = name.as_mut() {
+            msg.msg_name = storage as *mut libc::sockaddr_storage as *mut libc::c_void;
+            msg.msg_namelen = *storage_len;
+        }
+        msg.msg_iov = &mut iovec;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control_len as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+        }
+
+        let n = sendmsg(socket.as_raw_fd(), &msg)?;
+        Ok(self.consume(n))
+    }
+
+    // Receives once from a UDP socket with GRO enabled (see `enable_gro`)
+    // into the claim region, and commits what was received. Returns its size
+    // and the size of the segments it is made of: the kernel coalesces
+    // datagrams of the same size from the same flow, so all of them but the
+    // last are exactly that long. Without coalescing, the segment size is
+    // the size of the datagram.
+    //
+    // A coalesced receive can be as large as a UDP payload gets, so the
+    // buffer should have that much room. If what arrived did not fit, it is
+    // dropped and the error is NoSpace.
+    pub fn recv_gro<S: AsRawFd>(&mut self, socket: &S) -> io::Result<(usize, usize)> {
+        let free = self.free();
+        let Some(claimed) = self.claim(free) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                Error::no_space(UDP_MAX_PAYLOAD),
+            ));
+        };
+
+        let mut iovec = libc::iovec {
+            iov_base: claimed.as_mut_ptr() as *mut libc::c_void,
+            iov_len: claimed.len(),
+        };
+
+        // Room for a cmsghdr carrying an int, suitably aligned.
+        let mut control = [0u64; 4];
+        let control_len = unsafe { libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) };
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iovec;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control_len as _;
+
+        let n = recvmsg(socket.as_raw_fd(), &mut msg)?;
+        if msg.msg_flags & libc::MSG_TRUNC != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                Error::no_space(UDP_MAX_PAYLOAD),
+            ));
+        }
+
+        let mut segment = n;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                    let size = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                    segment = size as usize;
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        Ok((self.commit(n), segment))
+    }
+}
+
+// The most bytes a single GSO send of `segment` sized segments can carry,
+// in whole segments.
+fn gso_max_len(segment: usize) -> usize {
+    let max = (segment * GSO_MAX_SEGMENTS).min(UDP_MAX_PAYLOAD);
+    max - max % segment
+}
+
+fn sendmsg(fd: RawFd, msg: &libc::msghdr) -> io::Result<usize> {
+    loop {
+        let ret = unsafe { libc::sendmsg(fd, msg, 0) };
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn recvmsg(fd: RawFd, msg: &mut libc::msghdr) -> io::Result<usize> {
+    loop {
+        let ret = unsafe { libc::recvmsg(fd, msg, 0) };
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::enable_gro;
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn gso_send_and_gro_recv() {
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        if enable_gro(&rx).is_err() {
+            // Kernels before 5.0 have no UDP GRO.
+            return;
+        }
+
+        let mut out = MirroredBuffer::new(1 << 17, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut buf = MirroredBuffer::new(1 << 17, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 1000;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let data: Vec<u8> = (0..5500).map(|x| (x / 1000) as u8).collect();
+        out.fill_from(&mut &data[..]).unwrap();
+        let n = match out.send_gso(&tx, 1000, Some(rx.local_addr().unwrap())) {
+            Ok(n) => n,
+            // No UDP_SEGMENT before 4.18.
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return,
+            Err(err) => panic!("{err}"),
+        };
+        assert!(n == 5500);
+        assert!(out.used() == 0);
+
+        // However the datagrams are coalesced, they come in whole segments.
+        let mut received = 0;
+        while received < data.len() {
+            let (n, segment) = buf.recv_gro(&rx).unwrap();
+            assert!(segment == 1000 || (n == segment && n <= 1000));
+            received += n;
+        }
+        assert!(buf.committed().unwrap() == data);
+    }
+}
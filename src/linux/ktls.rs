@@ -0,0 +1,96 @@
+use crate::MirroredBuffer;
+use std::{io, mem, os::unix::io::AsRawFd, ptr};
+
+impl<'a> MirroredBuffer<'a> {
+    // Sends the committed region as plaintext on a kTLS-enabled socket with a
+    // single sendmsg call, and consumes what the socket accepted. The kernel
+    // encrypts and frames the data into records of `record_type`, one of the
+    // `codec::tls::CONTENT_TYPE_*` constants, so no userspace copy or
+    // encryption pass is needed.
+    //
+    // Setting up kTLS on the socket (TCP_ULP and the TLS_TX crypto state) is
+    // left to the TLS library that did the handshake.
+    pub fn send_ktls<S: AsRawFd>(&mut self, socket: &S, record_type: u8) -> io::Result<usize> {
+        let Some(committed) = self.committed() else {
+            return Ok(0);
+        };
+
+        let mut iovec = libc::iovec {
+            iov_base: committed.as_ptr() as *mut libc::c_void,
+            iov_len: committed.len(),
+        };
+
+        // Room for a cmsghdr carrying a single byte, suitably aligned.
+        let mut control = [0u64; 4];
+        let control_len = unsafe { libc::CMSG_SPACE(1) } as usize;
+        debug_assert!(control_len <= mem::size_of_val(&control));
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iovec;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control_len as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_TLS;
+            (*cmsg).cmsg_type = libc::TLS_SET_RECORD_TYPE;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(1) as _;
+            ptr::write(libc::CMSG_DATA(cmsg), record_type);
+        }
+
+        let n = loop {
+            let ret = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+            if ret >= 0 {
+                break ret as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+
+        Ok(self.consume(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{codec::tls, util::next_buffer_index, MirroredBuffer};
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+    };
+
+    // Without kTLS set up, TCP ignores the record type control message and
+    // sends the plaintext as is, which is enough to check the data path.
+    #[test]
+    fn ktls_send_plain_socket() {
+        let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tx = TcpStream::connect(ln.local_addr().unwrap()).unwrap();
+        let (mut rx, _) = ln.accept().unwrap();
+
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        assert!(
+            buf.send_ktls(&tx, tls::CONTENT_TYPE_APPLICATION_DATA)
+                .unwrap()
+                == 0
+        );
+
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let data: Vec<u8> = (0..64).collect();
+        buf.fill_from(&mut &data[..]).unwrap();
+        let n = buf
+            .send_ktls(&tx, tls::CONTENT_TYPE_APPLICATION_DATA)
+            .unwrap();
+        assert!(n == 64);
+        assert!(buf.used() == 0);
+
+        let mut received = [0u8; 64];
+        rx.read_exact(&mut received).unwrap();
+        assert!(received[..] == data[..]);
+    }
+}
@@ -0,0 +1,12 @@
+mod gso;
+mod ktls;
+mod numa;
+mod sendfile;
+mod splice;
+mod xdp;
+mod zerocopy;
+
+pub use gso::{enable_gro, GSO_MAX_SEGMENTS};
+pub use numa::NumaPolicy;
+pub use xdp::XdpUmem;
+pub use zerocopy::ZeroCopySender;
@@ -0,0 +1,106 @@
+use crate::{Error, MirroredBuffer};
+use std::{io, ptr};
+
+// From linux/mempolicy.h, which libc does not have.
+const MPOL_BIND: libc::c_int = 2;
+const MPOL_INTERLEAVE: libc::c_int = 3;
+const MPOL_F_NODE: libc::c_ulong = 1 << 0;
+const MPOL_F_ADDR: libc::c_ulong = 1 << 1;
+const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+
+// Where the pages of a buffer go, see `MirroredBuffer::with_numa_policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NumaPolicy {
+    // On the node, e.g. the one the NIC or the consuming thread is on.
+    Bind(u32),
+    // Spread over the nodes, page by page.
+    Interleave(Vec<u32>),
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Places the pages of the buffer as `policy` says, moving those already
+    // in memory, so the ring lives next to what uses it on machines with
+    // several NUMA nodes. The policy belongs to the segment, so it holds
+    // whichever half of the mapping a page is touched through.
+    pub fn with_numa_policy(self, policy: NumaPolicy) -> Result<MirroredBuffer<'a>, Error> {
+        let (mode, nodes) = match &policy {
+            NumaPolicy::Bind(node) => (MPOL_BIND, std::slice::from_ref(node)),
+            NumaPolicy::Interleave(nodes) => (MPOL_INTERLEAVE, &nodes[..]),
+        };
+        let bits = libc::c_ulong::BITS as usize;
+        let max = nodes.iter().max().map_or(0, |&node| node as usize);
+        let mut mask: Vec<libc::c_ulong> = vec![0; max / bits + 1];
+        for &node in nodes {
+            mask[node as usize / bits] |= 1 << (node as usize % bits);
+        }
+
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_mbind,
+                self.slice.as_ptr(),
+                self.slice.len(),
+                mode,
+                mask.as_ptr(),
+                // The kernel counts one bit less than it is told.
+                mask.len() * bits + 1,
+                MPOL_MF_MOVE,
+            )
+        };
+        if ret == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(self)
+    }
+
+    // The node the first page of the buffer is on, faulting it in if it is
+    // not in memory yet.
+    pub fn numa_node(&self) -> io::Result<u32> {
+        let mut node: libc::c_int = 0;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_get_mempolicy,
+                &mut node as *mut libc::c_int,
+                ptr::null_mut::<libc::c_ulong>(),
+                0,
+                self.slice.as_ptr(),
+                MPOL_F_NODE | MPOL_F_ADDR,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(node as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, ErrorKind, MirroredBuffer, NumaPolicy};
+    use std::io;
+
+    #[test]
+    fn numa_policy() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        // Kernels built without NUMA support have none of it.
+        if buf
+            .numa_node()
+            .is_err_and(|err| err.raw_os_error() == Some(libc::ENOSYS))
+        {
+            return;
+        }
+        let node = buf.numa_node().unwrap();
+
+        let mut buf = buf.with_numa_policy(NumaPolicy::Bind(node)).unwrap();
+        buf.push(b"abc").unwrap();
+        assert!(buf.numa_node().unwrap() == node);
+        let buf = buf
+            .with_numa_policy(NumaPolicy::Interleave(vec![node]))
+            .unwrap();
+        assert!(buf.committed().unwrap() == b"abc");
+
+        let err = buf.with_numa_policy(NumaPolicy::Bind(1000)).err().unwrap();
+        assert!(
+            matches!(err.kind(), ErrorKind::IO(err) if err.kind() == io::ErrorKind::InvalidInput)
+        );
+    }
+}
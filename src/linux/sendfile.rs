@@ -0,0 +1,122 @@
+use crate::MirroredBuffer;
+use std::{
+    cmp, io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+impl<'a> MirroredBuffer<'a> {
+    // Transmits the committed region to the socket `fd` with sendfile(2),
+    // reading straight from the backing shm file at the head's offset, so the
+    // data is not copied through userspace. What was sent is consumed.
+    //
+    // Like `flush_to`, this keeps going until the committed region is empty;
+    // if the socket would block after some bytes were sent the count so far
+    // is returned, otherwise the WouldBlock error is.
+    pub fn send_to_socket_zero_copy<S: AsRawFd>(&mut self, fd: &S) -> io::Result<usize> {
+        let fd = fd.as_raw_fd();
+        let mut sent = 0;
+
+        while self.used() > 0 {
+            // The backing file is only `size()` long, so committed data that
+            // wraps around its end takes a second sendfile.
+            let offset = self.head;
+            let len = cmp::min(self.used(), self.size() - offset);
+
+            match sendfile(fd, self.fd, self.file_offset + offset, len) {
+                Ok(0) => {
+                    if sent > 0 {
+                        return Ok(sent);
+                    }
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                Ok(n) => sent += self.consume(n),
+                Err(err) if sent > 0 && err.kind() == io::ErrorKind::WouldBlock => return Ok(sent),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(sent)
+    }
+}
+
+fn sendfile(out_fd: RawFd, in_fd: RawFd, offset: usize, len: usize) -> io::Result<usize> {
+    let mut offset = offset as libc::off_t;
+    loop {
+        let ret = unsafe { libc::sendfile(out_fd, in_fd, &mut offset, len) };
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        util::{get_page_size, next_buffer_index},
+        Arena, MirroredBuffer,
+    };
+    use std::{io::Read, os::unix::net::UnixStream};
+
+    #[test]
+    fn sendfile_send_to_socket_zero_copy() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (tx, mut rx) = UnixStream::pair().unwrap();
+        assert!(buf.send_to_socket_zero_copy(&tx).unwrap() == 0);
+
+        let offset = buf.size() - 100;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let data: Vec<u8> = (0..250).map(|x| x as u8).collect();
+        buf.fill_from(&mut &data[..]).unwrap();
+
+        assert!(buf.send_to_socket_zero_copy(&tx).unwrap() == 250);
+        assert!(buf.used() == 0);
+
+        let mut received = vec![0u8; 250];
+        rx.read_exact(&mut received).unwrap();
+        assert!(received == data);
+    }
+
+    #[test]
+    fn sendfile_would_block() {
+        let mut buf = MirroredBuffer::new(1 << 20, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (tx, _rx) = UnixStream::pair().unwrap();
+        tx.set_nonblocking(true).unwrap();
+
+        buf.commit(buf.size());
+        let sent = buf.send_to_socket_zero_copy(&tx).unwrap();
+        assert!(sent > 0 && sent < buf.size());
+        assert!(buf.used() == buf.size() - sent);
+
+        let err = buf.send_to_socket_zero_copy(&tx).unwrap_err();
+        assert!(err.kind() == std::io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn sendfile_arena_ring() {
+        let page_size = get_page_size().unwrap();
+        let arena = Arena::new(2 * page_size).unwrap();
+        let mut first = arena.ring(page_size).unwrap();
+        let mut second = arena.ring(page_size).unwrap();
+        first.claim(page_size).unwrap().fill(1);
+        first.commit(page_size);
+        let (tx, mut rx) = UnixStream::pair().unwrap();
+
+        // From the second ring's piece of the file, around its end.
+        let offset = page_size - 100;
+        second.commit(offset);
+        second.consume(offset);
+        let data: Vec<u8> = (0..250).map(|x| x as u8).collect();
+        second.fill_from(&mut &data[..]).unwrap();
+        assert!(second.send_to_socket_zero_copy(&tx).unwrap() == 250);
+
+        let mut received = vec![0u8; 250];
+        rx.read_exact(&mut received).unwrap();
+        assert!(received == data);
+    }
+}
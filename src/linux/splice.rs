@@ -0,0 +1,149 @@
+use crate::MirroredBuffer;
+use std::{
+    cmp, io,
+    os::unix::io::{AsRawFd, RawFd},
+    ptr,
+};
+
+impl<'a> MirroredBuffer<'a> {
+    // Moves up to `n` bytes from the pipe `fd` into the free region with
+    // splice(2), through the backing shm file rather than the mapping, and
+    // commits what was moved. The data never passes through userspace.
+    //
+    // As the backing file is only `size()` long, a region that wraps around
+    // its end takes a second splice. Blocks if the pipe is empty unless it is
+    // nonblocking; returns Ok(0) on EOF or if the buffer is full.
+    pub fn splice_from_pipe<P: AsRawFd>(&mut self, fd: &P, n: usize) -> io::Result<usize> {
+        let fd = fd.as_raw_fd();
+        let mut remaining = cmp::min(n, self.free());
+        let mut moved = 0;
+
+        while remaining > 0 {
+            let offset = self.tail;
+            let len = cmp::min(remaining, self.size() - offset);
+
+            match splice(fd, self.fd, self.file_offset + offset, len) {
+                Ok(0) => break,
+                Ok(n) => {
+                    moved += self.commit(n);
+                    remaining -= n;
+                    if n < len {
+                        break;
+                    }
+                }
+                Err(err) if moved > 0 && err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(moved)
+    }
+}
+
+fn splice(fd_in: RawFd, fd_out: RawFd, offset: usize, len: usize) -> io::Result<usize> {
+    let mut offset = offset as libc::loff_t;
+    loop {
+        let ret = unsafe {
+            libc::splice(
+                fd_in,
+                ptr::null_mut(),
+                fd_out,
+                &mut offset,
+                len,
+                libc::SPLICE_F_MOVE,
+            )
+        };
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        util::{get_page_size, next_buffer_index},
+        Arena, MirroredBuffer,
+    };
+    use std::{
+        fs::File,
+        io::Write,
+        os::unix::io::{FromRawFd, OwnedFd},
+    };
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        assert!(unsafe { libc::pipe(fds.as_mut_ptr()) } == 0);
+        unsafe {
+            (
+                File::from(OwnedFd::from_raw_fd(fds[0])),
+                File::from(OwnedFd::from_raw_fd(fds[1])),
+            )
+        }
+    }
+
+    #[test]
+    fn splice_from_pipe() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (rx, mut tx) = pipe();
+
+        // The spliced region wraps around the end of the backing file.
+        let offset = buf.size() - 100;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let data: Vec<u8> = (0..250).map(|x| x as u8).collect();
+        tx.write_all(&data).unwrap();
+
+        assert!(buf.splice_from_pipe(&rx, 200).unwrap() == 200);
+        assert!(buf.committed().unwrap() == &data[..200]);
+
+        drop(tx);
+        assert!(buf.splice_from_pipe(&rx, 100).unwrap() == 50);
+        assert!(buf.committed().unwrap() == data);
+
+        // EOF
+        assert!(buf.splice_from_pipe(&rx, 100).unwrap() == 0);
+        assert!(buf.used() == 250);
+    }
+
+    #[test]
+    fn splice_from_pipe_lazy_fill() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None)
+            .unwrap()
+            .with_lazy_fill(0xaa);
+        let (rx, mut tx) = pipe();
+
+        // What was spliced is what gets committed, and what follows is still
+        // filled once claimed.
+        tx.write_all(b"hello world").unwrap();
+        assert!(buf.splice_from_pipe(&rx, 100).unwrap() == 11);
+        assert!(buf.committed().unwrap() == b"hello world");
+        assert!(buf.claim(100).unwrap().iter().all(|&x| x == 0xaa));
+    }
+
+    #[test]
+    fn splice_from_pipe_arena_ring() {
+        let page_size = get_page_size().unwrap();
+        let arena = Arena::new(2 * page_size).unwrap();
+        let mut first = arena.ring(page_size).unwrap();
+        let mut second = arena.ring(page_size).unwrap();
+        first.claim(page_size).unwrap().fill(1);
+        first.commit(page_size);
+        let (rx, mut tx) = pipe();
+
+        // Into the second ring's piece of the file, around its end.
+        let offset = page_size - 100;
+        second.commit(offset);
+        second.consume(offset);
+        let data: Vec<u8> = (0..250).map(|x| x as u8).collect();
+        tx.write_all(&data).unwrap();
+        assert!(second.splice_from_pipe(&rx, 250).unwrap() == 250);
+        assert!(second.committed().unwrap() == data);
+        assert!(first.committed().unwrap().iter().all(|&x| x == 1));
+    }
+}
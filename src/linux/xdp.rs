@@ -0,0 +1,159 @@
+use crate::{util::get_page_size, Error, MirroredBuffer};
+use std::{
+    io, mem,
+    os::unix::io::AsRawFd,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// The smallest chunk AF_XDP takes. Chunks are powers of two, at most a page.
+const XDP_MIN_CHUNK_SIZE: usize = 2048;
+
+static UMEM_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+// A buffer laid out as the UMEM of AF_XDP sockets: fixed size chunks, which
+// the kernel writes received packets into, one per chunk. The chunks fill the
+// first half of the mapping, which is what gets registered; the mirror lets
+// a batch of consecutive chunks be processed as one slice, even when it runs
+// past the last chunk, see `chunks`.
+//
+// Setting up the fill, completion, RX and TX rings is left to the caller,
+// who hands out chunks by their address, see `chunk_addr`.
+pub struct XdpUmem<'a> {
+    buf: MirroredBuffer<'a>,
+    chunk_size: usize,
+    headroom: usize,
+}
+
+impl<'a> XdpUmem<'a> {
+    // Creates a UMEM of at least `size` bytes, cut into chunks of
+    // `chunk_size`, where the kernel leaves `headroom` bytes before each
+    // packet. Fails with InvalidSize if AF_XDP would not take the chunk size
+    // or the headroom.
+    pub fn new(size: usize, chunk_size: usize, headroom: usize) -> Result<XdpUmem<'a>, Error> {
+        let page_size = get_page_size()?;
+        if !chunk_size.is_power_of_two() || !(XDP_MIN_CHUNK_SIZE..=page_size).contains(&chunk_size)
+        {
+            return Err(Error::invalid_size(chunk_size));
+        }
+        if headroom >= chunk_size {
+            return Err(Error::invalid_size(headroom));
+        }
+        let suffix = format!("umem-{}", UMEM_INDEX.fetch_add(1, Ordering::Relaxed));
+        let buf = MirroredBuffer::new(size, Some(&suffix), None)?;
+        Ok(XdpUmem {
+            buf,
+            chunk_size,
+            headroom,
+        })
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.buf.size() / self.chunk_size
+    }
+
+    pub fn headroom(&self) -> usize {
+        self.headroom
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    // The UMEM address of chunk `index`, counting past the last chunk from
+    // the first again, e.g. to post it to the fill ring.
+    pub fn chunk_addr(&self, index: usize) -> u64 {
+        ((index % self.chunk_count()) * self.chunk_size) as u64
+    }
+
+    // The index of the chunk holding the UMEM address `addr`, e.g. of an RX
+    // descriptor.
+    pub fn chunk_index(&self, addr: u64) -> usize {
+        addr as usize / self.chunk_size
+    }
+
+    // The `count` chunks from chunk `first` on, as one slice, going from the
+    // last chunk to the first through the mirror.
+    pub fn chunks(&self, first: usize, count: usize) -> &[u8] {
+        assert!(count <= self.chunk_count(), "more chunks than the UMEM has");
+        let start = self.chunk_addr(first) as usize;
+        &self.buf.slice[start..start + count * self.chunk_size]
+    }
+
+    pub fn chunks_mut(&mut self, first: usize, count: usize) -> &mut [u8] {
+        assert!(count <= self.chunk_count(), "more chunks than the UMEM has");
+        let start = self.chunk_addr(first) as usize;
+        &mut self.buf.slice[start..start + count * self.chunk_size]
+    }
+
+    // Registers the UMEM with the AF_XDP socket, which pins the buffer, see
+    // `MirroredBuffer::registration_region`. Sockets sharing the UMEM are
+    // bound with XDP_SHARED_UMEM instead.
+    pub fn register<S: AsRawFd>(&self, socket: &S) -> io::Result<()> {
+        let region = self.buf.registration_region();
+        let reg = libc::xdp_umem_reg_v1 {
+            addr: region.addr as u64,
+            len: self.buf.size() as u64,
+            chunk_size: self.chunk_size as u32,
+            headroom: self.headroom as u32,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_XDP,
+                libc::XDP_UMEM_REG,
+                &reg as *const libc::xdp_umem_reg_v1 as *const libc::c_void,
+                mem::size_of_val(&reg) as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::get_page_size, ErrorKind, XdpUmem};
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    #[test]
+    fn xdp_umem_chunks() {
+        let page_size = get_page_size().unwrap();
+        for (chunk_size, headroom) in [(1024, 0), (3000, 0), (2 * page_size, 0), (2048, 2048)] {
+            let err = XdpUmem::new(1, chunk_size, headroom).err().unwrap();
+            assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+        }
+
+        let mut umem = XdpUmem::new(4 * page_size, 2048, 256).unwrap();
+        let count = umem.chunk_count();
+        assert!(count == 2 * page_size / 1024 && umem.buffer().size() == 4 * page_size);
+        assert!(umem.chunk_addr(1) == 2048 && umem.chunk_addr(count + 1) == 2048);
+        assert!(umem.chunk_index(2048 + 256 + 10) == 1);
+
+        // A batch past the last chunk is one slice.
+        umem.chunks_mut(count - 1, 1).fill(1);
+        umem.chunks_mut(0, 1).fill(2);
+        let batch = umem.chunks(count - 1, 2);
+        assert!(batch.len() == 4096);
+        assert!(batch[..2048].iter().all(|&b| b == 1));
+        assert!(batch[2048..].iter().all(|&b| b == 2));
+
+        // Registering takes privileges, and a kernel with AF_XDP.
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW, 0) };
+        if fd == -1 {
+            return;
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        umem.register(&socket).unwrap();
+        assert!(umem.buffer().is_pinned());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{FullPolicy, MirroredBuffer};
+use std::{cmp, collections::VecDeque, io, mem, os::unix::io::AsRawFd};
+
+// Not exported by libc.
+const SO_EE_ORIGIN_ZEROCOPY: u8 = 5;
+
+// Sends committed data with send(MSG_ZEROCOPY). The kernel transmits straight
+// from the buffer's pages, which therefore must not be reused before it is
+// done with them: sent bytes stay in flight, and are consumed only once the
+// completion notifications for them are read off the socket's error queue by
+// `reap_completions`.
+//
+// New data is staged with `claim`/`commit` as usual. Committed bytes that are
+// not in flight yet go out on the next `send`. Under FullPolicy::Overwrite,
+// claims and commits clamp instead, as overwriting would reuse pages that may
+// be in flight.
+pub struct ZeroCopySender<'a> {
+    buf: MirroredBuffer<'a>,
+    in_flight: usize,
+    next_seq: u32,
+    // One entry per zerocopy send, in order: its sequence number, its length
+    // and whether it completed.
+    pending: VecDeque<(u32, usize, bool)>,
+}
+
+impl<'a> ZeroCopySender<'a> {
+    // Sets SO_ZEROCOPY on `socket`, without which MSG_ZEROCOPY is rejected.
+    pub fn enable<S: AsRawFd>(socket: &S) -> io::Result<()> {
+        let one: libc::c_int = 1;
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ZEROCOPY,
+                &one as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn new(buf: MirroredBuffer<'a>) -> ZeroCopySender<'a> {
+        ZeroCopySender {
+            buf,
+            in_flight: 0,
+            next_seq: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    // Bytes still in flight are left committed, and must not be consumed
+    // before the kernel is done with them.
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    pub fn claim(&mut self, size: usize) -> Option<&mut [u8]> {
+        let size = self.room(size);
+        self.buf.claim(size)
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        let size = self.room(size);
+        self.buf.commit(size)
+    }
+
+    // No more than is free when overwriting.
+    fn room(&self, size: usize) -> usize {
+        match self.buf.full_policy() {
+            FullPolicy::Overwrite => cmp::min(size, self.buf.free()),
+            _ => size,
+        }
+    }
+
+    // Bytes sent but not yet released by the kernel.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    // Bytes committed but not sent yet.
+    pub fn unsent(&self) -> usize {
+        self.buf.used() - self.in_flight
+    }
+
+    // Sends the unsent committed bytes with a single send(MSG_ZEROCOPY) call,
+    // returning how many the socket accepted.
+    pub fn send<S: AsRawFd>(&mut self, socket: &S) -> io::Result<usize> {
+        let Some(committed) = self.buf.committed() else {
+            return Ok(0);
+        };
+        let unsent = &committed[self.in_flight..];
+        if unsent.is_empty() {
+            return Ok(0);
+        }
+
+        let n = loop {
+            let ret = unsafe {
+                libc::send(
+                    socket.as_raw_fd(),
+                    unsent.as_ptr() as *const libc::c_void,
+                    unsent.len(),
+                    libc::MSG_ZEROCOPY,
+                )
+            };
+            if ret >= 0 {
+                break ret as usize;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+
+        // Each successful call, even one sending 0 bytes, takes the next
+        // sequence number.
+        self.pending.push_back((self.next_seq, n, false));
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.in_flight += n;
+
+        Ok(n)
+    }
+
+    // Reads all available completion notifications off the error queue of
+    // `socket`, without blocking, and consumes the bytes of every send which
+    // completed along with all the ones before it. Returns how many bytes
+    // were consumed.
+    pub fn reap_completions<S: AsRawFd>(&mut self, socket: &S) -> io::Result<usize> {
+        let mut control = [0u64; 16];
+
+        loop {
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = mem::size_of_val(&control) as _;
+
+            let ret = unsafe {
+                libc::recvmsg(
+                    socket.as_raw_fd(),
+                    &mut msg,
+                    libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+                )
+            };
+            if ret == -1 {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => break,
+                    _ => return Err(err),
+                }
+            }
+
+            let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+            while !cmsg.is_null() {
+                let ee = unsafe {
+                    (libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err).read_unaligned()
+                };
+                if ee.ee_errno == 0 && ee.ee_origin == SO_EE_ORIGIN_ZEROCOPY {
+                    self.complete(ee.ee_info, ee.ee_data);
+                }
+                cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+            }
+        }
+
+        let mut released = 0;
+        while let Some(&(_, n, true)) = self.pending.front() {
+            self.pending.pop_front();
+            released += n;
+        }
+        self.in_flight -= released;
+        Ok(self.buf.consume(released))
+    }
+
+    // Marks the sends numbered [lo, hi] as completed.
+    fn complete(&mut self, lo: u32, hi: u32) {
+        for (seq, _, completed) in self.pending.iter_mut() {
+            if seq.wrapping_sub(lo) <= hi.wrapping_sub(lo) {
+                *completed = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ZeroCopySender;
+    use crate::{util::next_buffer_index, FullPolicy, MirroredBuffer};
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn zerocopy_send_and_reap() {
+        let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tx = TcpStream::connect(ln.local_addr().unwrap()).unwrap();
+        let (mut rx, _) = ln.accept().unwrap();
+
+        if ZeroCopySender::enable(&tx).is_err() {
+            return; // not supported by this kernel
+        }
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut sender = ZeroCopySender::new(buf);
+        assert!(sender.send(&tx).unwrap() == 0);
+
+        sender.claim(100).unwrap().fill(1);
+        sender.commit(100);
+        assert!(sender.send(&tx).unwrap() == 100);
+        sender.claim(50).unwrap().fill(2);
+        sender.commit(50);
+        assert!(sender.unsent() == 50);
+        assert!(sender.send(&tx).unwrap() == 50);
+        assert!(sender.in_flight() == 150);
+        assert!(sender.buffer().used() == 150);
+
+        let mut received = [0u8; 150];
+        rx.read_exact(&mut received).unwrap();
+        assert!(received[..100].iter().all(|&x| x == 1));
+        assert!(received[100..].iter().all(|&x| x == 2));
+
+        let mut reaped = 0;
+        for _ in 0..100 {
+            reaped += sender.reap_completions(&tx).unwrap();
+            if reaped == 150 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(reaped == 150);
+        assert!(sender.in_flight() == 0);
+        assert!(sender.into_inner().used() == 0);
+    }
+
+    #[test]
+    fn zerocopy_complete_out_of_order() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut sender = ZeroCopySender::new(buf);
+        sender.commit(30);
+        sender.in_flight = 30;
+        sender.next_seq = 3;
+        sender
+            .pending
+            .extend([(0, 10, false), (1, 10, false), (2, 10, false)]);
+
+        sender.complete(1, 2);
+        assert!(sender.pending.iter().filter(|p| p.2).count() == 2);
+        assert!(!sender.pending[0].2);
+
+        sender.complete(0, 0);
+        assert!(sender.pending.iter().all(|p| p.2));
+    }
+
+    #[test]
+    fn zerocopy_overwrite_clamps() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0))
+            .unwrap()
+            .with_full_policy(FullPolicy::Overwrite);
+        let size = buf.size();
+        let mut sender = ZeroCopySender::new(buf);
+
+        // A full buffer takes no more, leaving what may be in flight as is.
+        assert!(sender.claim(size + 10).unwrap().len() == size);
+        sender.claim(size).unwrap().fill(1);
+        assert!(sender.commit(size + 10) == size);
+        assert!(sender.claim(1).is_none() && sender.commit(1) == 0);
+        assert!(sender.unsent() == size);
+        let buf = sender.into_inner();
+        assert!(buf.committed().unwrap().iter().all(|&x| x == 1));
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    split::{CachePadded, Consumer, Shared},
+    Error, MirroredBuffer,
+};
+use std::{
+    hint,
+    ops::{Deref, DerefMut},
+    slice,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// A producer of a buffer several threads write to, see `split_mpsc`.
+//
+// Producers reserve space by moving a reservation index forward with a
+// compare and swap, which hands each of them a region of its own. They then
+// write their region and publish it by storing the shared tail, in the order
+// the regions were reserved: a producer whose region comes after one that is
+// not published yet spins until it is. The consumer only ever sees
+// published regions, so it sees whole frames in reservation order.
+#[derive(Clone)]
+pub struct MpscProducer<'a> {
+    pub(crate) shared: Arc<Shared<'a>>,
+    reserved: Arc<CachePadded<AtomicUsize>>,
+    pub(crate) ptr: *mut u8,
+}
+
+// Producers only touch the regions they reserved, which no other producer
+// nor the consumer touch until they are published.
+unsafe impl Send for MpscProducer<'_> {}
+unsafe impl Sync for MpscProducer<'_> {}
+
+// A region reserved by a producer. It is published when committed or
+// dropped; since the ring cannot have holes, a region dropped before it was
+// written is published as is.
+pub struct Reservation<'p, 'a> {
+    producer: &'p MpscProducer<'a>,
+    start: usize,
+    size: usize,
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Splits the buffer into a producer that can be cloned and shared by
+    // several threads, and a single consumer. Whatever is committed stays
+    // committed. Reservations being whole, they may run across the wrap,
+    // which a read-only mirror does not let them: fails with ReadOnlyMirror
+    // if the buffer has one.
+    pub fn split_mpsc(self) -> Result<(MpscProducer<'a>, Consumer<'a>), Error> {
+        if self.has_read_only_mirror() {
+            return Err(Error::read_only_mirror());
+        }
+        let (shared, ptr) = Shared::new(self);
+        let tail = shared.tail.load(Ordering::Relaxed);
+
+        Ok((
+            MpscProducer {
+                shared: shared.clone(),
+                reserved: Arc::new(CachePadded(AtomicUsize::new(tail))),
+                ptr,
+            },
+            Consumer::new(shared, ptr),
+        ))
+    }
+}
+
+impl<'a> MpscProducer<'a> {
+    pub fn name(&self) -> &str {
+        self.shared.buf.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.shared.buf.size()
+    }
+
+    // Reserves `size` bytes after whatever the other producers reserved, or
+    // returns None if they are not free. Unlike `claim`, this is all or
+    // nothing: a frame is never cut short.
+    pub fn reserve(&self, size: usize) -> Option<Reservation<'_, 'a>> {
+        if size == 0 || size > self.size() {
+            return None;
+        }
+
+        let mut start = self.reserved.load(Ordering::Relaxed);
+        loop {
+            let head = self.shared.head.load(Ordering::Acquire);
+            if start.wrapping_sub(head) + size > self.size() {
+                return None;
+            }
+            match self.reserved.compare_exchange_weak(
+                start,
+                start.wrapping_add(size),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => start = current,
+            }
+        }
+
+        Some(Reservation {
+            producer: self,
+            start,
+            size,
+        })
+    }
+
+    // Reserves room for `data`, copies it in and commits it.
+    pub fn push(&self, data: &[u8]) -> Result<(), Error> {
+        let Some(mut reservation) = self.reserve(data.len()) else {
+            return Err(Error::no_space(data.len()));
+        };
+        reservation.copy_from_slice(data);
+        reservation.commit();
+        Ok(())
+    }
+}
+
+impl Reservation<'_, '_> {
+    // Publishes the region to the consumer, once the regions reserved before
+    // it are.
+    pub fn commit(self) {}
+
+    fn publish(&self) {
+        let tail = &self.producer.shared.tail;
+        while tail.load(Ordering::Acquire) != self.start {
+            hint::spin_loop();
+        }
+        tail.store(self.start.wrapping_add(self.size), Ordering::Release);
+        self.producer.shared.data.notify();
+    }
+}
+
+impl Drop for Reservation<'_, '_> {
+    fn drop(&mut self) {
+        self.publish();
+    }
+}
+
+impl Deref for Reservation<'_, '_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let offset = self.start & self.producer.shared.buf.size_mask;
+        unsafe { slice::from_raw_parts(self.producer.ptr.add(offset), self.size) }
+    }
+}
+
+impl DerefMut for Reservation<'_, '_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let offset = self.start & self.producer.shared.buf.size_mask;
+        unsafe { slice::from_raw_parts_mut(self.producer.ptr.add(offset), self.size) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        codec::length_delimited::{Codec, HEADER_LEN},
+        util::next_buffer_index,
+        ErrorKind, MirroredBuffer,
+    };
+    use std::thread;
+
+    #[test]
+    fn mpsc_producers_keep_frames_whole() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let (producer, mut consumer) = buf.split_mpsc().unwrap();
+        assert!(producer.reserve(0).is_none());
+        assert!(producer.reserve(producer.size() + 1).is_none());
+        let err = producer.push(&vec![0; producer.size() + 1]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+
+        let producers = 4;
+        let frames = 2000;
+        let writers: Vec<_> = (0..producers)
+            .map(|id| {
+                let producer = producer.clone();
+                thread::spawn(move || {
+                    for i in 0..frames {
+                        // [len][producer id][sequence number][payload]
+                        let len = 5 + (i * 7) % 200;
+                        let mut frame = (len as u32).to_be_bytes().to_vec();
+                        frame.push(id as u8);
+                        frame.extend((i as u32).to_be_bytes());
+                        frame.resize(HEADER_LEN + len, id as u8);
+                        while producer.push(&frame).is_err() {
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(producer);
+
+        let mut codec = Codec::new().with_max_len(consumer.size() - HEADER_LEN);
+        let mut next = vec![0; producers];
+        let mut decoded = 0;
+        while decoded < producers * frames {
+            let Some((payload, size)) = consumer.decode(&mut codec).unwrap() else {
+                thread::yield_now();
+                continue;
+            };
+            let id = payload[0] as usize;
+            let i = u32::from_be_bytes(payload[1..5].try_into().unwrap()) as usize;
+            assert!(i == next[id]);
+            assert!(payload.len() == 5 + (i * 7) % 200);
+            assert!(payload[5..].iter().all(|&x| x == id as u8));
+            next[id] += 1;
+            consumer.consume(size);
+            decoded += 1;
+        }
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert!(consumer.used() == 0);
+    }
+
+    #[test]
+    fn mpsc_rejects_a_read_only_mirror() {
+        // A reservation from here would run across the wrap.
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+        let buf = buf.with_read_only_mirror().unwrap();
+        let err = buf.split_mpsc().err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::ReadOnlyMirror));
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    hint, io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    sync::{
+        atomic::{self, AtomicBool, AtomicU32, Ordering},
+        Mutex, OnceLock,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+// How many times a wait checks its condition, spinning, before it sleeps.
+const SPINS: usize = 100;
+
+// Lets threads sleep until a condition on some atomic, e.g. enough bytes
+// committed, holds, and whoever changes the atomic wake them.
+//
+// Waiters announce themselves in `waiters`, read `seq`, check the condition
+// again and only then sleep, for as long as `seq` did not change. Notifiers
+// store the atomic, then check `waiters`, and only if there are any bump
+// `seq` and wake them: with no waiters, notifying costs a fence and a load.
+// Both sides go through sequentially consistent operations, so either the
+// notifier sees the waiter or the waiter sees what the notifier stored.
+//
+// Sleeping is a futex wait on `seq` on Linux, and a condition variable
+// elsewhere.
+//
+// Event loops cannot sleep on either, so for them notifiers also signal an
+// event fd, once one was asked for. Async tasks register a waker instead,
+// which notifiers take and wake, with the same handshake as sleepers
+// through `has_waker`.
+pub(crate) struct Notify {
+    seq: AtomicU32,
+    waiters: AtomicU32,
+    event: OnceLock<Event>,
+    waker: Mutex<Option<Waker>>,
+    has_waker: AtomicBool,
+    #[cfg(not(target_os = "linux"))]
+    lock: std::sync::Mutex<()>,
+    #[cfg(not(target_os = "linux"))]
+    cond: std::sync::Condvar,
+}
+
+impl Notify {
+    pub(crate) fn new() -> Notify {
+        Notify {
+            seq: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            event: OnceLock::new(),
+            waker: Mutex::new(None),
+            has_waker: AtomicBool::new(false),
+            #[cfg(not(target_os = "linux"))]
+            lock: std::sync::Mutex::new(()),
+            #[cfg(not(target_os = "linux"))]
+            cond: std::sync::Condvar::new(),
+        }
+    }
+
+    // Returns once `done` holds, spinning for a while before sleeping, or
+    // gives up after `timeout` if any. Returns whether `done` held.
+    pub(crate) fn wait_until(
+        &self,
+        mut done: impl FnMut() -> bool,
+        timeout: Option<Duration>,
+    ) -> bool {
+        for _ in 0..SPINS {
+            if done() {
+                return true;
+            }
+            hint::spin_loop();
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+            let seq = self.seq.load(Ordering::SeqCst);
+            atomic::fence(Ordering::SeqCst);
+            if done() {
+                self.waiters.fetch_sub(1, Ordering::Relaxed);
+                return true;
+            }
+            let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if left == Some(Duration::ZERO) {
+                self.waiters.fetch_sub(1, Ordering::Relaxed);
+                return false;
+            }
+            self.sleep(seq, left);
+            self.waiters.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    // Wakes whoever waits, to be called after storing what they wait on.
+    pub(crate) fn notify(&self) {
+        if let Some(event) = self.event.get() {
+            event.signal();
+        }
+        atomic::fence(Ordering::SeqCst);
+        if self.has_waker.load(Ordering::SeqCst) {
+            let waker = {
+                let mut waker = self.waker.lock().unwrap();
+                self.has_waker.store(false, Ordering::Relaxed);
+                waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+        if self.waiters.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        self.wake();
+    }
+
+    // Ready once `done` holds; otherwise the task is woken on the next
+    // notify. Only the last task to poll is woken.
+    pub(crate) fn poll_until(
+        &self,
+        cx: &mut Context<'_>,
+        mut done: impl FnMut() -> bool,
+    ) -> Poll<()> {
+        if done() {
+            return Poll::Ready(());
+        }
+        {
+            let mut waker = self.waker.lock().unwrap();
+            match &mut *waker {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                waker => *waker = Some(cx.waker().clone()),
+            }
+            self.has_waker.store(true, Ordering::SeqCst);
+        }
+        atomic::fence(Ordering::SeqCst);
+        if done() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+
+    // The fd signalled on every notify, created on first use.
+    pub(crate) fn event_fd(&self) -> io::Result<BorrowedFd<'_>> {
+        if self.event.get().is_none() {
+            // Whoever loses a race to set it closes theirs.
+            let _ = self.event.set(Event::new()?);
+        }
+        Ok(self.event.get().unwrap().read.as_fd())
+    }
+
+    // Makes the event fd not readable until the next notify.
+    pub(crate) fn clear_event_fd(&self) {
+        if let Some(event) = self.event.get() {
+            event.clear();
+        }
+    }
+
+    // Sleeps unless `seq` moved past `seen`, for at most `timeout` if any.
+    // It may also return spuriously.
+    #[cfg(target_os = "linux")]
+    fn sleep(&self, seen: u32, timeout: Option<Duration>) {
+        let timeout = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as _,
+        });
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.seq.as_ptr(),
+                libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                seen,
+                timeout
+                    .as_ref()
+                    .map_or(std::ptr::null(), |timeout| timeout as *const _),
+            )
+        };
+    }
+
+    #[cfg(target_os = "linux")]
+    fn wake(&self) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.seq.as_ptr(),
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                i32::MAX,
+            )
+        };
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn sleep(&self, seen: u32, timeout: Option<Duration>) {
+        let guard = self.lock.lock().unwrap();
+        if self.seq.load(Ordering::SeqCst) != seen {
+            return;
+        }
+        match timeout {
+            Some(timeout) => drop(self.cond.wait_timeout(guard, timeout).unwrap()),
+            None => drop(self.cond.wait(guard).unwrap()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn wake(&self) {
+        // Taking the lock orders the bump of `seq` before a sleeper's check
+        // or after its wait started.
+        drop(self.lock.lock().unwrap());
+        self.cond.notify_all();
+    }
+}
+
+// Like `Notify::wait_until`, for a notifier in another process, over two
+// words in memory the processes share: `seq`, which only the notifier
+// writes, and `waiters`, which only the waiter does. Sleeping is a futex
+// wait that is not private to the process on Linux; elsewhere the waiter
+// polls, a millisecond at a time.
+pub(crate) fn wait_until_shared(
+    seq: &AtomicU32,
+    waiters: &AtomicU32,
+    mut done: impl FnMut() -> bool,
+    timeout: Option<Duration>,
+) -> bool {
+    for _ in 0..SPINS {
+        if done() {
+            return true;
+        }
+        hint::spin_loop();
+    }
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        waiters.fetch_add(1, Ordering::SeqCst);
+        let seen = seq.load(Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        if done() {
+            waiters.fetch_sub(1, Ordering::Relaxed);
+            return true;
+        }
+        let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if left == Some(Duration::ZERO) {
+            waiters.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        sleep_shared(seq, seen, left);
+        waiters.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Like `Notify::notify`, for `wait_until_shared`.
+pub(crate) fn notify_shared(seq: &AtomicU32, waiters: &AtomicU32) {
+    atomic::fence(Ordering::SeqCst);
+    if waiters.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+    seq.fetch_add(1, Ordering::SeqCst);
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::syscall(libc::SYS_futex, seq.as_ptr(), libc::FUTEX_WAKE, i32::MAX)
+    };
+}
+
+#[cfg(target_os = "linux")]
+fn sleep_shared(seq: &AtomicU32, seen: u32, timeout: Option<Duration>) {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as _,
+    });
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            seq.as_ptr(),
+            libc::FUTEX_WAIT,
+            seen,
+            timeout
+                .as_ref()
+                .map_or(std::ptr::null(), |timeout| timeout as *const _),
+        )
+    };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sleep_shared(seq: &AtomicU32, seen: u32, timeout: Option<Duration>) {
+    if seq.load(Ordering::SeqCst) != seen {
+        return;
+    }
+    let nap = Duration::from_millis(1);
+    std::thread::sleep(timeout.map_or(nap, |timeout| timeout.min(nap)));
+}
+
+// A non-blocking fd that is readable once signalled, until cleared: an
+// eventfd on Linux, and a pipe elsewhere.
+struct Event {
+    read: OwnedFd,
+    #[cfg(not(target_os = "linux"))]
+    write: OwnedFd,
+}
+
+impl Event {
+    #[cfg(target_os = "linux")]
+    fn new() -> io::Result<Event> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Event {
+            read: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new() -> io::Result<Event> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        for fd in &fds {
+            unsafe {
+                libc::fcntl(*fd, libc::F_SETFL, libc::O_NONBLOCK);
+                libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+        Ok(Event { read, write })
+    }
+
+    // A full eventfd counter or pipe is readable all the same, so failing
+    // with EAGAIN is fine.
+    fn signal(&self) {
+        #[cfg(target_os = "linux")]
+        let fd = self.read.as_raw_fd();
+        #[cfg(not(target_os = "linux"))]
+        let fd = self.write.as_raw_fd();
+        let one = 1u64.to_ne_bytes();
+        unsafe { libc::write(fd, one.as_ptr() as *const libc::c_void, one.len()) };
+    }
+
+    fn clear(&self) {
+        let mut drained = [0u8; 64];
+        while unsafe {
+            libc::read(
+                self.read.as_raw_fd(),
+                drained.as_mut_ptr() as *mut libc::c_void,
+                drained.len(),
+            )
+        } > 0
+        {}
+    }
+}
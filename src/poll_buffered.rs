@@ -0,0 +1,245 @@
+use crate::MirroredBuffer;
+use mio::{event::Event, event::Source, Interest, Registry, Token};
+use std::io::{self, Read, Write};
+
+// Buffers a mio source, typically a TcpStream, through a pair of
+// MirroredBuffers: `rx` is filled on readable events and `tx` is drained on
+// writable events. The interest registered with mio follows the state of the
+// buffers: readable while `rx` has room and the peer has not closed its side,
+// writable while `tx` has committed data.
+//
+// mio is edge-triggered, so both directions go on until the socket would
+// block. After committing to `tx` or consuming from `rx`, call
+// `update_interest` so the registration catches up.
+pub struct PollBuffered<'a, S> {
+    inner: S,
+    rx: MirroredBuffer<'a>,
+    tx: MirroredBuffer<'a>,
+    token: Token,
+    registered: Option<Interest>,
+    read_closed: bool,
+}
+
+impl<'a, S: Source + Read + Write> PollBuffered<'a, S> {
+    pub fn new(
+        inner: S,
+        rx: MirroredBuffer<'a>,
+        tx: MirroredBuffer<'a>,
+        token: Token,
+    ) -> PollBuffered<'a, S> {
+        PollBuffered {
+            inner,
+            rx,
+            tx,
+            token,
+            registered: None,
+            read_closed: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn rx(&self) -> &MirroredBuffer<'a> {
+        &self.rx
+    }
+
+    pub fn rx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.rx
+    }
+
+    pub fn tx(&self) -> &MirroredBuffer<'a> {
+        &self.tx
+    }
+
+    pub fn tx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.tx
+    }
+
+    pub fn buffers_mut(&mut self) -> (&mut MirroredBuffer<'a>, &mut MirroredBuffer<'a>) {
+        (&mut self.rx, &mut self.tx)
+    }
+
+    // Whether the peer closed its side. Whatever is still committed in `rx`
+    // is the last of the data.
+    pub fn is_read_closed(&self) -> bool {
+        self.read_closed
+    }
+
+    // The interest the buffers currently call for, if any.
+    pub fn interest(&self) -> Option<Interest> {
+        let readable = !self.read_closed && self.rx.free() > 0;
+        let writable = self.tx.used() > 0;
+        match (readable, writable) {
+            (true, true) => Some(Interest::READABLE | Interest::WRITABLE),
+            (true, false) => Some(Interest::READABLE),
+            (false, true) => Some(Interest::WRITABLE),
+            (false, false) => None,
+        }
+    }
+
+    // Registers, reregisters or deregisters the source so that its
+    // registration matches `interest()`. Reregistering rearms mio, so data
+    // that arrived while `rx` was full is reported again.
+    pub fn update_interest(&mut self, registry: &Registry) -> io::Result<()> {
+        let interest = self.interest();
+        if interest == self.registered {
+            return Ok(());
+        }
+
+        match (self.registered, interest) {
+            (None, Some(interest)) => registry.register(&mut self.inner, self.token, interest)?,
+            (Some(_), Some(interest)) => {
+                registry.reregister(&mut self.inner, self.token, interest)?
+            }
+            (Some(_), None) => registry.deregister(&mut self.inner)?,
+            (None, None) => {}
+        }
+        self.registered = interest;
+        Ok(())
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        if self.registered.take().is_some() {
+            registry.deregister(&mut self.inner)?;
+        }
+        Ok(())
+    }
+
+    // Reads into `rx` until the source would block, `rx` is full or EOF.
+    // Returns the number of bytes committed.
+    pub fn fill(&mut self) -> io::Result<usize> {
+        let mut filled = 0;
+        while !self.read_closed && self.rx.free() > 0 {
+            match self.rx.fill_from(&mut self.inner) {
+                Ok(0) => self.read_closed = true,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(filled)
+    }
+
+    // Writes `tx` out until the source would block or `tx` is empty. Returns
+    // the number of bytes consumed.
+    pub fn drain(&mut self) -> io::Result<usize> {
+        match self.tx.flush_to(&mut self.inner) {
+            Ok(n) => Ok(n),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Fills and drains as `event` allows, then updates the registration.
+    pub fn handle_event(&mut self, registry: &Registry, event: &Event) -> io::Result<()> {
+        if event.is_readable() || event.is_read_closed() {
+            self.fill()?;
+        }
+        if event.is_writable() {
+            self.drain()?;
+        }
+        self.update_interest(registry)
+    }
+
+    pub fn into_inner(self) -> (S, MirroredBuffer<'a>, MirroredBuffer<'a>) {
+        (self.inner, self.rx, self.tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PollBuffered;
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use mio::{
+        net::{TcpListener, TcpStream},
+        Events, Interest, Poll, Token,
+    };
+    use std::time::Duration;
+
+    fn buffered(stream: TcpStream, token: Token) -> PollBuffered<'static, TcpStream> {
+        PollBuffered::new(
+            stream,
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+            token,
+        )
+    }
+
+    #[test]
+    fn poll_buffered_echo() {
+        let mut poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(16);
+
+        let mut ln = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        poll.registry()
+            .register(&mut ln, Token(0), Interest::READABLE)
+            .unwrap();
+
+        let mut client = buffered(
+            TcpStream::connect(ln.local_addr().unwrap()).unwrap(),
+            Token(1),
+        );
+        let data: Vec<u8> = (0..10_000).map(|x| x as u8).collect();
+        let mut sent = 0;
+        let mut received = Vec::new();
+        let mut server: Option<PollBuffered<TcpStream>> = None;
+
+        while received.len() < data.len() {
+            // The client keeps tx topped up from `data`.
+            if sent < data.len() {
+                let n = client.tx_mut().fill_from(&mut &data[sent..]).unwrap();
+                sent += n;
+            }
+            client.update_interest(poll.registry()).unwrap();
+
+            poll.poll(&mut events, Some(Duration::from_secs(5)))
+                .unwrap();
+            assert!(!events.is_empty());
+
+            for event in events.iter() {
+                match event.token() {
+                    Token(0) => {
+                        let (stream, _) = ln.accept().unwrap();
+                        let mut s = buffered(stream, Token(2));
+                        s.update_interest(poll.registry()).unwrap();
+                        server = Some(s);
+                    }
+                    Token(1) => {
+                        client.handle_event(poll.registry(), event).unwrap();
+                        if let Some(committed) = client.rx().committed() {
+                            received.extend_from_slice(committed);
+                            let n = committed.len();
+                            client.rx_mut().consume(n);
+                        }
+                    }
+                    Token(2) => {
+                        // The server echoes rx into tx.
+                        let s = server.as_mut().unwrap();
+                        s.handle_event(poll.registry(), event).unwrap();
+                        let (rx, tx) = s.buffers_mut();
+                        if let Some(committed) = rx.committed() {
+                            let n = tx.free().min(committed.len());
+                            if let Some(claimed) = tx.claim(n) {
+                                claimed.copy_from_slice(&committed[..n]);
+                                tx.commit(n);
+                                rx.consume(n);
+                            }
+                        }
+                        s.drain().unwrap();
+                        s.update_interest(poll.registry()).unwrap();
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        assert!(received == data);
+        assert!(client.interest() == Some(Interest::READABLE));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::MirroredBuffer;
+use polling::{Event, Poller};
+use std::{
+    io::{self, Read, Write},
+    os::fd::{AsFd, AsRawFd},
+};
+
+// Buffers a source registered with a `polling::Poller`, the portable poller
+// under smol and async-io, through a pair of MirroredBuffers: `rx` is filled
+// on readable events and `tx` is drained on writable events.
+//
+// Poller registrations are oneshot: once an event is delivered, the source
+// has to be rearmed before it reports anything else. `handle_event` does
+// that with the interest the buffers call for, and `rearm` does the same
+// after the caller commits to `tx` or consumes from `rx`.
+pub struct PollerBuffered<'a, S> {
+    inner: S,
+    rx: MirroredBuffer<'a>,
+    tx: MirroredBuffer<'a>,
+    key: usize,
+    read_closed: bool,
+}
+
+impl<'a, S: AsFd + AsRawFd + Read + Write> PollerBuffered<'a, S> {
+    pub fn new(
+        inner: S,
+        rx: MirroredBuffer<'a>,
+        tx: MirroredBuffer<'a>,
+        key: usize,
+    ) -> PollerBuffered<'a, S> {
+        PollerBuffered {
+            inner,
+            rx,
+            tx,
+            key,
+            read_closed: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    pub fn rx(&self) -> &MirroredBuffer<'a> {
+        &self.rx
+    }
+
+    pub fn rx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.rx
+    }
+
+    pub fn tx(&self) -> &MirroredBuffer<'a> {
+        &self.tx
+    }
+
+    pub fn tx_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.tx
+    }
+
+    pub fn buffers_mut(&mut self) -> (&mut MirroredBuffer<'a>, &mut MirroredBuffer<'a>) {
+        (&mut self.rx, &mut self.tx)
+    }
+
+    // Whether the peer closed its side. Whatever is still committed in `rx`
+    // is the last of the data.
+    pub fn is_read_closed(&self) -> bool {
+        self.read_closed
+    }
+
+    // The interest the buffers currently call for: readable while `rx` has
+    // room and the peer has not closed its side, writable while `tx` has
+    // committed data.
+    pub fn interest(&self) -> Event {
+        Event::new(
+            self.key,
+            !self.read_closed && self.rx.free() > 0,
+            self.tx.used() > 0,
+        )
+    }
+
+    /// Adds the source to `poller` with the current interest.
+    ///
+    /// # Safety
+    ///
+    /// As with `Poller::add`, the source must be deregistered before it is
+    /// dropped, which includes dropping it through `into_inner`.
+    pub unsafe fn register(&self, poller: &Poller) -> io::Result<()> {
+        poller.add(&self.inner, self.interest())
+    }
+
+    // Rearms the source with the current interest.
+    pub fn rearm(&self, poller: &Poller) -> io::Result<()> {
+        poller.modify(&self.inner, self.interest())
+    }
+
+    pub fn deregister(&self, poller: &Poller) -> io::Result<()> {
+        poller.delete(&self.inner)
+    }
+
+    // Reads into `rx` until the source would block, `rx` is full or EOF.
+    // Returns the number of bytes committed.
+    pub fn fill(&mut self) -> io::Result<usize> {
+        let mut filled = 0;
+        while !self.read_closed && self.rx.free() > 0 {
+            match self.rx.fill_from(&mut self.inner) {
+                Ok(0) => self.read_closed = true,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(filled)
+    }
+
+    // Writes `tx` out until the source would block or `tx` is empty. Returns
+    // the number of bytes consumed.
+    pub fn drain(&mut self) -> io::Result<usize> {
+        match self.tx.flush_to(&mut self.inner) {
+            Ok(n) => Ok(n),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Fills and drains as `event` allows, then rearms the source.
+    pub fn handle_event(&mut self, poller: &Poller, event: &Event) -> io::Result<()> {
+        if event.readable {
+            self.fill()?;
+        }
+        if event.writable {
+            self.drain()?;
+        }
+        self.rearm(poller)
+    }
+
+    pub fn into_inner(self) -> (S, MirroredBuffer<'a>, MirroredBuffer<'a>) {
+        (self.inner, self.rx, self.tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PollerBuffered;
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use polling::{Events, Poller};
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    fn wait(poller: &Poller, events: &mut Events) {
+        events.clear();
+        poller.wait(events, Some(Duration::from_secs(5))).unwrap();
+        assert!(events.len() == 1);
+    }
+
+    #[test]
+    fn poller_buffered_echo() {
+        let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(ln.local_addr().unwrap()).unwrap();
+        let (stream, _) = ln.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+
+        let poller = Poller::new().unwrap();
+        let mut events = Events::new();
+        let mut server = PollerBuffered::new(
+            stream,
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+            MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap(),
+            7,
+        );
+        unsafe { server.register(&poller).unwrap() };
+        assert!(server.interest().readable && !server.interest().writable);
+
+        let data: Vec<u8> = (0..1000).map(|x| x as u8).collect();
+        client.write_all(&data).unwrap();
+
+        let mut received = 0;
+        while received < data.len() {
+            wait(&poller, &mut events);
+            let event = events.iter().next().unwrap();
+            assert!(event.key == 7 && event.readable);
+            server.handle_event(&poller, &event).unwrap();
+            received = server.rx().used();
+        }
+
+        // Echo rx into tx, which makes the source writable.
+        let (rx, tx) = server.buffers_mut();
+        tx.claim(received)
+            .unwrap()
+            .copy_from_slice(rx.committed().unwrap());
+        tx.commit(received);
+        rx.consume(received);
+        server.rearm(&poller).unwrap();
+        assert!(server.interest().writable);
+
+        wait(&poller, &mut events);
+        let event = events.iter().next().unwrap();
+        assert!(event.writable);
+        server.handle_event(&poller, &event).unwrap();
+        assert!(server.tx().used() == 0);
+
+        let mut echo = vec![0; data.len()];
+        client.read_exact(&mut echo).unwrap();
+        assert!(echo == data);
+
+        drop(client);
+        wait(&poller, &mut events);
+        let event = events.iter().next().unwrap();
+        server.handle_event(&poller, &event).unwrap();
+        assert!(server.is_read_closed());
+        assert!(!server.interest().readable && !server.interest().writable);
+
+        server.deregister(&poller).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{util::round_up_to_page_size, Error, FullPolicy, MirroredBuffer};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// Hands out buffers from size classes and takes them back when dropped, so a
+// server does not pay for creating and mapping a buffer on every new
+// connection.
+//
+// Each class holds buffers of one size, some created up front. When a class
+// runs out, it creates another buffer, which it then keeps as well.
+#[derive(Clone)]
+pub struct BufferPool {
+    // Sorted by size.
+    classes: Arc<[Class]>,
+}
+
+struct Class {
+    size: usize,
+    idle: Mutex<Vec<MirroredBuffer<'static>>>,
+}
+
+// A buffer from a pool, which goes back to it when dropped, with nothing
+// committed and the default full policy, unless it was resized.
+pub struct PooledBuffer {
+    buf: Option<MirroredBuffer<'static>>,
+    pool: BufferPool,
+    class: usize,
+}
+
+fn new_buffer(size: usize) -> Result<MirroredBuffer<'static>, Error> {
+    static POOL_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+    let suffix = format!("pool-{}", POOL_INDEX.fetch_add(1, Ordering::Relaxed));
+    MirroredBuffer::new(size, Some(&suffix), None)
+}
+
+impl BufferPool {
+    // Creates a pool with a class for each `(size, count)` of `classes`,
+    // holding `count` buffers of at least `size` bytes to begin with.
+    pub fn new(classes: &[(usize, usize)]) -> Result<BufferPool, Error> {
+        let mut built = Vec::with_capacity(classes.len());
+        for &(size, count) in classes {
+            let size = round_up_to_page_size(size)?;
+            if size == 0 || !size.is_power_of_two() {
+                return Err(Error::invalid_size(size));
+            }
+            let mut idle = Vec::with_capacity(count);
+            for _ in 0..count {
+                idle.push(new_buffer(size)?);
+            }
+            built.push(Class {
+                size,
+                idle: Mutex::new(idle),
+            });
+        }
+        built.sort_by_key(|class| class.size);
+        Ok(BufferPool {
+            classes: built.into(),
+        })
+    }
+
+    // The sizes of the classes, in increasing order.
+    pub fn class_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.classes.iter().map(|class| class.size)
+    }
+
+    // How many buffers wait in the pool.
+    pub fn idle(&self) -> usize {
+        let idle = self
+            .classes
+            .iter()
+            .map(|class| class.idle.lock().unwrap().len());
+        idle.sum()
+    }
+
+    // Hands out a buffer of the smallest class holding at least `size`
+    // bytes, or fails with InvalidSize if none does.
+    pub fn acquire(&self, size: usize) -> Result<PooledBuffer, Error> {
+        let Some(class) = self.classes.iter().position(|class| class.size >= size) else {
+            return Err(Error::invalid_size(size));
+        };
+        let idle = self.classes[class].idle.lock().unwrap().pop();
+        let buf = match idle {
+            Some(buf) => buf,
+            None => new_buffer(self.classes[class].size)?,
+        };
+        Ok(PooledBuffer {
+            buf: Some(buf),
+            pool: self.clone(),
+            class,
+        })
+    }
+}
+
+impl PooledBuffer {
+    // Takes the buffer out of the pool for good.
+    pub fn into_inner(mut self) -> MirroredBuffer<'static> {
+        self.buf.take().unwrap()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = MirroredBuffer<'static>;
+
+    fn deref(&self) -> &MirroredBuffer<'static> {
+        self.buf.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut MirroredBuffer<'static> {
+        self.buf.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let Some(mut buf) = self.buf.take() else {
+            return;
+        };
+        buf.head = 0;
+        buf.tail = 0;
+        buf.size_used = 0;
+        buf.usage.used.store(0, Ordering::Relaxed);
+        buf.full_policy = FullPolicy::Clamp;
+        let class = &self.pool.classes[self.class];
+        if buf.size() != class.size {
+            return;
+        }
+        class.idle.lock().unwrap().push(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::get_page_size, BufferPool, ErrorKind, FullPolicy};
+
+    #[test]
+    fn pool_reuses_buffers_by_class() {
+        let page_size = get_page_size().unwrap();
+        let pool = BufferPool::new(&[(4 * page_size, 1), (1, 2)]).unwrap();
+        assert!(pool.class_sizes().eq([page_size, 4 * page_size]));
+        assert!(pool.idle() == 3);
+        let err = pool.acquire(4 * page_size + 1).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+
+        // Back to the pool, reset.
+        let mut a = pool.acquire(1).unwrap();
+        assert!(a.size() == page_size && pool.idle() == 2);
+        let name = a.name().to_string();
+        a.claim(10).unwrap();
+        a.commit(10);
+        a.full_policy = FullPolicy::Overwrite;
+        drop(a);
+        assert!(pool.idle() == 3);
+        let b = pool.acquire(page_size).unwrap();
+        assert!(b.name() == name);
+        assert!(b.used() == 0 && b.full_policy() == FullPolicy::Clamp);
+
+        // A class that ran out grows.
+        let c = pool.acquire(page_size + 1).unwrap();
+        let d = pool.acquire(page_size + 1).unwrap();
+        assert!(c.size() == 4 * page_size && d.size() == 4 * page_size);
+        assert!(pool.idle() == 1);
+        drop((b, c, d));
+        assert!(pool.idle() == 4);
+
+        // Or lets go of a buffer for good.
+        let e = pool.acquire(1).unwrap().into_inner();
+        assert!(e.size() == page_size && pool.idle() == 3);
+    }
+}
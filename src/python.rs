@@ -0,0 +1,221 @@
+// Python bindings, under the `python` feature, for ingest pipelines that mix
+// Python and Rust. Build the extension module with maturin, which names it
+// `mirrored_buffer`.
+//
+// Regions are handed to Python through the buffer protocol, so
+// `memoryview(buf)` is the committed region, read-only, and the object
+// `claim` returns is the claimed region, writable. The mapping never moves
+// and each view keeps the buffer alive, but what a view shows is only
+// meaningful until the buffer is claimed from, committed to or consumed
+// from again, like the slices of `MirroredBuffer`.
+use crate::{ErrorKind, MirroredBuffer};
+use pyo3::{
+    exceptions::{PyMemoryError, PyOSError, PyValueError},
+    ffi,
+    prelude::*,
+};
+use std::{
+    os::raw::{c_int, c_void},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static PYTHON_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+#[pyclass(name = "MirroredBuffer", module = "mirrored_buffer")]
+pub struct PyMirroredBuffer {
+    buf: MirroredBuffer<'static>,
+}
+
+// A claimed region, until committed.
+#[pyclass(name = "Claim", module = "mirrored_buffer")]
+pub struct PyClaim {
+    // Keeps the mapping alive.
+    _buffer: Py<PyMirroredBuffer>,
+    ptr: usize,
+    len: usize,
+}
+
+fn to_py_err(err: crate::Error) -> PyErr {
+    match err.kind() {
+        ErrorKind::InvalidSize(_) => PyValueError::new_err(err.to_string()),
+        ErrorKind::NoSpace(_) => PyMemoryError::new_err(err.to_string()),
+        _ => PyOSError::new_err(err.to_string()),
+    }
+}
+
+// Fills `view` with the `len` bytes at `ptr`, owned by `obj`.
+unsafe fn fill_view(
+    obj: *mut ffi::PyObject,
+    view: *mut ffi::Py_buffer,
+    ptr: *mut u8,
+    len: usize,
+    readonly: bool,
+    flags: c_int,
+) -> PyResult<()> {
+    let ret = unsafe {
+        ffi::PyBuffer_FillInfo(
+            view,
+            obj,
+            ptr as *mut c_void,
+            len as ffi::Py_ssize_t,
+            readonly as c_int,
+            flags,
+        )
+    };
+    if ret == -1 {
+        return Err(Python::with_gil(PyErr::fetch));
+    }
+    Ok(())
+}
+
+#[pymethods]
+impl PyMirroredBuffer {
+    #[new]
+    fn new(size: usize) -> PyResult<Self> {
+        let suffix = format!("python-{}", PYTHON_INDEX.fetch_add(1, Ordering::Relaxed));
+        let buf = MirroredBuffer::new(size, Some(&suffix), None).map_err(to_py_err)?;
+        Ok(PyMirroredBuffer { buf })
+    }
+
+    #[getter]
+    fn size(&self) -> usize {
+        self.buf.size()
+    }
+
+    #[getter]
+    fn used(&self) -> usize {
+        self.buf.used()
+    }
+
+    #[getter]
+    fn free(&self) -> usize {
+        self.buf.free()
+    }
+
+    // Claims up to `size` bytes, or returns None if there is no room.
+    fn claim(slf: Bound<'_, Self>, size: usize) -> Option<PyClaim> {
+        let (ptr, len) = {
+            let mut this = slf.borrow_mut();
+            let claimed = this.buf.claim(size)?;
+            (claimed.as_mut_ptr() as usize, claimed.len())
+        };
+        Some(PyClaim {
+            _buffer: slf.unbind(),
+            ptr,
+            len,
+        })
+    }
+
+    fn commit(&mut self, size: usize) -> usize {
+        self.buf.commit(size)
+    }
+
+    fn consume(&mut self, size: usize) -> usize {
+        self.buf.consume(size)
+    }
+
+    // A copy of the committed region.
+    fn committed(&self) -> Vec<u8> {
+        self.buf.committed().unwrap_or_default().to_vec()
+    }
+
+    fn __len__(&self) -> usize {
+        self.buf.used()
+    }
+
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        let (ptr, len) = {
+            let this = slf.borrow();
+            match this.buf.committed() {
+                Some(committed) => (committed.as_ptr() as *mut u8, committed.len()),
+                None => (this.buf.slice.as_ptr() as *mut u8, 0),
+            }
+        };
+        unsafe { fill_view(slf.as_ptr(), view, ptr, len, true, flags) }
+    }
+
+    unsafe fn __releasebuffer__(&self, _view: *mut ffi::Py_buffer) {}
+}
+
+#[pymethods]
+impl PyClaim {
+    fn __len__(&self) -> usize {
+        self.len
+    }
+
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        let (ptr, len) = {
+            let this = slf.borrow();
+            (this.ptr as *mut u8, this.len)
+        };
+        unsafe { fill_view(slf.as_ptr(), view, ptr, len, false, flags) }
+    }
+
+    unsafe fn __releasebuffer__(&self, _view: *mut ffi::Py_buffer) {}
+}
+
+#[pymodule]
+fn mirrored_buffer(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyMirroredBuffer>()?;
+    m.add_class::<PyClaim>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mirrored_buffer;
+    use pyo3::{
+        ffi::c_str,
+        prelude::*,
+        types::{IntoPyDict, PyModule},
+    };
+
+    #[test]
+    fn python_claim_commit_consume() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "mirrored_buffer").unwrap();
+            mirrored_buffer(&module).unwrap();
+            let globals = [("mirrored_buffer", module)].into_py_dict(py).unwrap();
+            py.run(
+                c_str!(
+                    r#"
+buf = mirrored_buffer.MirroredBuffer(1)
+assert buf.free == buf.size and len(memoryview(buf)) == 0
+
+claim = buf.claim(5)
+memoryview(claim)[:] = b"hello"
+assert buf.commit(5) == 5 and buf.used == 5
+view = memoryview(buf)
+assert view.readonly and view.tobytes() == b"hello"
+try:
+    view[0] = 0
+    assert False
+except TypeError:
+    pass
+
+assert buf.consume(2) == 2
+assert bytes(memoryview(buf)) == b"llo" == buf.committed()
+
+try:
+    mirrored_buffer.MirroredBuffer(0)
+    assert False
+except ValueError:
+    pass
+"#
+                ),
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
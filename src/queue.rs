@@ -0,0 +1,170 @@
+use crate::{Error, MirroredBuffer};
+use bytemuck::Pod;
+use std::{cmp, marker::PhantomData, mem};
+
+// A buffer of `T`s rather than bytes, e.g. fixed-size market data or sensor
+// records, claimed, committed and consumed by the slot.
+//
+// Slots start at multiples of the size of `T` counted from the start of the
+// buffer, and wrap around its end like bytes do, so a slot may run past the
+// end into the mirror. Every slot is aligned still: the alignment of `T`
+// divides its size, which it is counted in, and the size of the buffer, a
+// power of two of at least a page, where the wrap leaves it.
+pub struct MirroredQueue<'a, T: Pod> {
+    buf: MirroredBuffer<'a>,
+    _slots: PhantomData<T>,
+}
+
+impl<'a, T: Pod> MirroredQueue<'a, T> {
+    // Queues `T`s in `buf`, which must hold at least one. Whatever it has
+    // committed is dropped. Fails with InvalidSize if `T` is zero-sized or
+    // larger than `buf`.
+    pub fn new(mut buf: MirroredBuffer<'a>) -> Result<MirroredQueue<'a, T>, Error> {
+        let slot = mem::size_of::<T>();
+        if slot == 0 || slot > buf.size() {
+            return Err(Error::invalid_size(slot));
+        }
+        buf.consume(buf.used());
+        buf.head = 0;
+        buf.tail = 0;
+        Ok(MirroredQueue {
+            buf,
+            _slots: PhantomData,
+        })
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    // How many slots the queue holds.
+    pub fn capacity(&self) -> usize {
+        self.buf.size() / mem::size_of::<T>()
+    }
+
+    // How many slots are committed.
+    pub fn len(&self) -> usize {
+        self.buf.used() / mem::size_of::<T>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.used() == 0
+    }
+
+    // How many slots there is room for, which the bytes left over past the
+    // last whole slot do not make one more of.
+    pub fn free_slots(&self) -> usize {
+        self.buf.free() / mem::size_of::<T>()
+    }
+
+    // Claims up to `n` slots, as many as there is room for, or None if there
+    // is none. A buffer with a read-only mirror may hand out fewer near the
+    // wrap, like its claims are shorter there.
+    pub fn claim_slots(&mut self, n: usize) -> Option<&mut [T]> {
+        let slot = mem::size_of::<T>();
+        let n = cmp::min(n, self.free_slots());
+        let claimed = self.buf.claim(n * slot)?;
+        let len = claimed.len() / slot * slot;
+        if len == 0 {
+            return None;
+        }
+        Some(bytemuck::cast_slice_mut(&mut claimed[..len]))
+    }
+
+    // Commits up to `n` slots, as many as there is room for, and returns how
+    // many were.
+    pub fn commit_slots(&mut self, n: usize) -> usize {
+        let slot = mem::size_of::<T>();
+        let n = cmp::min(n, self.free_slots());
+        self.buf.commit(n * slot) / slot
+    }
+
+    // Copies in and commits as many of `slots` as there is room for.
+    pub fn push_slots(&mut self, slots: &[T]) -> usize {
+        let n = cmp::min(slots.len(), self.free_slots());
+        let Some(claimed) = self.claim_slots(n) else {
+            return 0;
+        };
+        let n = claimed.len();
+        claimed.copy_from_slice(&slots[..n]);
+        self.commit_slots(n)
+    }
+
+    pub fn committed_slots(&self) -> Option<&[T]> {
+        Some(bytemuck::cast_slice(self.buf.committed()?))
+    }
+
+    // Consumes up to `n` slots, as many as are committed, and returns how
+    // many were.
+    pub fn consume_slots(&mut self, n: usize) -> usize {
+        let slot = mem::size_of::<T>();
+        let n = cmp::min(n, self.len());
+        self.buf.consume(n * slot) / slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, ErrorKind, MirroredBuffer, MirroredQueue};
+    use bytemuck::{Pod, Zeroable};
+
+    // 12 bytes, which the size of the buffer is no multiple of.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[repr(C)]
+    struct Tick {
+        price: u32,
+        size: u32,
+        venue: u32,
+    }
+
+    unsafe impl Zeroable for Tick {}
+    unsafe impl Pod for Tick {}
+
+    #[test]
+    fn queue_slots_across_the_wrap() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let mut queue = MirroredQueue::<Tick>::new(buf).unwrap();
+        assert!(queue.capacity() == size / 12 && queue.is_empty());
+
+        // Around the end of the buffer, a few times, in whole slots.
+        let tick = |i: usize| Tick {
+            price: i as u32,
+            size: 2 * i as u32,
+            venue: 3,
+        };
+        let mut next = 0;
+        let mut seen = 0;
+        for _ in 0..3 * size / 12 / 100 {
+            let claimed = queue.claim_slots(100).unwrap();
+            assert!(claimed.len() == 100);
+            for slot in claimed.iter_mut() {
+                *slot = tick(next);
+                next += 1;
+            }
+            assert!(queue.commit_slots(100) == 100);
+            let committed = queue.committed_slots().unwrap();
+            assert!(committed
+                .iter()
+                .zip(seen..)
+                .all(|(&slot, i)| slot == tick(i)));
+            seen += committed.len();
+            assert!(queue.consume_slots(committed.len()) == 100);
+        }
+        assert!(queue.is_empty());
+
+        // No further than there is room for.
+        let ticks: Vec<Tick> = (0..size / 12 + 1).map(tick).collect();
+        assert!(queue.push_slots(&ticks) == queue.capacity());
+        assert!(queue.free_slots() == 0 && queue.claim_slots(1).is_none());
+        assert!(queue.committed_slots().unwrap() == &ticks[..queue.capacity()]);
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let err = MirroredQueue::<()>::new(buf).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{codec::Decoder, Error, MirroredBuffer};
+use quinn::{RecvStream, SendStream};
+use std::io;
+
+// Glue between QUIC streams and MirroredBuffers. Stream data is read from
+// quinn straight into the claim region, so a frame reassembled from several
+// QUIC packets ends up contiguous in the committed region whatever its
+// position in the ring, and any of the codecs can decode it in place.
+impl<'a> MirroredBuffer<'a> {
+    // Reads from `stream` into the free region once and commits what was
+    // read. Ok(None) means the peer finished the stream; Ok(Some(0)) means
+    // the buffer is full.
+    pub async fn fill_from_quic(&mut self, stream: &mut RecvStream) -> io::Result<Option<usize>> {
+        let free = self.free();
+        let Some(claimed) = self.claim(free) else {
+            return Ok(Some(0));
+        };
+        match stream.read(claimed).await? {
+            Some(n) => Ok(Some(self.commit(n))),
+            None => Ok(None),
+        }
+    }
+
+    // Reads from `stream` until `decoder` finds a whole frame at the start of
+    // the committed region and returns its size; decode it again with
+    // `MirroredBuffer::decode` to borrow it. Ok(None) means the stream
+    // finished cleanly between frames.
+    pub async fn fill_frame_from_quic<D: Decoder>(
+        &mut self,
+        stream: &mut RecvStream,
+        decoder: &mut D,
+    ) -> io::Result<Option<usize>> {
+        loop {
+            if let Some(committed) = self.committed() {
+                match decoder.decode(committed) {
+                    Ok(Some((_, size))) => return Ok(Some(size)),
+                    Ok(None) => {}
+                    Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+                }
+            }
+
+            match self.fill_from_quic(stream).await? {
+                Some(0) => {
+                    let err = Error::no_space(self.size() + 1);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+                Some(_) => {}
+                None if self.used() == 0 => return Ok(None),
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+    }
+
+    // Writes the committed region to `stream` until it is empty, consuming
+    // what was written, and returns how much that was.
+    pub async fn flush_to_quic(&mut self, stream: &mut SendStream) -> io::Result<usize> {
+        let mut n = 0;
+        while let Some(committed) = self.committed() {
+            let written = stream.write(committed).await?;
+            self.consume(written);
+            n += written;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{codec::length_delimited::Codec, util::next_buffer_index, MirroredBuffer};
+    use quinn::{
+        rustls::{
+            pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+            RootCertStore,
+        },
+        ClientConfig, Endpoint, ServerConfig,
+    };
+    use rcgen::{CertificateParams, KeyPair};
+    use std::sync::Arc;
+
+    fn endpoints() -> (Endpoint, Endpoint) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+
+        let server_config = ServerConfig::with_single_cert(
+            vec![cert.der().clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        )
+        .unwrap();
+        let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(
+            ClientConfig::with_root_certificates(Arc::new(roots)).unwrap(),
+        );
+
+        (server, client)
+    }
+
+    fn buffer() -> MirroredBuffer<'static> {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 100;
+        buf.commit(offset);
+        buf.consume(offset);
+        buf
+    }
+
+    #[tokio::test]
+    async fn quinn_stream_echo_frames() {
+        let (server, client) = endpoints();
+        let addr = server.local_addr().unwrap();
+
+        let echo = tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let (mut send, mut recv) = conn.accept_bi().await.unwrap();
+            let (mut rx, mut tx) = (buffer(), buffer());
+            let mut codec = Codec::new();
+
+            let mut frames = 0;
+            while let Some(size) = rx
+                .fill_frame_from_quic(&mut recv, &mut codec)
+                .await
+                .unwrap()
+            {
+                let (payload, _) = rx.decode(&mut codec).unwrap().unwrap();
+                codec.encode(&mut tx, payload).unwrap();
+                rx.consume(size);
+                tx.flush_to_quic(&mut send).await.unwrap();
+                frames += 1;
+            }
+            send.finish().unwrap();
+            conn.closed().await;
+            frames
+        });
+
+        let conn = client.connect(addr, "localhost").unwrap().await.unwrap();
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        let (mut rx, mut tx) = (buffer(), buffer());
+        let mut codec = Codec::new();
+
+        for i in 0..50 {
+            let payload = vec![i as u8; 1 + i * 61 % 2000];
+            codec.encode(&mut tx, &payload).unwrap();
+            tx.flush_to_quic(&mut send).await.unwrap();
+
+            let size = rx
+                .fill_frame_from_quic(&mut recv, &mut codec)
+                .await
+                .unwrap()
+                .unwrap();
+            let (echo, _) = rx.decode(&mut codec).unwrap().unwrap();
+            assert!(echo == payload);
+            rx.consume(size);
+        }
+
+        send.finish().unwrap();
+        assert!(rx
+            .fill_frame_from_quic(&mut recv, &mut codec)
+            .await
+            .unwrap()
+            .is_none());
+        conn.close(0u32.into(), b"done");
+        assert!(echo.await.unwrap() == 50);
+    }
+}
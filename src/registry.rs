@@ -0,0 +1,95 @@
+// The buffers this process holds, for debug endpoints and operator tools to
+// show what a running server holds.
+//
+// Every buffer registers when it is created and drops out of the registry
+// with its last owner. The used size is the buffer's as of its last commit
+// or consume; for a split buffer, whose halves keep their own indices, that
+// is as of the split.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, Weak,
+};
+
+static REGISTRY: Mutex<Vec<Weak<Usage>>> = Mutex::new(Vec::new());
+
+// What a buffer shares with the registry.
+pub(crate) struct Usage {
+    name: String,
+    pub(crate) size: AtomicUsize,
+    pub(crate) used: AtomicUsize,
+}
+
+// What the registry knows of a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferInfo {
+    pub name: String,
+    pub size: usize,
+    pub used: usize,
+}
+
+impl BufferInfo {
+    // The share of the buffer in use, from 0 to 1.
+    pub fn utilization(&self) -> f64 {
+        self.used as f64 / self.size as f64
+    }
+}
+
+// Registers a buffer, dropping the buffers gone since from the registry.
+pub(crate) fn register(name: &str, size: usize) -> Arc<Usage> {
+    let usage = Arc::new(Usage {
+        name: name.to_string(),
+        size: AtomicUsize::new(size),
+        used: AtomicUsize::new(0),
+    });
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|usage| usage.strong_count() > 0);
+    registry.push(Arc::downgrade(&usage));
+    usage
+}
+
+// Lists the buffers alive, oldest first.
+pub fn list() -> Vec<BufferInfo> {
+    let registry = REGISTRY.lock().unwrap();
+    registry
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|usage| BufferInfo {
+            name: usage.name.clone(),
+            size: usage.size.load(Ordering::Relaxed),
+            used: usage.used.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::list;
+    use crate::{util::next_buffer_index, BufferPool, MirroredBuffer};
+
+    #[test]
+    fn registry_lists_live_buffers() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None).unwrap();
+        let name = buf.name().to_string();
+        let find = |name: &str| list().into_iter().find(|info| info.name == name);
+
+        let info = find(&name).unwrap();
+        assert!(info.size == buf.size() && info.used == 0);
+        buf.claim(buf.size() / 4).unwrap();
+        buf.commit(buf.size() / 4);
+        assert!(find(&name).unwrap().utilization() == 0.25);
+        buf.consume(1);
+        assert!(find(&name).unwrap().used == buf.size() / 4 - 1);
+
+        drop(buf);
+        assert!(find(&name).is_none());
+
+        // Pooled buffers count as held, and come back empty.
+        let pool = BufferPool::new(&[(1, 1)]).unwrap();
+        let mut pooled = pool.acquire(1).unwrap();
+        let name = pooled.name().to_string();
+        pooled.claim(1).unwrap();
+        pooled.commit(1);
+        drop(pooled);
+        assert!(find(&name).unwrap().used == 0);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::MirroredBuffer;
+use rustls::ConnectionCommon;
+use std::io;
+
+// Glue for driving a rustls connection from MirroredBuffers rather than a
+// socket, so ciphertext is staged in the rings: received records are handed
+// to rustls straight out of the committed region, even when they wrap around
+// the end of the ring, and outgoing records are written into the claim region
+// and committed, ready for `flush_to` or any of the zero-copy senders.
+impl<'a> MirroredBuffer<'a> {
+    // Hands the committed region to `conn` as received TLS data, consuming
+    // what it took. Like `ConnectionCommon::read_tls`, this takes at most what
+    // rustls is willing to buffer, so `process_new_packets` has to be called
+    // in between. Returns Ok(0) if nothing is committed.
+    pub fn read_tls_into<D>(&mut self, conn: &mut ConnectionCommon<D>) -> io::Result<usize> {
+        let Some(mut committed) = self.committed() else {
+            return Ok(0);
+        };
+        let n = conn.read_tls(&mut committed)?;
+        self.consume(n);
+        Ok(n)
+    }
+
+    // Writes the records `conn` has pending into the claim region and commits
+    // them. Returns Ok(0) if there was nothing to write or no free space.
+    pub fn write_tls_from<D>(&mut self, conn: &mut ConnectionCommon<D>) -> io::Result<usize> {
+        let mut n = 0;
+        while conn.wants_write() && self.free() > 0 {
+            match conn.write_tls(self)? {
+                0 => break,
+                written => n += written,
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::{
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+        ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection,
+    };
+    use std::{
+        io::{Read, Write},
+        sync::Arc,
+    };
+
+    fn connections() -> (ClientConnection, ServerConnection) {
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let client = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            )
+            .unwrap();
+
+        (
+            ClientConnection::new(Arc::new(client), "localhost".try_into().unwrap()).unwrap(),
+            ServerConnection::new(Arc::new(server)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn rustls_io_handshake_and_data() {
+        let (mut client, mut server) = connections();
+
+        // Both directions start close to the end of the ring so records wrap.
+        let mut to_server = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut to_client = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        for buf in [&mut to_server, &mut to_client] {
+            let offset = buf.size() - 100;
+            buf.commit(offset);
+            buf.consume(offset);
+        }
+
+        while client.is_handshaking() || server.is_handshaking() {
+            to_server.write_tls_from(&mut client).unwrap();
+            while to_server.read_tls_into(&mut server).unwrap() > 0 {
+                server.process_new_packets().unwrap();
+            }
+            to_client.write_tls_from(&mut server).unwrap();
+            while to_client.read_tls_into(&mut client).unwrap() > 0 {
+                client.process_new_packets().unwrap();
+            }
+        }
+
+        client.writer().write_all(b"ping").unwrap();
+        assert!(to_server.write_tls_from(&mut client).unwrap() > 4);
+        while to_server.read_tls_into(&mut server).unwrap() > 0 {
+            server.process_new_packets().unwrap();
+        }
+        assert!(to_server.used() == 0);
+
+        let mut plaintext = [0; 4];
+        server.reader().read_exact(&mut plaintext).unwrap();
+        assert!(&plaintext == b"ping");
+
+        // A write larger than the free space is committed in pieces.
+        let big = vec![9u8; to_client.size() * 2];
+        server.writer().write_all(&big).unwrap();
+        let mut received = Vec::new();
+        while received.len() < big.len() {
+            to_client.write_tls_from(&mut server).unwrap();
+            while to_client.read_tls_into(&mut client).unwrap() > 0 {
+                client.process_new_packets().unwrap();
+            }
+            let mut chunk = [0; 4096];
+            while let Ok(n) = client.reader().read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                received.extend_from_slice(&chunk[..n]);
+            }
+        }
+        assert!(received == big);
+        assert!(!server.wants_write());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::{split::CachePadded, Error, MirroredBuffer};
+use std::{
+    cmp, hint, slice,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+// How many times waits spin before they start yielding the thread.
+const SPINS: usize = 100;
+
+// A position in a sequenced ring, in bytes since it was created, as published
+// by the sequencer or reached by a stage. Clones share the position, so that
+// stages can wait on each other's.
+#[derive(Clone)]
+pub struct Sequence(Arc<CachePadded<AtomicUsize>>);
+
+impl Sequence {
+    fn new(value: usize) -> Sequence {
+        Sequence(Arc::new(CachePadded(AtomicUsize::new(value))))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+
+    fn set(&self, value: usize) {
+        self.0.store(value, Ordering::Release);
+    }
+}
+
+// The smallest of `sequences`, i.e. how far all of them got.
+fn minimum(sequences: &[Sequence]) -> usize {
+    sequences
+        .iter()
+        .map(Sequence::get)
+        .min()
+        .unwrap_or(usize::MAX)
+}
+
+// Spins, then yields, until `done` returns Some.
+fn wait<T>(mut done: impl FnMut() -> Option<T>) -> T {
+    let mut spins = 0;
+    loop {
+        if let Some(value) = done() {
+            return value;
+        }
+        if spins < SPINS {
+            spins += 1;
+            hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+// The producer of a ring shared by a pipeline of stages, in the manner of the
+// LMAX Disruptor, see `into_sequencer`.
+//
+// The sequencer publishes bytes by moving its cursor. Each stage reads what
+// the stages it depends on got through, which is at most what was published,
+// then moves its own sequence. Every stage gates the sequencer: space is only
+// reused once all stages are past it, so the last stages of the pipeline, the
+// ones nothing else depends on, are what holds the producer back.
+pub struct Sequencer<'a> {
+    buf: Arc<MirroredBuffer<'a>>,
+    ptr: *mut u8,
+    cursor: Sequence,
+    gating: Vec<Sequence>,
+    // Where the ring starts, which holds it until there are stages.
+    origin: usize,
+}
+
+// A stage of the pipeline: it sees the bytes its dependencies got through
+// that it did not move past yet.
+pub struct Stage<'a> {
+    buf: Arc<MirroredBuffer<'a>>,
+    ptr: *mut u8,
+    sequence: Sequence,
+    dependencies: Vec<Sequence>,
+}
+
+// The sequencer only writes the space every stage is past, and stages only
+// read what they all have yet to move past.
+unsafe impl Send for Sequencer<'_> {}
+unsafe impl Send for Stage<'_> {}
+
+impl<'a> MirroredBuffer<'a> {
+    // Turns the buffer into the producer of a sequenced ring. Whatever is
+    // committed is published. Claims being exact, they may run across the
+    // wrap, which a read-only mirror does not let them: fails with
+    // ReadOnlyMirror if the buffer has one.
+    pub fn into_sequencer(mut self) -> Result<Sequencer<'a>, Error> {
+        if self.has_read_only_mirror() {
+            return Err(Error::read_only_mirror());
+        }
+        self.fill_rest();
+        let ptr = self.slice.as_mut_ptr();
+        let origin = self.head;
+        let cursor = Sequence::new(origin.wrapping_add(self.size_used));
+
+        Ok(Sequencer {
+            buf: Arc::new(self),
+            ptr,
+            cursor,
+            gating: Vec::new(),
+            origin,
+        })
+    }
+}
+
+impl<'a> Sequencer<'a> {
+    pub fn name(&self) -> &str {
+        self.buf.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.buf.size()
+    }
+
+    // What was published so far.
+    pub fn cursor(&self) -> Sequence {
+        self.cursor.clone()
+    }
+
+    // How far the slowest stage got.
+    fn gating(&self) -> usize {
+        if self.gating.is_empty() {
+            return self.origin;
+        }
+        minimum(&self.gating)
+    }
+
+    // Adds a stage that sees what was published and what all of
+    // `dependencies` got through, starting from the oldest byte no stage
+    // moved past yet. Stages gate the sequencer for as long as it lives,
+    // dropped ones included.
+    pub fn stage(&mut self, dependencies: &[Sequence]) -> Stage<'a> {
+        let sequence = Sequence::new(self.gating());
+        self.gating.push(sequence.clone());
+
+        let mut dependencies = dependencies.to_vec();
+        dependencies.push(self.cursor());
+        Stage {
+            buf: self.buf.clone(),
+            ptr: self.ptr,
+            sequence,
+            dependencies,
+        }
+    }
+
+    // The space every stage is past, as seen now.
+    pub fn free(&self) -> usize {
+        let cursor = self.cursor.get();
+        self.size() - cursor.wrapping_sub(self.gating())
+    }
+
+    // Up to `size` bytes of free space to write to, or None if there is
+    // none right now.
+    pub fn try_claim(&mut self, size: usize) -> Option<&mut [u8]> {
+        let size = cmp::min(size, self.free());
+        if size == 0 {
+            return None;
+        }
+        let offset = self.cursor.get() & self.buf.size_mask;
+        Some(unsafe { slice::from_raw_parts_mut(self.ptr.add(offset), size) })
+    }
+
+    // Exactly `size` bytes of free space to write to, waiting for the stages
+    // to get past them if need be.
+    pub fn claim(&mut self, size: usize) -> &mut [u8] {
+        assert!(size <= self.size(), "claiming more than the ring holds");
+        wait(|| (self.free() >= size).then_some(()));
+        let offset = self.cursor.get() & self.buf.size_mask;
+        unsafe { slice::from_raw_parts_mut(self.ptr.add(offset), size) }
+    }
+
+    // Publishes `size` claimed bytes to the stages, or what is free if that
+    // is less, and returns the new cursor.
+    pub fn publish(&mut self, size: usize) -> usize {
+        let size = cmp::min(size, self.free());
+        let cursor = self.cursor.get().wrapping_add(size);
+        self.cursor.set(cursor);
+        cursor
+    }
+}
+
+impl<'a> Stage<'a> {
+    pub fn size(&self) -> usize {
+        self.buf.size()
+    }
+
+    // How far this stage got, for other stages to depend on.
+    pub fn sequence(&self) -> Sequence {
+        self.sequence.clone()
+    }
+
+    // How far all the dependencies got, as seen now.
+    pub fn available(&self) -> usize {
+        minimum(&self.dependencies)
+    }
+
+    // Waits until all the dependencies got to `sequence`, and returns how
+    // far they got.
+    pub fn wait_for(&self, sequence: usize) -> usize {
+        wait(|| {
+            let available = self.available();
+            (available.wrapping_sub(sequence) as isize >= 0).then_some(available)
+        })
+    }
+
+    // The bytes the dependencies got through that this stage did not move
+    // past yet.
+    pub fn committed(&self) -> Option<&[u8]> {
+        let start = self.sequence.get();
+        let len = self.available().wrapping_sub(start);
+        if len == 0 {
+            return None;
+        }
+        let offset = start & self.buf.size_mask;
+        Some(unsafe { slice::from_raw_parts(self.ptr.add(offset), len) })
+    }
+
+    // Moves this stage `size` bytes forward, or to where its dependencies
+    // are if that is less, and returns its new sequence.
+    pub fn advance(&mut self, size: usize) -> usize {
+        let start = self.sequence.get();
+        let size = cmp::min(size, self.available().wrapping_sub(start));
+        let sequence = start.wrapping_add(size);
+        self.sequence.set(sequence);
+        sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, ErrorKind, MirroredBuffer};
+    use std::thread;
+
+    #[test]
+    fn sequence_pipeline() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let mut sequencer = buf.into_sequencer().unwrap();
+        let size = sequencer.size();
+        let start = sequencer.cursor().get();
+
+        // journal -> replicate -> process
+        let mut journal = sequencer.stage(&[]);
+        let mut replicate = sequencer.stage(&[journal.sequence()]);
+        let mut process = sequencer.stage(&[replicate.sequence()]);
+
+        sequencer.claim(100).fill(1);
+        sequencer.publish(100);
+        assert!(journal.committed().unwrap().len() == 100);
+        assert!(replicate.committed().is_none());
+        assert!(journal.advance(60) == start + 60);
+        assert!(replicate.committed().unwrap().len() == 60);
+        assert!(process.committed().is_none());
+        assert!(sequencer.free() == size - 100);
+
+        journal.advance(40);
+        replicate.advance(100);
+        process.advance(100);
+        assert!(sequencer.free() == size);
+
+        let total = 10 * size;
+        let end = start + 100 + total;
+        let handles: Vec<_> = [journal, replicate, process]
+            .into_iter()
+            .map(|mut stage| {
+                thread::spawn(move || {
+                    let mut seen = 100;
+                    while seen < 100 + total {
+                        stage.wait_for(start + seen + 1);
+                        let committed = stage.committed().unwrap();
+                        assert!(committed
+                            .iter()
+                            .enumerate()
+                            .all(|(x, &b)| b == ((seen + x) % 251) as u8));
+                        seen += committed.len();
+                        stage.advance(committed.len());
+                    }
+                    assert!(stage.sequence().get() == end);
+                })
+            })
+            .collect();
+
+        let mut published = 100;
+        while published < 100 + total {
+            let n = (published * 7 % 1000 + 1).min(100 + total - published);
+            let claimed = sequencer.claim(n);
+            for (x, b) in claimed.iter_mut().enumerate() {
+                *b = ((published + x) % 251) as u8;
+            }
+            sequencer.publish(n);
+            published += n;
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(sequencer.free() == size);
+    }
+
+    #[test]
+    fn sequence_rejects_a_read_only_mirror() {
+        // A claim from here would run across the wrap.
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+        let buf = buf.with_read_only_mirror().unwrap();
+        let err = buf.into_sequencer().err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::ReadOnlyMirror));
+    }
+}
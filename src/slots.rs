@@ -0,0 +1,175 @@
+use crate::{Error, MirroredBuffer};
+
+// A buffer of slots of a fixed size, each starting at a multiple of an
+// alignment, for large elements handed around whole, e.g. video frames or
+// DMA blocks, claimed, committed and consumed a slot at a time.
+//
+// Slots are the size rounded up to the alignment apart, the stride, counted
+// from the start of the buffer, which the mapping is aligned for. They wrap
+// around the end of the buffer like bytes do, so a slot may run past the end
+// into the mirror, aligned still: the alignment divides both the stride and
+// the size of the buffer.
+pub struct SlotRing<'a> {
+    buf: MirroredBuffer<'a>,
+    slot_size: usize,
+    alignment: usize,
+    stride: usize,
+}
+
+impl<'a> SlotRing<'a> {
+    // Holds slots of `slot_size` bytes aligned to `alignment`, a power of two,
+    // in `buf`, which must hold at least one. Whatever it has committed is
+    // dropped. Alignments beyond the page size move the mapping like
+    // `MirroredBuffer::with_granularity` does. Fails with InvalidSize if a
+    // slot does not fit, if the alignment is not a power of two or does not
+    // divide the size of the buffer, or if a slot would run into a read-only
+    // mirror.
+    pub fn new(
+        mut buf: MirroredBuffer<'a>,
+        slot_size: usize,
+        alignment: usize,
+    ) -> Result<SlotRing<'a>, Error> {
+        if !alignment.is_power_of_two() {
+            return Err(Error::invalid_size(alignment));
+        }
+        let stride = slot_size.next_multiple_of(alignment);
+        if slot_size == 0 || stride > buf.size() {
+            return Err(Error::invalid_size(slot_size));
+        }
+        if buf.has_read_only_mirror() && !buf.size().is_multiple_of(stride) {
+            return Err(Error::invalid_size(stride));
+        }
+        if alignment > buf.granularity() {
+            buf = buf.with_granularity(alignment)?;
+        }
+        buf.consume(buf.used());
+        buf.head = 0;
+        buf.tail = 0;
+        Ok(SlotRing {
+            buf,
+            slot_size,
+            alignment,
+            stride,
+        })
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    // How far apart slots are.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    // How many slots the ring holds.
+    pub fn capacity(&self) -> usize {
+        self.buf.size() / self.stride
+    }
+
+    // How many slots are committed.
+    pub fn len(&self) -> usize {
+        self.buf.used() / self.stride
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.used() == 0
+    }
+
+    pub fn free_slots(&self) -> usize {
+        self.buf.free() / self.stride
+    }
+
+    // Claims the next slot, or None if there is no room for it.
+    pub fn claim_slot(&mut self) -> Option<&mut [u8]> {
+        if self.free_slots() == 0 {
+            return None;
+        }
+        let claimed = self.buf.claim(self.stride)?;
+        Some(&mut claimed[..self.slot_size])
+    }
+
+    // Commits the slot claimed last, or returns false if there is no room
+    // for it.
+    pub fn commit_slot(&mut self) -> bool {
+        if self.free_slots() == 0 {
+            return false;
+        }
+        self.buf.commit(self.stride);
+        true
+    }
+
+    // The oldest committed slot.
+    pub fn front_slot(&self) -> Option<&[u8]> {
+        Some(&self.buf.committed()?[..self.slot_size])
+    }
+
+    // Consumes the oldest committed slot, or returns false if there is none.
+    pub fn consume_slot(&mut self) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        self.buf.consume(self.stride);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        util::{get_page_size, next_buffer_index},
+        ErrorKind, MirroredBuffer, SlotRing,
+    };
+
+    #[test]
+    fn slot_ring_aligns_slots_across_the_wrap() {
+        let page_size = get_page_size().unwrap();
+        let size = 4 * page_size;
+        let aligned = |slot: &[u8], alignment| (slot.as_ptr() as usize).is_multiple_of(alignment);
+        let buf = MirroredBuffer::new(size, Some(&next_buffer_index()), None).unwrap();
+        let mut ring = SlotRing::new(buf, 1500, 512).unwrap();
+        assert!(ring.stride() == 1536 && ring.capacity() == size / 1536);
+
+        // Around the end of the buffer, a slot at a time, some of them
+        // straddling it.
+        for i in 0..3 * ring.capacity() {
+            let slot = ring.claim_slot().unwrap();
+            assert!(slot.len() == 1500 && aligned(slot, 512));
+            slot.fill(i as u8);
+            assert!(ring.commit_slot());
+            let slot = ring.front_slot().unwrap();
+            assert!(aligned(slot, 512));
+            assert!(slot.iter().all(|&x| x == i as u8));
+            assert!(ring.consume_slot() && ring.is_empty());
+        }
+
+        // No further than there is room for.
+        while ring.claim_slot().is_some() {
+            ring.commit_slot();
+        }
+        assert!(ring.len() == ring.capacity() && !ring.commit_slot());
+        while ring.consume_slot() {}
+        assert!(ring.front_slot().is_none());
+
+        // Aligned beyond the page size.
+        let buf = MirroredBuffer::new(size, Some(&next_buffer_index()), None).unwrap();
+        let mut ring = SlotRing::new(buf, 3 * page_size, 2 * page_size).unwrap();
+        assert!(ring.stride() == 4 * page_size && ring.buffer().granularity() == 2 * page_size);
+        assert!(aligned(ring.claim_slot().unwrap(), 2 * page_size));
+        let buf = MirroredBuffer::new(size, Some(&next_buffer_index()), None).unwrap();
+        let err = SlotRing::new(buf, 1, 3).err().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::InvalidSize(_)));
+    }
+}
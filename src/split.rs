@@ -0,0 +1,730 @@
+use crate::{codec::Decoder, notify::Notify, wipe_ring, Error, FullPolicy, MirroredBuffer};
+use std::{
+    cell::Cell,
+    cmp,
+    io::{self, Read},
+    ops::Deref,
+    os::fd::BorrowedFd,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+// What both halves of a split buffer share. The buffer itself is only kept to
+// own the mapping, which goes away once both halves are dropped; the halves
+// go through the raw pointer.
+//
+// `head` and `tail` count the bytes consumed and committed, wrapping around,
+// so the used size is their difference and the offset of either in the
+// mapping is it masked by the size mask. Each is stored by one half only,
+// with release ordering, and loaded by the other with acquire ordering: what
+// the producer wrote is visible to the consumer once it sees the new tail,
+// and a region is only handed out again once the consumer is done with it.
+// Neither half waits on the other unless asked to: each can sleep until the
+// other moved its index far enough, and is woken through `data` when the
+// tail moves or `space` when the head does.
+//
+// The two indices sit on cache lines of their own, so that the producer
+// storing the tail does not evict the line the consumer stores the head to,
+// and the other way around.
+pub(crate) struct Shared<'a> {
+    pub(crate) buf: MirroredBuffer<'a>,
+    pub(crate) head: CachePadded<AtomicUsize>,
+    pub(crate) tail: CachePadded<AtomicUsize>,
+    pub(crate) data: Notify,
+    pub(crate) space: Notify,
+}
+
+impl<'a> Shared<'a> {
+    // Shares `buf`, along with whatever it has committed, and returns the
+    // pointer to its mapping.
+    pub(crate) fn new(mut buf: MirroredBuffer<'a>) -> (Arc<Shared<'a>>, *mut u8) {
+        buf.fill_rest();
+        let ptr = buf.slice.as_mut_ptr();
+        let head = buf.head;
+        let tail = buf.head.wrapping_add(buf.size_used);
+        let shared = Arc::new(Shared {
+            buf,
+            head: CachePadded(AtomicUsize::new(head)),
+            tail: CachePadded(AtomicUsize::new(tail)),
+            data: Notify::new(),
+            space: Notify::new(),
+        });
+        (shared, ptr)
+    }
+}
+
+// Aligns and pads `T` to a cache line. x86_64 prefetches lines in adjacent
+// pairs and recent aarch64 cores have 128 byte lines, hence 128 on both.
+#[cfg_attr(any(target_arch = "x86_64", target_arch = "aarch64"), repr(align(128)))]
+#[cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    repr(align(64))
+)]
+pub(crate) struct CachePadded<T>(pub(crate) T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// The writing half of a split buffer: it claims and commits.
+//
+// It keeps the head as last loaded and only loads it again when that does
+// not leave enough free space, so it does not pull in the consumer's cache
+// line on every claim. When there is not enough, it does what the buffer's
+// full policy says.
+pub struct Producer<'a> {
+    pub(crate) shared: Arc<Shared<'a>>,
+    ptr: *mut u8,
+    tail: usize,
+    head_cache: usize,
+}
+
+// The reading half of a split buffer: it reads the committed region and
+// consumes it.
+//
+// Likewise, it keeps the tail as last loaded to bound what it consumes.
+pub struct Consumer<'a> {
+    pub(crate) shared: Arc<Shared<'a>>,
+    ptr: *mut u8,
+    head: usize,
+    tail_cache: Cell<usize>,
+}
+
+// The producer only ever touches the free region and the consumer only the
+// committed one, and the two never overlap.
+unsafe impl Send for Producer<'_> {}
+unsafe impl Send for Consumer<'_> {}
+
+// Dropping either half publishes or releases what is left, for whichever
+// half outlives it.
+impl Drop for Producer<'_> {
+    fn drop(&mut self) {
+        self.publish();
+    }
+}
+
+impl Drop for Consumer<'_> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+impl<'a> MirroredBuffer<'a> {
+    // Splits the buffer into a producer and a consumer that can be moved to
+    // different threads, e.g. one filling from a socket while the other
+    // decodes. Whatever is committed stays committed.
+    pub fn split(self) -> (Producer<'a>, Consumer<'a>) {
+        let (shared, ptr) = Shared::new(self);
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Relaxed);
+
+        (
+            Producer {
+                shared: shared.clone(),
+                ptr,
+                tail,
+                head_cache: head,
+            },
+            Consumer::new(shared, ptr),
+        )
+    }
+}
+
+impl<'a> Producer<'a> {
+    pub fn name(&self) -> &str {
+        self.shared.buf.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.shared.buf.size()
+    }
+
+    // The free space as seen now; it only grows until the next commit.
+    pub fn free(&self) -> usize {
+        let head = self.shared.head.load(Ordering::Acquire);
+        self.size() - self.tail.wrapping_sub(head)
+    }
+
+    // The free space as of the cached head, or as seen now if that is less
+    // than `want`.
+    fn free_at_least(&mut self, want: usize) -> usize {
+        let free = self.size() - self.tail.wrapping_sub(self.head_cache);
+        if free >= want {
+            return free;
+        }
+        self.head_cache = self.shared.head.load(Ordering::Acquire);
+        self.size() - self.tail.wrapping_sub(self.head_cache)
+    }
+
+    // Sleeps until at least `size` bytes are free.
+    pub fn wait_for_space(&mut self, size: usize) {
+        self.wait_for_space_inner(size, None);
+    }
+
+    // Like `wait_for_space`, but gives up after `timeout`. Returns whether
+    // the space is free.
+    pub fn wait_for_space_timeout(&mut self, size: usize, timeout: Duration) -> bool {
+        self.wait_for_space_inner(size, Some(timeout))
+    }
+
+    fn wait_for_space_inner(&mut self, size: usize, timeout: Option<Duration>) -> bool {
+        assert!(
+            size <= self.size(),
+            "waiting for more than the buffer holds"
+        );
+        if self.free_at_least(size) >= size {
+            return true;
+        }
+        let shared = self.shared.clone();
+        shared
+            .space
+            .wait_until(|| self.free_at_least(size) >= size, timeout)
+    }
+
+    // An fd that becomes readable when the consumer releases space, to
+    // register with an event loop. Once woken, call `clear_space_fd` before
+    // checking the free space, so that no release goes unnoticed. Until it
+    // is first asked for, there is no fd and releasing costs no syscall.
+    pub fn space_fd(&self) -> io::Result<BorrowedFd<'_>> {
+        self.shared.space.event_fd()
+    }
+
+    pub fn clear_space_fd(&self) {
+        self.shared.space.clear_event_fd();
+    }
+
+    // Ready once at least `size` bytes are free; otherwise the task is woken
+    // when the consumer releases space. Only the task that polled last is
+    // woken.
+    pub fn poll_write_ready(&mut self, cx: &mut Context<'_>, size: usize) -> Poll<()> {
+        assert!(
+            size <= self.size(),
+            "waiting for more than the buffer holds"
+        );
+        if self.free_at_least(size) >= size {
+            return Poll::Ready(());
+        }
+        let shared = self.shared.clone();
+        shared
+            .space
+            .poll_until(cx, || self.free_at_least(size) >= size)
+    }
+
+    // How much of `size` the full policy lets through, waiting for it if
+    // the policy is to block. Nothing if that is more than the buffer holds,
+    // which no wait would make room for.
+    fn room(&mut self, size: usize) -> usize {
+        let full_policy = self.shared.buf.full_policy;
+        match full_policy {
+            FullPolicy::Clamp | FullPolicy::Overwrite => cmp::min(size, self.free_at_least(size)),
+            FullPolicy::Error if self.free_at_least(size) < size => 0,
+            FullPolicy::Error => size,
+            FullPolicy::Block if size > self.size() => 0,
+            FullPolicy::Block => {
+                self.wait_for_space(size);
+                size
+            }
+        }
+    }
+
+    pub fn claim(&mut self, mut size: usize) -> Option<&mut [u8]> {
+        size = self.room(size);
+        if size == 0 {
+            return None;
+        }
+        let offset = self.tail & self.shared.buf.size_mask;
+        let size = self.shared.buf.writable(offset, size);
+        Some(unsafe { slice::from_raw_parts_mut(self.ptr.add(offset), size) })
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        let size = self.commit_batched(size);
+        self.publish();
+        size
+    }
+
+    // Commits like `commit` but without showing it to the consumer until the
+    // next `publish`, so that several frames written one after the other cost
+    // a single release store. The producer sees its unpublished commits
+    // right away: later claims start after them.
+    pub fn commit_batched(&mut self, size: usize) -> usize {
+        let size = self.room(size);
+        self.tail = self.tail.wrapping_add(size);
+        size
+    }
+
+    // Shows everything committed so far to the consumer.
+    pub fn publish(&mut self) {
+        self.shared.tail.store(self.tail, Ordering::Release);
+        self.shared.data.notify();
+    }
+
+    // Like `MirroredBuffer::push`.
+    pub fn push(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let size = self.room(data.len());
+        let full_policy = self.shared.buf.full_policy;
+        if size < data.len() && matches!(full_policy, FullPolicy::Error | FullPolicy::Block) {
+            return Err(Error::no_space(data.len()));
+        }
+        // In two pieces, in case the mirror is read-only.
+        let offset = self.tail & self.shared.buf.size_mask;
+        let first = cmp::min(size, self.size() - offset);
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(offset), first);
+            ptr::copy_nonoverlapping(data[first..].as_ptr(), self.ptr, size - first);
+        }
+        Ok(self.commit(size))
+    }
+
+    // Like `MirroredBuffer::fill_from`. With the Block policy, it waits for
+    // some free space rather than return 0.
+    pub fn fill_from<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        if self.shared.buf.full_policy == FullPolicy::Block {
+            self.wait_for_space(1);
+        }
+        let free = self.free_at_least(1);
+        let Some(claimed) = self.claim(free) else {
+            return Ok(0);
+        };
+
+        loop {
+            match r.read(claimed) {
+                Ok(n) => return Ok(self.commit(n)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl<'a> Consumer<'a> {
+    pub(crate) fn new(shared: Arc<Shared<'a>>, ptr: *mut u8) -> Consumer<'a> {
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Relaxed);
+        Consumer {
+            shared,
+            ptr,
+            head,
+            tail_cache: Cell::new(tail),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.shared.buf.name()
+    }
+
+    pub fn size(&self) -> usize {
+        self.shared.buf.size()
+    }
+
+    // The committed size as seen now; it only grows until the next consume.
+    pub fn used(&self) -> usize {
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        self.tail_cache.set(tail);
+        tail.wrapping_sub(self.head)
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        let used = self.used();
+        if used == 0 {
+            return None;
+        }
+        let offset = self.head & self.shared.buf.size_mask;
+        Some(unsafe { slice::from_raw_parts(self.ptr.add(offset), used) })
+    }
+
+    pub fn consume(&mut self, size: usize) -> usize {
+        let size = self.consume_batched(size);
+        self.release();
+        size
+    }
+
+    // Consumes like `consume` but without handing the space back to the
+    // producer until the next `release`, so that several frames handled one
+    // after the other cost a single release store.
+    pub fn consume_batched(&mut self, size: usize) -> usize {
+        let mut used = self.tail_cache.get().wrapping_sub(self.head);
+        if used < size {
+            used = self.used();
+        }
+        let size = cmp::min(size, used);
+        if self.shared.buf.zeroize {
+            let buf = &self.shared.buf;
+            let offset = self.head & buf.size_mask;
+            unsafe { wipe_ring(self.ptr, buf.size_total, offset, size) };
+        }
+        self.head = self.head.wrapping_add(size);
+        size
+    }
+
+    // Hands everything consumed so far back to the producer.
+    pub fn release(&mut self) {
+        self.shared.head.store(self.head, Ordering::Release);
+        self.shared.space.notify();
+    }
+
+    // Sleeps until at least `size` bytes are committed.
+    pub fn wait_for_data(&self, size: usize) {
+        self.wait_for_data_inner(size, None);
+    }
+
+    // Like `wait_for_data`, but gives up after `timeout`. Returns whether
+    // the data is committed.
+    pub fn wait_for_data_timeout(&self, size: usize, timeout: Duration) -> bool {
+        self.wait_for_data_inner(size, Some(timeout))
+    }
+
+    fn wait_for_data_inner(&self, size: usize, timeout: Option<Duration>) -> bool {
+        assert!(
+            size <= self.size(),
+            "waiting for more than the buffer holds"
+        );
+        self.shared.data.wait_until(|| self.used() >= size, timeout)
+    }
+
+    // Like `Producer::space_fd`, but readable when the producer publishes.
+    pub fn data_fd(&self) -> io::Result<BorrowedFd<'_>> {
+        self.shared.data.event_fd()
+    }
+
+    pub fn clear_data_fd(&self) {
+        self.shared.data.clear_event_fd();
+    }
+
+    // Like `Producer::poll_write_ready`, but ready once at least `size`
+    // bytes are committed.
+    pub fn poll_read_ready(&self, cx: &mut Context<'_>, size: usize) -> Poll<()> {
+        assert!(
+            size <= self.size(),
+            "waiting for more than the buffer holds"
+        );
+        self.shared.data.poll_until(cx, || self.used() >= size)
+    }
+
+    // Like `MirroredBuffer::decode`.
+    pub fn decode<D: Decoder>(
+        &self,
+        decoder: &mut D,
+    ) -> Result<Option<(D::Frame<'_>, usize)>, Error> {
+        match self.committed() {
+            Some(committed) => decoder.decode(committed),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        codec::length_delimited::{Codec, HEADER_LEN},
+        util::next_buffer_index,
+        ErrorKind, FullPolicy, MirroredBuffer,
+    };
+    use futures::{executor::block_on, future::poll_fn};
+    use std::{
+        io::Write,
+        os::{
+            fd::{AsRawFd, RawFd},
+            unix::net::UnixStream,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn split_fill_and_decode_across_threads() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+        buf.claim(1).unwrap()[0] = 42;
+        buf.commit(1);
+
+        let (mut producer, mut consumer) = buf.split();
+        assert!(producer.free() == producer.size() - 1);
+        assert!(consumer.committed().unwrap() == [42]);
+        assert!(consumer.consume(1) == 1);
+
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        let frames = 1000;
+        let writer = thread::spawn(move || {
+            for i in 0..frames {
+                let len = 1 + (i * 31) % 500;
+                let mut frame = (len as u32).to_be_bytes().to_vec();
+                frame.extend((0..len).map(|x| (x + i) as u8));
+                a.write_all(&frame).unwrap();
+            }
+        });
+
+        let reader = thread::spawn(move || loop {
+            if producer.free() == 0 {
+                thread::yield_now();
+            } else if producer.fill_from(&mut b).unwrap() == 0 {
+                return;
+            }
+        });
+
+        let mut codec = Codec::new().with_max_len(consumer.size() - HEADER_LEN);
+        let mut decoded = 0;
+        while decoded < frames {
+            let Some((payload, size)) = consumer.decode(&mut codec).unwrap() else {
+                thread::yield_now();
+                continue;
+            };
+            assert!(payload.len() == 1 + (decoded * 31) % 500);
+            assert!(payload
+                .iter()
+                .enumerate()
+                .all(|(x, &b)| b == (x + decoded) as u8));
+            consumer.consume(size);
+            decoded += 1;
+        }
+
+        writer.join().unwrap();
+        reader.join().unwrap();
+        assert!(consumer.used() == 0);
+    }
+
+    #[test]
+    fn split_indices_on_separate_cache_lines() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (producer, _consumer) = buf.split();
+
+        let head = &*producer.shared.head as *const _ as usize;
+        let tail = &*producer.shared.tail as *const _ as usize;
+        assert!(head.abs_diff(tail) >= 64);
+        assert!(head.is_multiple_of(64) && tail.is_multiple_of(64));
+    }
+
+    #[test]
+    fn split_batched_commit_and_consume() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (mut producer, mut consumer) = buf.split();
+
+        for i in 0..3 {
+            producer.claim(10).unwrap().fill(i);
+            assert!(producer.commit_batched(10) == 10);
+        }
+        assert!(producer.free() == producer.size() - 30);
+        assert!(consumer.committed().is_none());
+
+        producer.publish();
+        let committed = consumer.committed().unwrap();
+        assert!(committed.len() == 30);
+        assert!((0..30).all(|x| committed[x] == (x / 10) as u8));
+
+        assert!(consumer.consume_batched(10) == 10);
+        assert!(consumer.consume_batched(10) == 10);
+        assert!(consumer.used() == 10);
+        assert!(producer.free() == producer.size() - 30);
+
+        consumer.release();
+        assert!(producer.free() == producer.size() - 10);
+
+        // Dropping the producer publishes its batch.
+        producer.claim(5).unwrap();
+        producer.commit_batched(5);
+        drop(producer);
+        assert!(consumer.used() == 15);
+    }
+
+    #[test]
+    fn split_full_policies() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let (mut producer, consumer) = buf.with_full_policy(FullPolicy::Error).split();
+        assert!(producer.push(&vec![1; size - 10]).unwrap() == size - 10);
+        let err = producer.push(&[2; 20]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(20)));
+        assert!(producer.claim(20).is_none());
+        assert!(producer.commit(20) == 0);
+        assert!(consumer.used() == size - 10);
+        drop((producer, consumer));
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (mut producer, mut consumer) = buf.with_full_policy(FullPolicy::Block).split();
+        // More than the buffer holds fails rather than waits.
+        let err = producer.push(&vec![1; size + 1]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::NoSpace(_)));
+        assert!(producer.claim(size + 1).is_none());
+        assert!(producer.commit(size + 1) == 0);
+        let rounds = 100;
+        let writer = thread::spawn(move || {
+            for i in 0..rounds {
+                // Each push waits for the consumer to take the previous one.
+                assert!(producer.push(&vec![i as u8; size / 2 + 1]).unwrap() == size / 2 + 1);
+            }
+        });
+
+        let mut read = 0;
+        while read < rounds * (size / 2 + 1) {
+            let Some(committed) = consumer.committed() else {
+                thread::yield_now();
+                continue;
+            };
+            let n = committed.len();
+            assert!(n <= size / 2 + 1);
+            assert!(committed
+                .iter()
+                .enumerate()
+                .all(|(x, &b)| b == ((read + x) / (size / 2 + 1)) as u8));
+            consumer.consume(n);
+            read += n;
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn split_wait_for_data_and_space() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let (mut producer, mut consumer) = buf.split();
+
+        let rounds = 200;
+        let reader = thread::spawn(move || {
+            for i in 0..rounds {
+                consumer.wait_for_data(size / 4);
+                let committed = consumer.committed().unwrap();
+                assert!(committed[..size / 4].iter().all(|&x| x == i as u8));
+                consumer.consume(size / 4);
+            }
+            consumer
+        });
+
+        for i in 0..rounds {
+            producer.wait_for_space(size / 4);
+            producer.claim(size / 4).unwrap().fill(i as u8);
+            // Publish in two steps, the reader must wait for both.
+            producer.commit(size / 8);
+            producer.commit(size / 4 - size / 8);
+        }
+
+        let consumer = reader.join().unwrap();
+        assert!(consumer.used() == 0);
+        producer.wait_for_space(size);
+    }
+
+    #[test]
+    fn split_wait_timeouts() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let (mut producer, mut consumer) = buf.split();
+        let timeout = Duration::from_millis(20);
+
+        let start = Instant::now();
+        assert!(!consumer.wait_for_data_timeout(1, timeout));
+        assert!(start.elapsed() >= timeout);
+        assert!(producer.wait_for_space_timeout(size, timeout));
+
+        producer.claim(size).unwrap();
+        producer.commit(size);
+        let start = Instant::now();
+        assert!(!producer.wait_for_space_timeout(1, timeout));
+        assert!(start.elapsed() >= timeout);
+        assert!(consumer.wait_for_data_timeout(size, timeout));
+
+        let waiter = thread::spawn(move || {
+            assert!(producer.wait_for_space_timeout(10, Duration::from_secs(10)));
+        });
+        thread::sleep(timeout);
+        consumer.consume(10);
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn split_readiness_fds() {
+        let readable = |fd: RawFd| {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            unsafe { libc::poll(&mut pollfd, 1, 0) == 1 }
+        };
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let (mut producer, mut consumer) = buf.split();
+        let data = consumer.data_fd().unwrap().as_raw_fd();
+        assert!(!readable(data));
+
+        producer.claim(10).unwrap();
+        producer.commit(5);
+        producer.commit(5);
+        assert!(readable(data));
+        consumer.clear_data_fd();
+        assert!(!readable(data));
+        assert!(consumer.used() == 10);
+
+        let space = producer.space_fd().unwrap().as_raw_fd();
+        assert!(!readable(space));
+        consumer.consume(10);
+        assert!(readable(space));
+        producer.clear_space_fd();
+        assert!(!readable(space));
+
+        // A consumer woken by epoll sees the data.
+        let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: 7,
+        };
+        assert!(unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, data, &mut event) } == 0);
+        let writer = thread::spawn(move || {
+            producer.claim(3).unwrap().fill(9);
+            producer.commit(3);
+            producer
+        });
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }];
+        assert!(unsafe { libc::epoll_wait(epoll, events.as_mut_ptr(), 1, 10_000) } == 1);
+        assert!({ events[0].u64 } == 7);
+        consumer.clear_data_fd();
+        assert!(consumer.committed().unwrap() == [9; 3]);
+        unsafe { libc::close(epoll) };
+        drop(writer.join().unwrap());
+    }
+
+    #[test]
+    fn split_async_pipe() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let (mut producer, mut consumer) = buf.split();
+
+        let total = 20 * size;
+        let reader = thread::spawn(move || {
+            block_on(async {
+                let mut read = 0;
+                while read < total {
+                    poll_fn(|cx| consumer.poll_read_ready(cx, 1)).await;
+                    let committed = consumer.committed().unwrap();
+                    assert!(committed
+                        .iter()
+                        .enumerate()
+                        .all(|(x, &b)| b == ((read + x) % 251) as u8));
+                    read += consumer.consume(committed.len());
+                }
+            })
+        });
+
+        block_on(async {
+            let mut written = 0;
+            while written < total {
+                let n = (written % 1000 + 1).min(total - written);
+                poll_fn(|cx| producer.poll_write_ready(cx, n)).await;
+                for (x, b) in producer.claim(n).unwrap().iter_mut().enumerate() {
+                    *b = ((written + x) % 251) as u8;
+                }
+                written += producer.commit(n);
+            }
+        });
+        reader.join().unwrap();
+    }
+}
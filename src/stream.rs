@@ -0,0 +1,521 @@
+use crate::MirroredBuffer;
+use std::{
+    cmp,
+    io::{self, BufRead, IoSlice, IoSliceMut, Read, Write},
+};
+
+impl<'a> MirroredBuffer<'a> {
+    // Reads once from `r` into the free region and commits what was read.
+    //
+    // Like `Read::read` with an empty slice, this returns Ok(0) without
+    // reading anything when the buffer is full, so callers looking for EOF
+    // should check `free()` first. Reads interrupted by a signal are retried.
+    pub fn fill_from<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        let free = self.free();
+        let Some(claimed) = self.claim(free) else {
+            return Ok(0);
+        };
+
+        loop {
+            match r.read(claimed) {
+                Ok(n) => return Ok(self.commit(n)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Writes the committed region to `w` until it is empty, consuming exactly
+    // what `w` accepted, and returns the number of bytes written.
+    //
+    // Short writes are retried with the remainder. If `w` would block after
+    // some bytes were written, the count so far is returned; if it would
+    // block right away, the WouldBlock error is returned so nonblocking
+    // callers can wait for writability.
+    pub fn flush_to<W: Write + ?Sized>(&mut self, w: &mut W) -> io::Result<usize> {
+        let mut written = 0;
+        while let Some(committed) = self.committed() {
+            match w.write(committed) {
+                Ok(0) => {
+                    if written > 0 {
+                        return Ok(written);
+                    }
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                Ok(n) => written += self.consume(n),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock && written > 0 => {
+                    return Ok(written)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(written)
+    }
+
+    // Moves up to `n` committed bytes into the claim region of `dst`, in a
+    // single copy thanks to the mirroring of both, commits them there and
+    // consumes them here. Returns how many were moved, as many as `dst`
+    // takes by its full policy, so 0 if it is full or nothing is committed.
+    pub fn transfer(&mut self, dst: &mut MirroredBuffer<'_>, n: usize) -> usize {
+        let Some(committed) = self.committed() else {
+            return 0;
+        };
+        let n = n.min(committed.len());
+        let Some(claimed) = dst.claim(n) else {
+            return 0;
+        };
+        let n = claimed.len();
+        claimed.copy_from_slice(&committed[..n]);
+        dst.commit(n);
+        self.consume(n)
+    }
+
+    // The free region as a single IoSliceMut, empty if the buffer is full.
+    // Thanks to the mirroring, the free region never needs a second slice.
+    pub fn claim_io_slices(&mut self) -> [IoSliceMut<'_>; 1] {
+        let free = self.free();
+        [IoSliceMut::new(self.claim(free).unwrap_or_default())]
+    }
+
+    // The committed region as a single IoSlice, empty if nothing is committed.
+    pub fn committed_io_slices(&self) -> [IoSlice<'_>; 1] {
+        [IoSlice::new(self.committed().unwrap_or_default())]
+    }
+}
+
+// Reading takes from the committed region and consumes what was read; it
+// returns Ok(0) once nothing is committed.
+impl Read for MirroredBuffer<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(committed) = self.committed() else {
+            return Ok(0);
+        };
+        let n = committed.len().min(buf.len());
+        buf[..n].copy_from_slice(&committed[..n]);
+        Ok(self.consume(n))
+    }
+}
+
+// Without copying, the committed region is what there is to read, which
+// `BufRead` methods like `read_until` or `lines` scan in place.
+impl BufRead for MirroredBuffer<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.committed().unwrap_or_default())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        MirroredBuffer::consume(self, amt);
+    }
+}
+
+// Writing copies into the claim region and commits it; it returns Ok(0) once
+// the buffer is full, which `write_all` reports as WriteZero.
+impl Write for MirroredBuffer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let Some(claimed) = self.claim(len) else {
+            return Ok(0);
+        };
+
+        let mut n = 0;
+        for buf in bufs {
+            let m = buf.len().min(claimed.len() - n);
+            claimed[n..n + m].copy_from_slice(&buf[..m]);
+            n += m;
+        }
+        Ok(self.commit(n))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Reads from `r` with a single `read_vectored` call scattering into the free
+// regions of `bufs`, in order. Each buffer is filled before the next one, and
+// what was read is committed accordingly. Useful to read into several
+// connections' buffers, or a header and a payload buffer, with one syscall.
+pub fn read_vectored<R: Read + ?Sized>(
+    r: &mut R,
+    bufs: &mut [&mut MirroredBuffer<'_>],
+) -> io::Result<usize> {
+    let (n, claimed) = loop {
+        let mut slices: Vec<IoSliceMut> = bufs
+            .iter_mut()
+            .map(|buf| {
+                let [slice] = buf.claim_io_slices();
+                slice
+            })
+            .collect();
+        // What each buffer took, which a read-only mirror may make less than
+        // what it has free.
+        let claimed: Vec<usize> = slices.iter().map(|slice| slice.len()).collect();
+
+        match r.read_vectored(&mut slices) {
+            Ok(n) => break (n, claimed),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    };
+
+    let mut remaining = n;
+    for (buf, claimed) in bufs.iter_mut().zip(claimed) {
+        remaining -= buf.commit(cmp::min(remaining, claimed));
+    }
+    Ok(n)
+}
+
+// Writes the committed regions of `bufs`, in order, to `w` with a single
+// `write_vectored` call and consumes what was accepted.
+pub fn write_vectored<W: Write + ?Sized>(
+    w: &mut W,
+    bufs: &mut [&mut MirroredBuffer<'_>],
+) -> io::Result<usize> {
+    let n = loop {
+        let slices: Vec<IoSlice> = bufs
+            .iter()
+            .map(|buf| {
+                let [slice] = buf.committed_io_slices();
+                slice
+            })
+            .collect();
+
+        match w.write_vectored(&slices) {
+            Ok(n) => break n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    };
+
+    let mut remaining = n;
+    for buf in bufs.iter_mut() {
+        remaining -= MirroredBuffer::consume(buf, remaining);
+    }
+    Ok(n)
+}
+
+// Like `io::copy` from `r` into `buf`, but reading straight into its claim
+// region rather than through a buffer on the stack. Stops at EOF or once the
+// buffer is full, and returns how many bytes were committed.
+pub fn copy_into<R: Read + ?Sized>(r: &mut R, buf: &mut MirroredBuffer<'_>) -> io::Result<u64> {
+    let mut copied = 0;
+    while buf.free() > 0 {
+        match buf.fill_from(r)? {
+            0 => break,
+            n => copied += n as u64,
+        }
+    }
+    Ok(copied)
+}
+
+// Like `io::copy` from `buf` into `w`, but writing straight from its
+// committed region. Stops once the buffer is empty, and returns how many
+// bytes were consumed. Errors are returned as they come, like `io::copy`
+// does, with what `w` accepted until then consumed.
+pub fn copy_from<W: Write + ?Sized>(buf: &mut MirroredBuffer<'_>, w: &mut W) -> io::Result<u64> {
+    let mut copied = 0;
+    while let Some(committed) = buf.committed() {
+        match w.write(committed) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => copied += buf.consume(n) as u64,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_from, copy_into, read_vectored, write_vectored};
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use std::{
+        io::{self, BufRead, Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[test]
+    fn stream_fill_from() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+
+        let offset = size - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let data: Vec<u8> = (0..100).collect();
+        let mut r = &data[..];
+        assert!(buf.fill_from(&mut r).unwrap() == 100);
+        assert!(buf.committed().unwrap() == data);
+
+        // EOF
+        assert!(buf.fill_from(&mut r).unwrap() == 0);
+
+        let mut r = io::repeat(1);
+        assert!(buf.fill_from(&mut r).unwrap() == size - 100);
+        assert!(buf.free() == 0);
+        assert!(buf.fill_from(&mut r).unwrap() == 0);
+    }
+
+    #[test]
+    fn stream_fill_from_interrupted() {
+        struct Interrupting(bool);
+
+        impl Read for Interrupting {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0 = !self.0;
+                if self.0 {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                buf[0] = 42;
+                Ok(1)
+            }
+        }
+
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        assert!(buf.fill_from(&mut Interrupting(false)).unwrap() == 1);
+        assert!(buf.committed().unwrap() == [42]);
+
+        struct Failing;
+
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::ConnectionReset.into())
+            }
+        }
+
+        let err = buf.fill_from(&mut Failing).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::ConnectionReset);
+        assert!(buf.used() == 1);
+    }
+
+    // Accepts at most `chunk` bytes per write and blocks after `limit` bytes.
+    struct Throttled {
+        data: Vec<u8>,
+        chunk: usize,
+        limit: usize,
+    }
+
+    impl Write for Throttled {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.data.len() >= self.limit {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.chunk).min(self.limit - self.data.len());
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream_flush_to() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        assert!(buf.flush_to(&mut Vec::new()).unwrap() == 0);
+
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let data: Vec<u8> = (0..100).collect();
+        buf.fill_from(&mut &data[..]).unwrap();
+
+        let mut w = Throttled {
+            data: Vec::new(),
+            chunk: 7,
+            limit: 60,
+        };
+        assert!(buf.flush_to(&mut w).unwrap() == 60);
+        assert!(buf.used() == 40);
+
+        let err = buf.flush_to(&mut w).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WouldBlock);
+        assert!(buf.used() == 40);
+
+        w.limit = usize::MAX;
+        assert!(buf.flush_to(&mut w).unwrap() == 40);
+        assert!(buf.used() == 0);
+        assert!(w.data == data);
+    }
+
+    #[test]
+    fn stream_flush_to_write_zero() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        buf.fill_from(&mut &[1u8; 10][..]).unwrap();
+
+        let mut full = [0u8; 4];
+        let mut w = &mut full[..];
+        assert!(buf.flush_to(&mut w).unwrap() == 4);
+        assert!(buf.used() == 6);
+
+        let err = buf.flush_to(&mut w).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WriteZero);
+        assert!(buf.used() == 6);
+    }
+
+    #[test]
+    fn stream_copy() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        buf.commit(size - 3);
+        buf.consume(size - 3);
+
+        let data: Vec<u8> = (0..size + 10).map(|i| i as u8).collect();
+        let mut r = &data[..];
+        assert!(copy_into(&mut r, &mut buf).unwrap() == size as u64);
+        assert!(r.len() == 10 && buf.committed().unwrap() == &data[..size]);
+
+        let mut w = Vec::new();
+        assert!(copy_from(&mut buf, &mut w).unwrap() == size as u64);
+        assert!(w == data[..size] && buf.used() == 0);
+        assert!(copy_into(&mut r, &mut buf).unwrap() == 10);
+        assert!(copy_into(&mut r, &mut buf).unwrap() == 0);
+
+        let mut full = &mut [0u8; 4][..];
+        let err = copy_from(&mut buf, &mut full).err().unwrap();
+        assert!(err.kind() == io::ErrorKind::WriteZero && buf.used() == 6);
+    }
+
+    #[test]
+    fn stream_buf_read() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        buf.push(b"one\ntwo\nthree").unwrap();
+        let mut line = Vec::new();
+        assert!(buf.read_until(b'\n', &mut line).unwrap() == 4 && line == b"one\n");
+        assert!(buf.fill_buf().unwrap() == b"two\nthree");
+        let lines: Vec<String> = buf.lines().map(Result::unwrap).collect();
+        assert!(lines == ["two", "three"]);
+    }
+
+    #[test]
+    fn stream_transfer() {
+        let mut src = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut dst = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = src.size();
+        assert!(src.transfer(&mut dst, 10) == 0);
+
+        // Across the end of both rings.
+        src.push(&vec![0; size - 2]).unwrap();
+        src.consume(size - 2);
+        dst.push(&vec![0; size - 3]).unwrap();
+        dst.consume(size - 5);
+        src.push(b"abcdef").unwrap();
+        assert!(src.transfer(&mut dst, 4) == 4);
+        assert!(src.committed().unwrap() == b"ef");
+        assert!(dst.committed().unwrap() == b"\0\0abcd");
+
+        // No more than `dst` takes.
+        dst.push(&vec![1; size - 7]).unwrap();
+        assert!(src.transfer(&mut dst, 10) == 1);
+        assert!(src.committed().unwrap() == b"f" && dst.free() == 0);
+        assert!(src.transfer(&mut dst, 10) == 0);
+    }
+
+    #[test]
+    fn stream_io_slices() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+
+        let offset = size - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        assert!(buf.committed_io_slices()[0].is_empty());
+        let [mut free] = buf.claim_io_slices();
+        assert!(free.len() == size);
+        free[..20].fill(3);
+        buf.commit(20);
+
+        let [committed] = buf.committed_io_slices();
+        assert!(committed.len() == 20);
+        assert!(committed.iter().all(|&x| x == 3));
+
+        buf.commit(size);
+        assert!(buf.claim_io_slices()[0].is_empty());
+    }
+
+    #[test]
+    fn stream_read_write() {
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+
+        let offset = size - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        assert!(buf.read(&mut [0; 8]).unwrap() == 0);
+        buf.write_all(b"wrapped ").unwrap();
+        let slices = [io::IoSlice::new(b"across "), io::IoSlice::new(b"the end")];
+        assert!(buf.write_vectored(&slices).unwrap() == 14);
+
+        let mut out = String::new();
+        buf.read_to_string(&mut out).unwrap();
+        assert!(out == "wrapped across the end");
+        assert!(buf.used() == 0);
+
+        // A write is cut short by the free space, then refused.
+        let data = vec![5u8; size + 1];
+        assert!(buf.write(&data).unwrap() == size);
+        assert!(buf.write(&data).unwrap() == 0);
+        let err = buf.write_all(&data).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn stream_vectored_tcp() {
+        let ln = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut tx = TcpStream::connect(ln.local_addr().unwrap()).unwrap();
+        let (mut rx, _) = ln.accept().unwrap();
+
+        let mut header = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut payload = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        header.fill_from(&mut &b"header"[..]).unwrap();
+        payload.fill_from(&mut &b"payload"[..]).unwrap();
+
+        let mut written = 0;
+        while written < 13 {
+            written += write_vectored(&mut tx, &mut [&mut header, &mut payload]).unwrap();
+        }
+        assert!(header.used() == 0 && payload.used() == 0);
+
+        // Leave 4 bytes of room in the first buffer so the read spills into
+        // the second one.
+        let mut first = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut second = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        first.commit(first.size() - 4);
+        first.consume(first.size() - 4);
+        first.commit(first.size() - 4);
+
+        let mut read = 0;
+        while read < 13 {
+            read += read_vectored(&mut rx, &mut [&mut first, &mut second]).unwrap();
+        }
+        assert!(first.free() == 0);
+        assert!(&first.committed().unwrap()[first.size() - 4..] == b"head");
+        assert!(second.committed().unwrap() == b"erpayload");
+    }
+
+    #[test]
+    fn stream_read_vectored_read_only_mirror() {
+        // The first buffer is empty but, with a read-only mirror, takes only
+        // the 4 bytes left before the wrap.
+        let mut first = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        first.commit(first.size() - 4);
+        first.consume(first.size() - 4);
+        let mut first = first.with_read_only_mirror().unwrap();
+        let mut second = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+
+        let mut r = &b"headerpayload"[..];
+        assert!(read_vectored(&mut r, &mut [&mut first, &mut second]).unwrap() == 13);
+        assert!(first.committed().unwrap() == b"head");
+        assert!(second.committed().unwrap() == b"erpayload");
+    }
+}
@@ -0,0 +1,154 @@
+use crate::MirroredBuffer;
+use std::{
+    cmp,
+    io::{self, Read},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Limits the rate at which a buffer is committed to, e.g. for a relay that
+// shapes the traffic it forwards.
+//
+// This is a token bucket: it holds up to `burst` bytes worth of tokens,
+// refilled at `rate` bytes per second, and committing takes a token per
+// byte. Claims and commits are clamped to the tokens there are; `delay` says
+// how long until there are enough, and `wait` sleeps for that long.
+pub struct Throttled<'a> {
+    buf: MirroredBuffer<'a>,
+    rate: u64,
+    burst: usize,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl<'a> Throttled<'a> {
+    // Starts with a full bucket, so the first `burst` bytes go through at
+    // once.
+    pub fn new(buf: MirroredBuffer<'a>, rate: u64, burst: usize) -> Throttled<'a> {
+        assert!(rate > 0, "the rate must be positive");
+        assert!(burst > 0, "the burst must be positive");
+        Throttled {
+            buf,
+            rate,
+            burst,
+            tokens: burst as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut MirroredBuffer<'a> {
+        &mut self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn burst(&self) -> usize {
+        self.burst
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+        self.refilled = now;
+    }
+
+    // How many bytes can be committed now.
+    pub fn allowance(&mut self) -> usize {
+        self.refill();
+        self.tokens as usize
+    }
+
+    // How long until `size` bytes can be committed. A size past the burst is
+    // counted as the burst, since the bucket never holds more.
+    pub fn delay(&mut self, size: usize) -> Duration {
+        self.refill();
+        let missing = cmp::min(size, self.burst) as f64 - self.tokens;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing / self.rate as f64)
+    }
+
+    // Sleeps until `size` bytes, or the burst, can be committed.
+    pub fn wait(&mut self, size: usize) {
+        loop {
+            let delay = self.delay(size);
+            if delay.is_zero() {
+                return;
+            }
+            thread::sleep(delay);
+        }
+    }
+
+    pub fn claim(&mut self, size: usize) -> Option<&mut [u8]> {
+        let size = cmp::min(size, self.allowance());
+        self.buf.claim(size)
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        let size = cmp::min(size, self.allowance());
+        let size = self.buf.commit(size);
+        self.tokens -= size as f64;
+        size
+    }
+
+    // Like `MirroredBuffer::fill_from`, reading no more than the allowance.
+    // Ok(0) may also mean there is none left.
+    pub fn fill_from<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        let Some(claimed) = self.claim(usize::MAX) else {
+            return Ok(0);
+        };
+
+        loop {
+            match r.read(claimed) {
+                Ok(n) => return Ok(self.commit(n)),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, MirroredBuffer, Throttled};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn throttle_token_bucket() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let mut buf = Throttled::new(buf, 10_000, 100);
+
+        // The burst goes through at once, then the bucket is empty.
+        assert!(buf.claim(150).unwrap().len() == 100);
+        assert!(buf.commit(150) == 100);
+        assert!(buf.delay(50) > Duration::ZERO);
+        assert!(buf.delay(50) <= Duration::from_millis(5));
+        assert!(buf.delay(1000) <= Duration::from_millis(10));
+
+        buf.wait(50);
+        assert!(buf.commit(50) == 50);
+        assert!(buf.buffer().used() == 150);
+
+        // 100 bytes per 10ms, in bursts of at most 100.
+        let start = Instant::now();
+        let mut committed = 0;
+        while committed < 1000 {
+            buf.wait(100);
+            committed += buf.commit(100);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(80));
+        assert!(buf.buffer().used() == 1150);
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{Error, MirroredBuffer};
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use std::{
+    cmp, io, mem, ptr,
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+// Not exported by io-uring. Makes the kernel consume provided buffers
+// incrementally, available since Linux 6.12.
+const IOU_PBUF_RING_INC: u16 = 2;
+
+impl<'a> MirroredBuffer<'a> {
+    // The whole mapping, both halves included, as registered by
+    // `register_with`. Useful to register several buffers in one call, with
+    // the index of each one's iovec being what the entry builders expect.
+    pub fn fixed_iovec(&self) -> libc::iovec {
+        libc::iovec {
+            iov_base: self.slice.as_ptr() as *mut libc::c_void,
+            iov_len: self.slice.len(),
+        }
+    }
+
+    // Registers the mapping as the only fixed buffer of `ring`, at index 0.
+    // Both halves are registered, so the claim and committed regions are
+    // always covered, including when they wrap around the end of the ring.
+    pub fn register_with<S: squeue::EntryMarker, C: cqueue::EntryMarker>(
+        &self,
+        ring: &mut IoUring<S, C>,
+    ) -> io::Result<()> {
+        // The kernel pins the registered pages until they are unregistered,
+        // so they stay valid for it even if the buffer is dropped first.
+        unsafe { ring.submitter().register_buffers(&[self.fixed_iovec()]) }
+    }
+
+    // Builds a ReadFixed entry reading from `fd` into the free region, for a
+    // buffer registered at `buf_index`. Returns None if the buffer is full.
+    //
+    // Pass the entry's completion to `complete_read`. Until then, the free
+    // region belongs to the kernel and must not be claimed.
+    pub fn read_fixed_entry(&mut self, fd: types::Fd, buf_index: u16) -> Option<squeue::Entry> {
+        let free = cmp::min(self.free(), u32::MAX as usize);
+        let claimed = self.claim(free)?;
+        Some(
+            opcode::ReadFixed::new(fd, claimed.as_mut_ptr(), claimed.len() as u32, buf_index)
+                .offset(u64::MAX) // the file's current position, for streams
+                .build(),
+        )
+    }
+
+    // Builds a WriteFixed entry writing the committed region to `fd`, for a
+    // buffer registered at `buf_index`. Returns None if nothing is committed.
+    //
+    // Pass the entry's completion to `complete_write`. Until then, the
+    // committed region must not be consumed.
+    pub fn write_fixed_entry(&self, fd: types::Fd, buf_index: u16) -> Option<squeue::Entry> {
+        let committed = self.committed()?;
+        let len = cmp::min(committed.len(), u32::MAX as usize);
+        Some(
+            opcode::WriteFixed::new(fd, committed.as_ptr(), len as u32, buf_index)
+                .offset(u64::MAX)
+                .build(),
+        )
+    }
+
+    // Commits what the read of a `read_fixed_entry` put in the free region.
+    pub fn complete_read(&mut self, cqe: &cqueue::Entry) -> io::Result<usize> {
+        let n = cqe_result(cqe)?;
+        Ok(self.commit(n))
+    }
+
+    // Consumes what the write of a `write_fixed_entry` sent.
+    pub fn complete_write(&mut self, cqe: &cqueue::Entry) -> io::Result<usize> {
+        let n = cqe_result(cqe)?;
+        Ok(self.consume(n))
+    }
+}
+
+// Carves the first half of a MirroredBuffer's mapping into `size() / chunk`
+// chunks and provides them, in address order, to an io_uring provided-buffer
+// ring registered as buffer group `bgid`. A multishot receive selecting from
+// that group then deposits data straight into the buffer.
+//
+// The ring is registered with IOU_PBUF_RING_INC, so the kernel fills each
+// chunk completely, across as many receives as it takes, before moving on to
+// the next one. Received data is thus laid out back to back, and the last
+// chunk is followed by the first one, which the mirroring makes contiguous:
+// completions are simply committed, and the committed region is consumed as
+// usual. Chunks are provided again once they are fully consumed.
+pub struct ProvidedBufRing<'a> {
+    buf: MirroredBuffer<'a>,
+    entries: *mut types::BufRingEntry,
+    entries_len: usize,
+    bgid: u16,
+    chunk: usize,
+    tail: u16,
+    // Running totals of the bytes provided to the kernel and consumed.
+    provided: usize,
+    consumed: usize,
+}
+
+impl<'a> ProvidedBufRing<'a> {
+    pub fn new<S: squeue::EntryMarker, C: cqueue::EntryMarker>(
+        mut buf: MirroredBuffer<'a>,
+        ring: &mut IoUring<S, C>,
+        bgid: u16,
+        chunk: usize,
+    ) -> Result<ProvidedBufRing<'a>, Error> {
+        let count = buf.size() / chunk.max(1);
+        if !chunk.is_power_of_two() || count == 0 || count > 1 << 15 || buf.used() > 0 {
+            return Err(Error::invalid_size(chunk));
+        }
+        // The kernel writes to the chunks without claiming them.
+        buf.fill_rest();
+
+        let entries_len = count * mem::size_of::<types::BufRingEntry>();
+        let entries = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                entries_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if entries == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+
+        let mut provided = ProvidedBufRing {
+            buf,
+            entries: entries as *mut types::BufRingEntry,
+            entries_len,
+            bgid,
+            chunk,
+            tail: 0,
+            provided: 0,
+            consumed: 0,
+        };
+
+        // The kernel pins the entries' pages for as long as they are
+        // registered.
+        unsafe {
+            ring.submitter().register_buf_ring_with_flags(
+                entries as u64,
+                count as u16,
+                bgid,
+                IOU_PBUF_RING_INC,
+            )?;
+        }
+        provided.recycle();
+
+        Ok(provided)
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    pub fn chunk(&self) -> usize {
+        self.chunk
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    // A multishot receive on `fd` selecting buffers from this ring. It has to
+    // be submitted again once a completion comes without IORING_CQE_F_MORE,
+    // e.g. after ENOBUFS when every chunk holds unconsumed data.
+    pub fn recv_multi_entry(&self, fd: types::Fd) -> squeue::Entry {
+        opcode::RecvMulti::new(fd, self.bgid).build()
+    }
+
+    // Commits the data received by a completion of `recv_multi_entry`.
+    pub fn complete(&mut self, cqe: &cqueue::Entry) -> io::Result<usize> {
+        let n = cqe_result(cqe)?;
+        Ok(self.buf.commit(n))
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        self.buf.committed()
+    }
+
+    // Consumes committed data and provides the chunks it freed back to the
+    // kernel.
+    pub fn consume(&mut self, size: usize) -> usize {
+        let size = self.buf.consume(size);
+        self.consumed = self.consumed.wrapping_add(size);
+        self.recycle();
+        size
+    }
+
+    // Unregisters the buffer group and gives the buffer back.
+    pub fn unregister<S: squeue::EntryMarker, C: cqueue::EntryMarker>(
+        self,
+        ring: &mut IoUring<S, C>,
+    ) -> io::Result<MirroredBuffer<'a>> {
+        ring.submitter().unregister_buf_ring(self.bgid)?;
+        let this = mem::ManuallyDrop::new(self);
+        unsafe {
+            libc::munmap(this.entries as *mut libc::c_void, this.entries_len);
+            Ok(ptr::read(&this.buf))
+        }
+    }
+
+    fn recycle(&mut self) {
+        let count = self.buf.size() / self.chunk;
+        let base = self.buf.slice.as_ptr() as u64;
+        let mut published = false;
+
+        while self
+            .provided
+            .wrapping_add(self.chunk)
+            .wrapping_sub(self.consumed)
+            <= self.buf.size()
+        {
+            let bid = (self.provided / self.chunk) % count;
+            let entry = unsafe { &mut *self.entries.add(self.tail as usize % count) };
+            entry.set_addr(base + (bid * self.chunk) as u64);
+            entry.set_len(self.chunk as u32);
+            entry.set_bid(bid as u16);
+
+            self.tail = self.tail.wrapping_add(1);
+            self.provided = self.provided.wrapping_add(self.chunk);
+            published = true;
+        }
+
+        if published {
+            let tail = unsafe { types::BufRingEntry::tail(self.entries) as *const AtomicU16 };
+            unsafe { (*tail).store(self.tail, Ordering::Release) };
+        }
+    }
+}
+
+impl<'a> Drop for ProvidedBufRing<'a> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.entries as *mut libc::c_void, self.entries_len) };
+    }
+}
+
+fn cqe_result(cqe: &cqueue::Entry) -> io::Result<usize> {
+    let res = cqe.result();
+    if res < 0 {
+        return Err(io::Error::from_raw_os_error(-res));
+    }
+    Ok(res as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProvidedBufRing;
+    use crate::{util::next_buffer_index, MirroredBuffer};
+    use io_uring::{cqueue, types, IoUring};
+    use std::{
+        io::{Read, Write},
+        os::unix::{io::AsRawFd, net::UnixStream},
+    };
+
+    #[test]
+    fn uring_fixed_read_write() {
+        let Ok(mut ring) = IoUring::new(8) else {
+            return; // io_uring is not available
+        };
+
+        let mut buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        if buf.register_with(&mut ring).is_err() {
+            return;
+        }
+
+        let offset = buf.size() - 10;
+        buf.commit(offset);
+        buf.consume(offset);
+
+        let (mut local, remote) = UnixStream::pair().unwrap();
+        let fd = types::Fd(remote.as_raw_fd());
+
+        let data: Vec<u8> = (0..100).collect();
+        local.write_all(&data).unwrap();
+
+        let entry = buf.read_fixed_entry(fd, 0).unwrap().user_data(1);
+        unsafe { ring.submission().push(&entry).unwrap() };
+        ring.submit_and_wait(1).unwrap();
+        let cqe = ring.completion().next().unwrap();
+        assert!(cqe.user_data() == 1);
+        assert!(buf.complete_read(&cqe).unwrap() == 100);
+        assert!(buf.committed().unwrap() == data);
+
+        let entry = buf.write_fixed_entry(fd, 0).unwrap().user_data(2);
+        unsafe { ring.submission().push(&entry).unwrap() };
+        ring.submit_and_wait(1).unwrap();
+        let cqe = ring.completion().next().unwrap();
+        assert!(buf.complete_write(&cqe).unwrap() == 100);
+        assert!(buf.used() == 0);
+
+        let mut echoed = vec![0u8; 100];
+        local.read_exact(&mut echoed).unwrap();
+        assert!(echoed == data);
+    }
+
+    #[test]
+    fn uring_provided_buf_ring() {
+        let Ok(mut ring) = IoUring::new(8) else {
+            return; // io_uring is not available
+        };
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let size = buf.size();
+        let chunk = size / 4;
+        let Ok(mut provided) = ProvidedBufRing::new(buf, &mut ring, 7, chunk) else {
+            return; // provided buffer rings or IOU_PBUF_RING_INC are not available
+        };
+
+        let (mut local, remote) = UnixStream::pair().unwrap();
+        let fd = types::Fd(remote.as_raw_fd());
+        let entry = provided.recv_multi_entry(fd).user_data(1);
+        unsafe { ring.submission().push(&entry).unwrap() };
+        ring.submit().unwrap();
+
+        let mut receive = |provided: &mut ProvidedBufRing, n: usize| {
+            let mut received = 0;
+            while received < n {
+                ring.submit_and_wait(1).unwrap();
+                for cqe in ring.completion().collect::<Vec<_>>() {
+                    assert!(cqueue::more(cqe.flags()));
+                    received += provided.complete(&cqe).unwrap();
+                }
+            }
+        };
+
+        // Spans the first 3 chunks, the third partially.
+        let first: Vec<u8> = (0..3 * chunk - 100).map(|x| x as u8).collect();
+        local.write_all(&first).unwrap();
+        receive(&mut provided, first.len());
+        assert!(provided.committed().unwrap() == first);
+
+        // Frees the first 2 chunks, which are provided again after the last.
+        provided.consume(first.len());
+
+        // Fills the rest of the third chunk, the fourth, and wraps around to
+        // the first one.
+        let second: Vec<u8> = (0..chunk + 300).map(|x| (x * 7) as u8).collect();
+        local.write_all(&second).unwrap();
+        receive(&mut provided, second.len());
+        assert!(provided.committed().unwrap() == second);
+
+        provided.consume(second.len());
+        let buf = provided.unregister(&mut ring).unwrap();
+        assert!(buf.used() == 0);
+    }
+
+    #[test]
+    fn uring_provided_buf_ring_lazy_fill() {
+        let Ok(mut ring) = IoUring::new(8) else {
+            return; // io_uring is not available
+        };
+
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), None)
+            .unwrap()
+            .with_lazy_fill(0xaa);
+        let chunk = buf.size() / 4;
+        let Ok(mut provided) = ProvidedBufRing::new(buf, &mut ring, 8, chunk) else {
+            return; // provided buffer rings or IOU_PBUF_RING_INC are not available
+        };
+
+        // What the kernel received is what gets committed.
+        let (mut local, remote) = UnixStream::pair().unwrap();
+        let entry = provided
+            .recv_multi_entry(types::Fd(remote.as_raw_fd()))
+            .user_data(1);
+        unsafe { ring.submission().push(&entry).unwrap() };
+        local.write_all(b"hello world").unwrap();
+        ring.submit_and_wait(1).unwrap();
+        let cqe = ring.completion().next().unwrap();
+        assert!(provided.complete(&cqe).unwrap() == 11);
+        assert!(provided.committed().unwrap() == b"hello world");
+        provided.consume(11);
+        let mut buf = provided.unregister(&mut ring).unwrap();
+        assert!(buf.claim(chunk).unwrap().iter().all(|&x| x == 0xaa));
+    }
+}
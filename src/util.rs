@@ -1,19 +1,87 @@
-use std::io;
+use crate::Error;
+use std::sync::OnceLock;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+use std::{
+    ptr,
+    sync::atomic::{self, Ordering},
+};
 
-pub fn get_page_size() -> Result<usize, io::Error> {
+// The page size, asked of the system once, or set with `set_page_size`.
+static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+
+fn system_page_size() -> Option<usize> {
     let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
-    if page_size <= 0 {
-        return Err(io::Error::last_os_error());
+    (page_size > 0).then_some(page_size as usize)
+}
+
+pub fn get_page_size() -> Result<usize, Error> {
+    if let Some(&page_size) = PAGE_SIZE.get() {
+        return Ok(page_size);
+    }
+    let page_size = system_page_size().ok_or_else(Error::no_page_size)?;
+    Ok(*PAGE_SIZE.get_or_init(|| page_size))
+}
+
+// Sets the page size buffers are sized and mapped by, for environments where
+// the system does not tell it, or to size buffers by a larger power of two.
+// It must be a multiple of the system's page size, if the system tells it,
+// and be set before any buffer is created: fails with InvalidSize otherwise,
+// unless it is the page size already in use.
+pub fn set_page_size(page_size: usize) -> Result<(), Error> {
+    let fits = system_page_size().is_none_or(|system| page_size.is_multiple_of(system));
+    if !page_size.is_power_of_two() || !fits {
+        return Err(Error::invalid_size(page_size));
+    }
+    if *PAGE_SIZE.get_or_init(|| page_size) != page_size {
+        return Err(Error::invalid_size(page_size));
+    }
+    Ok(())
+}
+
+pub fn round_up_to_page_size(n: usize) -> Result<usize, Error> {
+    let page_size = get_page_size()?;
+    if n > 0 && n.is_multiple_of(page_size) {
+        return Ok(n);
     }
-    Ok(page_size as usize)
+    Ok((n / page_size + 1) * page_size)
 }
 
-pub fn round_up_to_page_size(n: usize) -> usize {
-    let page_size = get_page_size().expect("could not get the system's page size");
-    if n > 0 && n % page_size == 0 {
-        return n;
+// Zeroes the `len` bytes at `ptr` in a way the compiler cannot optimize
+// out, however dead the memory looks afterwards.
+pub(crate) unsafe fn wipe(ptr: *mut u8, len: usize) {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    unsafe {
+        libc::explicit_bzero(ptr as *mut libc::c_void, len)
+    };
+    #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+    {
+        for i in 0..len {
+            unsafe { ptr::write_volatile(ptr.add(i), 0) };
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
     }
-    (n / page_size + 1) * page_size
+}
+
+// Used to prevent opening a MirroredBuffer on an already existing one,
+// which results in an error as the underlying tmpfs file is opened in
+// O_EXCL mode. O_EXCL ensures shm_open fails if the underlying file
+// already exists.
+//
+// This can happen if we destroy a MirroredBuffer and then quickly create
+// a new one with the same exact name. It is a result of calling shm_unlink
+// when Dropping the MirroredBuffer - the syscall might take a some time to
+// complete, notably more than it takes the binary to go to the next test
+// and create a new MirroredBuffer with the same name.
+//
+// As a result, each test creates a unique MirroredBuffer by providing the
+// return value of `next_buffer_index()` as a suffix. The index is shared by
+// the tests of all modules, which run concurrently.
+#[cfg(test)]
+pub fn next_buffer_index() -> String {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static BUFFER_INDEX: AtomicUsize = AtomicUsize::new(0);
+    BUFFER_INDEX.fetch_add(1, Ordering::Relaxed).to_string()
 }
 
 #[cfg(test)]
@@ -24,11 +92,22 @@ mod tests {
     fn round_up_to_page_size() {
         let page_size = get_page_size().unwrap();
         println!("page size is {}", page_size);
-        assert!(super::round_up_to_page_size(0) == page_size);
-        assert!(super::round_up_to_page_size(1) == page_size);
-        assert!(super::round_up_to_page_size(page_size - 1) == page_size);
-        assert!(super::round_up_to_page_size(page_size) == page_size);
-        assert!(super::round_up_to_page_size(page_size + 1) == page_size * 2);
-        assert!(super::round_up_to_page_size(page_size * 2) == page_size * 2);
+        let round_up = |n| super::round_up_to_page_size(n).unwrap();
+        assert!(round_up(0) == page_size);
+        assert!(round_up(1) == page_size);
+        assert!(round_up(page_size - 1) == page_size);
+        assert!(round_up(page_size) == page_size);
+        assert!(round_up(page_size + 1) == page_size * 2);
+        assert!(round_up(page_size * 2) == page_size * 2);
+    }
+
+    #[test]
+    fn set_page_size() {
+        // Only the page size in use already can be set, once any test ran.
+        let page_size = get_page_size().unwrap();
+        assert!(super::set_page_size(page_size).is_ok());
+        assert!(super::set_page_size(page_size * 2).is_err());
+        assert!(super::set_page_size(page_size + 1).is_err());
+        assert!(get_page_size().unwrap() == page_size);
     }
 }
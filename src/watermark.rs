@@ -0,0 +1,158 @@
+use crate::MirroredBuffer;
+use std::io::{self, Read, Write};
+
+// Which way the used size crossed a watermark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watermark {
+    // It rose to the high watermark: time to push back, or shed.
+    High,
+    // It fell back to the low watermark: time to resume.
+    Low,
+}
+
+// Calls `on_cross` when the used size of a buffer crosses its watermarks,
+// which goes through this wrapper to be watched.
+//
+// The watermarks have hysteresis: once High fired, it only fires again after
+// Low did, so a buffer hovering around either watermark does not flap.
+pub struct Watermarked<'a, F: FnMut(Watermark)> {
+    buf: MirroredBuffer<'a>,
+    low: usize,
+    high: usize,
+    above: bool,
+    on_cross: F,
+}
+
+impl<'a, F: FnMut(Watermark)> Watermarked<'a, F> {
+    // Watches `buf` for its used size reaching `high`, and then going back
+    // down to `low`. Whatever `buf` has committed counts: if that is already
+    // at `high`, High fires on the first commit or consume.
+    pub fn new(
+        buf: MirroredBuffer<'a>,
+        low: usize,
+        high: usize,
+        on_cross: F,
+    ) -> Watermarked<'a, F> {
+        assert!(low < high, "the low watermark must be below the high one");
+        assert!(high <= buf.size(), "the high watermark is past the size");
+        Watermarked {
+            buf,
+            low,
+            high,
+            above: false,
+            on_cross,
+        }
+    }
+
+    pub fn buffer(&self) -> &MirroredBuffer<'a> {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> MirroredBuffer<'a> {
+        self.buf
+    }
+
+    pub fn low(&self) -> usize {
+        self.low
+    }
+
+    pub fn high(&self) -> usize {
+        self.high
+    }
+
+    // Whether High fired last.
+    pub fn is_above(&self) -> bool {
+        self.above
+    }
+
+    fn check(&mut self) {
+        let used = self.buf.used();
+        if !self.above && used >= self.high {
+            self.above = true;
+            (self.on_cross)(Watermark::High);
+        } else if self.above && used <= self.low {
+            self.above = false;
+            (self.on_cross)(Watermark::Low);
+        }
+    }
+
+    pub fn claim(&mut self, size: usize) -> Option<&mut [u8]> {
+        self.buf.claim(size)
+    }
+
+    pub fn commit(&mut self, size: usize) -> usize {
+        let size = self.buf.commit(size);
+        self.check();
+        size
+    }
+
+    pub fn committed(&self) -> Option<&[u8]> {
+        self.buf.committed()
+    }
+
+    pub fn consume(&mut self, size: usize) -> usize {
+        let size = self.buf.consume(size);
+        self.check();
+        size
+    }
+
+    pub fn fill_from<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        let n = self.buf.fill_from(r)?;
+        self.check();
+        Ok(n)
+    }
+
+    pub fn flush_to<W: Write + ?Sized>(&mut self, w: &mut W) -> io::Result<usize> {
+        let n = self.buf.flush_to(w);
+        // What was flushed before an error still counts.
+        self.check();
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util::next_buffer_index, MirroredBuffer, Watermark, Watermarked};
+    use std::cell::RefCell;
+
+    #[test]
+    fn watermark_fires_on_crossing_only() {
+        let buf = MirroredBuffer::new(1, Some(&next_buffer_index()), Some(0)).unwrap();
+        let fired = RefCell::new(Vec::new());
+        let mut buf = Watermarked::new(buf, 100, 1000, |mark| fired.borrow_mut().push(mark));
+
+        buf.claim(999).unwrap();
+        buf.commit(999);
+        assert!(fired.borrow().is_empty());
+        buf.claim(1).unwrap();
+        buf.commit(1);
+        assert!(*fired.borrow() == [Watermark::High]);
+        assert!(buf.is_above());
+
+        // No flapping between the watermarks.
+        buf.consume(500);
+        buf.claim(600).unwrap();
+        buf.commit(600);
+        assert!(fired.borrow().len() == 1);
+
+        buf.consume(900);
+        assert!(*fired.borrow() == [Watermark::High]);
+        buf.consume(100);
+        assert!(*fired.borrow() == [Watermark::High, Watermark::Low]);
+        assert!(!buf.is_above());
+
+        let mut w = Vec::new();
+        buf.claim(1000).unwrap();
+        buf.commit(1000);
+        assert!(buf.flush_to(&mut w).unwrap() == 1100);
+        assert!(
+            *fired.borrow()
+                == [
+                    Watermark::High,
+                    Watermark::Low,
+                    Watermark::High,
+                    Watermark::Low
+                ]
+        );
+    }
+}